@@ -1,16 +1,37 @@
-#[derive(Debug, Clone, PartialEq)]
+use std::fmt;
+
+use serde::{Serialize, Deserialize};
+
+use crate::date::Date;
+
+/// Where a node is in the source: the position of its first character and the
+/// position just past its last. A binary operation spans just its operator, so
+/// errors in it point there.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
 pub struct Span {
     pub line: usize,
     pub column: usize,
+    pub end_line: usize,
+    pub end_column: usize,
 }
 
-#[derive(Debug, Clone, PartialEq)]
+impl Span {
+    /// From the start of `self` to the end of `end`
+    pub fn to(&self, end: &Span) -> Span {
+        Span { end_line: end.end_line, end_column: end.end_column, ..self.clone() }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum Expr {
     Number(String, Span),
+    Date(Date, Span),
     String(String, Span),
     Char(String, Span),
     Variable(String, Span),
     Boolean(bool, Span),
+    /// The NULL pointer
+    Null(Span),
     BinaryOp(Box<Expr>, BinaryOp, Box<Expr>, Span),   
     UnaryOp(UnaryOp, Box<Expr>, Span),
 
@@ -20,8 +41,10 @@ pub enum Expr {
         span: Span,
     },
 
+    /// `array[indices]`, where `array` is usually a variable but may be any
+    /// element, field or pointer target holding an array
     ArrayAccess {
-        array: String,
+        array: Box<Expr>,
         indices: Vec<Expr>,
         span: Span,
     },
@@ -42,24 +65,167 @@ pub enum Expr {
         span: Span,
     },
 
+    /// NEW(type) allocates a cell holding the type's default value
+    New {
+        type_name: Type,
+        span: Span,
+    },
+
     // SetAccess {
     //     set: Box<Expr>,
     //     element: Box<Expr>,
     // },
+}
 
-    // EnumAccess {
-    //     enum_type: String,
-    //     value: String,
-    // },
+impl Expr {
+    pub fn span(&self) -> &Span {
+        match self {
+            Expr::Number(_, span)
+            | Expr::Date(_, span)
+            | Expr::String(_, span)
+            | Expr::Char(_, span)
+            | Expr::Variable(_, span)
+            | Expr::Boolean(_, span)
+            | Expr::Null(span)
+            | Expr::BinaryOp(_, _, _, span)
+            | Expr::UnaryOp(_, _, span)
+            | Expr::FunctionCall { span, .. }
+            | Expr::ArrayAccess { span, .. }
+            | Expr::FieldAccess { span, .. }
+            | Expr::PointerDeref { span, .. }
+            | Expr::PointerRef { span, .. }
+            | Expr::New { span, .. } => span,
+        }
+    }
+
+    /// The storage this expression names, if it is a variable or an element,
+    /// field or pointer target reached from one, as a BYREF argument must be
+    pub fn to_lvalue(&self) -> Option<LValue> {
+        match self {
+            Expr::Variable(name, span) => Some(LValue::Variable(name.clone(), span.clone())),
+            Expr::ArrayAccess { array, indices, span } => Some(LValue::Index {
+                target: Box::new(array.to_lvalue()?),
+                indices: indices.clone(),
+                span: span.clone(),
+            }),
+            Expr::FieldAccess { object, field, span } => Some(LValue::Field {
+                target: Box::new(object.to_lvalue()?),
+                field: field.clone(),
+                span: span.clone(),
+            }),
+            Expr::PointerDeref { pointer, span } => Some(LValue::Deref {
+                target: Box::new(pointer.to_lvalue()?),
+                span: span.clone(),
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// The expression written back out as source
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Operands that bind more loosely than their operator need parentheses
+        let operand = |operand: &Expr, precedence: u8| match operand {
+            Expr::BinaryOp(_, op, _, _) if op.precedence() < precedence => format!("({})", operand),
+            _ => operand.to_string(),
+        };
+        match self {
+            Expr::Number(number, _) => write!(f, "{}", number),
+            Expr::Date(date, _) => write!(f, "{}", date),
+            Expr::String(string, _) => write!(f, "\"{}\"", string),
+            Expr::Char(ch, _) => write!(f, "'{}'", ch),
+            Expr::Variable(name, _) => write!(f, "{}", name),
+            Expr::Boolean(b, _) => write!(f, "{}", if *b { "TRUE" } else { "FALSE" }),
+            Expr::Null(_) => write!(f, "NULL"),
+            // Operators of equal precedence group to the left
+            Expr::BinaryOp(left, op, right, _) => {
+                write!(f, "{} {} {}", operand(left, op.precedence()), op.symbol(), operand(right, op.precedence() + 1))
+            }
+            Expr::UnaryOp(UnaryOp::Negate, inner, _) => write!(f, "-{}", operand(inner, u8::MAX)),
+            Expr::UnaryOp(UnaryOp::Not, inner, _) => write!(f, "NOT {}", operand(inner, u8::MAX)),
+            Expr::FunctionCall { name, args, .. } => write!(f, "{}({})", name, list(args)),
+            Expr::ArrayAccess { array, indices, .. } => write!(f, "{}[{}]", array, list(indices)),
+            Expr::FieldAccess { object, field, .. } => write!(f, "{}.{}", object, field),
+            Expr::PointerDeref { pointer, .. } => write!(f, "{}^", pointer),
+            Expr::PointerRef { target, .. } => write!(f, "^{}", target),
+            Expr::New { type_name, .. } => write!(f, "NEW({:?})", type_name),
+        }
+    }
+}
+
+fn list(exprs: &[Expr]) -> String {
+    exprs.iter().map(Expr::to_string).collect::<Vec<_>>().join(", ")
 }
 
+/// Somewhere a value can be stored: a variable, or an element, field or pointer
+/// target reached from one through any number of steps, as in
+/// `Students[i].LastName` or `Node.Left^.Value`
 #[derive(Debug, Clone, PartialEq)]
+pub enum LValue {
+    Variable(String, Span),
+    Index {
+        target: Box<LValue>,
+        indices: Vec<Expr>,
+        span: Span,
+    },
+    Field {
+        target: Box<LValue>,
+        field: String,
+        span: Span,
+    },
+    Deref {
+        target: Box<LValue>,
+        span: Span,
+    },
+}
+
+impl LValue {
+    pub fn span(&self) -> &Span {
+        match self {
+            LValue::Variable(_, span)
+            | LValue::Index { span, .. }
+            | LValue::Field { span, .. }
+            | LValue::Deref { span, .. } => span,
+        }
+    }
+
+    /// The variable the chain starts from
+    pub fn variable(&self) -> &str {
+        match self {
+            LValue::Variable(name, _) => name,
+            LValue::Index { target, .. } | LValue::Field { target, .. } | LValue::Deref { target, .. } => target.variable(),
+        }
+    }
+
+    /// The variable a store here changes, or `None` when it changes what a pointer points to
+    pub fn stored_variable(&self) -> Option<&str> {
+        match self {
+            LValue::Variable(name, _) => Some(name),
+            LValue::Index { target, .. } | LValue::Field { target, .. } => target.stored_variable(),
+            LValue::Deref { .. } => None,
+        }
+    }
+}
+
+impl fmt::Display for LValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LValue::Variable(name, _) => write!(f, "{}", name),
+            LValue::Index { target, indices, .. } => write!(f, "{}[{}]", target, list(indices)),
+            LValue::Field { target, field, .. } => write!(f, "{}.{}", target, field),
+            LValue::Deref { target, .. } => write!(f, "{}^", target),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum BinaryOp {
     Add,
     Subtract,
     Multiply,
     Divide,
-    _Div,  // Integer division
+    Div,  // Integer division
     Modulus,
     Equals,
     NotEquals,
@@ -69,29 +235,59 @@ pub enum BinaryOp {
     GreaterThanOrEqual,
     And,
     Or,
+    /// Set membership: `x IN S`
+    In,
+    /// String concatenation: `a & b`
+    Concat,
 }
 
 impl BinaryOp {
+    /// The operator as it is written in a program
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            BinaryOp::Add => "+",
+            BinaryOp::Subtract => "-",
+            BinaryOp::Multiply => "*",
+            BinaryOp::Divide => "/",
+            BinaryOp::Div => "DIV",
+            BinaryOp::Modulus => "MOD",
+            BinaryOp::Equals => "=",
+            BinaryOp::NotEquals => "<>",
+            BinaryOp::LessThan => "<",
+            BinaryOp::GreaterThan => ">",
+            BinaryOp::LessThanOrEqual => "<=",
+            BinaryOp::GreaterThanOrEqual => ">=",
+            BinaryOp::And => "AND",
+            BinaryOp::Or => "OR",
+            BinaryOp::In => "IN",
+            BinaryOp::Concat => "&",
+        }
+    }
+
     pub fn precedence(&self) -> u8 {
         match self {
             BinaryOp::Or => 1,
             BinaryOp::And => 2,
             BinaryOp::Equals | BinaryOp::NotEquals 
             | BinaryOp::LessThan | BinaryOp::GreaterThan
-            | BinaryOp::LessThanOrEqual | BinaryOp::GreaterThanOrEqual => 3,
-            BinaryOp::Add | BinaryOp::Subtract => 4,
-            BinaryOp::Multiply | BinaryOp::Divide | BinaryOp::_Div | BinaryOp::Modulus => 5,
+            | BinaryOp::LessThanOrEqual | BinaryOp::GreaterThanOrEqual
+            | BinaryOp::In => 3,
+            // Looser than arithmetic, so "Total: " & A + B joins the sum
+            BinaryOp::Concat => 4,
+            BinaryOp::Add | BinaryOp::Subtract => 5,
+            BinaryOp::Multiply | BinaryOp::Divide | BinaryOp::Div | BinaryOp::Modulus => 6,
         }
     }
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum UnaryOp {
     Not,
     Negate,
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[allow(clippy::upper_case_acronyms)]
 pub enum FileMode {
     READ,
     WRITE,
@@ -119,9 +315,20 @@ pub struct Procedure {
 pub struct Param {
     pub name: String,
     pub type_name: Type,
+    pub mode: PassingMode,
     pub span: Span,
 }
 
+/// How an argument reaches a parameter. A BYREF parameter is written back to
+/// the caller's variable, array element or record field when the call returns.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[allow(clippy::upper_case_acronyms)]
+pub enum PassingMode {
+    #[default]
+    BYVAL,
+    BYREF,
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum Stmt {
 
@@ -145,6 +352,7 @@ pub enum Stmt {
         span: Span,
     },
 
+    #[allow(dead_code)]
     DeclareMultiple {
         declarations: Vec<(String, Option<Box<Expr>>)>,
         type_name: Type,
@@ -152,8 +360,7 @@ pub enum Stmt {
     },
 
     Assign {
-        name: String,
-        indices: Option<Vec<Expr>>,
+        target: LValue,
         expression: Box<Expr>,
         span: Span,
     },
@@ -211,7 +418,7 @@ pub enum Stmt {
 
     ReadFile {
         filename: Box<Expr>,
-        name: String,
+        target: LValue,
         span: Span,
     },
     
@@ -245,7 +452,7 @@ pub enum Stmt {
     },
 
     Input {
-        name: String,
+        target: LValue,
         span: Span,
     },
     
@@ -270,9 +477,155 @@ pub enum Stmt {
         otherwise: Option<Vec<Stmt>>,
         span: Span,
     },
+
+    /// A statement that failed to parse. The parser records the error and carries on,
+    /// so the rest of the program is still available to the language service.
+    Error {
+        span: Span,
+    },
 }
 
-#[derive(Debug, Clone, PartialEq)]
+impl Stmt {
+    pub fn span(&self) -> &Span {
+        match self {
+            Stmt::TypeDeclaration { span, .. }
+            | Stmt::Define { span, .. }
+            | Stmt::Declare { span, .. }
+            | Stmt::DeclareMultiple { span, .. }
+            | Stmt::Assign { span, .. }
+            | Stmt::Constant { span, .. }
+            | Stmt::If { span, .. }
+            | Stmt::While { span, .. }
+            | Stmt::For { span, .. }
+            | Stmt::RepeatUntil { span, .. }
+            | Stmt::OpenFile { span, .. }
+            | Stmt::CloseFile { span, .. }
+            | Stmt::WriteFile { span, .. }
+            | Stmt::ReadFile { span, .. }
+            | Stmt::Seek { span, .. }
+            | Stmt::GetRecord { span, .. }
+            | Stmt::PutRecord { span, .. }
+            | Stmt::Return { span, .. }
+            | Stmt::Call { span, .. }
+            | Stmt::Input { span, .. }
+            | Stmt::Output { span, .. }
+            | Stmt::FunctionDeclaration { span, .. }
+            | Stmt::ProcedureDeclaration { span, .. }
+            | Stmt::Case { span, .. }
+            | Stmt::Error { span } => span,
+        }
+    }
+
+    pub fn span_mut(&mut self) -> &mut Span {
+        match self {
+            Stmt::TypeDeclaration { span, .. }
+            | Stmt::Define { span, .. }
+            | Stmt::Declare { span, .. }
+            | Stmt::DeclareMultiple { span, .. }
+            | Stmt::Assign { span, .. }
+            | Stmt::Constant { span, .. }
+            | Stmt::If { span, .. }
+            | Stmt::While { span, .. }
+            | Stmt::For { span, .. }
+            | Stmt::RepeatUntil { span, .. }
+            | Stmt::OpenFile { span, .. }
+            | Stmt::CloseFile { span, .. }
+            | Stmt::WriteFile { span, .. }
+            | Stmt::ReadFile { span, .. }
+            | Stmt::Seek { span, .. }
+            | Stmt::GetRecord { span, .. }
+            | Stmt::PutRecord { span, .. }
+            | Stmt::Return { span, .. }
+            | Stmt::Call { span, .. }
+            | Stmt::Input { span, .. }
+            | Stmt::Output { span, .. }
+            | Stmt::FunctionDeclaration { span, .. }
+            | Stmt::ProcedureDeclaration { span, .. }
+            | Stmt::Case { span, .. }
+            | Stmt::Error { span } => span,
+        }
+    }
+
+    /// What a TYPE, FUNCTION or PROCEDURE declaration declares, as the kind of
+    /// name ("Type", "Function" or "Procedure") and the name
+    pub fn declared_name(&self) -> Option<(&'static str, &str)> {
+        match self {
+            Stmt::TypeDeclaration { name, .. } => Some(("Type", name)),
+            Stmt::FunctionDeclaration { function, .. } => Some(("Function", &function.name)),
+            Stmt::ProcedureDeclaration { procedure, .. } => Some(("Procedure", &procedure.name)),
+            _ => None,
+        }
+    }
+}
+
+/// Every TYPE, FUNCTION and PROCEDURE declaration in `statements`, including
+/// those in nested blocks and routine bodies, in source order. They are hoisted:
+/// all of them take effect before the first statement runs.
+pub fn declarations(statements: &[Stmt]) -> Vec<&Stmt> {
+    let mut found = Vec::new();
+    collect_declarations(statements, &mut found);
+    found
+}
+
+fn collect_declarations<'a>(statements: &'a [Stmt], found: &mut Vec<&'a Stmt>) {
+    for stmt in statements {
+        match stmt {
+            Stmt::TypeDeclaration { .. } => found.push(stmt),
+            Stmt::FunctionDeclaration { function, .. } => {
+                found.push(stmt);
+                collect_declarations(&function.body, found);
+            }
+            Stmt::ProcedureDeclaration { procedure, .. } => {
+                found.push(stmt);
+                collect_declarations(&procedure.body, found);
+            }
+            Stmt::If { then_stmt, else_stmt, .. } => {
+                collect_declarations(then_stmt, found);
+                if let Some(else_stmt) = else_stmt {
+                    collect_declarations(else_stmt, found);
+                }
+            }
+            Stmt::While { body, .. } | Stmt::For { body, .. } | Stmt::RepeatUntil { body, .. } => {
+                collect_declarations(body, found);
+            }
+            Stmt::Case { cases, otherwise, .. } => {
+                for case in cases {
+                    collect_declarations(&case.body, found);
+                }
+                if let Some(otherwise) = otherwise {
+                    collect_declarations(otherwise, found);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Call `f` on each block of statements nested directly in `stmt`
+pub fn for_each_block(stmt: &Stmt, mut f: impl FnMut(&[Stmt])) {
+    match stmt {
+        Stmt::If { then_stmt, else_stmt, .. } => {
+            f(then_stmt);
+            if let Some(else_stmt) = else_stmt {
+                f(else_stmt);
+            }
+        }
+        Stmt::While { body, .. } | Stmt::For { body, .. } | Stmt::RepeatUntil { body, .. } => f(body),
+        Stmt::Case { cases, otherwise, .. } => {
+            for case in cases {
+                f(&case.body);
+            }
+            if let Some(otherwise) = otherwise {
+                f(otherwise);
+            }
+        }
+        Stmt::FunctionDeclaration { function, .. } => f(&function.body),
+        Stmt::ProcedureDeclaration { procedure, .. } => f(&procedure.body),
+        _ => {}
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct TypeField {
     pub name: String,
     pub type_name: Type,
@@ -297,12 +650,40 @@ pub enum TypeDeclarationVariant {
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct CaseBranch {
-    pub value: Box<Expr>,
+    /// The branch runs when any of these match
+    pub labels: Vec<CaseLabel>,
     pub body: Vec<Stmt>,
     pub span: Span,
 }
 
+/// One of the comma-separated labels before a CASE branch's colon
 #[derive(Debug, Clone, PartialEq)]
+pub enum CaseLabel {
+    Value(Expr),
+    /// `low TO high`, both ends included
+    Range(Expr, Expr),
+}
+
+impl CaseLabel {
+    pub fn span(&self) -> Span {
+        match self {
+            CaseLabel::Value(value) => value.span().clone(),
+            CaseLabel::Range(low, high) => low.span().to(high.span()),
+        }
+    }
+}
+
+impl fmt::Display for CaseLabel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CaseLabel::Value(value) => write!(f, "{}", value),
+            CaseLabel::Range(low, high) => write!(f, "{} TO {}", low, high),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[allow(clippy::upper_case_acronyms)]
 pub enum Type {
     INTEGER,
     REAL,
@@ -0,0 +1,180 @@
+use std::collections::HashMap;
+
+use crate::ast::Type;
+use crate::date::Date;
+use crate::host::RandomSource;
+use crate::value::{self, Value};
+
+/// Names of the built-in functions that only need their evaluated arguments.
+/// EOF and NOW are also built in, but they need the open file table and the
+/// clock, so each engine handles them. SUCC and PRED need the TYPE
+/// declarations and go through `enum_neighbour`.
+pub const BUILTIN_NAMES: &[&str] = &[
+    "MOD", "DIV", "LENGTH", "UCASE", "LCASE", "SUBSTRING", "MID", "RIGHT",
+    "RANDOM", "RAND", "ROUND", "INT", "EOF",
+    "DAY", "MONTH", "YEAR", "DAYINDEX", "SETDATE", "NOW",
+    "SUCC", "PRED",
+];
+
+pub fn is_builtin(name: &str) -> bool {
+    BUILTIN_NAMES.contains(&name)
+}
+
+fn expect_args(name: &str, args: &[Value], count: usize, what: &str) -> Result<(), String> {
+    if args.len() != count {
+        return Err(format!("{} expects {}, got {}", name, what, args.len()));
+    }
+    Ok(())
+}
+
+/// A whole REAL as an INTEGER, if it is in range
+fn real_to_integer(name: &str, r: f64) -> Result<Value, String> {
+    // i64::MAX as f64 rounds up to 2^63, which is already out of range
+    if r >= i64::MIN as f64 && r < i64::MAX as f64 {
+        Ok(Value::Integer(r as i64))
+    } else {
+        Err(format!("{}: {} is outside the INTEGER range", name, r))
+    }
+}
+
+/// Evaluate a built-in function over already evaluated arguments, drawing
+/// RANDOM and RAND values from `random`.
+/// Returns `None` when `name` is not a pure built-in.
+pub fn call_builtin(name: &str, args: &[Value], random: &mut impl RandomSource) -> Option<Result<Value, String>> {
+    let result = match name {
+        "MOD" => expect_args(name, args, 2, "2 arguments").and_then(|_| {
+            match (&args[0], &args[1]) {
+                (Value::Integer(_), Value::Integer(0)) => Err("Modulo by zero".to_string()),
+                (Value::Integer(l), Value::Integer(r)) => l.checked_rem(*r)
+                    .map(Value::Integer)
+                    .ok_or_else(|| format!("Integer overflow: MOD({}, {}) is outside the INTEGER range", l, r)),
+                (l, r) => Err(format!("MOD requires integer arguments, got {:?} and {:?}", l, r)),
+            }
+        }),
+        "DIV" => expect_args(name, args, 2, "2 arguments").and_then(|_| {
+            match (&args[0], &args[1]) {
+                (Value::Integer(_), Value::Integer(0)) => Err("Division by zero in DIV".to_string()),
+                (Value::Integer(x), Value::Integer(y)) => x.checked_div(*y)
+                    .map(Value::Integer)
+                    .ok_or_else(|| format!("Integer overflow: DIV({}, {}) is outside the INTEGER range", x, y)),
+                (l, r) => Err(format!("DIV requires integer arguments, got {:?} and {:?}", l, r)),
+            }
+        }),
+        "LENGTH" => expect_args(name, args, 1, "1 argument").and_then(|_| {
+            match &args[0] {
+                Value::String(s) => Ok(Value::Integer(s.len() as i64)),
+                Value::Set { elements, .. } => Ok(Value::Integer(elements.len() as i64)),
+                other => Err(format!("LENGTH requires string or set argument, got {:?}", other)),
+            }
+        }),
+        "UCASE" => expect_args(name, args, 1, "1 argument").and_then(|_| {
+            match &args[0] {
+                Value::String(s) => Ok(Value::String(s.to_uppercase())),
+                Value::Char(c) => Ok(Value::String(c.to_uppercase().to_string())),
+                other => Err(format!("UCASE requires string or char argument, got {:?}", other)),
+            }
+        }),
+        "LCASE" => expect_args(name, args, 1, "1 argument").and_then(|_| {
+            match &args[0] {
+                Value::String(s) => Ok(Value::String(s.to_lowercase())),
+                Value::Char(c) => Ok(Value::String(c.to_lowercase().to_string())),
+                other => Err(format!("LCASE requires string or char argument, got {:?}", other)),
+            }
+        }),
+        "SUBSTRING" | "MID" => expect_args(name, args, 3, "3 arguments (string, start, length)").and_then(|_| {
+            match (&args[0], &args[1], &args[2]) {
+                (Value::String(s), Value::Integer(start), Value::Integer(length)) => {
+                    // 1-based indexing: convert to 0-based
+                    let start_idx = start.saturating_sub(1) as usize;
+                    let end_idx = start_idx.saturating_add(*length as usize).min(s.len());
+                    if start_idx >= s.len() {
+                        Ok(Value::String(String::new()))
+                    } else {
+                        Ok(Value::String(s[start_idx..end_idx].to_string()))
+                    }
+                }
+                (s, start, length) => Err(format!("{} expects (STRING, INTEGER, INTEGER) arguments, got {:?}, {:?}, {:?}", name, s, start, length)),
+            }
+        }),
+        "RIGHT" => expect_args(name, args, 2, "2 arguments").and_then(|_| {
+            match (&args[0], &args[1]) {
+                (Value::String(_), Value::Integer(length)) if *length < 0 => {
+                    Err(format!("RIGHT requires non-negative length, got {}", length))
+                }
+                (Value::String(s), Value::Integer(length)) => {
+                    // Handle case where length > string length
+                    let length = (*length as usize).min(s.len());
+                    let start_idx = s.len().saturating_sub(length);
+                    Ok(Value::String(s[start_idx..].to_string()))
+                }
+                (s, length) => Err(format!("RIGHT expects (STRING, INTEGER) arguments, got {:?}, {:?}", s, length)),
+            }
+        }),
+        "RANDOM" => expect_args(name, args, 0, "0 argument").map(|_| {
+            Value::Real(random.real_up_to(1.0))
+        }),
+        "RAND" => expect_args(name, args, 1, "1 argument").and_then(|_| {
+            match &args[0] {
+                Value::Integer(max) if *max < 0 => Err(format!("RAND requires non-negative maximum, got {}", max)),
+                Value::Integer(max) => Ok(Value::Real(random.real_up_to(*max as f64))),
+                other => Err(format!("RAND requires integer argument, got {:?}", other)),
+            }
+        }),
+        "ROUND" => expect_args(name, args, 2, "2 arguments").and_then(|_| {
+            match (&args[0], &args[1]) {
+                (Value::Real(r), Value::Integer(p)) => {
+                    // Round to p decimal places
+                    let multiplier = 10_f64.powi((*p).clamp(i32::MIN as i64, i32::MAX as i64) as i32);
+                    Ok(Value::Real((r * multiplier).round() / multiplier))
+                }
+                // If precision is not integer, just round to nearest integer
+                (Value::Real(r), _) => real_to_integer(name, r.round()),
+                // If already integer, return as-is
+                (Value::Integer(i), _) => Ok(Value::Integer(*i)),
+                (val, _) => Err(format!("ROUND requires numeric argument, got {:?}", val)),
+            }
+        }),
+        "INT" => expect_args(name, args, 1, "1 argument").and_then(|_| {
+            match &args[0] {
+                Value::Real(r) => real_to_integer(name, r.floor()),
+                Value::Integer(i) => Ok(Value::Integer(*i)),
+                other => Err(format!("INT requires numeric argument, got {:?}", other)),
+            }
+        }),
+        "DAY" | "MONTH" | "YEAR" | "DAYINDEX" => expect_args(name, args, 1, "1 argument").and_then(|_| {
+            match &args[0] {
+                Value::Date(date) => Ok(Value::Integer(match name {
+                    "DAY" => date.day() as i64,
+                    "MONTH" => date.month() as i64,
+                    "YEAR" => date.year() as i64,
+                    _ => date.day_index() as i64,
+                })),
+                other => Err(format!("{} requires date argument, got {:?}", name, other)),
+            }
+        }),
+        "SETDATE" => expect_args(name, args, 3, "3 arguments (day, month, year)").and_then(|_| {
+            match (&args[0], &args[1], &args[2]) {
+                (Value::Integer(day), Value::Integer(month), Value::Integer(year)) => {
+                    Date::new(*day, *month, *year).map(Value::Date)
+                }
+                (day, month, year) => Err(format!("SETDATE expects (INTEGER, INTEGER, INTEGER) arguments, got {:?}, {:?}, {:?}", day, month, year)),
+            }
+        }),
+        _ => return None,
+    };
+    Some(result)
+}
+
+/// SUCC and PRED: the enum value declared after or before the argument
+pub fn enum_neighbour(name: &str, args: &[Value], type_definitions: &HashMap<String, Type>) -> Result<Value, String> {
+    expect_args(name, args, 1, "1 argument")?;
+    let Value::Enum { type_name, value, index } = &args[0] else {
+        return Err(format!("{} requires an enum argument, got {:?}", name, args[0]));
+    };
+    let (index, limit) = match name {
+        "SUCC" => (*index as i64 + 1, "last"),
+        _ => (*index as i64 - 1, "first"),
+    };
+    value::enum_value(type_name, index, type_definitions)
+        .map_err(|_| format!("{}({}): {} is the {} value of {}", name, value, value, limit, type_name))
+}
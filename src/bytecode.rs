@@ -0,0 +1,491 @@
+use serde::{Serialize, Deserialize};
+
+use crate::ast::{BinaryOp, UnaryOp, FileMode, PassingMode, Type, Span};
+use crate::value::Value;
+
+/// File header of a compiled `.pseuc` program
+pub const MAGIC: &[u8; 5] = b"PSEUC";
+pub const VERSION: u8 = 14;
+
+/// A resolved variable: a slot in the global table or in the current call frame
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum VarRef {
+    Global(u32),
+    Local(u32),
+}
+
+/// One step along a store path, from a variable to the part of it being assigned
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum PathStep {
+    /// An element, at the next `n` indices from the stack
+    Index(u32),
+    /// The field `names[i]`
+    Field(u32),
+    /// What the pointer reached so far points to
+    Deref,
+}
+
+/// A single VM instruction. Operands that are not plain numbers are indices
+/// into the program's constant, name, type or routine tables.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Instr {
+    /// Push `constants[i]`
+    Const(u32),
+    /// Push the value of a variable
+    Load(VarRef),
+    /// Pop a value and assign it to a declared variable
+    Store(VarRef),
+    /// Pop the indices `paths[path]` needs, then a value, and assign the value
+    /// where the path leads from `var`
+    StorePath { var: VarRef, path: u32 },
+    /// Pop `n` indices and push `var[indices]`
+    LoadIndex(VarRef, u32),
+    /// Pop `count` indices then an array or set, and push its element.
+    /// `names[name]` is the array as written, for errors.
+    Index { name: u32, count: u32 },
+    /// Pop a record and push its field `names[i]`
+    Field(u32),
+    /// Pop a pointer and push the value it points to
+    Deref,
+    /// Push a pointer to a variable
+    Ref(VarRef),
+    /// Allocate a heap cell holding the default value of `types[i]` and push a pointer to it
+    New(u32),
+    Binary(BinaryOp),
+    Unary(UnaryOp),
+    Jump(u32),
+    /// Pop a condition and jump if it is false
+    JumpIfFalse(u32),
+    /// Pop a value into a hidden slot without any declaration checks
+    SetTemp(VarRef),
+    /// Pop two values and push whether they are equal (CASE labels)
+    CaseEq,
+    /// Pop the low and high ends of a range and the value below them, and push
+    /// whether the value is in the range (CASE labels)
+    CaseIn,
+    /// Warn that the CASE value in a hidden slot matched no label and there is no OTHERWISE
+    CaseUnmatched(VarRef),
+
+    /// Declare a variable of `types[ty]`, popping the initial value if `init`
+    Declare { var: VarRef, ty: u32, init: bool },
+    /// Pop a (start, end) pair per dimension and declare an array of `types[ty]`
+    DeclareArray { var: VarRef, ty: u32, dims: u32 },
+    /// Pop `count` strings and build a set of the type named `names[type_name]`
+    Define { var: VarRef, type_name: u32, count: u32 },
+    /// Lock a variable as constant, popping its new value if `init`
+    Constant { var: VarRef, init: bool },
+    /// Register `types[ty]` under `names[name]`
+    DefineType { name: u32, ty: u32 },
+
+    /// Pop step, end and start and enter a FOR loop. `state` is the first of four
+    /// hidden slots: saved counter, end, step and current value.
+    ForInit { var: VarRef, state: VarRef },
+    /// Leave the loop when the counter has passed the end value
+    ForTest { state: VarRef, exit: u32 },
+    /// Advance the counter by the step
+    ForStep { var: VarRef, state: VarRef },
+    /// Restore the counter to what it was before the loop
+    ForEnd { var: VarRef, state: VarRef },
+
+    /// Pop the indices `paths[path]` needs, if any, and make a reference to
+    /// `var` or where the path leads from it, for parameter `param` of
+    /// `routines[routine]`, which is BYREF
+    ByRef { var: VarRef, path: Option<u32>, routine: u32, param: u32 },
+    /// Call `routines[routine]` with `argc` arguments: the BYVAL ones popped
+    /// from the stack and the BYREF ones taken from the references made by `ByRef`
+    Call { routine: u32, argc: u32 },
+    /// Pop `argc` arguments and call the built-in `names[name]`
+    CallBuiltin { name: u32, argc: u32 },
+    /// Pop the return value and leave the current function
+    Return,
+    /// Leave the current routine without a value, an error in a function
+    Leave,
+
+    /// Pop `n` values and print them on one line
+    Output(u32),
+    /// Read a line into `var`, or where `paths[path]` leads from it after
+    /// popping the indices the path needs
+    Input { var: VarRef, path: Option<u32> },
+    /// Pop a filename and open it
+    OpenFile(FileMode),
+    CloseFile,
+    /// Pop the indices `paths[path]` needs, if any, then a filename, and read
+    /// a line into the variable or where the path leads from it
+    ReadFile { var: VarRef, path: Option<u32> },
+    /// Pop `n` values then a filename, and write them to the file
+    WriteFile(u32),
+    /// Pop an address then a filename
+    Seek,
+    GetRecord(VarRef),
+    PutRecord(VarRef),
+
+    Halt,
+}
+
+/// A compiled procedure or function
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Routine {
+    pub name: String,
+    /// Parameter types; parameter `i` lives in local slot `i`
+    pub params: Vec<Type>,
+    /// Passing mode of each parameter. A BYREF parameter's slot refers to the
+    /// caller's storage for the whole call.
+    pub modes: Vec<PassingMode>,
+    /// `None` for procedures
+    pub return_type: Option<Type>,
+    /// Names of all local slots, hidden slots have an empty name
+    pub locals: Vec<String>,
+    pub entry: u32,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Program {
+    pub code: Vec<Instr>,
+    /// Source position of each instruction, for runtime errors
+    pub spans: Vec<Span>,
+    pub constants: Vec<Value>,
+    pub names: Vec<String>,
+    pub types: Vec<Type>,
+    /// Steps from a variable to the part of it a store writes to
+    pub paths: Vec<Vec<PathStep>>,
+    /// Names of all global slots, hidden slots have an empty name
+    pub globals: Vec<String>,
+    pub routines: Vec<Routine>,
+}
+
+impl Program {
+    pub fn to_bytes(&self) -> Result<Vec<u8>, String> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.push(VERSION);
+        let body = bincode::serialize(self)
+            .map_err(|e| format!("Failed to encode bytecode: {}", e))?;
+        bytes.extend(body);
+        Ok(bytes)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        if bytes.len() < MAGIC.len() + 1 || &bytes[..MAGIC.len()] != MAGIC {
+            return Err("Not a compiled pseudocode file".to_string());
+        }
+        let version = bytes[MAGIC.len()];
+        if version != VERSION {
+            return Err(format!("Unsupported bytecode version {} (expected {})", version, VERSION));
+        }
+        let program: Program = bincode::deserialize(&bytes[MAGIC.len() + 1..])
+            .map_err(|e| format!("Failed to decode bytecode: {}", e))?;
+        program.validate().map_err(|e| format!("Invalid bytecode: {}", e))?;
+        Ok(program)
+    }
+
+    /// Check what the VM takes on trust: every operand names an entry that
+    /// exists, jumps stay inside the code, and whichever way an instruction is
+    /// reached the stack holds the same number of values, never too few for it.
+    /// The compiler only produces programs that pass; a file that was damaged
+    /// or written by hand might not.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.spans.len() != self.code.len() {
+            return Err(format!("{} instructions but {} source positions", self.code.len(), self.spans.len()));
+        }
+        for step in self.paths.iter().flatten() {
+            if let PathStep::Field(field) = step {
+                self.check_index("name", *field, self.names.len())?;
+            }
+        }
+        for routine in &self.routines {
+            if routine.modes.len() != routine.params.len() || routine.locals.len() < routine.params.len() {
+                return Err(format!("routine '{}' has {} parameters, {} passing modes and {} local slots",
+                    routine.name, routine.params.len(), routine.modes.len(), routine.locals.len()));
+            }
+        }
+        self.check_flow(None)?;
+        for routine in &self.routines {
+            self.check_flow(Some(routine))?;
+        }
+        Ok(())
+    }
+
+    /// Follow every path from the start of the main program, or of `routine`,
+    /// to where it halts or returns, checking each instruction on the way.
+    /// Stack heights are counted from where the code started.
+    fn check_flow(&self, routine: Option<&Routine>) -> Result<(), String> {
+        let start = routine.map_or(0, |routine| routine.entry as usize);
+        // Values and BYREF references on the stacks before each instruction
+        let mut heights: Vec<Option<(usize, usize)>> = vec![None; self.code.len()];
+        let mut pending = vec![(start, (0, 0))];
+        while let Some((pc, height)) = pending.pop() {
+            let instr = self.code.get(pc)
+                .ok_or_else(|| format!("execution can reach instruction {}, past the end of the code", pc))?;
+            let at = |msg: String| format!("instruction {} ({:?}): {}", pc, instr, msg);
+            match heights[pc] {
+                Some(seen) if seen == height => continue,
+                Some(seen) => return Err(at(format!("reached with {} values on the stack one way and {} another", seen.0, height.0))),
+                None => heights[pc] = Some(height),
+            }
+            self.check_operands(instr, routine).map_err(at)?;
+
+            let (pops, pushes, reference_pops, reference_pushes) = self.stack_effect(instr);
+            let (values, references) = height;
+            if values < pops || references < reference_pops {
+                return Err(at("the stack does not hold the values it takes".to_string()));
+            }
+            let next = (values - pops + pushes, references - reference_pops + reference_pushes);
+            match instr {
+                Instr::Halt => {}
+                Instr::Return | Instr::Leave => {
+                    if routine.is_none() {
+                        return Err(at("return outside of a routine".to_string()));
+                    }
+                    if next != (0, 0) {
+                        return Err(at("returns leaving values on the stack".to_string()));
+                    }
+                }
+                Instr::Jump(to) => pending.push((*to as usize, next)),
+                Instr::JumpIfFalse(to) | Instr::ForTest { exit: to, .. } => {
+                    pending.push((*to as usize, next));
+                    pending.push((pc + 1, next));
+                }
+                _ => pending.push((pc + 1, next)),
+            }
+        }
+        Ok(())
+    }
+
+    /// Check that the operands of `instr`, running in `routine` or the main
+    /// program, name entries that exist
+    fn check_operands(&self, instr: &Instr, routine: Option<&Routine>) -> Result<(), String> {
+        let var = |var: &VarRef| match var {
+            VarRef::Global(i) => self.check_index("global slot", *i, self.globals.len()),
+            VarRef::Local(i) => match routine {
+                Some(routine) => self.check_index("local slot", *i, routine.locals.len()),
+                None => Err(format!("local slot {} outside of a routine", i)),
+            },
+        };
+        // FOR loops keep four hidden slots, from `state` on
+        let for_state = |state: &VarRef| {
+            let last = match *state {
+                VarRef::Global(i) => VarRef::Global(i.saturating_add(3)),
+                VarRef::Local(i) => VarRef::Local(i.saturating_add(3)),
+            };
+            var(state).and(var(&last))
+        };
+        let name = |i: &u32| self.check_index("name", *i, self.names.len());
+        let ty = |i: &u32| self.check_index("type", *i, self.types.len());
+        let path = |i: &u32| self.check_index("path", *i, self.paths.len());
+        let routine_at = |i: &u32| {
+            self.check_index("routine", *i, self.routines.len())?;
+            Ok::<_, String>(&self.routines[*i as usize])
+        };
+        match instr {
+            Instr::Const(i) => self.check_index("constant", *i, self.constants.len()),
+            Instr::Load(v) | Instr::Store(v) | Instr::LoadIndex(v, _) | Instr::Ref(v) | Instr::SetTemp(v)
+            | Instr::CaseUnmatched(v) | Instr::Constant { var: v, .. } | Instr::GetRecord(v) | Instr::PutRecord(v) => var(v),
+            Instr::StorePath { var: v, path: p } => var(v).and(path(p)),
+            Instr::Input { var: v, path: p } | Instr::ReadFile { var: v, path: p } => var(v).and(p.as_ref().map_or(Ok(()), path)),
+            Instr::Index { name: n, .. } | Instr::Field(n) | Instr::CallBuiltin { name: n, .. } => name(n),
+            Instr::New(t) => ty(t),
+            Instr::Declare { var: v, ty: t, .. } => var(v).and(ty(t)),
+            Instr::DeclareArray { var: v, ty: t, .. } => {
+                var(v).and(ty(t))?;
+                match self.types[*t as usize] {
+                    Type::ARRAY { .. } => Ok(()),
+                    ref other => Err(format!("declares an array of type {:?}", other)),
+                }
+            }
+            Instr::Define { var: v, type_name, .. } => var(v).and(name(type_name)),
+            Instr::DefineType { name: n, ty: t } => name(n).and(ty(t)),
+            Instr::ForInit { var: v, state } | Instr::ForStep { var: v, state } | Instr::ForEnd { var: v, state } => {
+                var(v).and(for_state(state))
+            }
+            Instr::ForTest { state, .. } => for_state(state),
+            Instr::ByRef { var: v, path: p, routine: r, param } => {
+                var(v).and(p.as_ref().map_or(Ok(()), path))?;
+                match routine_at(r)?.modes.get(*param as usize) {
+                    Some(PassingMode::BYREF) => Ok(()),
+                    _ => Err(format!("parameter {} is not BYREF", param)),
+                }
+            }
+            Instr::Call { routine: r, argc } => {
+                let params = routine_at(r)?.params.len();
+                if params != *argc as usize {
+                    return Err(format!("passes {} arguments for {} parameters", argc, params));
+                }
+                Ok(())
+            }
+            Instr::Binary(_) | Instr::Unary(_) | Instr::Deref | Instr::Jump(_) | Instr::JumpIfFalse(_) | Instr::CaseEq
+            | Instr::CaseIn | Instr::Return | Instr::Leave | Instr::Output(_) | Instr::OpenFile(_)
+            | Instr::CloseFile | Instr::WriteFile(_) | Instr::Seek | Instr::Halt => Ok(()),
+        }
+    }
+
+    fn check_index(&self, table: &str, i: u32, len: usize) -> Result<(), String> {
+        if i as usize >= len {
+            return Err(format!("{} {} does not exist, there are {}", table, i, len));
+        }
+        Ok(())
+    }
+
+    /// Values popped and pushed by `instr`, then BYREF references popped and
+    /// pushed. Its operands must have been checked.
+    fn stack_effect(&self, instr: &Instr) -> (usize, usize, usize, usize) {
+        let indices = |path: &Option<u32>| path.map_or(0, |path| path_indices(&self.paths[path as usize]));
+        match instr {
+            Instr::Const(_) | Instr::Load(_) | Instr::Ref(_) | Instr::New(_) => (0, 1, 0, 0),
+            Instr::Store(_) | Instr::SetTemp(_) | Instr::JumpIfFalse(_) => (1, 0, 0, 0),
+            Instr::StorePath { path, .. } => (indices(&Some(*path)) + 1, 0, 0, 0),
+            Instr::LoadIndex(_, n) => (*n as usize, 1, 0, 0),
+            Instr::Index { count, .. } => (*count as usize + 1, 1, 0, 0),
+            Instr::Field(_) | Instr::Deref | Instr::Unary(_) => (1, 1, 0, 0),
+            Instr::Binary(_) | Instr::CaseEq => (2, 1, 0, 0),
+            Instr::CaseIn => (3, 1, 0, 0),
+            Instr::Declare { init, .. } | Instr::Constant { init, .. } => (*init as usize, 0, 0, 0),
+            Instr::DeclareArray { dims, .. } => (*dims as usize * 2, 0, 0, 0),
+            Instr::Define { count, .. } => (*count as usize, 0, 0, 0),
+            Instr::ForInit { .. } => (3, 0, 0, 0),
+            Instr::ByRef { path, .. } => (indices(path), 0, 0, 1),
+            Instr::Call { routine, argc } => {
+                let routine = &self.routines[*routine as usize];
+                let byref = routine.modes.iter().filter(|mode| **mode == PassingMode::BYREF).count();
+                (*argc as usize - byref, routine.return_type.is_some() as usize, byref, 0)
+            }
+            Instr::CallBuiltin { argc, .. } => (*argc as usize, 1, 0, 0),
+            Instr::Return => (1, 0, 0, 0),
+            Instr::Output(n) => (*n as usize, 0, 0, 0),
+            Instr::Input { path, .. } => (indices(path), 0, 0, 0),
+            Instr::ReadFile { path, .. } => (indices(path) + 1, 0, 0, 0),
+            Instr::WriteFile(n) => (*n as usize + 1, 0, 0, 0),
+            Instr::OpenFile(_) | Instr::CloseFile | Instr::GetRecord(_) | Instr::PutRecord(_) => (1, 0, 0, 0),
+            Instr::Seek => (2, 0, 0, 0),
+            Instr::Jump(_) | Instr::CaseUnmatched(_) | Instr::DefineType { .. } | Instr::ForTest { .. }
+            | Instr::ForStep { .. } | Instr::ForEnd { .. } | Instr::Leave | Instr::Halt => (0, 0, 0, 0),
+        }
+    }
+}
+
+/// How many indices `path` takes from the stack
+pub fn path_indices(path: &[PathStep]) -> usize {
+    path.iter()
+        .map(|step| match step {
+            PathStep::Index(n) => *n as usize,
+            _ => 0,
+        })
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::Compiler;
+    use crate::parser::Parser;
+
+    const PROGRAM: &str = "\
+FUNCTION Twice(N : INTEGER) RETURNS INTEGER
+    RETURN N * 2
+ENDFUNCTION
+DECLARE X : INTEGER
+X <- 1
+IF X > 0 THEN
+    OUTPUT Twice(X)
+ENDIF
+";
+
+    fn compile(source: &str) -> Program {
+        let statements = Parser::new(source).parse_program().expect("parses");
+        Compiler::new().compile(&statements).expect("compiles")
+    }
+
+    /// The error loading `program` from its bytes gives
+    fn load_error(program: &Program) -> String {
+        Program::from_bytes(&program.to_bytes().unwrap()).expect_err("should be rejected")
+    }
+
+    fn find(program: &Program, matches: impl Fn(&Instr) -> bool) -> usize {
+        program.code.iter().position(matches).expect("instruction is compiled")
+    }
+
+    #[test]
+    fn compiled_programs_round_trip() {
+        let program = compile(PROGRAM);
+        assert_eq!(Program::from_bytes(&program.to_bytes().unwrap()), Ok(program));
+    }
+
+    #[test]
+    fn rejects_other_files_and_versions() {
+        assert!(Program::from_bytes(b"PSEU").is_err());
+        let mut bytes = compile(PROGRAM).to_bytes().unwrap();
+        bytes[MAGIC.len()] = VERSION + 1;
+        assert!(Program::from_bytes(&bytes).unwrap_err().contains("version"));
+    }
+
+    #[test]
+    fn rejects_jumps_past_the_end() {
+        let mut program = compile(PROGRAM);
+        let at = find(&program, |instr| matches!(instr, Instr::JumpIfFalse(_)));
+        program.code[at] = Instr::JumpIfFalse(1000);
+        assert!(load_error(&program).contains("past the end of the code"));
+    }
+
+    #[test]
+    fn rejects_missing_table_entries() {
+        let mut program = compile(PROGRAM);
+        let at = find(&program, |instr| matches!(instr, Instr::Const(_)));
+        program.code[at] = Instr::Const(1000);
+        assert!(load_error(&program).contains("constant 1000 does not exist"));
+
+        let mut program = compile(PROGRAM);
+        let at = find(&program, |instr| matches!(instr, Instr::Store(_)));
+        program.code[at] = Instr::Store(VarRef::Global(1000));
+        assert!(load_error(&program).contains("global slot 1000 does not exist"));
+
+        let mut program = compile(PROGRAM);
+        let at = find(&program, |instr| matches!(instr, Instr::Call { .. }));
+        program.code[at] = Instr::Call { routine: 7, argc: 1 };
+        assert!(load_error(&program).contains("routine 7 does not exist"));
+    }
+
+    #[test]
+    fn rejects_locals_and_returns_outside_routines() {
+        let mut program = compile(PROGRAM);
+        let at = find(&program, |instr| matches!(instr, Instr::Store(_)));
+        program.code[at] = Instr::Store(VarRef::Local(0));
+        assert!(load_error(&program).contains("local slot 0 outside of a routine"));
+
+        let mut program = compile(PROGRAM);
+        let at = find(&program, |instr| matches!(instr, Instr::Halt));
+        program.code[at] = Instr::Leave;
+        assert!(load_error(&program).contains("return outside of a routine"));
+    }
+
+    #[test]
+    fn rejects_calls_with_the_wrong_number_of_arguments() {
+        let mut program = compile(PROGRAM);
+        let at = find(&program, |instr| matches!(instr, Instr::Call { .. }));
+        program.code[at] = Instr::Call { routine: 0, argc: 2 };
+        assert!(load_error(&program).contains("passes 2 arguments for 1 parameters"));
+    }
+
+    #[test]
+    fn rejects_code_that_takes_more_than_the_stack_holds() {
+        let mut program = compile(PROGRAM);
+        let at = find(&program, |instr| matches!(instr, Instr::Output(_)));
+        program.code[at] = Instr::Output(2);
+        assert!(load_error(&program).contains("the stack does not hold the values it takes"));
+    }
+
+    #[test]
+    fn rejects_branches_that_disagree_about_the_stack() {
+        let mut program = compile(PROGRAM);
+        // The THEN branch leaves its value behind, so the code after the IF
+        // is reached with one value more that way than when the test fails
+        let at = find(&program, |instr| matches!(instr, Instr::Output(_)));
+        program.code[at] = Instr::Output(0);
+        assert!(load_error(&program).contains("on the stack one way"));
+    }
+
+    #[test]
+    fn rejects_returns_that_leave_values_behind() {
+        let mut program = compile(PROGRAM);
+        let at = find(&program, |instr| matches!(instr, Instr::Return));
+        program.code[at] = Instr::Leave;
+        assert!(load_error(&program).contains("returns leaving values on the stack"));
+    }
+}
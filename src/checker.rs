@@ -0,0 +1,1091 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use crate::ast::{declarations, for_each_block, BinaryOp, CaseLabel, Expr, LValue, Param, PassingMode, Span, Stmt, Type, TypeDeclarationVariant, UnaryOp};
+use crate::builtins::{call_builtin, is_builtin};
+use crate::date::Date;
+use crate::diagnostic::{codes, Diagnostic};
+use crate::host::RandomSource;
+use crate::value::{self, Value};
+
+/// Static type of an expression, as far as the checker can tell
+#[derive(Debug, Clone, PartialEq)]
+enum Ty {
+    Integer,
+    Real,
+    String,
+    Char,
+    Boolean,
+    Date,
+    /// `dimensions` is 0 for an `ARRAY OF T` parameter, which takes an array of any shape
+    Array { dimensions: usize, element: Box<Ty> },
+    Record(String),
+    Enum(String),
+    Pointer(Box<Ty>),
+    Set(Box<Ty>),
+    /// Not known statically, usually because an error was already reported.
+    /// Compatible with everything so one mistake is reported once.
+    Unknown,
+}
+
+impl fmt::Display for Ty {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Ty::Integer => write!(f, "INTEGER"),
+            Ty::Real => write!(f, "REAL"),
+            Ty::String => write!(f, "STRING"),
+            Ty::Char => write!(f, "CHAR"),
+            Ty::Boolean => write!(f, "BOOLEAN"),
+            Ty::Date => write!(f, "DATE"),
+            Ty::Array { dimensions: 0 | 1, element } => write!(f, "ARRAY OF {}", element),
+            Ty::Array { dimensions, element } => write!(f, "{}D ARRAY OF {}", dimensions, element),
+            Ty::Record(name) | Ty::Enum(name) => write!(f, "{}", name),
+            Ty::Pointer(target) => write!(f, "^{}", target),
+            Ty::Set(element) => write!(f, "SET OF {}", element),
+            Ty::Unknown => write!(f, "unknown"),
+        }
+    }
+}
+
+impl Ty {
+    fn of_value(value: &Value) -> Ty {
+        match value {
+            Value::Integer(_) => Ty::Integer,
+            Value::Real(_) => Ty::Real,
+            Value::String(_) => Ty::String,
+            Value::Char(_) => Ty::Char,
+            Value::Boolean(_) => Ty::Boolean,
+            Value::Date(_) => Ty::Date,
+            Value::Enum { type_name, .. } => Ty::Enum(type_name.clone()),
+            _ => Ty::Unknown,
+        }
+    }
+
+    /// A value of this type. Operators and built-ins are checked by running the
+    /// runtime's own implementation on sample values, so the checker accepts
+    /// exactly what the interpreter and VM accept.
+    fn sample(&self) -> Option<Value> {
+        let value = match self {
+            Ty::Integer => Value::Integer(1),
+            Ty::Real => Value::Real(1.0),
+            Ty::String => Value::String(String::new()),
+            Ty::Char => Value::Char('a'),
+            Ty::Boolean => Value::Boolean(true),
+            Ty::Date => Value::Date(Date::default()),
+            Ty::Array { .. } => Value::Array {
+                element_type: Box::new(Type::INTEGER),
+                dimensions: Vec::new(),
+                start_indices: Vec::new(),
+                data: Vec::new(),
+            },
+            Ty::Record(name) => Value::Record { type_name: name.clone(), fields: HashMap::new() },
+            Ty::Enum(name) => Value::Enum { type_name: name.clone(), value: String::new(), index: 0 },
+            Ty::Pointer(_) => Value::Pointer(None),
+            Ty::Set(_) => Value::Set { element_type: Box::new(Type::INTEGER), elements: Vec::new() },
+            Ty::Unknown => return None,
+        };
+        Some(value)
+    }
+}
+
+/// Whether a value of type `value` may be stored where `target` is expected
+fn assignable(target: &Ty, value: &Ty) -> bool {
+    match (target, value) {
+        (Ty::Unknown, _) | (_, Ty::Unknown) => true,
+        (Ty::Real, Ty::Integer) => true,
+        (Ty::Array { dimensions: d1, element: e1 }, Ty::Array { dimensions: d2, element: e2 }) => {
+            (d1 == d2 || *d1 == 0 || *d2 == 0) && (e1 == e2 || **e1 == Ty::Unknown || **e2 == Ty::Unknown)
+        }
+        (Ty::Pointer(t1), Ty::Pointer(t2)) => t1 == t2 || **t1 == Ty::Unknown || **t2 == Ty::Unknown,
+        (t1, t2) => t1 == t2,
+    }
+}
+
+/// RANDOM and RAND only need some number while checking
+struct FixedRandom;
+
+impl RandomSource for FixedRandom {
+    fn real_up_to(&mut self, max: f64) -> f64 {
+        max
+    }
+}
+
+/// Signature of a user-defined function or procedure
+struct Signature {
+    params: Vec<(Ty, PassingMode)>,
+    /// `None` for procedures
+    return_type: Option<Ty>,
+}
+
+/// Semantic analysis run before a program executes. Finds type errors on every
+/// path through the program, not just the one a particular run takes.
+///
+/// Declarations are collected before anything is checked, so a routine may use
+/// globals, types and other routines declared further down the file.
+pub struct TypeChecker {
+    /// TYPE declarations by name
+    types: HashMap<String, Type>,
+    /// Enum value name to the enum type it belongs to
+    enum_values: HashMap<String, String>,
+    functions: HashMap<String, Signature>,
+    procedures: HashMap<String, Signature>,
+    globals: HashMap<String, Ty>,
+    /// Globals that are constant by this point in the program
+    constants: HashSet<String>,
+    /// Parameters and variables of the routine being checked
+    locals: Option<HashMap<String, Ty>>,
+    /// Locals of the routine being checked that are constant by this point
+    local_constants: HashSet<String>,
+    /// Return type of the routine being checked, `None` for procedures
+    return_type: Option<Ty>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Default for TypeChecker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TypeChecker {
+    pub fn new() -> Self {
+        Self {
+            types: HashMap::new(),
+            enum_values: HashMap::new(),
+            functions: HashMap::new(),
+            procedures: HashMap::new(),
+            globals: HashMap::new(),
+            constants: HashSet::new(),
+            locals: None,
+            local_constants: HashSet::new(),
+            return_type: None,
+            diagnostics: Vec::new(),
+        }
+    }
+
+    /// Check a whole program, returning every error found in source order
+    pub fn check(mut self, statements: &[Stmt]) -> Vec<Diagnostic> {
+        self.diagnostics.extend(duplicate_declarations(statements));
+        self.collect_types(statements);
+        self.collect_routines(statements);
+        let mut globals = HashMap::new();
+        self.collect_variables(statements, &mut globals);
+        self.globals = globals;
+        collect_constants(statements, &mut self.constants);
+
+        self.check_block(statements);
+
+        self.diagnostics.sort_by_key(|d| d.span.map(|span| (span.start_line, span.start_column)));
+        self.diagnostics
+    }
+
+    fn error(&mut self, code: &str, message: String, span: &Span) {
+        self.diagnostics.push(Diagnostic::error(code, message).with_span(span));
+    }
+
+    fn mismatch(&mut self, message: String, span: &Span) {
+        self.error(codes::TYPE_MISMATCH, message, span);
+    }
+
+    /// TYPE declarations anywhere in the program
+    fn collect_types(&mut self, statements: &[Stmt]) {
+        for stmt in statements {
+            match stmt {
+                Stmt::TypeDeclaration { name, variant, .. } => {
+                    let type_def = match variant {
+                        TypeDeclarationVariant::Record { fields } => Type::Record { name: name.clone(), fields: fields.clone() },
+                        TypeDeclarationVariant::Enum { values } => {
+                            for value in values {
+                                self.enum_values.insert(value.clone(), name.clone());
+                            }
+                            Type::Enum { name: name.clone(), values: values.clone() }
+                        }
+                        TypeDeclarationVariant::Pointer { points_to } => Type::Pointer { points_to: points_to.clone() },
+                        TypeDeclarationVariant::Set { element_type } => Type::Set { element_type: element_type.clone() },
+                    };
+                    self.types.insert(name.clone(), type_def);
+                }
+                Stmt::FunctionDeclaration { function, .. } => self.collect_types(&function.body),
+                Stmt::ProcedureDeclaration { procedure, .. } => self.collect_types(&procedure.body),
+                _ => for_each_block(stmt, |block| self.collect_types(block)),
+            }
+        }
+    }
+
+    fn collect_routines(&mut self, statements: &[Stmt]) {
+        for stmt in statements {
+            match stmt {
+                Stmt::FunctionDeclaration { function, .. } => {
+                    let signature = Signature {
+                        params: self.param_types(&function.params),
+                        return_type: Some(self.resolve(&function.return_type)),
+                    };
+                    self.functions.insert(function.name.clone(), signature);
+                }
+                Stmt::ProcedureDeclaration { procedure, .. } => {
+                    let signature = Signature {
+                        params: self.param_types(&procedure.params),
+                        return_type: None,
+                    };
+                    self.procedures.insert(procedure.name.clone(), signature);
+                }
+                _ => for_each_block(stmt, |block| self.collect_routines(block)),
+            }
+        }
+    }
+
+    fn param_types(&self, params: &[Param]) -> Vec<(Ty, PassingMode)> {
+        params.iter().map(|param| (self.resolve(&param.type_name), param.mode)).collect()
+    }
+
+    /// Variables declared in a block and the blocks nested in it, but not in nested routines
+    fn collect_variables(&self, statements: &[Stmt], variables: &mut HashMap<String, Ty>) {
+        for stmt in statements {
+            match stmt {
+                Stmt::Declare { name, type_name, .. } => {
+                    variables.insert(name.clone(), self.resolve(type_name));
+                }
+                Stmt::DeclareMultiple { declarations, type_name, .. } => {
+                    for (name, _) in declarations {
+                        variables.insert(name.clone(), self.resolve(type_name));
+                    }
+                }
+                Stmt::Define { name, type_name, .. } => {
+                    variables.insert(name.clone(), self.resolve(&Type::Custom(type_name.clone())));
+                }
+                Stmt::Constant { name, value: Some(_), .. } => {
+                    variables.entry(name.clone()).or_insert(Ty::Unknown);
+                }
+                Stmt::FunctionDeclaration { .. } | Stmt::ProcedureDeclaration { .. } => {}
+                _ => for_each_block(stmt, |block| self.collect_variables(block, variables)),
+            }
+        }
+    }
+
+    /// Resolve a declared type. Unknown type names are reported where they are declared.
+    fn resolve(&self, type_name: &Type) -> Ty {
+        self.resolve_depth(type_name, 0)
+    }
+
+    fn resolve_depth(&self, type_name: &Type, depth: usize) -> Ty {
+        // Only a TYPE that names itself, e.g. TYPE P = ^P, gets this deep
+        if depth > 32 {
+            return Ty::Unknown;
+        }
+        match type_name {
+            Type::INTEGER => Ty::Integer,
+            Type::REAL => Ty::Real,
+            Type::STRING => Ty::String,
+            Type::CHAR => Ty::Char,
+            Type::BOOLEAN => Ty::Boolean,
+            Type::DATE => Ty::Date,
+            Type::ARRAY { dimensions, element_type } => Ty::Array {
+                dimensions: dimensions.len(),
+                element: Box::new(self.resolve_depth(element_type, depth + 1)),
+            },
+            Type::Custom(name) => match self.types.get(name) {
+                Some(type_def) => self.resolve_depth(type_def, depth + 1),
+                None => Ty::Unknown,
+            },
+            Type::Record { name, .. } => Ty::Record(name.clone()),
+            Type::Enum { name, .. } => Ty::Enum(name.clone()),
+            Type::Pointer { points_to } => Ty::Pointer(Box::new(self.resolve_depth(points_to, depth + 1))),
+            Type::Set { element_type } => Ty::Set(Box::new(self.resolve_depth(element_type, depth + 1))),
+        }
+    }
+
+    /// Report type names in a declared type that no TYPE declares
+    fn check_type_exists(&mut self, type_name: &Type, span: &Span) {
+        match type_name {
+            Type::Custom(name) if !self.types.contains_key(name) => {
+                self.error(codes::UNDECLARED, format!("Type {} is not declared", name), span);
+            }
+            Type::ARRAY { element_type, .. } | Type::Set { element_type } => self.check_type_exists(element_type, span),
+            Type::Pointer { points_to } => self.check_type_exists(points_to, span),
+            _ => {}
+        }
+    }
+
+    /// Whether a value of `type_name` holds a `record` by value, directly or
+    /// in the fields and elements inside it, so that one could never be built
+    fn holds_record(&self, type_name: &Type, record: &str, seen: &mut HashSet<String>) -> bool {
+        match type_name {
+            Type::ARRAY { element_type, .. } => self.holds_record(element_type, record, seen),
+            Type::Custom(name) if name == record => true,
+            Type::Custom(name) if seen.insert(name.clone()) => match self.types.get(name) {
+                Some(Type::Record { fields, .. }) => fields.iter().any(|field| self.holds_record(&field.type_name, record, seen)),
+                _ => false,
+            },
+            _ => false,
+        }
+    }
+
+    fn variable(&self, name: &str) -> Option<Ty> {
+        self.locals.as_ref()
+            .and_then(|locals| locals.get(name))
+            .or_else(|| self.globals.get(name))
+            .cloned()
+    }
+
+    /// Whether `name` is a constant here. A bare `CONSTANT Name` only locks the
+    /// variable from where it appears, so it counts from there on.
+    fn is_constant(&self, name: &str) -> bool {
+        match &self.locals {
+            Some(locals) if locals.contains_key(name) => self.local_constants.contains(name),
+            _ => self.constants.contains(name),
+        }
+    }
+
+    /// Lock a variable of the current scope. A routine locking a global is left
+    /// to the runtime, as the global is only constant once the routine has run.
+    fn make_constant(&mut self, name: &str) {
+        match &self.locals {
+            Some(locals) if locals.contains_key(name) => {
+                self.local_constants.insert(name.to_string());
+            }
+            Some(_) => {}
+            None => {
+                self.constants.insert(name.to_string());
+            }
+        }
+    }
+
+    fn check_not_constant(&mut self, name: &str, what: &str, span: &Span) {
+        if self.is_constant(name) {
+            self.error(codes::CONSTANT_CHANGED, format!("{} would change constant '{}'", what, name), span);
+        }
+    }
+
+    /// Bind a FOR counter for the duration of its loop, returning what it shadowed
+    fn bind_counter(&mut self, counter: &str, ty: Ty) -> Option<Ty> {
+        match &mut self.locals {
+            Some(locals) => locals.insert(counter.to_string(), ty),
+            None => self.globals.insert(counter.to_string(), ty),
+        }
+    }
+
+    fn unbind_counter(&mut self, counter: &str, shadowed: Option<Ty>) {
+        let scope = match &mut self.locals {
+            Some(locals) => locals,
+            None => &mut self.globals,
+        };
+        match shadowed {
+            Some(ty) => scope.insert(counter.to_string(), ty),
+            None => scope.remove(counter),
+        };
+    }
+
+    fn check_block(&mut self, statements: &[Stmt]) {
+        for stmt in statements {
+            self.check_stmt(stmt);
+        }
+    }
+
+    fn check_stmt(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::Declare { name, type_name, initial_value, span } => {
+                self.check_declare(name, type_name, initial_value, span);
+            }
+            Stmt::DeclareMultiple { declarations, type_name, span } => {
+                for (name, initial_value) in declarations {
+                    self.check_declare(name, type_name, initial_value, span);
+                }
+            }
+            Stmt::Define { name, values, type_name, span } => {
+                match self.types.get(type_name).cloned() {
+                    Some(Type::Set { element_type }) => {
+                        for value in values {
+                            if let Err(msg) = value::parse_value_string(value, &element_type) {
+                                self.mismatch(format!("{} in the values of set '{}'", msg, name), span);
+                            }
+                        }
+                    }
+                    Some(_) => self.mismatch(format!("DEFINE needs a SET type, but {} is not one", type_name), span),
+                    None => self.error(codes::UNDECLARED, format!("Type {} is not declared", type_name), span),
+                }
+            }
+            Stmt::Constant { name, value: Some(expr), .. } => {
+                // The constant takes the type of its value
+                let ty = self.expr_type(expr);
+                let scope = match &mut self.locals {
+                    Some(locals) if locals.contains_key(name) => locals,
+                    _ => &mut self.globals,
+                };
+                if let Some(declared @ Ty::Unknown) = scope.get_mut(name) {
+                    *declared = ty;
+                }
+            }
+            Stmt::Constant { name, value: None, span } => {
+                if self.variable(name).is_none() {
+                    self.undeclared_variable(name, span);
+                }
+                self.make_constant(name);
+            }
+            Stmt::Assign { target, expression, span } => {
+                // Assigning through a pointer changes what it points to, not the pointer
+                if let Some(variable) = target.stored_variable() {
+                    self.check_not_constant(variable, "Assignment", span);
+                }
+                let target_type = self.assign_target(target);
+                let value = self.expr_type(expression);
+                if !assignable(&target_type, &value) {
+                    self.mismatch(format!("Cannot assign {} to '{}' of type {}", value, target, target_type), expression.span());
+                }
+            }
+            Stmt::Input { target, span } => {
+                if let Some(variable) = target.stored_variable() {
+                    self.check_not_constant(variable, "INPUT", span);
+                }
+                match self.assign_target(target) {
+                    Ty::Integer | Ty::Real | Ty::String | Ty::Char | Ty::Boolean | Ty::Date | Ty::Unknown => {}
+                    ty => self.mismatch(format!("INPUT cannot read a value of type {} into '{}'", ty, target), span),
+                }
+            }
+            Stmt::Output { exprs, .. } => {
+                for expr in exprs {
+                    self.expr_type(expr);
+                }
+            }
+            Stmt::If { condition, then_stmt, else_stmt, .. } => {
+                self.check_condition(condition, "IF");
+                self.check_block(then_stmt);
+                if let Some(else_stmt) = else_stmt {
+                    self.check_block(else_stmt);
+                }
+            }
+            Stmt::While { condition, body, .. } => {
+                self.check_condition(condition, "WHILE");
+                self.check_block(body);
+            }
+            Stmt::RepeatUntil { body, condition, .. } => {
+                self.check_block(body);
+                self.check_condition(condition, "UNTIL");
+            }
+            Stmt::For { counter, start, end, step, body, .. } => {
+                // The counter runs through INTEGERs, or through the values of an enum
+                let counter_type = match self.expr_type(start) {
+                    Ty::Enum(name) => Ty::Enum(name),
+                    ty => {
+                        if !assignable(&Ty::Integer, &ty) {
+                            self.mismatch(format!("FOR loop start value must be INTEGER, found {}", ty), start.span());
+                        }
+                        Ty::Integer
+                    }
+                };
+                self.expect_type(end, &counter_type, "FOR loop end value");
+                if let Some(step) = step {
+                    self.expect_type(step, &Ty::Integer, "FOR loop step");
+                }
+                let shadowed = self.bind_counter(counter, counter_type);
+                self.check_block(body);
+                self.unbind_counter(counter, shadowed);
+            }
+            Stmt::Case { expression, cases, otherwise, .. } => {
+                let subject = self.expr_type(expression);
+                let mut covered = Vec::new();
+                for case in cases {
+                    for label in &case.labels {
+                        if self.check_case_label(label, &subject) {
+                            self.check_case_overlap(label, &mut covered);
+                        }
+                    }
+                    self.check_block(&case.body);
+                }
+                if let Some(otherwise) = otherwise {
+                    self.check_block(otherwise);
+                }
+            }
+            Stmt::Call { name, args, span } => {
+                let args = args.as_deref().unwrap_or(&[]);
+                let arg_types: Vec<Ty> = args.iter().map(|arg| self.expr_type(arg)).collect();
+                match self.procedures.get(name) {
+                    Some(signature) => {
+                        let params = signature.params.clone();
+                        self.check_arguments("Procedure", name, &params, args, &arg_types, span);
+                    }
+                    None => self.error(codes::UNDECLARED, format!("Procedure {} is not declared", name), span),
+                }
+            }
+            Stmt::Return { value, span } => {
+                match (self.locals.is_some(), self.return_type.clone(), value) {
+                    (false, _, _) => {
+                        self.error(codes::TYPE_MISMATCH, "RETURN outside of a function or procedure".to_string(), span);
+                    }
+                    (true, None, Some(expr)) => {
+                        self.expr_type(expr);
+                        self.mismatch("A procedure cannot return a value".to_string(), expr.span());
+                    }
+                    (true, Some(return_type), Some(expr)) => {
+                        let ty = self.expr_type(expr);
+                        if !assignable(&return_type, &ty) {
+                            self.mismatch(format!("Function returns {}, but this value is {}", return_type, ty), expr.span());
+                        }
+                    }
+                    (true, Some(return_type), None) => {
+                        self.mismatch(format!("Function returns {}, but this RETURN has no value", return_type), span);
+                    }
+                    (true, None, None) => {}
+                }
+            }
+            Stmt::FunctionDeclaration { function, .. } => {
+                let return_type = self.resolve(&function.return_type);
+                self.check_type_exists(&function.return_type, &function.span);
+                self.check_routine(&function.params, &function.body, Some(return_type));
+            }
+            Stmt::ProcedureDeclaration { procedure, .. } => {
+                self.check_routine(&procedure.params, &procedure.body, None);
+            }
+            Stmt::TypeDeclaration { name, variant, span } => {
+                match variant {
+                    TypeDeclarationVariant::Record { fields } => {
+                        for field in fields {
+                            self.check_type_exists(&field.type_name, &field.span);
+                            if self.holds_record(&field.type_name, name, &mut HashSet::new()) {
+                                self.mismatch(format!("Record {} cannot contain itself, except through a pointer", name), &field.span);
+                            }
+                        }
+                    }
+                    TypeDeclarationVariant::Pointer { points_to } => self.check_type_exists(points_to, span),
+                    TypeDeclarationVariant::Set { element_type } => self.check_type_exists(element_type, span),
+                    TypeDeclarationVariant::Enum { .. } => {}
+                }
+            }
+            Stmt::OpenFile { filename, .. } | Stmt::CloseFile { filename, .. } => {
+                self.expect_type(filename, &Ty::String, "File name");
+            }
+            Stmt::ReadFile { filename, target, span } => {
+                self.expect_type(filename, &Ty::String, "File name");
+                if let Some(variable) = target.stored_variable() {
+                    self.check_not_constant(variable, "READFILE", span);
+                }
+                match self.assign_target(target) {
+                    Ty::String | Ty::Date | Ty::Integer | Ty::Real | Ty::Char | Ty::Boolean | Ty::Unknown => {}
+                    ty => self.mismatch(format!("READFILE reads a line into a STRING, DATE, number, CHAR or BOOLEAN, but '{}' is {}", target, ty), span),
+                }
+            }
+            Stmt::WriteFile { filename, exprs, .. } => {
+                self.expect_type(filename, &Ty::String, "File name");
+                for expr in exprs {
+                    self.expr_type(expr);
+                }
+            }
+            Stmt::Seek { filename, address, .. } => {
+                self.expect_type(filename, &Ty::String, "File name");
+                self.expect_type(address, &Ty::Integer, "SEEK address");
+            }
+            Stmt::GetRecord { filename, variable, span } | Stmt::PutRecord { filename, variable, span } => {
+                self.expect_type(filename, &Ty::String, "File name");
+                if matches!(stmt, Stmt::GetRecord { .. }) {
+                    self.check_not_constant(variable, "GETRECORD", span);
+                }
+                if self.variable(variable).is_none() {
+                    self.undeclared_variable(variable, span);
+                }
+            }
+            Stmt::Error { .. } => {}
+        }
+    }
+
+    /// Check a CASE label against the type of the value being tested, returning
+    /// whether it can match values of that type
+    fn check_case_label(&mut self, label: &CaseLabel, subject: &Ty) -> bool {
+        let bounds = match label {
+            CaseLabel::Value(value) => {
+                let label = self.expr_type(value);
+                // Labels are compared by value, so even INTEGER and REAL never match
+                if *subject != label && *subject != Ty::Unknown && label != Ty::Unknown {
+                    self.mismatch(format!("CASE label of type {} can never match a value of type {}", label, subject), value.span());
+                    return false;
+                }
+                return *subject != Ty::Unknown && label != Ty::Unknown;
+            }
+            CaseLabel::Range(low, high) => [low, high],
+        };
+        // Both ends are checked, but a range is only reported once
+        let mut reported = false;
+        let mut matches = *subject != Ty::Unknown;
+        for bound in bounds {
+            let bound_type = self.expr_type(bound);
+            let problem = if bound_type == Ty::Unknown {
+                None
+            } else if !matches!(bound_type, Ty::Integer | Ty::Char | Ty::Enum(_)) {
+                Some(format!("CASE range of type {} must be INTEGER, CHAR or an enum", bound_type))
+            } else if *subject != bound_type && *subject != Ty::Unknown {
+                Some(format!("CASE range of type {} can never match a value of type {}", bound_type, subject))
+            } else {
+                continue;
+            };
+            matches = false;
+            if let Some(problem) = problem.filter(|_| !reported) {
+                self.mismatch(problem, bound.span());
+                reported = true;
+            }
+        }
+        matches
+    }
+
+    /// Warn about a CASE label that matches values an earlier label of the same
+    /// CASE already does. `covered` holds the earlier labels' ranges of ordinals.
+    fn check_case_overlap(&mut self, label: &CaseLabel, covered: &mut Vec<(i64, i64, Span)>) {
+        let bounds = match label {
+            CaseLabel::Value(value) => self.ordinal(value).map(|ordinal| (ordinal, ordinal)),
+            CaseLabel::Range(low, high) => self.ordinal(low).zip(self.ordinal(high)),
+        };
+        // Only labels written as literals or enum values can be compared
+        let Some((low, high)) = bounds else {
+            return;
+        };
+        if low > high {
+            self.diagnostics.push(Diagnostic::warning(codes::UNREACHABLE_CASE_LABEL, format!("CASE range {} is empty, so it can never match", label))
+                .with_span(label.span())
+                .with_note("a range goes from its lower end TO its higher end"));
+            return;
+        }
+
+        let mut earlier: Vec<&(i64, i64, Span)> = covered.iter().filter(|(l, h, _)| *l <= high && low <= *h).collect();
+        if let Some((_, _, first)) = earlier.first().copied() {
+            let first = first.clone();
+            earlier.sort_by_key(|(l, _, _)| *l);
+            // The first value of this label that no earlier label matches
+            let mut uncovered = low;
+            for (l, h, _) in earlier {
+                if *l > uncovered {
+                    break;
+                }
+                uncovered = uncovered.max(h.saturating_add(1));
+            }
+            let warning = if uncovered > high {
+                Diagnostic::warning(codes::UNREACHABLE_CASE_LABEL, format!("CASE label {} can never match, earlier labels already do", label))
+                    .with_label(&first, "matched here first")
+            } else {
+                Diagnostic::warning(codes::OVERLAPPING_CASE_LABEL, format!("CASE label {} overlaps an earlier label", label))
+                    .with_label(&first, "earlier label")
+                    .with_note("values matching both run the earlier branch")
+            };
+            self.diagnostics.push(warning.with_span(label.span()));
+        }
+        covered.push((low, high, label.span()));
+    }
+
+    /// The position of a literal INTEGER, CHAR or enum value in the order of its type
+    fn ordinal(&self, expr: &Expr) -> Option<i64> {
+        match expr {
+            Expr::Number(number, _) => number.parse().ok(),
+            Expr::Char(ch, _) => ch.chars().next().map(|c| c as i64),
+            Expr::UnaryOp(UnaryOp::Negate, operand, _) => self.ordinal(operand)?.checked_neg(),
+            Expr::Variable(name, _) if self.variable(name).is_none() => {
+                let Some(Type::Enum { values, .. }) = self.types.get(self.enum_values.get(name)?) else {
+                    return None;
+                };
+                values.iter().position(|value| value == name).map(|index| index as i64)
+            }
+            _ => None,
+        }
+    }
+
+    fn check_declare(&mut self, name: &str, type_name: &Type, initial_value: &Option<Box<Expr>>, span: &Span) {
+        self.check_type_exists(type_name, span);
+        if let Type::ARRAY { dimensions, .. } = type_name {
+            for (start, end) in dimensions {
+                self.expect_type(start, &Ty::Integer, "Array lower bound");
+                self.expect_type(end, &Ty::Integer, "Array upper bound");
+            }
+        }
+        if let Some(expr) = initial_value {
+            let declared = self.resolve(type_name);
+            let value = self.expr_type(expr);
+            if !assignable(&declared, &value) {
+                self.mismatch(format!("Cannot initialise '{}' of type {} with {}", name, declared, value), expr.span());
+            }
+        }
+    }
+
+    fn check_routine(&mut self, params: &[Param], body: &[Stmt], return_type: Option<Ty>) {
+        let mut locals = HashMap::new();
+        for param in params {
+            self.check_type_exists(&param.type_name, &param.span);
+            locals.insert(param.name.clone(), self.resolve(&param.type_name));
+        }
+        self.collect_variables(body, &mut locals);
+        let mut constants = HashSet::new();
+        collect_constants(body, &mut constants);
+
+        // Routines declared inside a block are still checked on their own
+        let outer_locals = self.locals.replace(locals);
+        let outer_constants = std::mem::replace(&mut self.local_constants, constants);
+        let outer_return = std::mem::replace(&mut self.return_type, return_type);
+        self.check_block(body);
+        self.locals = outer_locals;
+        self.local_constants = outer_constants;
+        self.return_type = outer_return;
+    }
+
+    fn check_condition(&mut self, condition: &Expr, keyword: &str) {
+        let ty = self.expr_type(condition);
+        if let Some(sample) = ty.sample() {
+            if sample.as_condition().is_err() {
+                self.mismatch(format!("{} condition must be BOOLEAN, found {}", keyword, ty), condition.span());
+            }
+        }
+    }
+
+    fn expect_type(&mut self, expr: &Expr, expected: &Ty, what: &str) {
+        let ty = self.expr_type(expr);
+        if !assignable(expected, &ty) {
+            self.mismatch(format!("{} must be {}, found {}", what, expected, ty), expr.span());
+        }
+    }
+
+    fn undeclared_variable(&mut self, name: &str, span: &Span) {
+        self.error(codes::UNDECLARED, format!("Variable '{}' is not declared", name), span);
+    }
+
+    /// Type of the place an assignment, INPUT or READFILE writes to
+    fn assign_target(&mut self, target: &LValue) -> Ty {
+        match target {
+            LValue::Variable(name, span) => match self.variable(name) {
+                Some(ty) => ty,
+                None => {
+                    self.undeclared_variable(name, span);
+                    Ty::Unknown
+                }
+            },
+            LValue::Index { target: container, indices, span } => {
+                let ty = self.assign_target(container);
+                let element = self.index(&container.to_string(), &ty, indices, span);
+                if matches!(ty, Ty::Set(_)) {
+                    self.mismatch(format!("Cannot assign to an element of set '{}', sets are immutable", container), span);
+                    return Ty::Unknown;
+                }
+                element
+            }
+            LValue::Field { target: record, field, span } => {
+                let ty = self.assign_target(record);
+                self.field(&ty, field, span)
+            }
+            LValue::Deref { target: pointer, span } => {
+                let ty = self.assign_target(pointer);
+                self.deref(&ty, span)
+            }
+        }
+    }
+
+    /// Element type of `container[indices]`
+    fn index(&mut self, name: &str, container: &Ty, indices: &[Expr], span: &Span) -> Ty {
+        for index in indices {
+            self.expect_type(index, &Ty::Integer, "Index");
+        }
+        match container {
+            Ty::Array { dimensions, element } => {
+                if *dimensions != 0 && indices.len() != *dimensions {
+                    self.error(
+                        codes::WRONG_COUNT,
+                        format!("Array '{}' has {} dimension(s), but {} index(es) were given", name, dimensions, indices.len()),
+                        span,
+                    );
+                }
+                (**element).clone()
+            }
+            Ty::Set(element) => {
+                if indices.len() != 1 {
+                    self.error(codes::WRONG_COUNT, format!("Set '{}' takes 1 index, but {} were given", name, indices.len()), span);
+                }
+                (**element).clone()
+            }
+            Ty::Unknown => Ty::Unknown,
+            other => {
+                self.mismatch(format!("'{}' is {}, not an array", name, other), span);
+                Ty::Unknown
+            }
+        }
+    }
+
+    fn field(&mut self, record: &Ty, field: &str, span: &Span) -> Ty {
+        match record {
+            Ty::Record(type_name) => {
+                let field_type = match self.types.get(type_name) {
+                    Some(Type::Record { fields, .. }) => fields.iter()
+                        .find(|f| f.name == field)
+                        .map(|f| f.type_name.clone()),
+                    _ => return Ty::Unknown,
+                };
+                match field_type {
+                    Some(field_type) => self.resolve(&field_type),
+                    None => {
+                        self.error(codes::UNDECLARED, format!("Record type {} has no field '{}'", type_name, field), span);
+                        Ty::Unknown
+                    }
+                }
+            }
+            Ty::Unknown => Ty::Unknown,
+            other => {
+                self.mismatch(format!("Field access needs a record, found {}", other), span);
+                Ty::Unknown
+            }
+        }
+    }
+
+    fn deref(&mut self, pointer: &Ty, span: &Span) -> Ty {
+        match pointer {
+            Ty::Pointer(target) => (**target).clone(),
+            Ty::Unknown => Ty::Unknown,
+            other => {
+                self.mismatch(format!("Only pointers can be dereferenced with ^, found {}", other), span);
+                Ty::Unknown
+            }
+        }
+    }
+
+    /// Result of `left op right`: `None` when either side is unknown, `Some(None)`
+    /// when the runtime rejects the operands
+    fn operator_result(&self, op: &BinaryOp, left: &Ty, right: &Ty) -> Option<Option<Ty>> {
+        let (left, right) = (left.sample()?, right.sample()?);
+        Some(value::binary_op(op, &left, &right).ok().map(|value| Ty::of_value(&value)))
+    }
+
+    /// Result of IN, or of an operator between two sets. Sample sets are empty,
+    /// so their element types are compared here rather than by the runtime.
+    /// `None` for any other operator.
+    fn set_operator(&mut self, op: &BinaryOp, left: &Ty, right: &Ty, span: &Span) -> Option<Ty> {
+        match (op, left, right) {
+            (BinaryOp::In, _, Ty::Set(element)) => {
+                if !assignable(element, left) {
+                    self.mismatch(format!("Cannot look for {} in a set of {}", left, element), span);
+                }
+                Some(Ty::Boolean)
+            }
+            (_, Ty::Set(l), Ty::Set(r)) => {
+                let result = match op {
+                    BinaryOp::Add | BinaryOp::Subtract | BinaryOp::Multiply => left.clone(),
+                    BinaryOp::Equals | BinaryOp::NotEquals
+                    | BinaryOp::LessThanOrEqual | BinaryOp::GreaterThanOrEqual => Ty::Boolean,
+                    _ => {
+                        self.mismatch(format!("Operator {} cannot be applied to sets", op.symbol()), span);
+                        return Some(Ty::Unknown);
+                    }
+                };
+                if !(assignable(l, r) && assignable(r, l)) {
+                    self.mismatch(format!("Operator {} cannot be applied to {} and {}", op.symbol(), left, right), span);
+                    return Some(Ty::Unknown);
+                }
+                Some(result)
+            }
+            _ => None,
+        }
+    }
+
+    fn check_arguments(&mut self, kind: &str, name: &str, params: &[(Ty, PassingMode)], args: &[Expr], arg_types: &[Ty], span: &Span) {
+        if args.len() != params.len() {
+            self.error(
+                codes::WRONG_COUNT,
+                format!("{} {} expects {} argument(s), got {}", kind, name, params.len(), args.len()),
+                span,
+            );
+            return;
+        }
+        for (i, (((param, mode), arg), arg_type)) in params.iter().zip(args).zip(arg_types).enumerate() {
+            if *mode == PassingMode::BYREF {
+                let target = arg.to_lvalue();
+                match target.as_ref().map(|target| target.stored_variable()) {
+                    None => self.mismatch(
+                        format!("Argument {} of {} is BYREF and must be a variable, array element or record field", i + 1, name),
+                        arg.span(),
+                    ),
+                    Some(Some(variable)) if self.is_constant(variable) => self.error(
+                        codes::CONSTANT_CHANGED,
+                        format!("Argument {} of {} is BYREF, so it cannot be constant '{}'", i + 1, name, variable),
+                        arg.span(),
+                    ),
+                    // The parameter is the argument's storage, so no conversion is possible either way
+                    Some(_) if !(assignable(param, arg_type) && assignable(arg_type, param)) => self.mismatch(
+                        format!("BYREF argument {} of {} must be exactly {}, found {}", i + 1, name, param, arg_type),
+                        arg.span(),
+                    ),
+                    Some(_) => {}
+                }
+            } else if !assignable(param, arg_type) {
+                self.mismatch(
+                    format!("Argument {} of {} must be {}, found {}", i + 1, name, param, arg_type),
+                    arg.span(),
+                );
+            }
+        }
+    }
+
+    /// Type of an expression, reporting any errors inside it
+    fn expr_type(&mut self, expr: &Expr) -> Ty {
+        match expr {
+            Expr::Number(number, _) => {
+                if number.contains('.') { Ty::Real } else { Ty::Integer }
+            }
+            Expr::Date(..) => Ty::Date,
+            Expr::String(..) => Ty::String,
+            Expr::Char(..) => Ty::Char,
+            Expr::Boolean(..) => Ty::Boolean,
+            // NULL can be stored in a pointer to anything
+            Expr::Null(..) => Ty::Pointer(Box::new(Ty::Unknown)),
+            Expr::Variable(name, span) => {
+                if let Some(ty) = self.variable(name) {
+                    ty
+                } else if let Some(enum_type) = self.enum_values.get(name) {
+                    Ty::Enum(enum_type.clone())
+                } else {
+                    self.undeclared_variable(name, span);
+                    Ty::Unknown
+                }
+            }
+            Expr::BinaryOp(left, op, right, span) => {
+                let left = self.expr_type(left);
+                let right = self.expr_type(right);
+                if let Some(ty) = self.set_operator(op, &left, &right, span) {
+                    return ty;
+                }
+                match self.operator_result(op, &left, &right) {
+                    Some(Some(ty)) => ty,
+                    Some(None) => {
+                        self.mismatch(format!("Operator {} cannot be applied to {} and {}", op.symbol(), left, right), span);
+                        Ty::Unknown
+                    }
+                    None => Ty::Unknown,
+                }
+            }
+            Expr::UnaryOp(op, operand, span) => {
+                let ty = self.expr_type(operand);
+                let Some(sample) = ty.sample() else {
+                    return Ty::Unknown;
+                };
+                match value::unary_op(op, sample) {
+                    Ok(value) => Ty::of_value(&value),
+                    Err(_) => {
+                        let symbol = match op {
+                            UnaryOp::Not => "NOT",
+                            UnaryOp::Negate => "-",
+                        };
+                        self.mismatch(format!("Operator {} cannot be applied to {}", symbol, ty), span);
+                        Ty::Unknown
+                    }
+                }
+            }
+            Expr::FunctionCall { name, args, span } => {
+                let arg_types: Vec<Ty> = args.iter().map(|arg| self.expr_type(arg)).collect();
+                if is_builtin(name) {
+                    return self.builtin_call(name, &arg_types, span);
+                }
+                match self.functions.get(name) {
+                    Some(signature) => {
+                        let params = signature.params.clone();
+                        let return_type = signature.return_type.clone().unwrap_or(Ty::Unknown);
+                        self.check_arguments("Function", name, &params, args, &arg_types, span);
+                        return_type
+                    }
+                    None => {
+                        let message = if self.procedures.contains_key(name) {
+                            format!("{} is a procedure and does not return a value, use CALL", name)
+                        } else {
+                            format!("Function {} is not declared", name)
+                        };
+                        self.error(codes::UNDECLARED, message, span);
+                        Ty::Unknown
+                    }
+                }
+            }
+            Expr::ArrayAccess { array, indices, span } => {
+                let ty = self.expr_type(array);
+                self.index(&array.to_string(), &ty, indices, span)
+            }
+            Expr::FieldAccess { object, field, span } => {
+                let ty = self.expr_type(object);
+                self.field(&ty, field, span)
+            }
+            Expr::PointerDeref { pointer, span } => {
+                let ty = self.expr_type(pointer);
+                self.deref(&ty, span)
+            }
+            Expr::PointerRef { target, span } => {
+                if !matches!(target.as_ref(), Expr::Variable(..)) {
+                    self.mismatch("Pointer reference (^) can only be applied to variables".to_string(), span);
+                }
+                Ty::Pointer(Box::new(self.expr_type(target)))
+            }
+            Expr::New { type_name, span } => {
+                self.check_type_exists(type_name, span);
+                Ty::Pointer(Box::new(self.resolve(type_name)))
+            }
+        }
+    }
+
+    fn builtin_call(&mut self, name: &str, arg_types: &[Ty], span: &Span) -> Ty {
+        let samples: Option<Vec<Value>> = arg_types.iter().map(Ty::sample).collect();
+        let Some(samples) = samples else {
+            return Ty::Unknown;
+        };
+        let result = if name == "EOF" {
+            // EOF needs the open files, so it is not in call_builtin
+            match samples.as_slice() {
+                [Value::String(_)] => Ok(Value::Boolean(true)),
+                _ => Err(()),
+            }
+        } else if name == "NOW" {
+            // NOW needs the clock, so it is not in call_builtin either
+            match samples.as_slice() {
+                [] => Ok(Value::Date(Date::default())),
+                _ => Err(()),
+            }
+        } else if name == "SUCC" || name == "PRED" {
+            // The neighbour has the same enum type as the argument
+            match samples.as_slice() {
+                [value @ Value::Enum { .. }] => Ok(value.clone()),
+                _ => Err(()),
+            }
+        } else {
+            match call_builtin(name, &samples, &mut FixedRandom) {
+                Some(result) => result.map_err(|_| ()),
+                None => return Ty::Unknown,
+            }
+        };
+        match result {
+            Ok(value) => Ty::of_value(&value),
+            Err(()) => {
+                let types: Vec<String> = arg_types.iter().map(|ty| ty.to_string()).collect();
+                self.mismatch(format!("{} cannot be called with ({})", name, types.join(", ")), span);
+                Ty::Unknown
+            }
+        }
+    }
+}
+
+/// An error at each TYPE, FUNCTION or PROCEDURE that reuses the name of an
+/// earlier one, pointing back at the first. Declarations are hoisted, so the
+/// interpreter and compiler refuse these too, before the program starts.
+pub fn duplicate_declarations(statements: &[Stmt]) -> Vec<Diagnostic> {
+    let mut first: HashMap<(&str, &str), &Span> = HashMap::new();
+    let mut duplicates = Vec::new();
+    for stmt in declarations(statements) {
+        let Some(declared) = stmt.declared_name() else { continue };
+        match first.get(&declared) {
+            Some(first_span) => duplicates.push(
+                Diagnostic::error(codes::DUPLICATE_DECLARATION, format!("{} {} already declared", declared.0, declared.1))
+                    .with_span(stmt.span())
+                    .with_label(*first_span, "first declared here"),
+            ),
+            None => {
+                first.insert(declared, stmt.span());
+            }
+        }
+    }
+    duplicates
+}
+
+/// Names given a value by `CONSTANT Name = ...` or `CONSTANT Name <- ...` in a
+/// block and the blocks nested in it, but not in nested routines
+fn collect_constants(statements: &[Stmt], constants: &mut HashSet<String>) {
+    for stmt in statements {
+        match stmt {
+            Stmt::Constant { name, value: Some(_), .. } => {
+                constants.insert(name.clone());
+            }
+            Stmt::FunctionDeclaration { .. } | Stmt::ProcedureDeclaration { .. } => {}
+            _ => for_each_block(stmt, |block| collect_constants(block, constants)),
+        }
+    }
+}
@@ -1,12 +1,31 @@
 use std::fs;
 use std::io::{self, Write};
 use std::env;
+use std::path::Path;
+use std::time::Duration;
+use crate::ast::Stmt;
+use crate::checker::TypeChecker;
+use crate::diagnostic::Diagnostic;
 use crate::parser::Parser;
 use crate::interpreter::Interpreter;
+use crate::compiler::Compiler;
+use crate::bytecode::Program;
+use crate::vm::Vm;
+use crate::debug::CliDebugger;
+use crate::host::NativeHost;
+use crate::limits::{Limits, DEFAULT_MAX_CALL_DEPTH};
+use crate::test_runner::{Engine, DEFAULT_TIMEOUT};
 use crate::log_error;
 
 pub fn run() {
-    let args: Vec<String> = env::args().collect();
+    let mut args: Vec<String> = env::args().collect();
+    let limits = match take_limits(&mut args) {
+        Ok(limits) => limits,
+        Err(msg) => {
+            eprintln!("Error: {}", msg);
+            std::process::exit(1);
+        }
+    };
     
     // Handle help
     if args.len() == 1 || args.contains(&"--help".to_string()) || args.contains(&"-h".to_string()) {
@@ -26,14 +45,14 @@ pub fn run() {
         "eval" => {
             if args.len() == 2 {
                 // Interactive mode
-                run_interactive();
+                run_interactive(&limits);
             } else if args.len() == 3 {
                 // Execute file
                 let filename = &args[2];
                 if validate_pseu_file(filename).is_err() {
                     std::process::exit(1);
                 }
-                execute_file(filename);
+                execute_file(filename, &limits);
             } else {
                 eprintln!("Error: 'eval' command takes 0 or 1 argument");
                 eprintln!("Usage: pseudocode eval [filename]");
@@ -64,6 +83,66 @@ pub fn run() {
             }
             compile_file(filename);
         }
+        "debug" => {
+            if args.len() != 3 {
+                eprintln!("Error: 'debug' command requires a filename");
+                eprintln!("Usage: pseudocode debug <filename>");
+                std::process::exit(1);
+            }
+            let filename = &args[2];
+            if validate_pseu_file(filename).is_err() {
+                std::process::exit(1);
+            }
+            debug_file(filename, &limits);
+        }
+        "dap" => {
+            if args.len() != 2 {
+                eprintln!("Error: 'dap' command takes no arguments");
+                eprintln!("Usage: pseudocode dap");
+                std::process::exit(1);
+            }
+            crate::dap::serve(&limits);
+        }
+        "lsp" => {
+            if args.len() != 2 {
+                eprintln!("Error: 'lsp' command takes no arguments");
+                eprintln!("Usage: pseudocode lsp");
+                std::process::exit(1);
+            }
+            crate::lsp::serve();
+        }
+        "test" => {
+            let bless = args.iter().any(|arg| arg == "--bless");
+            args.retain(|arg| arg != "--bless");
+            let (engines, timeout) = match take_test_options(&mut args) {
+                Ok(options) => options,
+                Err(msg) => {
+                    eprintln!("Error: {}", msg);
+                    std::process::exit(1);
+                }
+            };
+            if args.len() != 3 {
+                eprintln!("Error: 'test' command requires a directory");
+                eprintln!("Usage: pseudocode test <directory> [--bless] [--engine interpreter|vm|both] [--timeout-ms <n>]");
+                std::process::exit(1);
+            }
+            if !crate::test_runner::run_tests(&args[2], bless, engines, timeout, &limits) {
+                std::process::exit(1);
+            }
+        }
+        "run" => {
+            if args.len() != 3 {
+                eprintln!("Error: 'run' command requires a compiled filename");
+                eprintln!("Usage: pseudocode run <filename.pseuc>");
+                std::process::exit(1);
+            }
+            let filename = &args[2];
+            if !filename.ends_with(".pseuc") {
+                log_error!(format!("File '{}' must have a .pseuc extension", filename));
+                std::process::exit(1);
+            }
+            run_compiled(filename, &limits);
+        }
         _ => {
             eprintln!("Error: Unknown command '{}'", command);
             print_help();
@@ -72,6 +151,73 @@ pub fn run() {
     }
 }
 
+/// Flags setting each execution limit, all taking a number
+const LIMIT_FLAGS: [&str; 6] = [
+    "--max-steps",
+    "--max-call-depth",
+    "--max-output-bytes",
+    "--max-elements",
+    "--max-open-files",
+    "--max-time-ms",
+];
+
+/// Remove the execution limit flags and their values from `args`, returning the limits they set
+fn take_limits(args: &mut Vec<String>) -> Result<Limits, String> {
+    let mut limits = Limits::default();
+    let mut i = 1;
+    while i < args.len() {
+        if !LIMIT_FLAGS.contains(&args[i].as_str()) {
+            i += 1;
+            continue;
+        }
+        let flag = args.remove(i);
+        let value = (i < args.len()).then(|| args.remove(i))
+            .and_then(|value| value.parse::<u64>().ok())
+            .ok_or_else(|| format!("'{}' expects a whole number", flag))?;
+        let limit = match flag.as_str() {
+            "--max-steps" => &mut limits.max_steps,
+            "--max-call-depth" => &mut limits.max_call_depth,
+            "--max-output-bytes" => &mut limits.max_output_bytes,
+            "--max-elements" => &mut limits.max_elements,
+            "--max-open-files" => &mut limits.max_open_files,
+            "--max-time-ms" => &mut limits.max_time_ms,
+            _ => unreachable!("only limit flags get this far"),
+        };
+        *limit = Some(value);
+    }
+    // The interpreter's stack only has room for the default depth, and both
+    // engines keep to the same limits
+    if limits.max_call_depth.is_some_and(|depth| depth > DEFAULT_MAX_CALL_DEPTH) {
+        return Err(format!("'--max-call-depth' can be at most {}", DEFAULT_MAX_CALL_DEPTH));
+    }
+    Ok(limits)
+}
+
+/// Remove `test`'s `--engine` and `--timeout-ms` flags and their values from
+/// `args`, returning the engines to run on and how long each program may take
+fn take_test_options(args: &mut Vec<String>) -> Result<(&'static [Engine], Duration), String> {
+    let mut engines = Engine::parse("both").expect("'both' names the engines");
+    let mut timeout = DEFAULT_TIMEOUT;
+    let mut i = 2;
+    while i < args.len() {
+        if args[i] != "--engine" && args[i] != "--timeout-ms" {
+            i += 1;
+            continue;
+        }
+        let flag = args.remove(i);
+        let value = (i < args.len()).then(|| args.remove(i));
+        if flag == "--engine" {
+            engines = value.as_deref().and_then(Engine::parse)
+                .ok_or_else(|| "'--engine' expects interpreter, vm or both".to_string())?;
+        } else {
+            let ms = value.and_then(|value| value.parse::<u64>().ok())
+                .ok_or_else(|| "'--timeout-ms' expects a whole number".to_string())?;
+            timeout = Duration::from_millis(ms);
+        }
+    }
+    Ok((engines, timeout))
+}
+
 /// Validate that the filename has a .pseu extension
 fn validate_pseu_file(filename: &str) -> Result<(), ()> {
     if filename.ends_with(".pseu") {
@@ -90,27 +236,64 @@ fn print_help() {
     println!("                     - 'pseudocode eval'          : Interactive mode (like Python)");
     println!("                     - 'pseudocode eval file.pseu': Execute file");
     println!();
-    println!("  check <filename>   Check syntax without executing");
+    println!("  check <filename>   Check syntax and types without executing");
     println!("                     - 'pseudocode check file.pseu'");
     println!();
-    println!("  compile <filename> Compile pseudocode (coming soon)");
-    println!("                     - 'pseudocode compile file.pseu'");
+    println!("  compile <filename> Compile pseudocode to bytecode");
+    println!("                     - 'pseudocode compile file.pseu': Writes file.pseuc");
+    println!();
+    println!("  debug <filename>   Step through a program, pausing at breakpoints");
+    println!("                     - 'pseudocode debug file.pseu': Type 'help' once paused");
+    println!();
+    println!("  dap                Serve the Debug Adapter Protocol on stdin/stdout");
+    println!("                     - For editors such as VS Code; the program comes from 'launch'");
+    println!();
+    println!("  lsp                Serve the Language Server Protocol on stdin/stdout");
+    println!("                     - For editors such as VS Code, Neovim and Helix");
+    println!();
+    println!("  run <filename>     Execute compiled bytecode");
+    println!("                     - 'pseudocode run file.pseuc'");
+    println!();
+    println!("  test <directory>   Run every program and compare it with its expected output");
+    println!("                     - 'name.in' is the input, 'name.out' the expected output");
+    println!("                       and 'name.err' the expected errors, if any");
+    println!("                     - 'pseudocode test unit_tests --bless': Rewrite the expectations");
+    println!("                     - '--engine interpreter|vm|both': Run under eval, the VM, or");
+    println!("                       both (the default), which must print the same");
+    println!("                     - '--timeout-ms <n>': Fail a program still running after n ms");
+    println!("                       ({} if not given)", DEFAULT_TIMEOUT.as_millis());
     println!();
     println!("  --help, -h         Show this help message");
     println!();
+    println!("Limits (for eval, debug, dap, run and test, unlimited unless given):");
+    println!("  --max-steps <n>        Statements and loop iterations executed under eval,");
+    println!("                         debug and dap; VM instructions executed under run");
+    println!("  --max-call-depth <n>   Procedure and function calls nested at once (at most,");
+    println!("                         and if not given, {})", DEFAULT_MAX_CALL_DEPTH);
+    println!("  --max-output-bytes <n> Bytes written by OUTPUT");
+    println!("  --max-elements <n>     Array elements and NEW cells allocated");
+    println!("  --max-open-files <n>   Files open at once");
+    println!("  --max-time-ms <n>      Wall-clock running time in milliseconds");
+    println!();
     println!("Examples:");
     println!("  pseudocode eval");
     println!("  pseudocode eval program.pseu");
     println!("  pseudocode check program.pseu");
+    println!("  pseudocode debug program.pseu");
+    println!("  pseudocode compile program.pseu");
+    println!("  pseudocode run program.pseuc");
+    println!("  pseudocode test unit_tests");
+    println!("  pseudocode eval program.pseu --max-steps 1000000 --max-time-ms 2000");
 }
 
-fn run_interactive() {
+fn run_interactive(limits: &Limits) {
     println!("Pseudocode Interactive Interpreter");
     println!("Type 'exit' or 'quit' to exit, or 'help' for help");
     println!("Press Enter on an empty line to finish multiline input");
     println!();
     
     let mut interpreter = Interpreter::new();
+    interpreter.set_limits(limits.clone());
     
     loop {
         // Accumulate multiline input
@@ -149,7 +332,7 @@ fn run_interactive() {
                     line_count += 1;
                     
                     // Try to parse to see if we have a complete statement
-                    let mut test_parser = Parser::new(&input_buffer.trim());
+                    let mut test_parser = Parser::new(input_buffer.trim());
                     match test_parser.parse_program() {
                         Ok(_) => {
                             // Complete statement, break and execute
@@ -157,7 +340,7 @@ fn run_interactive() {
                         }
                         Err(e) => {
                             // Check if error suggests we need more input
-                            let error_lower = e.to_lowercase();
+                            let error_lower = e.message.to_lowercase();
                             if error_lower.contains("unexpected end of file") ||
                                error_lower.contains("was not closed") ||
                                error_lower.contains("expected") && (
@@ -214,6 +397,7 @@ fn run_interactive() {
             
             if input == "clear" {
                 interpreter = Interpreter::new();
+                interpreter.set_limits(limits.clone());
                 println!("Interpreter state cleared.");
                 continue;
             }
@@ -223,40 +407,56 @@ fn run_interactive() {
         let mut parser = Parser::new(input);
         match parser.parse_program() {
             Ok(statements) => {
+                if let Err(e) = interpreter.declare(&statements) {
+                    report(&e, Some(input), "<stdin>");
+                    continue;
+                }
                 for stmt in statements {
-                    match interpreter.evaluate_stmt(&stmt) {
+                    let result = interpreter.evaluate_stmt(&stmt);
+                    report_warnings(interpreter.take_warnings(), Some(input), "<stdin>");
+                    match result {
                         Ok(()) => {
                             // Statement executed successfully
                         }
-                        Err(_e) => {
-                            // Error already logged by log_error! macro with line numbers
+                        Err(e) => {
+                            report(&e, Some(input), "<stdin>");
                             break;
                         }
                     }
                 }
             }
-            Err(_e) => {
+            Err(e) => {
+                report(&e, Some(input), "<stdin>");
                 break;
             }
         }
     }
 }
 
-fn execute_file(filename: &str) {
+fn execute_file(filename: &str, limits: &Limits) {
     match fs::read_to_string(filename) {
         Ok(content) => {
             let mut parser = Parser::new(&content);
             match parser.parse_program() {
                 Ok(statements) => {
+                    type_check(&statements, &content, filename);
                     let mut interpreter = Interpreter::with_source_file(filename);
+                    interpreter.set_limits(limits.clone());
+                    if let Err(e) = interpreter.declare(&statements) {
+                        report(&e, Some(&content), filename);
+                        std::process::exit(1);
+                    }
                     for stmt in statements.iter() {
-                        if let Err(_e) = interpreter.evaluate_stmt(stmt) {
-                            // Error already logged by log_error! macro with line numbers
+                        let result = interpreter.evaluate_stmt(stmt);
+                        report_warnings(interpreter.take_warnings(), Some(&content), filename);
+                        if let Err(e) = result {
+                            report(&e, Some(&content), filename);
                             std::process::exit(1);
                         }
                     }
                 }
-                Err(_) => {
+                Err(e) => {
+                    report(&e, Some(&content), filename);
                     std::process::exit(1);
                 }
             }
@@ -268,21 +468,56 @@ fn execute_file(filename: &str) {
     }
 }
 
+fn debug_file(filename: &str, limits: &Limits) {
+    let content = match fs::read_to_string(filename) {
+        Ok(content) => content,
+        Err(e) => {
+            eprintln!("Error: Failed to read file '{}': {}", filename, e);
+            std::process::exit(1);
+        }
+    };
+    let statements = match Parser::new(&content).parse_program() {
+        Ok(statements) => statements,
+        Err(e) => {
+            report(&e, Some(&content), filename);
+            std::process::exit(1);
+        }
+    };
+    type_check(&statements, &content, filename);
+    let mut interpreter = Interpreter::with_source_file(filename);
+    interpreter.set_limits(limits.clone());
+    interpreter.set_debugger(Box::new(CliDebugger::new(&content, &statements)));
+    if let Err(e) = interpreter.declare(&statements) {
+        report(&e, Some(&content), filename);
+        std::process::exit(1);
+    }
+    for stmt in statements.iter() {
+        let result = interpreter.evaluate_stmt(stmt);
+        report_warnings(interpreter.take_warnings(), Some(&content), filename);
+        if let Err(e) = result {
+            report(&e, Some(&content), filename);
+            std::process::exit(1);
+        }
+    }
+    println!("Program finished.");
+}
+
 fn check_syntax(filename: &str) {
     match fs::read_to_string(filename) {
         Ok(content) => {
             let mut parser = Parser::new(&content);
-            match parser.parse_program() {
-                Ok(statements) => {
-                    println!("Syntax check passed!");
-                    println!("Found {} statement(s)", statements.len());
-                    std::process::exit(0);
-                }
-                Err(e) => {
-                    eprintln!("Syntax Error: {}", e);
-                    std::process::exit(1);
+            let (statements, errors) = parser.parse_program_with_errors();
+            if !errors.is_empty() {
+                for e in &errors {
+                    report(e, Some(&content), filename);
                 }
+                eprintln!("Found {} syntax error(s)", errors.len());
+                std::process::exit(1);
             }
+            type_check(&statements, &content, filename);
+            println!("Syntax check passed!");
+            println!("Found {} statement(s)", statements.len());
+            std::process::exit(0);
         }
         Err(e) => {
             eprintln!("Error: Failed to read file '{}': {}", filename, e);
@@ -291,9 +526,100 @@ fn check_syntax(filename: &str) {
     }
 }
 
-fn compile_file(_filename: &str) {
-    eprintln!("Error: Compiler not yet implemented");
-    eprintln!("This feature will be available in a future version.");
-    std::process::exit(1);
+fn compile_file(filename: &str) {
+    let content = match fs::read_to_string(filename) {
+        Ok(content) => content,
+        Err(e) => {
+            eprintln!("Error: Failed to read file '{}': {}", filename, e);
+            std::process::exit(1);
+        }
+    };
+
+    let mut parser = Parser::new(&content);
+    let statements = match parser.parse_program() {
+        Ok(statements) => statements,
+        Err(e) => {
+            report(&e, Some(&content), filename);
+            std::process::exit(1);
+        }
+    };
+    type_check(&statements, &content, filename);
+
+    let program = match Compiler::new().compile(&statements) {
+        Ok(program) => program,
+        Err(e) => {
+            report(&e, Some(&content), filename);
+            std::process::exit(1);
+        }
+    };
+
+    let output = Path::new(filename).with_extension("pseuc");
+    let written = program.to_bytes()
+        .and_then(|bytes| fs::write(&output, bytes).map_err(|e| e.to_string()));
+    if let Err(e) = written {
+        eprintln!("Error: Failed to write '{}': {}", output.display(), e);
+        std::process::exit(1);
+    }
+    println!("Compiled '{}' to '{}'", filename, output.display());
 }
 
+fn run_compiled(filename: &str, limits: &Limits) {
+    let bytes = match fs::read(filename) {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            eprintln!("Error: Failed to read file '{}': {}", filename, e);
+            std::process::exit(1);
+        }
+    };
+
+    let program = match Program::from_bytes(&bytes) {
+        Ok(program) => program,
+        Err(e) => {
+            eprintln!("Error: '{}': {}", filename, e);
+            std::process::exit(1);
+        }
+    };
+
+    let source_dir = Path::new(filename).parent().map(|p| p.to_path_buf());
+    let mut vm = Vm::new(program, NativeHost::native(source_dir));
+    vm.set_limits(limits.clone());
+    let result = vm.run();
+    let warnings = vm.take_warnings();
+    if result.is_ok() && warnings.is_empty() {
+        return;
+    }
+    // Quote the source file the program was compiled from, if it is still next to it
+    let source_file = Path::new(filename).with_extension("pseu");
+    let source = fs::read_to_string(&source_file).ok();
+    report_warnings(warnings, source.as_deref(), &source_file.to_string_lossy());
+    if let Err(e) = result {
+        report(&e, source.as_deref(), &source_file.to_string_lossy());
+        std::process::exit(1);
+    }
+}
+
+/// Run the type checker, reporting every error and warning and exiting if
+/// there are any errors
+fn type_check(statements: &[Stmt], content: &str, filename: &str) {
+    let diagnostics = TypeChecker::new().check(statements);
+    for d in &diagnostics {
+        report(d, Some(content), filename);
+    }
+    let errors = diagnostics.iter().filter(|d| d.is_error()).count();
+    if errors > 0 {
+        eprintln!("Found {} type error(s)", errors);
+        std::process::exit(1);
+    }
+}
+
+/// Print warnings raised while the program ran
+fn report_warnings(warnings: Vec<Diagnostic>, source: Option<&str>, filename: &str) {
+    for warning in &warnings {
+        report(warning, source, filename);
+    }
+}
+
+/// Print a diagnostic rustc-style, quoting the offending source lines when available
+fn report(diagnostic: &Diagnostic, source: Option<&str>, filename: &str) {
+    eprintln!("{}", diagnostic.render(source, filename));
+}
@@ -0,0 +1,741 @@
+use std::collections::HashMap;
+
+use crate::ast::{declarations, CaseLabel, Expr, LValue, Stmt, Type, Param, PassingMode, TypeDeclarationVariant, Span};
+use crate::builtins::is_builtin;
+use crate::checker::duplicate_declarations;
+use crate::bytecode::{Instr, PathStep, Program, Routine, VarRef};
+use crate::diagnostic::{codes, Diagnostic};
+use crate::value::Value;
+
+/// Variables of the routine currently being compiled
+struct LocalScope {
+    routine: u32,
+    ids: HashMap<String, u32>,
+    names: Vec<String>,
+    is_function: bool,
+}
+
+enum RoutineBody<'a> {
+    Function(&'a [Param], &'a [Stmt]),
+    Procedure(&'a [Param], &'a [Stmt]),
+}
+
+/// Lowers a parsed program into stack bytecode for the VM.
+///
+/// Variables are resolved to slots at compile time: names used by the main
+/// program become globals, names declared inside a subroutine (and its
+/// parameters) become locals of its call frame.
+pub struct Compiler {
+    program: Program,
+    global_ids: HashMap<String, u32>,
+    function_ids: HashMap<String, u32>,
+    procedure_ids: HashMap<String, u32>,
+    constant_ids: HashMap<String, u32>,
+    name_ids: HashMap<String, u32>,
+    /// Values of the enums declared so far, by name
+    enum_values: HashMap<String, Value>,
+    scope: Option<LocalScope>,
+    // Statement being compiled, recorded for every emitted instruction
+    span: Span,
+}
+
+impl Default for Compiler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Compiler {
+    pub fn new() -> Self {
+        Self {
+            program: Program::default(),
+            global_ids: HashMap::new(),
+            function_ids: HashMap::new(),
+            procedure_ids: HashMap::new(),
+            constant_ids: HashMap::new(),
+            name_ids: HashMap::new(),
+            enum_values: HashMap::new(),
+            scope: None,
+            span: Span::default(),
+        }
+    }
+
+    pub fn compile(mut self, statements: &[Stmt]) -> Result<Program, Diagnostic> {
+        let mut bodies = Vec::new();
+        self.declare(statements, &mut bodies)?;
+
+        for stmt in statements {
+            self.compile_stmt(stmt)?;
+        }
+        self.emit(Instr::Halt);
+
+        for (routine, body) in bodies {
+            self.compile_routine(routine, body)?;
+        }
+
+        Ok(self.program)
+    }
+
+    fn error(&self, msg: String) -> Diagnostic {
+        Diagnostic::error(codes::COMPILE, msg).with_span(&self.span)
+    }
+
+    fn emit(&mut self, instr: Instr) -> usize {
+        self.program.code.push(instr);
+        self.program.spans.push(self.span.clone());
+        self.program.code.len() - 1
+    }
+
+    /// Compile `compile` with its instructions reporting errors at `span`,
+    /// as the interpreter reports them at the expression that failed
+    fn compile_at(&mut self, span: &Span, compile: impl FnOnce(&mut Self) -> Result<(), Diagnostic>) -> Result<(), Diagnostic> {
+        let outer = std::mem::replace(&mut self.span, span.clone());
+        let result = compile(self);
+        self.span = outer;
+        result
+    }
+
+    fn here(&self) -> u32 {
+        self.program.code.len() as u32
+    }
+
+    /// Point the jump at `at` to the current position
+    fn patch(&mut self, at: usize) {
+        let target = self.here();
+        match &mut self.program.code[at] {
+            Instr::Jump(to) | Instr::JumpIfFalse(to) => *to = target,
+            Instr::ForTest { exit, .. } => *exit = target,
+            _ => unreachable!("patching a non-jump instruction"),
+        }
+    }
+
+    fn constant(&mut self, value: Value) -> u32 {
+        // Only scalar literals are pooled, so the debug form is a stable key
+        let key = format!("{:?}", value);
+        if let Some(id) = self.constant_ids.get(&key) {
+            return *id;
+        }
+        self.program.constants.push(value);
+        let id = self.program.constants.len() as u32 - 1;
+        self.constant_ids.insert(key, id);
+        id
+    }
+
+    fn name(&mut self, name: &str) -> u32 {
+        if let Some(id) = self.name_ids.get(name) {
+            return *id;
+        }
+        self.program.names.push(name.to_string());
+        let id = self.program.names.len() as u32 - 1;
+        self.name_ids.insert(name.to_string(), id);
+        id
+    }
+
+    fn type_id(&mut self, type_name: &Type) -> u32 {
+        self.program.types.push(type_name.clone());
+        self.program.types.len() as u32 - 1
+    }
+
+    /// Register every TYPE, procedure and function up front, wherever it is
+    /// declared, so the program can use them above their declarations. Types
+    /// are defined by the first instructions the program runs.
+    fn declare<'a>(&mut self, statements: &'a [Stmt], bodies: &mut Vec<(u32, RoutineBody<'a>)>) -> Result<(), Diagnostic> {
+        if let Some(duplicate) = duplicate_declarations(statements).into_iter().next() {
+            return Err(duplicate);
+        }
+        for stmt in declarations(statements) {
+            self.span = stmt.span().clone();
+            match stmt {
+                Stmt::FunctionDeclaration { function, .. } => {
+                    let id = self.program.routines.len() as u32;
+                    self.program.routines.push(Routine {
+                        name: function.name.clone(),
+                        params: function.params.iter().map(|p| p.type_name.clone()).collect(),
+                        modes: function.params.iter().map(|p| p.mode).collect(),
+                        return_type: Some(function.return_type.clone()),
+                        locals: Vec::new(),
+                        entry: 0,
+                    });
+                    self.function_ids.insert(function.name.clone(), id);
+                    bodies.push((id, RoutineBody::Function(&function.params, &function.body)));
+                }
+                Stmt::ProcedureDeclaration { procedure, .. } => {
+                    let id = self.program.routines.len() as u32;
+                    self.program.routines.push(Routine {
+                        name: procedure.name.clone(),
+                        params: procedure.params.iter().map(|p| p.type_name.clone()).collect(),
+                        modes: procedure.params.iter().map(|p| p.mode).collect(),
+                        return_type: None,
+                        locals: Vec::new(),
+                        entry: 0,
+                    });
+                    self.procedure_ids.insert(procedure.name.clone(), id);
+                    bodies.push((id, RoutineBody::Procedure(&procedure.params, &procedure.body)));
+                }
+                Stmt::TypeDeclaration { name, variant, .. } => self.define_type(name, variant),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn define_type(&mut self, name: &str, variant: &TypeDeclarationVariant) {
+        let type_def = match variant {
+            TypeDeclarationVariant::Record { fields } => Type::Record {
+                name: name.to_string(),
+                fields: fields.clone(),
+            },
+            TypeDeclarationVariant::Enum { values } => {
+                for (index, value) in values.iter().enumerate() {
+                    let literal = Value::Enum { type_name: name.to_string(), value: value.clone(), index };
+                    self.enum_values.insert(value.clone(), literal);
+                }
+                Type::Enum {
+                    name: name.to_string(),
+                    values: values.clone(),
+                }
+            }
+            TypeDeclarationVariant::Pointer { points_to } => Type::Pointer {
+                points_to: points_to.clone(),
+            },
+            TypeDeclarationVariant::Set { element_type } => Type::Set {
+                element_type: element_type.clone(),
+            },
+        };
+        let name = self.name(name);
+        let ty = self.type_id(&type_def);
+        self.emit(Instr::DefineType { name, ty });
+    }
+
+    /// Names declared anywhere in a routine body, excluding nested routines
+    fn collect_locals(statements: &[Stmt], names: &mut Vec<String>) {
+        for stmt in statements {
+            match stmt {
+                Stmt::Declare { name, .. } | Stmt::Define { name, .. } => names.push(name.clone()),
+                Stmt::Constant { name, value: Some(_), .. } => names.push(name.clone()),
+                Stmt::DeclareMultiple { declarations, .. } => {
+                    names.extend(declarations.iter().map(|(name, _)| name.clone()));
+                }
+                Stmt::If { then_stmt, else_stmt, .. } => {
+                    Self::collect_locals(then_stmt, names);
+                    if let Some(else_stmt) = else_stmt {
+                        Self::collect_locals(else_stmt, names);
+                    }
+                }
+                Stmt::While { body, .. } | Stmt::For { body, .. } | Stmt::RepeatUntil { body, .. } => {
+                    Self::collect_locals(body, names);
+                }
+                Stmt::Case { cases, otherwise, .. } => {
+                    for case in cases {
+                        Self::collect_locals(&case.body, names);
+                    }
+                    if let Some(otherwise) = otherwise {
+                        Self::collect_locals(otherwise, names);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn compile_routine(&mut self, routine: u32, body: RoutineBody) -> Result<(), Diagnostic> {
+        let (params, statements, is_function) = match body {
+            RoutineBody::Function(params, statements) => (params, statements, true),
+            RoutineBody::Procedure(params, statements) => (params, statements, false),
+        };
+
+        let mut scope = LocalScope {
+            routine,
+            ids: HashMap::new(),
+            names: Vec::new(),
+            is_function,
+        };
+        let mut declared: Vec<String> = params.iter().map(|p| p.name.clone()).collect();
+        Self::collect_locals(statements, &mut declared);
+        for name in declared {
+            if !scope.ids.contains_key(&name) {
+                scope.ids.insert(name.clone(), scope.names.len() as u32);
+                scope.names.push(name);
+            }
+        }
+        self.scope = Some(scope);
+
+        self.program.routines[routine as usize].entry = self.here();
+        for stmt in statements {
+            self.compile_stmt(stmt)?;
+        }
+        self.emit(Instr::Leave);
+
+        let scope = self.scope.take().expect("routine scope");
+        self.program.routines[scope.routine as usize].locals = scope.names;
+        Ok(())
+    }
+
+    /// Compile the indices along `target`, returning the variable it starts from
+    /// and, unless it is that variable, the path to the part being written
+    fn compile_lvalue(&mut self, target: &LValue) -> Result<(VarRef, Option<u32>), Diagnostic> {
+        let mut steps = Vec::new();
+        let var = self.compile_path(target, &mut steps)?;
+        if steps.is_empty() {
+            return Ok((var, None));
+        }
+        self.program.paths.push(steps);
+        Ok((var, Some(self.program.paths.len() as u32 - 1)))
+    }
+
+    fn compile_path(&mut self, target: &LValue, steps: &mut Vec<PathStep>) -> Result<VarRef, Diagnostic> {
+        match target {
+            LValue::Variable(name, _) => Ok(self.resolve(name)),
+            LValue::Index { target, indices, .. } => {
+                let var = self.compile_path(target, steps)?;
+                for index in indices {
+                    self.compile_expr(index)?;
+                }
+                steps.push(PathStep::Index(indices.len() as u32));
+                Ok(var)
+            }
+            LValue::Field { target, field, .. } => {
+                let var = self.compile_path(target, steps)?;
+                steps.push(PathStep::Field(self.name(field)));
+                Ok(var)
+            }
+            LValue::Deref { target, .. } => {
+                let var = self.compile_path(target, steps)?;
+                steps.push(PathStep::Deref);
+                Ok(var)
+            }
+        }
+    }
+
+    /// Resolve a variable name to its slot, allocating one on first use
+    fn resolve(&mut self, name: &str) -> VarRef {
+        if let Some(scope) = &mut self.scope {
+            if let Some(id) = scope.ids.get(name) {
+                return VarRef::Local(*id);
+            }
+            if let Some(id) = self.global_ids.get(name) {
+                return VarRef::Global(*id);
+            }
+            let id = scope.names.len() as u32;
+            scope.ids.insert(name.to_string(), id);
+            scope.names.push(name.to_string());
+            return VarRef::Local(id);
+        }
+        if let Some(id) = self.global_ids.get(name) {
+            return VarRef::Global(*id);
+        }
+        let id = self.program.globals.len() as u32;
+        self.global_ids.insert(name.to_string(), id);
+        self.program.globals.push(name.to_string());
+        VarRef::Global(id)
+    }
+
+    /// The enum value spelt `name`, unless a variable of that name hides it
+    fn enum_literal(&self, name: &str) -> Option<Value> {
+        let is_variable = self.global_ids.contains_key(name)
+            || self.scope.as_ref().is_some_and(|scope| scope.ids.contains_key(name));
+        if is_variable {
+            return None;
+        }
+        self.enum_values.get(name).cloned()
+    }
+
+    /// Allocate `count` consecutive unnamed slots in the current scope
+    fn hidden(&mut self, count: u32) -> VarRef {
+        if let Some(scope) = &mut self.scope {
+            let id = scope.names.len() as u32;
+            scope.names.extend((0..count).map(|_| String::new()));
+            VarRef::Local(id)
+        } else {
+            let id = self.program.globals.len() as u32;
+            self.program.globals.extend((0..count).map(|_| String::new()));
+            VarRef::Global(id)
+        }
+    }
+
+    fn compile_block(&mut self, statements: &[Stmt]) -> Result<(), Diagnostic> {
+        for stmt in statements {
+            self.compile_stmt(stmt)?;
+        }
+        Ok(())
+    }
+
+    /// Push whether the CASE value in `subject` matches `label`
+    fn compile_case_label(&mut self, subject: VarRef, label: &CaseLabel) -> Result<(), Diagnostic> {
+        self.span = label.span().clone();
+        self.emit(Instr::Load(subject));
+        match label {
+            CaseLabel::Value(value) => {
+                self.compile_expr(value)?;
+                self.emit(Instr::CaseEq);
+            }
+            CaseLabel::Range(low, high) => {
+                self.compile_expr(low)?;
+                self.compile_expr(high)?;
+                self.emit(Instr::CaseIn);
+            }
+        }
+        Ok(())
+    }
+
+    fn compile_declare(&mut self, name: &str, type_name: &Type, initial_value: &Option<Box<Expr>>) -> Result<(), Diagnostic> {
+        if let Type::ARRAY { dimensions, .. } = type_name {
+            for (start, end) in dimensions {
+                self.compile_expr(start)?;
+                self.compile_expr(end)?;
+            }
+            let var = self.resolve(name);
+            let ty = self.type_id(type_name);
+            self.emit(Instr::DeclareArray { var, ty, dims: dimensions.len() as u32 });
+            return Ok(());
+        }
+
+        if let Some(expr) = initial_value {
+            self.compile_expr(expr)?;
+        }
+        let var = self.resolve(name);
+        let ty = self.type_id(type_name);
+        self.emit(Instr::Declare { var, ty, init: initial_value.is_some() });
+        Ok(())
+    }
+
+    fn compile_stmt(&mut self, stmt: &Stmt) -> Result<(), Diagnostic> {
+        self.span = stmt.span().clone();
+        match stmt {
+            Stmt::Declare { name, type_name, initial_value, .. } => {
+                self.compile_declare(name, type_name, initial_value)?;
+            }
+            Stmt::DeclareMultiple { declarations, type_name, .. } => {
+                for (name, initial_value) in declarations {
+                    self.compile_declare(name, type_name, initial_value)?;
+                }
+            }
+            Stmt::Define { name, values, type_name, .. } => {
+                for value in values {
+                    let id = self.constant(Value::String(value.clone()));
+                    self.emit(Instr::Const(id));
+                }
+                let var = self.resolve(name);
+                let type_name = self.name(type_name);
+                self.emit(Instr::Define { var, type_name, count: values.len() as u32 });
+            }
+            Stmt::Constant { name, value, .. } => {
+                if let Some(expr) = value {
+                    self.compile_expr(expr)?;
+                }
+                let var = self.resolve(name);
+                self.emit(Instr::Constant { var, init: value.is_some() });
+            }
+            Stmt::Assign { target, expression, .. } => {
+                self.compile_expr(expression)?;
+                match self.compile_lvalue(target)? {
+                    (var, None) => self.emit(Instr::Store(var)),
+                    (var, Some(path)) => self.emit(Instr::StorePath { var, path }),
+                };
+            }
+            Stmt::Output { exprs, .. } => {
+                for expr in exprs {
+                    self.compile_expr(expr)?;
+                }
+                self.emit(Instr::Output(exprs.len() as u32));
+            }
+            Stmt::Input { target, .. } => {
+                let (var, path) = self.compile_lvalue(target)?;
+                self.emit(Instr::Input { var, path });
+            }
+            Stmt::If { condition, then_stmt, else_stmt, .. } => {
+                self.compile_expr(condition)?;
+                let to_else = self.emit(Instr::JumpIfFalse(0));
+                self.compile_block(then_stmt)?;
+                if let Some(else_stmt) = else_stmt {
+                    let to_end = self.emit(Instr::Jump(0));
+                    self.patch(to_else);
+                    self.compile_block(else_stmt)?;
+                    self.patch(to_end);
+                } else {
+                    self.patch(to_else);
+                }
+            }
+            Stmt::While { condition, body, .. } => {
+                let head = self.here();
+                self.compile_expr(condition)?;
+                let to_end = self.emit(Instr::JumpIfFalse(0));
+                self.compile_block(body)?;
+                self.emit(Instr::Jump(head));
+                self.patch(to_end);
+            }
+            Stmt::RepeatUntil { body, condition, .. } => {
+                let head = self.here();
+                self.compile_block(body)?;
+                self.compile_expr(condition)?;
+                self.emit(Instr::JumpIfFalse(head));
+            }
+            Stmt::For { counter, start, end, step, body, span } => {
+                self.compile_expr(start)?;
+                self.compile_expr(end)?;
+                match step {
+                    Some(step) => self.compile_expr(step)?,
+                    None => {
+                        let one = self.constant(Value::Integer(1));
+                        self.emit(Instr::Const(one));
+                    }
+                }
+                let var = self.resolve(counter);
+                let state = self.hidden(4);
+                self.emit(Instr::ForInit { var, state });
+                let head = self.here();
+                let to_exit = self.emit(Instr::ForTest { state, exit: 0 });
+                self.compile_block(body)?;
+                self.span = span.clone();
+                self.emit(Instr::ForStep { var, state });
+                self.emit(Instr::Jump(head));
+                self.patch(to_exit);
+                self.emit(Instr::ForEnd { var, state });
+            }
+            Stmt::Case { expression, cases, otherwise, span } => {
+                self.compile_expr(expression)?;
+                let subject = self.hidden(1);
+                self.emit(Instr::SetTemp(subject));
+
+                let mut to_end = Vec::new();
+                for case in cases {
+                    // Any label but the last jumps into the body when it matches
+                    let (last, others) = case.labels.split_last().expect("CASE branch without labels");
+                    let mut to_body = Vec::new();
+                    for label in others {
+                        self.compile_case_label(subject, label)?;
+                        let to_following = self.emit(Instr::JumpIfFalse(0));
+                        to_body.push(self.emit(Instr::Jump(0)));
+                        self.patch(to_following);
+                    }
+                    self.compile_case_label(subject, last)?;
+                    let to_next = self.emit(Instr::JumpIfFalse(0));
+                    for at in to_body {
+                        self.patch(at);
+                    }
+                    self.compile_block(&case.body)?;
+                    to_end.push(self.emit(Instr::Jump(0)));
+                    self.patch(to_next);
+                }
+                match otherwise {
+                    Some(otherwise) => self.compile_block(otherwise)?,
+                    None => {
+                        self.span = span.clone();
+                        self.emit(Instr::CaseUnmatched(subject));
+                    }
+                }
+                for at in to_end {
+                    self.patch(at);
+                }
+            }
+            // Registered by `declare` before any code is compiled
+            Stmt::FunctionDeclaration { .. } | Stmt::ProcedureDeclaration { .. } | Stmt::TypeDeclaration { .. } => {}
+            Stmt::Call { name, args, .. } => {
+                let routine = *self.procedure_ids.get(name)
+                    .ok_or_else(|| self.error(format!("Procedure {} not found", name)))?;
+                let args = args.as_deref().unwrap_or(&[]);
+                self.compile_call(routine, args)?;
+            }
+            Stmt::Return { value, .. } => {
+                let is_function = match &self.scope {
+                    Some(scope) => scope.is_function,
+                    None => return Err(self.error("RETURN statement outside of function".to_string())),
+                };
+                match value {
+                    Some(expr) if is_function => {
+                        self.compile_expr(expr)?;
+                        self.emit(Instr::Return);
+                    }
+                    Some(_) => return Err(self.error("RETURN with a value is only allowed in a function".to_string())),
+                    None => {
+                        self.emit(Instr::Leave);
+                    }
+                }
+            }
+            Stmt::OpenFile { filename, mode, .. } => {
+                self.compile_expr(filename)?;
+                self.emit(Instr::OpenFile(mode.clone()));
+            }
+            Stmt::CloseFile { filename, .. } => {
+                self.compile_expr(filename)?;
+                self.emit(Instr::CloseFile);
+            }
+            Stmt::ReadFile { filename, target, .. } => {
+                self.compile_expr(filename)?;
+                let (var, path) = self.compile_lvalue(target)?;
+                self.emit(Instr::ReadFile { var, path });
+            }
+            Stmt::WriteFile { filename, exprs, .. } => {
+                self.compile_expr(filename)?;
+                for expr in exprs {
+                    self.compile_expr(expr)?;
+                }
+                self.emit(Instr::WriteFile(exprs.len() as u32));
+            }
+            Stmt::Seek { filename, address, .. } => {
+                self.compile_expr(filename)?;
+                self.compile_expr(address)?;
+                self.emit(Instr::Seek);
+            }
+            Stmt::GetRecord { filename, variable, .. } => {
+                self.compile_expr(filename)?;
+                let var = self.resolve(variable);
+                self.emit(Instr::GetRecord(var));
+            }
+            Stmt::PutRecord { filename, variable, .. } => {
+                self.compile_expr(filename)?;
+                let var = self.resolve(variable);
+                self.emit(Instr::PutRecord(var));
+            }
+            Stmt::Error { .. } => {
+                return Err(self.error("Cannot compile a statement that failed to parse".to_string()));
+            }
+        }
+        Ok(())
+    }
+
+    fn compile_call(&mut self, routine: u32, args: &[Expr]) -> Result<(), Diagnostic> {
+        let expected = self.program.routines[routine as usize].params.len();
+        if args.len() != expected {
+            let (kind, name) = {
+                let routine = &self.program.routines[routine as usize];
+                let kind = if routine.return_type.is_some() { "Function" } else { "Procedure" };
+                (kind, routine.name.clone())
+            };
+            return Err(self.error(format!("{} {} expects {} arguments, got {}", kind, name, expected, args.len())));
+        }
+        let modes = self.program.routines[routine as usize].modes.clone();
+        for (param, (arg, mode)) in args.iter().zip(modes).enumerate() {
+            match mode {
+                PassingMode::BYVAL => self.compile_expr(arg)?,
+                PassingMode::BYREF => {
+                    let target = arg.to_lvalue().ok_or_else(|| {
+                        self.error("BYREF argument must be a variable, array element or record field".to_string())
+                    })?;
+                    self.compile_at(arg.span(), |compiler| {
+                        let (var, path) = compiler.compile_lvalue(&target)?;
+                        compiler.emit(Instr::ByRef { var, path, routine, param: param as u32 });
+                        Ok(())
+                    })?;
+                }
+            }
+        }
+        self.emit(Instr::Call { routine, argc: args.len() as u32 });
+        Ok(())
+    }
+
+    fn compile_expr(&mut self, expr: &Expr) -> Result<(), Diagnostic> {
+        self.compile_at(expr.span(), |compiler| compiler.compile_expr_kind(expr))
+    }
+
+    fn compile_expr_kind(&mut self, expr: &Expr) -> Result<(), Diagnostic> {
+        match expr {
+            Expr::Number(num, _) => {
+                let value = if num.contains('.') {
+                    Value::Real(num.parse().map_err(|_| self.error(format!("Invalid real number: {}", num)))?)
+                } else {
+                    Value::Integer(num.parse().map_err(|_| self.error(format!("Invalid integer number: {}", num)))?)
+                };
+                let id = self.constant(value);
+                self.emit(Instr::Const(id));
+            }
+            Expr::Date(date, _) => {
+                let id = self.constant(Value::Date(*date));
+                self.emit(Instr::Const(id));
+            }
+            Expr::String(s, _) => {
+                let id = self.constant(Value::String(s.clone()));
+                self.emit(Instr::Const(id));
+            }
+            Expr::Char(ch, _) => {
+                let id = self.constant(Value::Char(ch.chars().next().unwrap_or('\0')));
+                self.emit(Instr::Const(id));
+            }
+            Expr::Boolean(b, _) => {
+                let id = self.constant(Value::Boolean(*b));
+                self.emit(Instr::Const(id));
+            }
+            Expr::Variable(name, _) => match self.enum_literal(name) {
+                Some(value) => {
+                    let id = self.constant(value);
+                    self.emit(Instr::Const(id));
+                }
+                None => {
+                    let var = self.resolve(name);
+                    self.emit(Instr::Load(var));
+                }
+            },
+            Expr::BinaryOp(left, op, right, _) => {
+                self.compile_expr(left)?;
+                self.compile_expr(right)?;
+                self.emit(Instr::Binary(op.clone()));
+            }
+            Expr::UnaryOp(op, operand, _) => {
+                self.compile_expr(operand)?;
+                self.emit(Instr::Unary(op.clone()));
+            }
+            Expr::FunctionCall { name, args, .. } => {
+                if is_builtin(name) {
+                    for arg in args {
+                        self.compile_expr(arg)?;
+                    }
+                    let name = self.name(name);
+                    self.emit(Instr::CallBuiltin { name, argc: args.len() as u32 });
+                } else {
+                    let routine = *self.function_ids.get(name)
+                        .ok_or_else(|| self.error(format!("Function '{}' not found", name)))?;
+                    self.compile_call(routine, args)?;
+                }
+            }
+            Expr::ArrayAccess { array, indices, .. } => {
+                // A variable is indexed in its slot, anything else on the stack
+                let var = match array.as_ref() {
+                    Expr::Variable(name, _) => Some(self.resolve(name)),
+                    _ => {
+                        self.compile_expr(array)?;
+                        None
+                    }
+                };
+                for index in indices {
+                    self.compile_expr(index)?;
+                }
+                match var {
+                    Some(var) => self.emit(Instr::LoadIndex(var, indices.len() as u32)),
+                    None => {
+                        let name = self.name(&array.to_string());
+                        self.emit(Instr::Index { name, count: indices.len() as u32 })
+                    }
+                };
+            }
+            Expr::FieldAccess { object, field, .. } => {
+                self.compile_expr(object)?;
+                let field = self.name(field);
+                self.emit(Instr::Field(field));
+            }
+            Expr::PointerDeref { pointer, .. } => {
+                self.compile_expr(pointer)?;
+                self.emit(Instr::Deref);
+            }
+            Expr::Null(_) => {
+                let id = self.constant(Value::Pointer(None));
+                self.emit(Instr::Const(id));
+            }
+            Expr::New { type_name, .. } => {
+                let ty = self.type_id(type_name);
+                self.emit(Instr::New(ty));
+            }
+            Expr::PointerRef { target, .. } => {
+                match target.as_ref() {
+                    Expr::Variable(name, _) => {
+                        let var = self.resolve(name);
+                        self.emit(Instr::Ref(var));
+                    }
+                    _ => {
+                        return Err(self.error(format!("Pointer reference (^) can only be applied to variables, got {:?}", target)));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,494 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::fs;
+use std::path::Path;
+use std::rc::Rc;
+
+use serde_json::{json, Value as Json};
+
+use crate::ast::Stmt;
+use crate::checker::TypeChecker;
+use crate::debugger::{is_pausable, Debugger, Pause, Stepper, Stops};
+use crate::diagnostic::Diagnostic;
+use crate::files::NativeFileSystem;
+use crate::framing::{next_message, write_message};
+use crate::host::{Console, FileSystem, Host, InputSource, SystemClock, ThreadRandom};
+use crate::interpreter::Interpreter;
+use crate::limits::Limits;
+use crate::parser::Parser;
+use crate::value::{value_to_string, Value};
+
+/// The program runs on this, its only thread as far as the editor knows
+const THREAD_ID: u64 = 1;
+
+type DapInterpreter = Interpreter<DapConsole, LaunchInput, NativeFileSystem, ThreadRandom, SystemClock>;
+
+/// Serve the Debug Adapter Protocol on stdin and stdout until the editor
+/// disconnects. The program runs once `launch` and `configurationDone` have
+/// both arrived, pausing for requests whenever the stepper says so.
+/// `initialized` is only sent once the program has been launched, so the
+/// editor sets breakpoints knowing which statements and routines there are.
+///
+/// Requests are only read while the program is paused or not yet running, so
+/// `pause` cannot interrupt a running program; breakpoints can.
+pub fn serve(limits: &Limits) {
+    let out = Rc::new(RefCell::new(Outgoing::default()));
+    let session = Rc::new(RefCell::new(Session::new(out.clone())));
+    let mut configured = false;
+    let mut launch: Option<Launch> = None;
+
+    while let Some(request) = next_message() {
+        match session.borrow_mut().handle(&request, None) {
+            Flow::Launch(program) => launch = Some(program),
+            Flow::Configured => configured = true,
+            Flow::Quit => return,
+            Flow::Stay | Flow::Resume => {}
+        }
+        if configured {
+            if let Some(program) = launch.take() {
+                run(program, &session, &out, limits);
+            }
+        }
+    }
+}
+
+/// Run a launched program to the end, then tell the editor how it went
+fn run(launch: Launch, session: &Rc<RefCell<Session>>, out: &Rc<RefCell<Outgoing>>, limits: &Limits) {
+    let source_dir = Path::new(&launch.path).parent().map(|p| p.to_path_buf());
+    let host = Host {
+        console: DapConsole { out: out.clone() },
+        input: LaunchInput { lines: launch.input },
+        files: NativeFileSystem::new(source_dir),
+        random: ThreadRandom,
+        clock: SystemClock,
+    };
+    let mut interpreter = Interpreter::with_host(host);
+    interpreter.set_limits(limits.clone());
+    interpreter.set_debugger(Box::new(Adapter(session.clone())));
+
+    let mut exit_code = 0;
+    let result = interpreter.declare(&launch.statements)
+        .and_then(|_| launch.statements.iter().try_for_each(|stmt| interpreter.evaluate_stmt(stmt)));
+    for warning in interpreter.take_warnings() {
+        let output = warning.render(Some(&launch.source), &launch.path);
+        out.borrow_mut().event("output", json!({ "category": "stderr", "output": output }));
+    }
+    if let Err(e) = result {
+        let output = e.render(Some(&launch.source), &launch.path);
+        out.borrow_mut().event("output", json!({ "category": "stderr", "output": output }));
+        exit_code = 1;
+    }
+    interpreter.host_mut().files.close_all();
+    let mut out = out.borrow_mut();
+    out.event("exited", json!({ "exitCode": exit_code }));
+    out.event("terminated", json!({}));
+}
+
+/// Messages to the editor, numbered in the order they are sent
+#[derive(Default)]
+struct Outgoing {
+    seq: u64,
+}
+
+impl Outgoing {
+    fn send(&mut self, mut message: Json) {
+        self.seq += 1;
+        message["seq"] = json!(self.seq);
+        write_message(&message);
+    }
+
+    fn respond(&mut self, request: &Json, body: Json) {
+        self.send(json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+            "success": true,
+            "body": body,
+        }));
+    }
+
+    fn fail(&mut self, request: &Json, message: &str) {
+        self.send(json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+            "success": false,
+            "message": message,
+        }));
+    }
+
+    fn event(&mut self, event: &str, body: Json) {
+        self.send(json!({ "type": "event", "event": event, "body": body }));
+    }
+}
+
+/// OUTPUT becomes `output` events, since stdout carries the protocol
+struct DapConsole {
+    out: Rc<RefCell<Outgoing>>,
+}
+
+impl Console for DapConsole {
+    fn write(&mut self, text: &str) {
+        self.out.borrow_mut().event("output", json!({ "category": "stdout", "output": text }));
+    }
+}
+
+/// INPUT reads the lines listed under `input` in the launch configuration,
+/// since stdin carries the protocol
+struct LaunchInput {
+    lines: VecDeque<String>,
+}
+
+impl InputSource for LaunchInput {
+    fn read_line(&mut self) -> Result<String, String> {
+        self.lines.pop_front()
+            .ok_or_else(|| "No input left: list the lines INPUT reads under \"input\" in the launch configuration".to_string())
+    }
+}
+
+/// A program checked and ready to run
+struct Launch {
+    path: String,
+    source: String,
+    statements: Vec<Stmt>,
+    input: VecDeque<String>,
+}
+
+/// What a request asks of the program
+enum Flow {
+    /// Stay paused, or carry on waiting for the program to start
+    Stay,
+    /// Run on from the pause
+    Resume,
+    Launch(Launch),
+    Configured,
+    Quit,
+}
+
+/// What a `variablesReference` points at. References only last until the
+/// program resumes.
+enum Handle {
+    /// Variables of the call this many calls deep
+    Locals(usize),
+    Globals,
+    /// Elements or fields of an array, record or set
+    Children(Value),
+}
+
+/// Breakpoints, and the state of the pause the editor is looking at
+struct Session {
+    out: Rc<RefCell<Outgoing>>,
+    stepper: Stepper,
+    /// Where the launched program's breakpoints can go
+    stops: Stops,
+    path: String,
+    handles: Vec<Handle>,
+}
+
+impl Session {
+    fn new(out: Rc<RefCell<Outgoing>>) -> Self {
+        Self {
+            out,
+            stepper: Stepper::new(false),
+            stops: Stops::default(),
+            path: String::new(),
+            handles: Vec::new(),
+        }
+    }
+
+    /// Answer `request`. `interpreter` is the paused program, `None` before it starts.
+    fn handle(&mut self, request: &Json, interpreter: Option<&mut DapInterpreter>) -> Flow {
+        let args = &request["arguments"];
+        let command = request["command"].as_str().unwrap_or_default();
+        let mut flow = Flow::Stay;
+        let body = match (command, interpreter) {
+            ("initialize", _) => {
+                self.out.borrow_mut().respond(request, json!({
+                    "supportsConfigurationDoneRequest": true,
+                    "supportsFunctionBreakpoints": true,
+                    "supportsEvaluateForHovers": true,
+                }));
+                return flow;
+            }
+            ("launch", _) => match self.launch(args) {
+                Ok(launch) => {
+                    flow = Flow::Launch(launch);
+                    self.out.borrow_mut().respond(request, json!({}));
+                    self.out.borrow_mut().event("initialized", json!({}));
+                    return flow;
+                }
+                Err(message) => Err(message),
+            },
+            ("setBreakpoints", _) => {
+                self.stepper.clear_lines();
+                let lines: Vec<u64> = args["breakpoints"].as_array().into_iter().flatten()
+                    .filter_map(|breakpoint| breakpoint["line"].as_u64())
+                    .collect();
+                // A breakpoint between statements moves to the next one
+                let breakpoints: Vec<Json> = lines.iter()
+                    .map(|&line| match self.stops.line_from(line as usize) {
+                        Some(stop) => {
+                            self.stepper.add_line(stop);
+                            json!({ "verified": true, "line": stop })
+                        }
+                        None => json!({ "verified": false, "line": line, "message": "No statement on or after this line" }),
+                    })
+                    .collect();
+                Ok(json!({ "breakpoints": breakpoints }))
+            }
+            ("setFunctionBreakpoints", _) => {
+                self.stepper.clear_routines();
+                let names: Vec<&str> = args["breakpoints"].as_array().into_iter().flatten()
+                    .filter_map(|breakpoint| breakpoint["name"].as_str())
+                    .collect();
+                let breakpoints: Vec<Json> = names.iter()
+                    .map(|name| match self.stops.has_routine(name) {
+                        true => {
+                            self.stepper.add_routine(name);
+                            json!({ "verified": true })
+                        }
+                        false => json!({ "verified": false, "message": format!("No procedure or function named '{}'", name) }),
+                    })
+                    .collect();
+                Ok(json!({ "breakpoints": breakpoints }))
+            }
+            ("setExceptionBreakpoints", _) => Ok(json!({})),
+            ("configurationDone", _) => {
+                flow = Flow::Configured;
+                Ok(json!({}))
+            }
+            ("threads", _) => Ok(json!({ "threads": [{ "id": THREAD_ID, "name": "main" }] })),
+            ("stackTrace", Some(interpreter)) => Ok(self.stack_trace(interpreter)),
+            ("scopes", Some(_)) => Ok(self.scopes(args["frameId"].as_u64().unwrap_or(0) as usize)),
+            ("variables", Some(interpreter)) => self.variables(args["variablesReference"].as_u64().unwrap_or(0) as usize, interpreter),
+            ("evaluate", Some(interpreter)) => self.evaluate(args, interpreter),
+            ("continue" | "next" | "stepIn" | "stepOut", Some(_)) => {
+                match command {
+                    "continue" => self.stepper.resume(),
+                    "next" => self.stepper.step_over(),
+                    "stepIn" => self.stepper.step_in(),
+                    _ => self.stepper.step_out(),
+                }
+                flow = Flow::Resume;
+                Ok(json!({ "allThreadsContinued": true }))
+            }
+            ("stackTrace" | "scopes" | "variables" | "evaluate" | "continue" | "next" | "stepIn" | "stepOut", None) => {
+                Err("The program is not paused".to_string())
+            }
+            ("pause", _) => Ok(json!({})),
+            ("disconnect" | "terminate", _) => {
+                flow = Flow::Quit;
+                Ok(json!({}))
+            }
+            _ => Err(format!("Unsupported request '{}'", command)),
+        };
+        match body {
+            Ok(body) => self.out.borrow_mut().respond(request, body),
+            Err(message) => self.out.borrow_mut().fail(request, &message),
+        }
+        flow
+    }
+
+    /// Parse and type-check the program named in the launch configuration
+    fn launch(&mut self, args: &Json) -> Result<Launch, String> {
+        let path = args["program"].as_str()
+            .ok_or("The launch configuration needs a \"program\" to debug")?
+            .to_string();
+        if !path.ends_with(".pseu") {
+            return Err(format!("File '{}' must have a .pseu extension", path));
+        }
+        let source = fs::read_to_string(&path)
+            .map_err(|e| format!("Failed to read file '{}': {}", path, e))?;
+        let render = |errors: &[Diagnostic]| -> String {
+            errors.iter().map(|e| e.render(Some(&source), &path)).collect::<Vec<_>>().join("\n")
+        };
+        let statements = Parser::new(&source).parse_program()
+            .map_err(|e| render(&[e]))?;
+        // Warnings are left to the editor's language server
+        let errors: Vec<Diagnostic> = TypeChecker::new().check(&statements)
+            .into_iter()
+            .filter(Diagnostic::is_error)
+            .collect();
+        if !errors.is_empty() {
+            return Err(render(&errors));
+        }
+        if args["stopOnEntry"].as_bool().unwrap_or(false) {
+            self.stepper.step_in();
+        }
+        let input = args["input"].as_array().into_iter().flatten()
+            .map(|line| line.as_str().map_or_else(|| line.to_string(), str::to_string))
+            .collect();
+        self.path = path.clone();
+        self.stops = Stops::new(&statements);
+        Ok(Launch { path, source, statements, input })
+    }
+
+    /// Frames innermost first. A frame's id is its depth plus one, so none is 0.
+    fn stack_trace(&self, interpreter: &DapInterpreter) -> Json {
+        let name = Path::new(&self.path).file_name().map_or(self.path.clone(), |name| name.to_string_lossy().into_owned());
+        let source = json!({ "name": name, "path": self.path });
+        let calls = interpreter.call_stack();
+        let frames: Vec<Json> = self.stepper.positions().iter().enumerate().rev()
+            .map(|(depth, line)| {
+                let name = match depth {
+                    0 => "main program",
+                    _ => calls.get(depth - 1).map_or("?", |call| call.split('(').next().unwrap_or(call)),
+                };
+                json!({ "id": depth + 1, "name": name, "line": line, "column": 1, "source": source })
+            })
+            .collect();
+        json!({ "stackFrames": frames, "totalFrames": frames.len() })
+    }
+
+    fn scopes(&mut self, frame_id: usize) -> Json {
+        let mut scopes = Vec::new();
+        let depth = frame_id.saturating_sub(1);
+        if depth > 0 {
+            let reference = self.handle_for(Handle::Locals(depth));
+            scopes.push(json!({ "name": "Locals", "variablesReference": reference, "expensive": false }));
+        }
+        let reference = self.handle_for(Handle::Globals);
+        scopes.push(json!({ "name": "Globals", "variablesReference": reference, "expensive": false }));
+        json!({ "scopes": scopes })
+    }
+
+    fn variables(&mut self, reference: usize, interpreter: &DapInterpreter) -> Result<Json, String> {
+        let variables = match reference.checked_sub(1).and_then(|index| self.handles.get(index)) {
+            Some(Handle::Locals(depth)) => interpreter.locals_at(*depth),
+            Some(Handle::Globals) => interpreter.globals(),
+            Some(Handle::Children(value)) => children(value),
+            None => return Err(format!("No variables with reference {}", reference)),
+        };
+        let variables: Vec<Json> = variables.into_iter()
+            .map(|(name, value)| {
+                let display = describe(&value);
+                let reference = self.reference_to(value);
+                json!({ "name": name, "value": display, "variablesReference": reference })
+            })
+            .collect();
+        Ok(json!({ "variables": variables }))
+    }
+
+    /// Expressions are evaluated in the innermost call, the only one whose
+    /// variables are in reach
+    fn evaluate(&mut self, args: &Json, interpreter: &mut DapInterpreter) -> Result<Json, String> {
+        let innermost = self.stepper.positions().len();
+        if args["frameId"].as_u64().is_some_and(|frame| frame as usize != innermost) {
+            return Err("Expressions can only be evaluated in the innermost frame".to_string());
+        }
+        let text = args["expression"].as_str().unwrap_or_default();
+        let expr = Parser::new(text).parse_lone_expression().map_err(|e| e.message)?;
+        let value = interpreter.evaluate(&expr).map_err(|e| e.message)?;
+        let result = describe(&value);
+        let reference = self.reference_to(value);
+        Ok(json!({ "result": result, "variablesReference": reference }))
+    }
+
+    fn handle_for(&mut self, handle: Handle) -> usize {
+        self.handles.push(handle);
+        self.handles.len()
+    }
+
+    /// A reference to expand `value` with, 0 if it has nothing inside
+    fn reference_to(&mut self, value: Value) -> usize {
+        let expandable = match &value {
+            Value::Array { data, .. } => !data.is_empty(),
+            Value::Set { elements, .. } => !elements.is_empty(),
+            Value::Record { .. } => true,
+            _ => false,
+        };
+        if expandable { self.handle_for(Handle::Children(value)) } else { 0 }
+    }
+}
+
+/// Shown for a value in the variables view
+fn describe(value: &Value) -> String {
+    match value {
+        Value::String(s) => format!("\"{}\"", s),
+        Value::Char(c) => format!("'{}'", c),
+        Value::Record { type_name, .. } => type_name.clone(),
+        Value::Array { dimensions, start_indices, .. } => {
+            let bounds: Vec<String> = dimensions.iter().zip(start_indices)
+                .map(|(size, start)| format!("{}:{}", start, start + *size as i64 - 1))
+                .collect();
+            format!("ARRAY[{}]", bounds.join(", "))
+        }
+        _ => value_to_string(value),
+    }
+}
+
+/// What a value expands to: array elements by index, with a multi-dimensional
+/// array split into rows; record fields by name; set elements in order
+fn children(value: &Value) -> Vec<(String, Value)> {
+    match value {
+        Value::Array { element_type, dimensions, start_indices, data } => {
+            let row_size: usize = dimensions[1..].iter().product();
+            data.chunks(row_size.max(1)).enumerate()
+                .map(|(i, row)| {
+                    let name = format!("[{}]", start_indices[0] + i as i64);
+                    let value = match dimensions.len() {
+                        1 => row[0].clone(),
+                        _ => Value::Array {
+                            element_type: element_type.clone(),
+                            dimensions: dimensions[1..].to_vec(),
+                            start_indices: start_indices[1..].to_vec(),
+                            data: row.to_vec(),
+                        },
+                    };
+                    (name, value)
+                })
+                .collect()
+        }
+        Value::Record { fields, .. } => {
+            let mut fields: Vec<(String, Value)> = fields.iter().map(|(name, value)| (name.clone(), value.clone())).collect();
+            fields.sort_by(|a, b| a.0.cmp(&b.0));
+            fields
+        }
+        Value::Set { elements, .. } => elements.iter().enumerate()
+            .map(|(i, element)| (format!("[{}]", i + 1), element.clone()))
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Installed in the interpreter, pausing it for the session
+struct Adapter(Rc<RefCell<Session>>);
+
+impl Debugger<DapConsole, LaunchInput, NativeFileSystem, ThreadRandom, SystemClock> for Adapter {
+    fn before_statement(&mut self, interpreter: &mut DapInterpreter, stmt: &Stmt) -> Result<(), Diagnostic> {
+        if !is_pausable(stmt) {
+            return Ok(());
+        }
+        let mut session = self.0.borrow_mut();
+        let depth = interpreter.call_stack().len();
+        let Some(pause) = session.stepper.arrive(stmt.span().line, depth, interpreter.current_call()) else {
+            return Ok(());
+        };
+        let reason = match pause {
+            Pause::Entry => "entry",
+            Pause::Step => "step",
+            Pause::Line(_) => "breakpoint",
+            Pause::Routine(_) => "function breakpoint",
+        };
+        session.out.borrow_mut().event("stopped", json!({ "reason": reason, "threadId": THREAD_ID, "allThreadsStopped": true }));
+
+        loop {
+            let flow = match next_message() {
+                Some(request) => session.handle(&request, Some(interpreter)),
+                None => Flow::Quit,
+            };
+            match flow {
+                Flow::Resume => {
+                    session.handles.clear();
+                    return Ok(());
+                }
+                Flow::Quit => {
+                    // Flush files the program was writing before leaving
+                    interpreter.host_mut().files.close_all();
+                    std::process::exit(0);
+                }
+                _ => {}
+            }
+        }
+    }
+}
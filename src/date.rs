@@ -0,0 +1,204 @@
+use std::fmt;
+
+use serde::{Serialize, Deserialize};
+
+/// A calendar date. Fields are ordered so the derived ordering is chronological.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct Date {
+    year: i32,
+    month: u32,
+    day: u32,
+}
+
+fn is_leap_year(year: i32) -> bool {
+    (year % 4 == 0 && year % 100 != 0) || year % 400 == 0
+}
+
+fn days_in_month(month: u32, year: i32) -> u32 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+impl Date {
+    /// The date `day`/`month`/`year`, if it exists. Years run from 1 to 9999.
+    pub fn new(day: i64, month: i64, year: i64) -> Result<Date, String> {
+        if !(1..=9999).contains(&year) {
+            return Err(format!("Invalid date: year {} is not between 1 and 9999", year));
+        }
+        if !(1..=12).contains(&month) {
+            return Err(format!("Invalid date: month {} is not between 1 and 12", month));
+        }
+        let (year, month) = (year as i32, month as u32);
+        let max_day = days_in_month(month, year);
+        if day < 1 || day > max_day as i64 {
+            return Err(format!("Invalid date: month {} of {} has no day {}", month, year, day));
+        }
+        Ok(Date { year, month, day: day as u32 })
+    }
+
+    /// Parse a date written dd/mm/yyyy, as in date literals and input
+    pub fn parse(text: &str) -> Result<Date, String> {
+        let parts: Vec<&str> = text.trim().split('/').collect();
+        let numbers: Option<Vec<i64>> = match parts.as_slice() {
+            [day, month, year] if year.len() == 4 => [day, month, year].iter()
+                .map(|part| part.parse().ok())
+                .collect(),
+            _ => None,
+        };
+        match numbers.as_deref() {
+            Some([day, month, year]) => Date::new(*day, *month, *year),
+            _ => Err(format!("Invalid date: '{}' (expected dd/mm/yyyy)", text.trim())),
+        }
+    }
+
+    pub fn day(&self) -> u32 {
+        self.day
+    }
+
+    pub fn month(&self) -> u32 {
+        self.month
+    }
+
+    pub fn year(&self) -> i32 {
+        self.year
+    }
+
+    /// Days since 01/01/1970, negative before it
+    pub fn day_number(&self) -> i64 {
+        // Count from March so the leap day falls at the end of the year
+        let year = self.year as i64 - if self.month <= 2 { 1 } else { 0 };
+        let era = year.div_euclid(400);
+        let year_of_era = year.rem_euclid(400);
+        let month_from_march = (self.month as i64 + 9) % 12;
+        let day_of_year = (153 * month_from_march + 2) / 5 + self.day as i64 - 1;
+        let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+        era * 146097 + day_of_era - 719468
+    }
+
+    /// The date `days` days after 01/01/1970
+    pub fn from_day_number(days: i64) -> Result<Date, String> {
+        // 01/01/0001 and 31/12/9999 are within 3 million days of 1970, and this
+        // keeps the arithmetic below from overflowing
+        if days.abs() > 3_000_000 {
+            return Err("Date out of range: years run from 1 to 9999".to_string());
+        }
+        let days = days + 719468;
+        let era = days.div_euclid(146097);
+        let day_of_era = days.rem_euclid(146097);
+        let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let month_from_march = (5 * day_of_year + 2) / 153;
+        let day = day_of_year - (153 * month_from_march + 2) / 5 + 1;
+        let month = if month_from_march < 10 { month_from_march + 3 } else { month_from_march - 9 };
+        let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+        if !(1..=9999).contains(&year) {
+            return Err("Date out of range: years run from 1 to 9999".to_string());
+        }
+        Date::new(day, month, year)
+    }
+
+    /// Day of the week, 1 for Sunday through 7 for Saturday
+    pub fn day_index(&self) -> u32 {
+        // 01/01/1970 was a Thursday
+        ((self.day_number() + 4).rem_euclid(7) + 1) as u32
+    }
+
+    pub fn add_days(&self, days: i64) -> Result<Date, String> {
+        Date::from_day_number(self.day_number().saturating_add(days))
+    }
+
+    /// Number of days from `earlier` to this date
+    pub fn days_since(&self, earlier: &Date) -> i64 {
+        self.day_number() - earlier.day_number()
+    }
+}
+
+/// DATE variables start out as 01/01/1970
+impl Default for Date {
+    fn default() -> Self {
+        Date { year: 1970, month: 1, day: 1 }
+    }
+}
+
+impl fmt::Display for Date {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:02}/{:02}/{:04}", self.day, self.month, self.year)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(text: &str) -> Date {
+        Date::parse(text).unwrap()
+    }
+
+    #[test]
+    fn leap_years() {
+        assert!(Date::new(29, 2, 2024).is_ok());
+        assert!(Date::new(29, 2, 2000).is_ok());
+        // Centuries are leap years only every 400 years
+        assert!(Date::new(29, 2, 1900).is_err());
+        assert!(Date::new(29, 2, 2023).is_err());
+    }
+
+    #[test]
+    fn month_ends() {
+        for (month, last) in [(1, 31), (2, 28), (3, 31), (4, 30), (5, 31), (6, 30),
+                              (7, 31), (8, 31), (9, 30), (10, 31), (11, 30), (12, 31)] {
+            assert!(Date::new(last, month, 2023).is_ok(), "{}/{} exists", last, month);
+            assert!(Date::new(last + 1, month, 2023).is_err(), "{}/{} does not exist", last + 1, month);
+        }
+        assert!(Date::new(0, 1, 2023).is_err());
+        assert!(Date::new(1, 13, 2023).is_err());
+    }
+
+    #[test]
+    fn adding_days_crosses_month_and_year_ends() {
+        assert_eq!(date("28/02/2024").add_days(1), Ok(date("29/02/2024")));
+        assert_eq!(date("28/02/2023").add_days(1), Ok(date("01/03/2023")));
+        assert_eq!(date("29/02/2024").add_days(365), Ok(date("28/02/2025")));
+        assert_eq!(date("31/12/1999").add_days(1), Ok(date("01/01/2000")));
+        assert_eq!(date("01/03/2000").add_days(-1), Ok(date("29/02/2000")));
+        assert_eq!(date("01/01/1970").add_days(-1), Ok(date("31/12/1969")));
+    }
+
+    #[test]
+    fn day_numbers_round_trip() {
+        assert_eq!(Date::default().day_number(), 0);
+        for text in ["01/01/0001", "28/02/1900", "01/03/1900", "29/02/2000", "31/12/9999"] {
+            let day = date(text);
+            assert_eq!(Date::from_day_number(day.day_number()), Ok(day));
+        }
+        assert_eq!(date("01/01/2025").days_since(&date("01/01/2024")), 366);
+    }
+
+    #[test]
+    fn years_outside_1_to_9999_are_rejected() {
+        assert!(date("31/12/9999").add_days(1).is_err());
+        assert!(date("01/01/0001").add_days(-1).is_err());
+        assert!(Date::from_day_number(i64::MAX).is_err());
+        assert!(Date::new(1, 1, 0).is_err());
+    }
+
+    #[test]
+    fn days_of_the_week() {
+        // A Thursday, a Sunday and a Saturday
+        assert_eq!(date("01/01/1970").day_index(), 5);
+        assert_eq!(date("03/03/2024").day_index(), 1);
+        assert_eq!(date("29/02/2020").day_index(), 7);
+    }
+
+    #[test]
+    fn parsing_and_display() {
+        assert_eq!(date(" 5/3/2024 ").to_string(), "05/03/2024");
+        assert!(Date::parse("05/03/24").is_err());
+        assert!(Date::parse("05-03-2024").is_err());
+        assert!(Date::parse("31/04/2024").is_err());
+    }
+}
@@ -0,0 +1,246 @@
+use std::io::{self, Write};
+
+use crate::ast::{Expr, Stmt};
+use crate::debugger::{is_pausable, Debugger, Pause, Stepper, Stops};
+use crate::diagnostic::Diagnostic;
+use crate::host::{Clock, Console, FileSystem, InputSource, RandomSource};
+use crate::interpreter::Interpreter;
+use crate::parser::Parser;
+use crate::value::value_to_string;
+
+const HELP: &str = "\
+Commands:
+  break <line>, b        Stop before the statement on a line
+  break <name>           Stop on entry to a procedure or function
+  delete <line|name>, d  Remove a breakpoint
+  info, i                List breakpoints and watch expressions
+  continue, c            Run to the next breakpoint
+  step, s                Run one statement, stopping inside any call it makes
+  next, n                Run one statement, including any calls it makes
+  finish, f              Run until the current procedure or function returns
+  print <expr>, p        Show the value of an expression here
+  watch <expr>, w        Show an expression every time the program stops
+  unwatch <n>            Stop showing watch expression n
+  backtrace, bt          Show the calls in progress
+  locals                 Show the variables of the current call
+  globals                Show the variables of the main program
+  list, l                Show the source around the current line
+  quit, q                Stop the program and leave the debugger
+An empty line repeats the last command.";
+
+/// The `pseudocode debug` command line: pauses the interpreter, then reads
+/// commands from standard input until told to carry on.
+pub struct CliDebugger {
+    stepper: Stepper,
+    /// Where breakpoints can go
+    stops: Stops,
+    source: Vec<String>,
+    watches: Vec<(String, Expr)>,
+    last_command: String,
+}
+
+impl CliDebugger {
+    /// Debug the program with `statements`, parsed from `source`
+    pub fn new(source: &str, statements: &[Stmt]) -> Self {
+        Self {
+            stepper: Stepper::new(true),
+            stops: Stops::new(statements),
+            source: source.lines().map(str::to_string).collect(),
+            watches: Vec::new(),
+            last_command: String::new(),
+        }
+    }
+
+    fn show_line(&self, line: usize, marker: &str) {
+        if let Some(text) = line.checked_sub(1).and_then(|index| self.source.get(index)) {
+            println!("{} {:>4} | {}", marker, line, text);
+        }
+    }
+
+    fn show_pause(&self, pause: &Pause, line: usize) {
+        match pause {
+            Pause::Entry => println!("Stopped at line {}, before the program starts", line),
+            Pause::Step => println!("Stopped at line {}", line),
+            Pause::Line(_) => println!("Stopped at line {} (breakpoint)", line),
+            Pause::Routine(name) => println!("Stopped at line {} (entering {})", line, name),
+        }
+        self.show_line(line, ">");
+    }
+
+    fn show_watches<C, I, F, R, K>(&self, interpreter: &mut Interpreter<C, I, F, R, K>)
+    where C: Console, I: InputSource, F: FileSystem, R: RandomSource, K: Clock {
+        for (n, (text, expr)) in self.watches.iter().enumerate() {
+            println!("  watch {}: {} = {}", n + 1, text, show_value(interpreter.evaluate(expr)));
+        }
+    }
+
+    fn show_frames<C, I, F, R, K>(&self, interpreter: &Interpreter<C, I, F, R, K>)
+    where C: Console, I: InputSource, F: FileSystem, R: RandomSource, K: Clock {
+        let positions = self.stepper.positions();
+        let calls = interpreter.call_stack();
+        for (depth, line) in positions.iter().enumerate().rev() {
+            let name = match depth {
+                0 => "main program",
+                // Call stack entries hold the arguments too: keep the name
+                _ => calls.get(depth - 1).map_or("?", |call| call.split('(').next().unwrap_or(call)),
+            };
+            println!("#{} {} at line {}", positions.len() - 1 - depth, name, line);
+        }
+    }
+
+    fn show_breakpoints(&self) {
+        let lines: Vec<String> = self.stepper.lines().map(|line| line.to_string()).collect();
+        let routines: Vec<&str> = self.stepper.routines().collect();
+        if lines.is_empty() && routines.is_empty() {
+            println!("No breakpoints");
+        }
+        if !lines.is_empty() {
+            println!("Breakpoints on lines: {}", lines.join(", "));
+        }
+        if !routines.is_empty() {
+            println!("Breakpoints on entry to: {}", routines.join(", "));
+        }
+        for (n, (text, _)) in self.watches.iter().enumerate() {
+            println!("Watch {}: {}", n + 1, text);
+        }
+    }
+
+    fn list(&self, line: usize) {
+        let first = line.saturating_sub(3).max(1);
+        for number in first..=line + 3 {
+            self.show_line(number, if number == line { ">" } else { " " });
+        }
+    }
+
+    /// Set or remove a breakpoint on a line number or a routine name. A
+    /// breakpoint between statements moves to the next one.
+    fn breakpoint(&mut self, target: &str, set: bool) {
+        match (target.parse::<usize>(), set) {
+            _ if target.is_empty() => println!("Give a line number or a procedure or function name"),
+            (Ok(line), true) => match self.stops.line_from(line) {
+                Some(stop) => {
+                    self.stepper.add_line(stop);
+                    if stop != line {
+                        println!("No statement starts on line {}", line);
+                    }
+                    println!("Breakpoint on line {}", stop);
+                }
+                None => println!("No statement on or after line {}, so no breakpoint was set", line),
+            },
+            (Ok(line), false) if self.stepper.remove_line(line) => println!("Removed the breakpoint on line {}", line),
+            (Err(_), true) if self.stops.has_routine(target) => {
+                self.stepper.add_routine(target);
+                println!("Breakpoint on entry to {}", target);
+            }
+            (Err(_), true) => println!("No procedure or function named {}, so no breakpoint was set", target),
+            (Err(_), false) if self.stepper.remove_routine(target) => println!("Removed the breakpoint on {}", target),
+            (_, false) => println!("No breakpoint on {}", target),
+        }
+    }
+}
+
+fn show_value(value: Result<crate::value::Value, Diagnostic>) -> String {
+    match value {
+        Ok(value) => value_to_string(&value),
+        Err(e) => format!("<error: {}>", e.message),
+    }
+}
+
+fn parse_expression(text: &str) -> Result<Expr, Diagnostic> {
+    Parser::new(text).parse_lone_expression()
+}
+
+fn show_variables(variables: Vec<(String, crate::value::Value)>, empty: &str) {
+    if variables.is_empty() {
+        println!("{}", empty);
+    }
+    for (name, value) in variables {
+        println!("  {} = {}", name, value_to_string(&value));
+    }
+}
+
+impl<C, I, F, R, K> Debugger<C, I, F, R, K> for CliDebugger
+where C: Console, I: InputSource, F: FileSystem, R: RandomSource, K: Clock {
+    fn before_statement(&mut self, interpreter: &mut Interpreter<C, I, F, R, K>, stmt: &Stmt) -> Result<(), Diagnostic> {
+        if !is_pausable(stmt) {
+            return Ok(());
+        }
+        let line = stmt.span().line;
+        let depth = interpreter.call_stack().len();
+        let Some(pause) = self.stepper.arrive(line, depth, interpreter.current_call()) else {
+            return Ok(());
+        };
+        self.show_pause(&pause, line);
+        self.show_watches(interpreter);
+
+        loop {
+            print!("(debug) ");
+            let _ = io::stdout().flush();
+            let mut input = String::new();
+            if io::stdin().read_line(&mut input).unwrap_or(0) == 0 {
+                // End of input: nobody is left to give commands
+                input = "quit".to_string();
+            }
+            let mut input = input.trim().to_string();
+            if input.is_empty() {
+                input = self.last_command.clone();
+            }
+            self.last_command = input.clone();
+            let (command, argument) = input.split_once(' ').unwrap_or((input.as_str(), ""));
+            let argument = argument.trim();
+
+            match command {
+                "" => {}
+                "break" | "b" => self.breakpoint(argument, true),
+                "delete" | "d" => self.breakpoint(argument, false),
+                "info" | "i" => self.show_breakpoints(),
+                "continue" | "c" => {
+                    self.stepper.resume();
+                    return Ok(());
+                }
+                "step" | "s" => {
+                    self.stepper.step_in();
+                    return Ok(());
+                }
+                "next" | "n" => {
+                    self.stepper.step_over();
+                    return Ok(());
+                }
+                "finish" | "f" => {
+                    self.stepper.step_out();
+                    return Ok(());
+                }
+                "print" | "p" => match parse_expression(argument) {
+                    Ok(expr) => println!("{}", show_value(interpreter.evaluate(&expr))),
+                    Err(e) => println!("{}", e.message),
+                },
+                "watch" | "w" => match parse_expression(argument) {
+                    Ok(expr) => {
+                        self.watches.push((argument.to_string(), expr));
+                        println!("Watch {}: {}", self.watches.len(), argument);
+                    }
+                    Err(e) => println!("{}", e.message),
+                },
+                "unwatch" => match argument.parse::<usize>() {
+                    Ok(n) if (1..=self.watches.len()).contains(&n) => {
+                        let (text, _) = self.watches.remove(n - 1);
+                        println!("Removed watch {}: {}", n, text);
+                    }
+                    _ => println!("No watch expression {}", argument),
+                },
+                "backtrace" | "bt" => self.show_frames(interpreter),
+                "locals" => show_variables(interpreter.locals(), "No local variables"),
+                "globals" => show_variables(interpreter.globals(), "No global variables"),
+                "list" | "l" => self.list(line),
+                "help" | "h" => println!("{}", HELP),
+                "quit" | "q" => {
+                    // Flush files the program was writing before leaving
+                    interpreter.host_mut().files.close_all();
+                    println!("Stopped.");
+                    std::process::exit(0);
+                }
+                _ => println!("Unknown command '{}'. Type 'help' for the commands.", command),
+            }
+        }
+    }
+}
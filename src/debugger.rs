@@ -0,0 +1,214 @@
+use std::collections::BTreeSet;
+
+use crate::ast::{declarations, for_each_block, Stmt};
+use crate::diagnostic::Diagnostic;
+use crate::interpreter::Interpreter;
+
+/// Pauses a program between statements. Installed with `Interpreter::set_debugger`.
+pub trait Debugger<C, I, F, R, K> {
+    /// Called before every statement runs, including statements nested in
+    /// IF, WHILE and FOR bodies and in procedures and functions. The program
+    /// stops with the error if this returns one.
+    fn before_statement(&mut self, interpreter: &mut Interpreter<C, I, F, R, K>, stmt: &Stmt) -> Result<(), Diagnostic>;
+}
+
+/// Why the program paused
+#[derive(Debug, Clone, PartialEq)]
+pub enum Pause {
+    /// Before the first statement, so breakpoints can be set
+    Entry,
+    /// A step finished
+    Step,
+    /// A breakpoint on this line
+    Line(usize),
+    /// A breakpoint on entry to this procedure or function
+    Routine(String),
+}
+
+/// How to carry on from a pause
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Resume {
+    /// Run to the next breakpoint
+    Continue,
+    /// Stop at the next statement, wherever it is
+    StepIn,
+    /// Stop at the next statement not inside a call made from this depth
+    StepOver(usize),
+    /// Stop at the next statement after the call at this depth returns
+    StepOut(usize),
+}
+
+/// Breakpoints and stepping, shared by the debugger front ends.
+///
+/// Depth counts the calls in progress: 0 in the main program, 1 inside a
+/// procedure it called, and so on.
+#[derive(Debug)]
+pub struct Stepper {
+    lines: BTreeSet<usize>,
+    routines: BTreeSet<String>,
+    resume: Resume,
+    /// The line each call in progress has reached, the main program's first
+    positions: Vec<usize>,
+    /// Which call each of `positions` belongs to, 0 for the main program
+    calls: Vec<u64>,
+    started: bool,
+}
+
+impl Stepper {
+    /// With `stop_on_entry`, the program pauses before its first statement
+    pub fn new(stop_on_entry: bool) -> Self {
+        Self {
+            lines: BTreeSet::new(),
+            routines: BTreeSet::new(),
+            resume: if stop_on_entry { Resume::StepIn } else { Resume::Continue },
+            positions: Vec::new(),
+            calls: Vec::new(),
+            started: false,
+        }
+    }
+
+    pub fn add_line(&mut self, line: usize) {
+        self.lines.insert(line);
+    }
+
+    /// Returns whether there was a breakpoint on the line
+    pub fn remove_line(&mut self, line: usize) -> bool {
+        self.lines.remove(&line)
+    }
+
+    pub fn add_routine(&mut self, name: &str) {
+        self.routines.insert(name.to_string());
+    }
+
+    /// Returns whether there was a breakpoint on the routine
+    pub fn remove_routine(&mut self, name: &str) -> bool {
+        self.routines.remove(name)
+    }
+
+    pub fn clear_lines(&mut self) {
+        self.lines.clear();
+    }
+
+    pub fn clear_routines(&mut self) {
+        self.routines.clear();
+    }
+
+    pub fn lines(&self) -> impl Iterator<Item = usize> + '_ {
+        self.lines.iter().copied()
+    }
+
+    pub fn routines(&self) -> impl Iterator<Item = &str> {
+        self.routines.iter().map(String::as_str)
+    }
+
+    /// The line each call in progress has reached, the main program's first
+    pub fn positions(&self) -> &[usize] {
+        &self.positions
+    }
+
+    pub fn resume(&mut self) {
+        self.resume = Resume::Continue;
+    }
+
+    pub fn step_in(&mut self) {
+        self.resume = Resume::StepIn;
+    }
+
+    pub fn step_over(&mut self) {
+        self.resume = Resume::StepOver(self.depth());
+    }
+
+    /// Run until the current call returns. In the main program this is the
+    /// same as continuing.
+    pub fn step_out(&mut self) {
+        self.resume = match self.depth() {
+            0 => Resume::Continue,
+            depth => Resume::StepOut(depth),
+        };
+    }
+
+    fn depth(&self) -> usize {
+        self.positions.len().saturating_sub(1)
+    }
+
+    /// Note that a statement on `line` is about to run at `depth`, and decide
+    /// whether to pause there. `call` names the procedure or function the
+    /// statement is in, with a number no other call shares.
+    pub fn arrive(&mut self, line: usize, depth: usize, call: Option<(&str, u64)>) -> Option<Pause> {
+        let entry = !self.started;
+        self.started = true;
+        let id = call.map_or(0, |(_, id)| id);
+        let entered = self.calls.get(depth) != Some(&id);
+        self.positions.truncate(depth + 1);
+        self.positions.resize(depth + 1, line);
+        self.positions[depth] = line;
+        self.calls.truncate(depth + 1);
+        self.calls.resize(depth + 1, id);
+        self.calls[depth] = id;
+
+        let stepped = match self.resume {
+            Resume::Continue => false,
+            Resume::StepIn => true,
+            Resume::StepOver(from) => depth <= from,
+            Resume::StepOut(from) => depth < from,
+        };
+        let pause = match call {
+            Some((name, _)) if entered && self.routines.contains(name) => Some(Pause::Routine(name.to_string())),
+            _ if self.lines.contains(&line) => Some(Pause::Line(line)),
+            _ if stepped && entry => Some(Pause::Entry),
+            _ if stepped => Some(Pause::Step),
+            _ => None,
+        };
+        if pause.is_some() {
+            self.resume = Resume::Continue;
+        }
+        pause
+    }
+}
+
+/// Whether the debugger stops before `stmt`. Declaring a procedure or
+/// function only records it, so stepping passes over its header.
+pub fn is_pausable(stmt: &Stmt) -> bool {
+    !matches!(stmt, Stmt::FunctionDeclaration { .. } | Stmt::ProcedureDeclaration { .. })
+}
+
+/// Where a program can stop: the lines its statements start on, and the
+/// procedures and functions it declares
+#[derive(Debug, Default)]
+pub struct Stops {
+    lines: BTreeSet<usize>,
+    routines: BTreeSet<String>,
+}
+
+impl Stops {
+    pub fn new(statements: &[Stmt]) -> Self {
+        let mut stops = Self::default();
+        stops.add_lines(statements);
+        stops.routines = declarations(statements).into_iter()
+            .filter_map(|stmt| match stmt.declared_name() {
+                Some(("Function" | "Procedure", name)) => Some(name.to_string()),
+                _ => None,
+            })
+            .collect();
+        stops
+    }
+
+    fn add_lines(&mut self, statements: &[Stmt]) {
+        for stmt in statements {
+            if is_pausable(stmt) {
+                self.lines.insert(stmt.span().line);
+            }
+            for_each_block(stmt, |block| self.add_lines(block));
+        }
+    }
+
+    /// Where a breakpoint on `line` takes effect: the first line from it on
+    /// that a statement starts on, if any does
+    pub fn line_from(&self, line: usize) -> Option<usize> {
+        self.lines.range(line..).next().copied()
+    }
+
+    pub fn has_routine(&self, name: &str) -> bool {
+        self.routines.contains(name)
+    }
+}
@@ -0,0 +1,299 @@
+use std::fmt;
+
+use serde::{Serialize, Deserialize};
+
+use crate::ast::Span;
+
+/// Error codes, and warning codes starting with W, numbered by the phase that reports them
+pub mod codes {
+    /// Character the lexer does not recognise
+    pub const UNEXPECTED_CHARACTER: &str = "E0001";
+    /// Malformed program
+    pub const SYNTAX: &str = "E0100";
+    /// Value of the wrong type for where it is used
+    pub const TYPE_MISMATCH: &str = "E0200";
+    /// Name that no declaration introduces
+    pub const UNDECLARED: &str = "E0201";
+    /// Wrong number of arguments or array indices
+    pub const WRONG_COUNT: &str = "E0202";
+    /// Assignment, INPUT or BYREF argument that would change a constant
+    pub const CONSTANT_CHANGED: &str = "E0203";
+    /// TYPE, FUNCTION or PROCEDURE declared twice with the same name
+    pub const DUPLICATE_DECLARATION: &str = "E0204";
+    /// Program the bytecode compiler cannot translate
+    pub const COMPILE: &str = "E0300";
+    /// Error raised while the program runs
+    pub const RUNTIME: &str = "E0400";
+    /// Program went over one of the execution limits it was run with
+    pub const LIMIT_EXCEEDED: &str = "E0500";
+    /// CASE label matching some of the same values as an earlier label
+    pub const OVERLAPPING_CASE_LABEL: &str = "W0200";
+    /// CASE label that can never match, because earlier labels cover it or its range is empty
+    pub const UNREACHABLE_CASE_LABEL: &str = "W0201";
+    /// CASE without OTHERWISE whose value matched none of its labels
+    pub const UNMATCHED_CASE: &str = "W0400";
+}
+
+/// Start of the note listing the calls in progress when a runtime error happened
+const CALL_STACK: &str = "call stack: ";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Severity {
+    Error,
+    Warning,
+    Note,
+}
+
+impl Severity {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+            Severity::Note => "note",
+        }
+    }
+}
+
+/// A range of source text. Lines and columns are 1-based and the end column is exclusive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SourceSpan {
+    pub start_line: usize,
+    pub start_column: usize,
+    pub end_line: usize,
+    pub end_column: usize,
+}
+
+impl SourceSpan {
+    pub fn new(start_line: usize, start_column: usize, end_line: usize, end_column: usize) -> Self {
+        Self { start_line, start_column, end_line, end_column }
+    }
+
+    /// A single character
+    pub fn point(line: usize, column: usize) -> Self {
+        Self::new(line, column, line, column + 1)
+    }
+}
+
+impl From<&Span> for SourceSpan {
+    fn from(span: &Span) -> Self {
+        // Spans built before parsing, such as the compiler's starting one, have no end
+        if span.end_line == 0 {
+            return SourceSpan::point(span.line, span.column);
+        }
+        SourceSpan::new(span.line, span.column, span.end_line, span.end_column)
+    }
+}
+
+impl From<Span> for SourceSpan {
+    fn from(span: Span) -> Self {
+        SourceSpan::from(&span)
+    }
+}
+
+/// A secondary span pointing at something related to the error
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Label {
+    pub span: SourceSpan,
+    pub message: String,
+}
+
+/// An error or warning reported by any phase: lexing, parsing, compiling or running
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub code: String,
+    pub message: String,
+    /// Where the problem is; `None` when no position is known
+    pub span: Option<SourceSpan>,
+    pub labels: Vec<Label>,
+    pub notes: Vec<String>,
+}
+
+impl Diagnostic {
+    pub fn new(severity: Severity, code: &str, message: impl Into<String>) -> Self {
+        Self {
+            severity,
+            code: code.to_string(),
+            message: message.into(),
+            span: None,
+            labels: Vec::new(),
+            notes: Vec::new(),
+        }
+    }
+
+    pub fn error(code: &str, message: impl Into<String>) -> Self {
+        Self::new(Severity::Error, code, message)
+    }
+
+    pub fn warning(code: &str, message: impl Into<String>) -> Self {
+        Self::new(Severity::Warning, code, message)
+    }
+
+    /// Whether the diagnostic stops the program from running, unlike warnings and notes
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+
+    pub fn with_span(mut self, span: impl Into<SourceSpan>) -> Self {
+        self.span = Some(span.into());
+        self
+    }
+
+    /// Attach `span` unless a more precise one is already set
+    pub fn or_span(mut self, span: impl Into<SourceSpan>) -> Self {
+        if self.span.is_none() {
+            self.span = Some(span.into());
+        }
+        self
+    }
+
+    pub fn with_label(mut self, span: impl Into<SourceSpan>, message: impl Into<String>) -> Self {
+        self.labels.push(Label { span: span.into(), message: message.into() });
+        self
+    }
+
+    pub fn with_note(mut self, note: impl Into<String>) -> Self {
+        self.notes.push(note.into());
+        self
+    }
+
+    /// Note listing the calls in progress, outermost first. Deep recursion
+    /// only shows its ends, since the calls in between are rarely telling.
+    pub fn with_call_stack<S: AsRef<str>>(self, calls: &[S]) -> Self {
+        const SHOWN: usize = 10;
+        let names = |calls: &[S]| calls.iter().map(|call| call.as_ref()).collect::<Vec<_>>().join(" -> ");
+        if calls.len() <= 2 * SHOWN {
+            return self.with_note(format!("{}{}", CALL_STACK, names(calls)));
+        }
+        let hidden = calls.len() - 2 * SHOWN;
+        self.with_note(format!(
+            "{}{} -> ... {} more ... -> {}",
+            CALL_STACK,
+            names(&calls[..SHOWN]),
+            hidden,
+            names(&calls[calls.len() - SHOWN..])
+        ))
+    }
+
+    /// The call stack note, unless the diagnostic has one already or no call
+    /// is in progress. Errors pass out through every call, and the innermost
+    /// one sees them first, with the whole stack. `calls` is only asked for
+    /// the stack when there is no note yet, so passing an error out through
+    /// deep recursion stays cheap.
+    pub fn or_call_stack<S: AsRef<str>>(self, calls: impl FnOnce() -> Vec<S>) -> Self {
+        if self.notes.iter().any(|note| note.starts_with(CALL_STACK)) {
+            return self;
+        }
+        let calls = calls();
+        if calls.is_empty() {
+            return self;
+        }
+        self.with_call_stack(&calls)
+    }
+
+    /// Line of the primary span, or 0 when unknown
+    pub fn line(&self) -> usize {
+        self.span.map(|span| span.start_line).unwrap_or(0)
+    }
+
+    /// Render rustc-style, quoting the offending lines of `source` when it is available:
+    ///
+    /// ```text
+    /// error[E0100]: Expected type
+    ///  --> program.pseu:1:13
+    ///   |
+    /// 1 | DECLARE X : NUMBER
+    ///   |             ^^^^^^
+    /// ```
+    pub fn render(&self, source: Option<&str>, filename: &str) -> String {
+        let mut out = format!("{}[{}]: {}\n", self.severity.as_str(), self.code, self.message);
+
+        let lines: Vec<&str> = source.map(|s| s.lines().collect()).unwrap_or_default();
+
+        // Primary span first, then labels in source order
+        let mut marks: Vec<(SourceSpan, char, &str)> = Vec::new();
+        if let Some(span) = self.span {
+            marks.push((span, '^', ""));
+        }
+        let mut labels: Vec<&Label> = self.labels.iter().collect();
+        labels.sort_by_key(|label| (label.span.start_line, label.span.start_column));
+        marks.extend(labels.iter().map(|label| (label.span, '-', label.message.as_str())));
+        marks.retain(|(span, _, _)| span.start_line >= 1 && span.start_line <= lines.len());
+
+        let widest_line = marks.iter().map(|(span, _, _)| span.start_line).max()
+            .or(self.span.map(|span| span.start_line))
+            .unwrap_or(0);
+        let pad = " ".repeat(widest_line.to_string().len());
+
+        if let Some(span) = self.span {
+            out.push_str(&format!("{}--> {}:{}:{}\n", pad, filename, span.start_line, span.start_column));
+        } else {
+            out.push_str(&format!("{}--> {}\n", pad, filename));
+        }
+
+        if !marks.is_empty() {
+            out.push_str(&format!("{} |\n", pad));
+            let mut last_line = 0;
+            for (span, marker, message) in &marks {
+                let text = lines[span.start_line - 1];
+                if span.start_line != last_line {
+                    out.push_str(&format!("{:>width$} | {}\n", span.start_line, text, width = pad.len()));
+                    last_line = span.start_line;
+                }
+                out.push_str(&format!("{} | {}\n", pad, underline(text, span, *marker, message)));
+            }
+        }
+
+        if !self.notes.is_empty() {
+            out.push_str(&format!("{} |\n", pad));
+            for note in &self.notes {
+                out.push_str(&format!("{} = note: {}\n", pad, note));
+            }
+        }
+        out
+    }
+}
+
+/// Markers under `span` on its first line. Tabs before the span are kept so
+/// the markers line up with the quoted source.
+fn underline(text: &str, span: &SourceSpan, marker: char, message: &str) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    let start = span.start_column.saturating_sub(1).min(chars.len());
+    let end = if span.end_line == span.start_line {
+        span.end_column.saturating_sub(1).min(chars.len())
+    } else {
+        chars.len()
+    };
+
+    let mut line: String = chars[..start].iter()
+        .map(|&c| if c == '\t' { '\t' } else { ' ' })
+        .collect();
+    line.push_str(&marker.to_string().repeat(end.saturating_sub(start).max(1)));
+    if !message.is_empty() {
+        line.push(' ');
+        line.push_str(message);
+    }
+    line
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.span {
+            Some(span) => write!(f, "{} at line {}:{}", self.message, span.start_line, span.start_column),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+/// Errors from the value, builtin and file helpers are plain messages raised at run time
+impl From<String> for Diagnostic {
+    fn from(message: String) -> Self {
+        Diagnostic::error(codes::RUNTIME, message)
+    }
+}
+
+impl From<&str> for Diagnostic {
+    fn from(message: &str) -> Self {
+        Diagnostic::error(codes::RUNTIME, message)
+    }
+}
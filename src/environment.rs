@@ -0,0 +1,345 @@
+use std::collections::HashMap;
+
+use crate::ast::Type;
+use crate::heap::Heap;
+use crate::value::{self, Address, Reference, Value};
+
+fn dangling() -> String {
+    "Dangling pointer: the variable it points to no longer exists".to_string()
+}
+
+/// A variable's storage. Either part may be missing: a FOR counter or INPUT
+/// target can be given a value before anything declares its type. A BYREF
+/// parameter has no value of its own, only the `reference` it stands for.
+#[derive(Debug, Clone, Default)]
+struct Slot {
+    value: Option<Value>,
+    ty: Option<Type>,
+    constant: bool,
+    reference: Option<Reference>,
+}
+
+impl Slot {
+    fn is_empty(&self) -> bool {
+        self.value.is_none() && self.ty.is_none() && self.reference.is_none()
+    }
+}
+
+/// What a parameter is bound to on call: a copy of its argument, or for
+/// BYREF the argument's storage itself
+#[derive(Debug, Clone)]
+pub enum Binding {
+    Value(Value),
+    Reference(Reference),
+}
+
+/// Variables of the main program or of one call
+#[derive(Debug, Default)]
+struct Scope {
+    /// 0 for the globals. Each call gets a new id, so a pointer into a call
+    /// that has returned cannot find a later call's variables instead.
+    id: u64,
+    ids: HashMap<String, usize>,
+    slots: Vec<Slot>,
+}
+
+impl Scope {
+    fn address(&self, name: &str) -> Option<Address> {
+        self.slot(name)?;
+        Some(Address::Variable { frame: self.id, slot: self.ids[name] })
+    }
+
+    /// The slot for `name`, unless it is unused or has been removed
+    fn slot(&self, name: &str) -> Option<&Slot> {
+        self.ids.get(name)
+            .map(|id| &self.slots[*id])
+            .filter(|slot| !slot.is_empty())
+    }
+
+    fn slot_mut(&mut self, name: &str) -> Option<&mut Slot> {
+        self.ids.get(name)
+            .map(|id| &mut self.slots[*id])
+            .filter(|slot| !slot.is_empty())
+    }
+
+    /// The slot for `name`, allocating the next one on first use
+    fn entry(&mut self, name: &str) -> &mut Slot {
+        let next = self.slots.len();
+        let id = *self.ids.entry(name.to_string()).or_insert(next);
+        if id == next {
+            self.slots.push(Slot::default());
+        }
+        &mut self.slots[id]
+    }
+}
+
+/// Storage visible to the interpreter: the globals plus a frame per active
+/// call, and the heap cells pointers may point to.
+///
+/// Code inside a procedure or function sees its own frame and the globals, but
+/// not the locals of whoever called it, and a frame's locals are gone once the
+/// call returns. Parameters take the first slots of a frame, in order. Reading
+/// or writing a BYREF parameter reads or writes the storage it refers to.
+#[derive(Debug, Default)]
+pub struct Environment {
+    globals: Scope,
+    frames: Vec<Scope>,
+    calls: u64,
+    heap: Heap,
+}
+
+impl Environment {
+    pub fn clear(&mut self) {
+        self.globals = Scope::default();
+        self.frames.clear();
+        self.calls = 0;
+        self.heap.clear();
+    }
+
+    /// Enter a call, binding each parameter to its argument
+    pub fn push_frame(&mut self, params: impl IntoIterator<Item = (String, Type, Binding)>) {
+        self.calls += 1;
+        let mut frame = Scope { id: self.calls, ..Scope::default() };
+        for (name, ty, binding) in params {
+            *frame.entry(&name) = match binding {
+                Binding::Value(value) => Slot { value: Some(value), ty: Some(ty), ..Slot::default() },
+                Binding::Reference(reference) => Slot { ty: Some(ty), reference: Some(reference), ..Slot::default() },
+            };
+        }
+        self.frames.push(frame);
+    }
+
+    /// Leave a call, dropping its locals
+    pub fn pop_frame(&mut self) {
+        self.frames.pop();
+    }
+
+    fn current_mut(&mut self) -> &mut Scope {
+        self.frames.last_mut().unwrap_or(&mut self.globals)
+    }
+
+    fn slot(&self, name: &str) -> Option<&Slot> {
+        self.frames.last()
+            .and_then(|frame| frame.slot(name))
+            .or_else(|| self.globals.slot(name))
+    }
+
+    fn slot_mut(&mut self, name: &str) -> Option<&mut Slot> {
+        if let Some(frame) = self.frames.last_mut() {
+            if frame.slot(name).is_some() {
+                return frame.slot_mut(name);
+            }
+        }
+        self.globals.slot_mut(name)
+    }
+
+    /// The value held by `slot`, or by the storage it refers to
+    fn slot_value<'a>(&'a self, slot: &'a Slot) -> Option<&'a Value> {
+        match &slot.reference {
+            Some(reference) => self.resolve(reference).ok(),
+            None => slot.value.as_ref(),
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Value> {
+        self.slot_value(self.slot(name)?)
+    }
+
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut Value> {
+        match self.slot(name)?.reference.clone() {
+            Some(reference) => self.resolve_mut(&reference).ok(),
+            None => self.slot_mut(name)?.value.as_mut(),
+        }
+    }
+
+    pub fn type_of(&self, name: &str) -> Option<&Type> {
+        self.slot(name).and_then(|slot| slot.ty.as_ref())
+    }
+
+    /// Declare `name` in the innermost scope, shadowing any global of the same name
+    pub fn declare(&mut self, name: &str, value: Value, ty: Type) {
+        *self.current_mut().entry(name) = Slot { value: Some(value), ty: Some(ty), ..Slot::default() };
+    }
+
+    /// Store into the visible variable called `name`, or into a new one in the innermost scope
+    pub fn set(&mut self, name: &str, value: Value) {
+        if let Some(stored) = self.get_mut(name) {
+            *stored = value;
+            return;
+        }
+        match self.slot_mut(name) {
+            Some(slot) => slot.value = Some(value),
+            None => self.current_mut().entry(name).value = Some(value),
+        }
+    }
+
+    /// Like `set`, for the declared type
+    pub fn set_type(&mut self, name: &str, ty: Type) {
+        match self.slot_mut(name) {
+            Some(slot) => slot.ty = Some(ty),
+            None => self.current_mut().entry(name).ty = Some(ty),
+        }
+    }
+
+    /// Forget `name` in the innermost scope, e.g. a FOR counter after its loop
+    pub fn remove(&mut self, name: &str) {
+        if let Some(slot) = self.current_mut().slot_mut(name) {
+            *slot = Slot::default();
+        }
+    }
+
+    /// Address of the visible variable called `name`, for ^name
+    pub fn address_of(&self, name: &str) -> Option<Address> {
+        self.frames.last()
+            .and_then(|frame| frame.address(name))
+            .or_else(|| self.globals.address(name))
+    }
+
+    /// The storage the visible variable called `name` stands for, to pass it BYREF
+    pub fn reference_to(&self, name: &str) -> Option<Reference> {
+        match &self.slot(name)?.reference {
+            Some(reference) => Some(reference.clone()),
+            None => Some(Reference { address: self.address_of(name)?, steps: Vec::new() }),
+        }
+    }
+
+    /// Store `value` in a new heap cell, for NEW
+    pub fn allocate(&mut self, value: Value) -> Address {
+        self.heap.allocate(value)
+    }
+
+    /// The scope of an active call, or the globals for frame 0. A pointer may
+    /// refer to any active call, not just the innermost one.
+    fn scope(&self, frame: u64) -> Option<&Scope> {
+        match frame {
+            0 => Some(&self.globals),
+            _ => self.frames.iter().rev().find(|scope| scope.id == frame),
+        }
+    }
+
+    fn scope_mut(&mut self, frame: u64) -> Option<&mut Scope> {
+        match frame {
+            0 => Some(&mut self.globals),
+            _ => self.frames.iter_mut().rev().find(|scope| scope.id == frame),
+        }
+    }
+
+    /// The slot of the variable at `frame` and `slot`, unless its call has returned
+    fn slot_at(&self, frame: u64, slot: usize) -> Result<&Slot, String> {
+        self.scope(frame)
+            .and_then(|scope| scope.slots.get(slot))
+            .filter(|slot| slot.value.is_some() || slot.reference.is_some())
+            .ok_or_else(dangling)
+    }
+
+    /// Value of the variable or heap cell at `address`, unless its call has returned
+    pub fn load(&self, address: &Address) -> Result<&Value, String> {
+        match *address {
+            Address::Variable { frame, slot } => {
+                let slot = self.slot_at(frame, slot)?;
+                self.slot_value(slot).ok_or_else(dangling)
+            }
+            Address::Heap(cell) => self.heap.get(cell),
+        }
+    }
+
+    /// Store through a pointer into the variable or heap cell at `address`
+    pub fn store(&mut self, address: &Address, value: Value) -> Result<(), String> {
+        if let Address::Variable { frame, slot } = *address {
+            if self.slot_at(frame, slot)?.constant {
+                return Err("Cannot assign through a pointer to a constant - constants are locked".to_string());
+            }
+        }
+        *self.load_mut(address)? = value;
+        Ok(())
+    }
+
+    fn load_mut(&mut self, address: &Address) -> Result<&mut Value, String> {
+        match *address {
+            Address::Variable { frame, slot } => {
+                if let Some(reference) = self.slot_at(frame, slot)?.reference.clone() {
+                    return self.resolve_mut(&reference);
+                }
+                self.scope_mut(frame)
+                    .and_then(|scope| scope.slots.get_mut(slot))
+                    .and_then(|slot| slot.value.as_mut())
+                    .ok_or_else(dangling)
+            }
+            Address::Heap(cell) => self.heap.get_mut(cell),
+        }
+    }
+
+    /// The value `reference` refers to
+    pub fn resolve(&self, reference: &Reference) -> Result<&Value, String> {
+        value::part(self.load(&reference.address)?, &reference.steps, "BYREF argument")
+    }
+
+    fn resolve_mut(&mut self, reference: &Reference) -> Result<&mut Value, String> {
+        value::part_mut(self.load_mut(&reference.address)?, &reference.steps, "BYREF argument")
+    }
+
+    /// Whether the variable at `address` is a constant, which cannot be passed BYREF
+    pub fn is_constant_at(&self, address: &Address) -> bool {
+        match *address {
+            Address::Variable { frame, slot } => self.slot_at(frame, slot).is_ok_and(|slot| slot.constant),
+            Address::Heap(_) => false,
+        }
+    }
+
+    pub fn is_constant(&self, name: &str) -> bool {
+        self.slot(name).is_some_and(|slot| slot.constant)
+    }
+
+    /// Lock `name` so it can no longer be assigned
+    pub fn make_constant(&mut self, name: &str) {
+        if let Some(slot) = self.slot_mut(name) {
+            slot.constant = true;
+        }
+    }
+
+    /// Number of the innermost call, 0 in the main program. Every call gets its own.
+    pub fn frame_id(&self) -> u64 {
+        self.frames.last().map_or(0, |frame| frame.id)
+    }
+
+    /// Every variable of `scope` with a value, sorted by name
+    fn variables(&self, scope: &Scope) -> Vec<(String, Value)> {
+        let mut variables: Vec<(String, Value)> = scope.ids.keys()
+            .filter_map(|name| Some((name.clone(), self.slot_value(scope.slot(name)?)?.clone())))
+            .collect();
+        variables.sort_by(|a, b| a.0.cmp(&b.0));
+        variables
+    }
+
+    /// Variables of the innermost call, empty in the main program
+    pub fn locals(&self) -> Vec<(String, Value)> {
+        self.frames.last().map(|frame| self.variables(frame)).unwrap_or_default()
+    }
+
+    /// Variables of the call `depth` calls deep, 1 being a call made from the
+    /// main program
+    pub fn locals_at(&self, depth: usize) -> Vec<(String, Value)> {
+        depth.checked_sub(1)
+            .and_then(|index| self.frames.get(index))
+            .map(|frame| self.variables(frame))
+            .unwrap_or_default()
+    }
+
+    pub fn globals(&self) -> Vec<(String, Value)> {
+        self.variables(&self.globals)
+    }
+
+    /// Names of every variable currently visible
+    pub fn names(&self) -> Vec<String> {
+        let mut names: Vec<String> = Vec::new();
+        let scopes = self.frames.last().into_iter().chain(std::iter::once(&self.globals));
+        for scope in scopes {
+            for name in scope.ids.keys() {
+                if scope.slot(name).is_some() && !names.contains(name) {
+                    names.push(name.clone());
+                }
+            }
+        }
+        names
+    }
+}
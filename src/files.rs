@@ -0,0 +1,403 @@
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{BufReader, BufWriter, Read, Write, Seek, SeekFrom, BufRead};
+use std::path::{Path, PathBuf};
+
+use crate::ast::FileMode;
+use crate::host::FileSystem;
+
+#[derive(Debug)]
+enum FileHandle {
+    Read(BufReader<File>),
+    Write(BufWriter<File>),
+    // For RANDOM mode - can both read and write. The record number is set by
+    // SEEK and used by the next GETRECORD or PUTRECORD.
+    Random(File, Option<u64>),
+}
+
+/// Convert a SEEK address to a record number counting from 0
+fn record_number(address: i64) -> Result<u64, String> {
+    if address < 1 {
+        return Err(format!("SEEK address must be 1 or more, got {}", address));
+    }
+    Ok(address as u64 - 1)
+}
+
+/// Byte offset of record `number` when each record is `size` bytes
+fn record_offset(number: u64, size: usize) -> Result<u64, String> {
+    number.checked_mul(size as u64)
+        .ok_or_else(|| format!("SEEK address {} is too large for records of {} bytes", number + 1, size))
+}
+
+/// Files on disk, resolved relative to the source file directory
+pub struct NativeFileSystem {
+    open_files: HashMap<String, FileHandle>,
+    // Source file directory for resolving relative file paths
+    source_dir: Option<PathBuf>,
+}
+
+impl NativeFileSystem {
+    pub fn new(source_dir: Option<PathBuf>) -> Self {
+        Self {
+            open_files: HashMap::new(),
+            source_dir,
+        }
+    }
+
+    /// Resolve a file path relative to the source file directory
+    fn resolve_file_path(&self, filename: &str) -> PathBuf {
+        let path = Path::new(filename);
+        if path.is_absolute() {
+            // Absolute path, use as-is
+            path.to_path_buf()
+        } else if let Some(ref source_dir) = self.source_dir {
+            // Relative path, resolve against source directory
+            source_dir.join(path)
+        } else {
+            // No source directory, use current working directory
+            path.to_path_buf()
+        }
+    }
+
+    fn handle(&mut self, filename: &str) -> Result<&mut FileHandle, String> {
+        self.open_files.get_mut(filename)
+            .ok_or_else(|| format!("File '{}' is not open", filename))
+    }
+}
+
+impl FileSystem for NativeFileSystem {
+    fn open(&mut self, filename: &str, mode: &FileMode) -> Result<(), String> {
+        if self.open_files.contains_key(filename) {
+            return Err(format!("File {} already open", filename));
+        }
+
+        let resolved_path = self.resolve_file_path(filename);
+        let resolved_path_str = resolved_path.to_string_lossy().to_string();
+
+        let handle = match mode {
+            FileMode::READ => {
+                let file = OpenOptions::new().read(true).open(&resolved_path)
+                    .map_err(|e| format!("Failed to open file {} for reading: {}", resolved_path_str, e))?;
+                FileHandle::Read(BufReader::new(file))
+            }
+            FileMode::WRITE => {
+                let file = OpenOptions::new().write(true).create(true).truncate(true).open(&resolved_path)
+                    .map_err(|e| format!("Failed to open file {} for writing: {}", resolved_path_str, e))?;
+                FileHandle::Write(BufWriter::new(file))
+            }
+            FileMode::RANDOM => {
+                let file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(&resolved_path)
+                    .map_err(|e| format!("Failed to open file {} for random access: {}", resolved_path_str, e))?;
+                FileHandle::Random(file, None)
+            }
+        };
+
+        self.open_files.insert(filename.to_string(), handle);
+        Ok(())
+    }
+
+    fn close(&mut self, filename: &str) -> Result<(), String> {
+        if self.open_files.remove(filename).is_none() {
+            return Err(format!("File '{}' is not open", filename));
+        }
+        Ok(())
+    }
+
+    fn close_all(&mut self) {
+        self.open_files.clear();
+    }
+
+    fn read_line(&mut self, filename: &str) -> Result<String, String> {
+        let mut line = String::new();
+        match self.handle(filename)? {
+            FileHandle::Read(reader) => {
+                reader.read_line(&mut line)
+                    .map_err(|e| format!("Failed to read from file '{}': {}", filename, e))?;
+            },
+            FileHandle::Random(file, _) => {
+                let mut buffer = [0u8; 1024];
+                let mut bytes_read = 0;
+                loop {
+                    match file.read(&mut buffer[bytes_read..]) {
+                        Ok(0) => break, // EOF
+                        Ok(n) => {
+                            bytes_read += n;
+                            if buffer[..bytes_read].contains(&b'\n') {
+                                break; // Found newline
+                            }
+                        }
+                        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
+                        Err(e) => return Err(format!("Failed to read from file '{}': {}", filename, e)),
+                    }
+                }
+                line = String::from_utf8_lossy(&buffer[..bytes_read]).to_string();
+            },
+            FileHandle::Write(_) => {
+                return Err(format!("Cannot read from file '{}' opened in WRITE mode", filename));
+            },
+        }
+
+        if line.ends_with('\n') {
+            line.pop();
+            if line.ends_with('\r') {
+                line.pop();
+            }
+        }
+        Ok(line)
+    }
+
+    fn write(&mut self, filename: &str, output: &str) -> Result<(), String> {
+        match self.handle(filename)? {
+            FileHandle::Write(writer) => {
+                writer.write_all(output.as_bytes())
+                    .map_err(|e| format!("Failed to write to file '{}': {}", filename, e))?;
+                writer.flush()
+                    .map_err(|e| format!("Failed to flush file '{}': {}", filename, e))?;
+            },
+            FileHandle::Random(file, _) => {
+                file.write_all(output.as_bytes())
+                    .map_err(|e| format!("Failed to write to file '{}': {}", filename, e))?;
+                file.flush()
+                    .map_err(|e| format!("Failed to flush file '{}': {}", filename, e))?;
+            },
+            FileHandle::Read(_) => {
+                return Err(format!("Cannot write to file '{}' opened in READ mode", filename));
+            },
+        }
+        Ok(())
+    }
+
+    fn seek(&mut self, filename: &str, address: i64) -> Result<(), String> {
+        match self.handle(filename)? {
+            FileHandle::Random(_, record) => {
+                *record = Some(record_number(address)?);
+                Ok(())
+            },
+            _ => Err("SEEK only works with files opened in RANDOM mode".to_string()),
+        }
+    }
+
+    fn get_record(&mut self, filename: &str, size: usize) -> Result<Vec<u8>, String> {
+        match self.handle(filename)? {
+            FileHandle::Random(file, record) => {
+                if let Some(number) = record.take() {
+                    file.seek(SeekFrom::Start(record_offset(number, size)?))
+                        .map_err(|e| format!("Failed to seek in file '{}': {}", filename, e))?;
+                }
+                let mut buffer = vec![0u8; size];
+                match file.read_exact(&mut buffer) {
+                    Ok(_) => Ok(buffer),
+                    Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+                        Err("End of file reached in GETRECORD".to_string())
+                    }
+                    Err(e) => Err(format!("Failed to read record from file '{}': {}", filename, e)),
+                }
+            }
+            _ => Err("GETRECORD only works with files opened in RANDOM mode".to_string()),
+        }
+    }
+
+    fn put_record(&mut self, filename: &str, record_data: &[u8]) -> Result<(), String> {
+        match self.handle(filename)? {
+            FileHandle::Random(file, record) => {
+                if let Some(number) = record.take() {
+                    file.seek(SeekFrom::Start(record_offset(number, record_data.len())?))
+                        .map_err(|e| format!("Failed to seek in file '{}': {}", filename, e))?;
+                }
+                file.write_all(record_data)
+                    .map_err(|e| format!("Failed to write record to file '{}': {}", filename, e))?;
+                file.flush()
+                    .map_err(|e| format!("Failed to flush file '{}': {}", filename, e))?;
+                Ok(())
+            }
+            _ => Err("PUTRECORD only works with files opened in RANDOM mode".to_string()),
+        }
+    }
+
+    fn eof(&mut self, filename: &str) -> Result<bool, String> {
+        match self.handle(filename)? {
+            FileHandle::Read(reader) => {
+                // Try to peek at the buffer - if it's empty, we're at EOF
+                let buffer = reader.fill_buf()
+                    .map_err(|e| format!("Failed to read from file '{}': {}", filename, e))?;
+                Ok(buffer.is_empty())
+            },
+            FileHandle::Random(file, _) => {
+                // For random access, check current position vs file size
+                let pos = file.stream_position()
+                    .map_err(|e| format!("Failed to read from file '{}': {}", filename, e))?;
+                let metadata = file.metadata()
+                    .map_err(|e| format!("Failed to read from file '{}': {}", filename, e))?;
+                Ok(pos >= metadata.len())
+            },
+            // Write mode - always false (can't be at EOF for writing)
+            FileHandle::Write(_) => Ok(false),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct VirtualHandle {
+    mode: FileMode,
+    position: usize,
+    // Record number set by SEEK, used by the next GETRECORD or PUTRECORD
+    record: Option<u64>,
+}
+
+/// In-memory files, used where there is no real file system (the browser).
+/// Writes go straight to the stored content, so they are visible before CLOSEFILE.
+#[derive(Debug, Default)]
+pub struct VirtualFileSystem {
+    files: HashMap<String, Vec<u8>>,
+    open_files: HashMap<String, VirtualHandle>,
+}
+
+impl VirtualFileSystem {
+    pub fn set_file(&mut self, filename: String, content: String) {
+        self.files.insert(filename, content.into_bytes());
+    }
+
+    pub fn get_file(&self, filename: &str) -> Option<String> {
+        self.files.get(filename)
+            .map(|content| String::from_utf8_lossy(content).to_string())
+    }
+
+    /// Like `set_file`, for binary content such as a file of records
+    pub fn set_file_bytes(&mut self, filename: String, content: Vec<u8>) {
+        self.files.insert(filename, content);
+    }
+
+    pub fn get_file_bytes(&self, filename: &str) -> Option<Vec<u8>> {
+        self.files.get(filename).cloned()
+    }
+
+    /// The open file's handle and content
+    fn open_file(&mut self, filename: &str) -> Result<(&mut VirtualHandle, &mut Vec<u8>), String> {
+        let handle = self.open_files.get_mut(filename)
+            .ok_or_else(|| format!("File '{}' is not open", filename))?;
+        let content = self.files.entry(filename.to_string()).or_default();
+        Ok((handle, content))
+    }
+}
+
+impl FileSystem for VirtualFileSystem {
+    fn open(&mut self, filename: &str, mode: &FileMode) -> Result<(), String> {
+        if self.open_files.contains_key(filename) {
+            return Err(format!("File {} already open", filename));
+        }
+
+        match mode {
+            FileMode::READ => {
+                if !self.files.contains_key(filename) {
+                    return Err(format!("File '{}' not found in virtual file system", filename));
+                }
+            }
+            FileMode::WRITE => {
+                self.files.insert(filename.to_string(), Vec::new());
+            }
+            FileMode::RANDOM => {
+                self.files.entry(filename.to_string()).or_default();
+            }
+        }
+
+        self.open_files.insert(filename.to_string(), VirtualHandle { mode: mode.clone(), position: 0, record: None });
+        Ok(())
+    }
+
+    fn close(&mut self, filename: &str) -> Result<(), String> {
+        if self.open_files.remove(filename).is_none() {
+            return Err(format!("File '{}' is not open", filename));
+        }
+        Ok(())
+    }
+
+    fn close_all(&mut self) {
+        self.open_files.clear();
+    }
+
+    fn read_line(&mut self, filename: &str) -> Result<String, String> {
+        let (handle, content) = self.open_file(filename)?;
+        if handle.mode == FileMode::WRITE {
+            return Err(format!("Cannot read from file '{}' opened in WRITE mode", filename));
+        }
+
+        let start = handle.position.min(content.len());
+        let end = content[start..].iter().position(|&b| b == b'\n')
+            .map(|i| start + i + 1)
+            .unwrap_or(content.len());
+        handle.position = end;
+
+        let line = String::from_utf8_lossy(&content[start..end]).to_string();
+        Ok(line.trim_end_matches('\n').trim_end_matches('\r').to_string())
+    }
+
+    fn write(&mut self, filename: &str, output: &str) -> Result<(), String> {
+        let (handle, content) = self.open_file(filename)?;
+        if handle.mode == FileMode::READ {
+            return Err(format!("Cannot write to file '{}' opened in READ mode", filename));
+        }
+
+        // Overwrite from the current position, growing the file as needed
+        let end = handle.position + output.len();
+        if content.len() < end {
+            content.resize(end, 0);
+        }
+        content[handle.position..end].copy_from_slice(output.as_bytes());
+        handle.position = end;
+        Ok(())
+    }
+
+    fn seek(&mut self, filename: &str, address: i64) -> Result<(), String> {
+        let (handle, _) = self.open_file(filename)?;
+        if handle.mode != FileMode::RANDOM {
+            return Err("SEEK only works with files opened in RANDOM mode".to_string());
+        }
+        handle.record = Some(record_number(address)?);
+        Ok(())
+    }
+
+    fn get_record(&mut self, filename: &str, size: usize) -> Result<Vec<u8>, String> {
+        let (handle, content) = self.open_file(filename)?;
+        if handle.mode != FileMode::RANDOM {
+            return Err("GETRECORD only works with files opened in RANDOM mode".to_string());
+        }
+        if let Some(number) = handle.record.take() {
+            handle.position = record_offset(number, size)? as usize;
+        }
+
+        let end = handle.position + size;
+        if end > content.len() {
+            return Err("End of file reached in GETRECORD".to_string());
+        }
+        let record = content[handle.position..end].to_vec();
+        handle.position = end;
+        Ok(record)
+    }
+
+    fn put_record(&mut self, filename: &str, record_data: &[u8]) -> Result<(), String> {
+        let (handle, content) = self.open_file(filename)?;
+        if handle.mode != FileMode::RANDOM {
+            return Err("PUTRECORD only works with files opened in RANDOM mode".to_string());
+        }
+        if let Some(number) = handle.record.take() {
+            handle.position = record_offset(number, record_data.len())? as usize;
+        }
+
+        let end = handle.position + record_data.len();
+        if content.len() < end {
+            content.resize(end, 0);
+        }
+        content[handle.position..end].copy_from_slice(record_data);
+        handle.position = end;
+        Ok(())
+    }
+
+    fn eof(&mut self, filename: &str) -> Result<bool, String> {
+        let (handle, content) = self.open_file(filename)?;
+        Ok(match handle.mode {
+            // Write mode - always false (can't be at EOF for writing)
+            FileMode::WRITE => false,
+            _ => handle.position >= content.len(),
+        })
+    }
+}
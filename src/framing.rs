@@ -0,0 +1,54 @@
+use std::io::{self, BufRead, Write};
+
+use serde_json::Value as Json;
+
+/// Read the next message from stdin, `None` once the other end has gone away
+pub fn next_message() -> Option<Json> {
+    loop {
+        match read_message(&mut io::stdin().lock()) {
+            Ok(Some(message)) => return Some(message),
+            Ok(None) => return None,
+            // A garbled message is dropped; the next one may be fine
+            Err(e) if e.kind() == io::ErrorKind::InvalidData => eprintln!("Ignoring a message: {}", e),
+            Err(_) => return None,
+        }
+    }
+}
+
+/// Read one `Content-Length`-framed JSON message, `None` at the end of input.
+/// The Debug Adapter Protocol and the Language Server Protocol both frame
+/// messages this way.
+pub fn read_message(input: &mut impl BufRead) -> io::Result<Option<Json>> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            if length.is_some() {
+                break;
+            }
+            continue;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("Content-Length") {
+                length = value.trim().parse::<usize>().ok();
+            }
+        }
+    }
+    let mut body = vec![0; length.unwrap_or(0)];
+    input.read_exact(&mut body)?;
+    serde_json::from_slice(&body)
+        .map(Some)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// Write one message to stdout, framed as `read_message` expects
+pub fn write_message(message: &Json) {
+    let body = message.to_string();
+    let mut stdout = io::stdout().lock();
+    let _ = write!(stdout, "Content-Length: {}\r\n\r\n{}", body.len(), body);
+    let _ = stdout.flush();
+}
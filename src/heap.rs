@@ -0,0 +1,33 @@
+use crate::value::{Address, Value};
+
+// Only a pointer kept from before the heap was cleared can miss
+fn dangling(cell: usize) -> String {
+    format!("Dangling pointer: heap cell {} no longer exists", cell)
+}
+
+/// Cells allocated with NEW. Nothing frees a cell, so a heap address stays
+/// valid until the program is reset.
+#[derive(Debug, Default)]
+pub struct Heap {
+    cells: Vec<Value>,
+}
+
+impl Heap {
+    pub fn clear(&mut self) {
+        self.cells.clear();
+    }
+
+    /// Store `value` in a new cell and return its address
+    pub fn allocate(&mut self, value: Value) -> Address {
+        self.cells.push(value);
+        Address::Heap(self.cells.len() - 1)
+    }
+
+    pub fn get(&self, cell: usize) -> Result<&Value, String> {
+        self.cells.get(cell).ok_or_else(|| dangling(cell))
+    }
+
+    pub fn get_mut(&mut self, cell: usize) -> Result<&mut Value, String> {
+        self.cells.get_mut(cell).ok_or_else(|| dangling(cell))
+    }
+}
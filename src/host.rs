@@ -0,0 +1,207 @@
+use std::collections::VecDeque;
+use std::io::Write;
+use std::path::PathBuf;
+
+use rand::Rng;
+
+use crate::ast::FileMode;
+use crate::date::Date;
+use crate::files::{NativeFileSystem, VirtualFileSystem};
+
+/// Where OUTPUT goes
+pub trait Console {
+    fn write(&mut self, text: &str);
+}
+
+/// Where INPUT reads from
+pub trait InputSource {
+    /// Read one line, without its line terminator
+    fn read_line(&mut self) -> Result<String, String>;
+
+    /// Whether a line can be read right away. Sources that wait for a line,
+    /// like stdin, always can.
+    fn has_line(&self) -> bool {
+        true
+    }
+}
+
+/// Files opened by a running program, keyed by the name used in the source
+pub trait FileSystem {
+    fn open(&mut self, filename: &str, mode: &FileMode) -> Result<(), String>;
+    fn close(&mut self, filename: &str) -> Result<(), String>;
+    /// Close every open file, e.g. when a program is reset
+    fn close_all(&mut self);
+    /// Read one line, without its line terminator
+    fn read_line(&mut self, filename: &str) -> Result<String, String>;
+    fn write(&mut self, filename: &str, output: &str) -> Result<(), String>;
+    /// Go to record number `address`, counting from 1. Records have no fixed
+    /// size, so the move happens at the next GETRECORD or PUTRECORD.
+    fn seek(&mut self, filename: &str, address: i64) -> Result<(), String>;
+    /// Read the next record, which is `size` bytes long
+    fn get_record(&mut self, filename: &str, size: usize) -> Result<Vec<u8>, String>;
+    /// Write `record` as the next record
+    fn put_record(&mut self, filename: &str, record: &[u8]) -> Result<(), String>;
+    fn eof(&mut self, filename: &str) -> Result<bool, String>;
+}
+
+/// Source of the values returned by RANDOM and RAND
+pub trait RandomSource {
+    /// A real number between 0 and `max`, inclusive
+    fn real_up_to(&mut self, max: f64) -> f64;
+}
+
+/// Source of the date returned by NOW
+pub trait Clock {
+    fn today(&mut self) -> Result<Date, String>;
+}
+
+/// Everything a running program can reach outside its own variables
+pub struct Host<C, I, F, R, K> {
+    pub console: C,
+    pub input: I,
+    pub files: F,
+    pub random: R,
+    pub clock: K,
+}
+
+/// stdin/stdout, the real file system and the system clock, used by the CLI
+pub type NativeHost = Host<StdConsole, StdinInput, NativeFileSystem, ThreadRandom, SystemClock>;
+
+/// In-memory console, input queue and file system, and a date set by the page,
+/// used by the browser engine
+pub type VirtualHost = Host<BufferConsole, QueueInput, VirtualFileSystem, ThreadRandom, FixedClock>;
+
+impl NativeHost {
+    /// Create a native host, resolving relative file paths against `source_dir`
+    pub fn native(source_dir: Option<PathBuf>) -> Self {
+        Host {
+            console: StdConsole,
+            input: StdinInput,
+            files: NativeFileSystem::new(source_dir),
+            random: ThreadRandom,
+            clock: SystemClock,
+        }
+    }
+}
+
+impl VirtualHost {
+    pub fn virtual_host() -> Self {
+        Host {
+            console: BufferConsole::default(),
+            input: QueueInput::default(),
+            files: VirtualFileSystem::default(),
+            random: ThreadRandom,
+            clock: FixedClock::default(),
+        }
+    }
+}
+
+pub struct StdConsole;
+
+impl Console for StdConsole {
+    fn write(&mut self, text: &str) {
+        let mut stdout = std::io::stdout();
+        let _ = stdout.write_all(text.as_bytes());
+        let _ = stdout.flush();
+    }
+}
+
+/// Collects output so the caller can pick it up after running
+#[derive(Debug, Default)]
+pub struct BufferConsole {
+    output: String,
+}
+
+impl BufferConsole {
+    pub fn output(&self) -> &str {
+        &self.output
+    }
+
+    pub fn clear(&mut self) {
+        self.output.clear();
+    }
+}
+
+impl Console for BufferConsole {
+    fn write(&mut self, text: &str) {
+        self.output.push_str(text);
+    }
+}
+
+pub struct StdinInput;
+
+impl InputSource for StdinInput {
+    fn read_line(&mut self) -> Result<String, String> {
+        let mut input = String::new();
+        std::io::stdin()
+            .read_line(&mut input)
+            .map_err(|_| "Failed to read input")?;
+        Ok(input.trim_end_matches(['\n', '\r']).to_string())
+    }
+}
+
+/// Input lines supplied ahead of time, consumed in the order they were added
+#[derive(Debug, Default)]
+pub struct QueueInput {
+    queue: VecDeque<String>,
+}
+
+impl QueueInput {
+    pub fn push(&mut self, input: String) {
+        self.queue.push_back(input);
+    }
+
+    pub fn clear(&mut self) {
+        self.queue.clear();
+    }
+}
+
+impl InputSource for QueueInput {
+    fn read_line(&mut self) -> Result<String, String> {
+        self.queue.pop_front()
+            .ok_or_else(|| "No input available. Use add_input() to provide input values.".to_string())
+    }
+
+    fn has_line(&self) -> bool {
+        !self.queue.is_empty()
+    }
+}
+
+pub struct ThreadRandom;
+
+impl RandomSource for ThreadRandom {
+    fn real_up_to(&mut self, max: f64) -> f64 {
+        rand::thread_rng().gen_range(0.0..=max)
+    }
+}
+
+/// Today's date in UTC
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn today(&mut self) -> Result<Date, String> {
+        let elapsed = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_err(|_| "The system clock is set before 1970")?;
+        Date::from_day_number((elapsed.as_secs() / 86_400) as i64)
+    }
+}
+
+/// A date supplied by the embedder, for the browser where there is no system
+/// clock to read
+#[derive(Debug, Default)]
+pub struct FixedClock {
+    today: Option<Date>,
+}
+
+impl FixedClock {
+    pub fn set(&mut self, today: Date) {
+        self.today = Some(today);
+    }
+}
+
+impl Clock for FixedClock {
+    fn today(&mut self) -> Result<Date, String> {
+        self.today.ok_or_else(|| "NOW is not available: the current date has not been set".to_string())
+    }
+}
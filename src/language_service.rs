@@ -1,5 +1,9 @@
-use crate::ast::{Stmt, Type, Span};
+use std::collections::HashMap;
 
+use crate::ast::{PassingMode, Stmt, Type, Span};
+use crate::lexer::{Lexer, Token};
+
+#[allow(dead_code)]
 #[derive(Debug, Clone)]
 pub struct VariableSymbol {
     pub name: String,
@@ -7,12 +11,16 @@ pub struct VariableSymbol {
     pub span: Span,
 }
 
+#[allow(dead_code)]
 #[derive(Debug, Clone)]
 pub struct ConstantSymbol {
     pub name: String,
+    /// The value as written, `None` when `CONSTANT Name` locks an existing variable
+    pub value: Option<String>,
     pub span: Span,
 }
 
+#[allow(dead_code)]
 #[derive(Debug, Clone)]
 pub struct FunctionSymbol {
     pub name: String,
@@ -21,6 +29,7 @@ pub struct FunctionSymbol {
     pub span: Span,
 }
 
+#[allow(dead_code)]
 #[derive(Debug, Clone)]
 pub struct ProcedureSymbol {
     pub name: String,
@@ -28,6 +37,7 @@ pub struct ProcedureSymbol {
     pub span: Span,
 }
 
+#[allow(dead_code)]
 #[derive(Debug, Clone)]
 pub struct TypeSymbol {
     pub name: String,
@@ -38,6 +48,7 @@ pub struct TypeSymbol {
 pub struct ParamInfo {
     pub name: String,
     pub type_name: Option<Type>,
+    pub mode: PassingMode,
 }
 
 #[derive(Debug, Clone)]
@@ -49,6 +60,7 @@ pub struct SymbolTable {
     pub types: Vec<TypeSymbol>,
 }
 
+#[allow(dead_code)]
 #[derive(Debug, Clone)]
 pub struct CompletionContext {
     pub after_declare: bool,
@@ -108,9 +120,10 @@ impl SymbolExtractor {
                     span: span.clone(),
                 });
             }
-            Stmt::Constant { name, span, .. } => {
+            Stmt::Constant { name, value, span } => {
                 table.constants.push(ConstantSymbol {
                     name: name.clone(),
+                    value: value.as_ref().map(|value| value.to_string()),
                     span: span.clone(),
                 });
             }
@@ -119,6 +132,7 @@ impl SymbolExtractor {
                     .map(|p| ParamInfo {
                         name: p.name.clone(),
                         type_name: Some(p.type_name.clone()),
+                        mode: p.mode,
                     })
                     .collect();
 
@@ -138,6 +152,7 @@ impl SymbolExtractor {
                     .map(|p| ParamInfo {
                         name: p.name.clone(),
                         type_name: Some(p.type_name.clone()),
+                        mode: p.mode,
                     })
                     .collect();
 
@@ -163,6 +178,15 @@ impl SymbolExtractor {
     }
 }
 
+/// `line` split before the character at 1-based `column`; a column outside
+/// the line leaves the whole line before the cursor
+fn split_at_column(line: &str, column: usize) -> (&str, &str) {
+    match column.checked_sub(1).and_then(|index| line.char_indices().nth(index)) {
+        Some((offset, _)) => line.split_at(offset),
+        None => (line, ""),
+    }
+}
+
 pub struct ContextAnalyzer;
 
 impl ContextAnalyzer {
@@ -174,17 +198,7 @@ impl ContextAnalyzer {
             ""
         };
         
-        let before_cursor = if column > 0 && column <= current_line.len() + 1 {
-            &current_line[..(column - 1).min(current_line.len())]
-        } else {
-            current_line
-        };
-        
-        let after_cursor = if column > 0 && column <= current_line.len() + 1 {
-            &current_line[(column - 1).min(current_line.len())..]
-        } else {
-            ""
-        };
+        let (before_cursor, after_cursor) = split_at_column(current_line, column);
 
         let previous_lines: String = if line > 1 {
             lines[0..(line - 1)].join("\n")
@@ -223,7 +237,7 @@ impl ContextAnalyzer {
     fn extract_prefix(text: &str) -> String {
         text.trim()
             .split(|c: char| !c.is_alphanumeric() && c != '_')
-            .last()
+            .next_back()
             .unwrap_or("")
             .to_string()
     }
@@ -265,14 +279,14 @@ pub const KEYWORDS: &[&str] = &[
     "RETURN", "CALL", "INPUT", "OUTPUT",
     "OPENFILE", "CLOSEFILE", "READFILE", "WRITEFILE", "SEEK",
     "GETRECORD", "PUTRECORD",
-    "INTEGER", "REAL", "STRING", "CHAR", "BOOLEAN", "ARRAY", "OF",
-    "AND", "OR", "NOT", "TRUE", "FALSE",
+    "INTEGER", "REAL", "STRING", "CHAR", "BOOLEAN", "DATE", "ARRAY", "OF",
+    "AND", "OR", "NOT", "TRUE", "FALSE", "NULL", "NEW", "IN",
     "TYPE", "ENDTYPE", "CASE", "ENDCASE", "OTHERWISE",
-    "RETURNS"
+    "RETURNS", "BYREF", "BYVAL"
 ];
 
 pub const TYPES: &[&str] = &[
-    "INTEGER", "REAL", "STRING", "CHAR", "BOOLEAN", "ARRAY"
+    "INTEGER", "REAL", "STRING", "CHAR", "BOOLEAN", "DATE", "ARRAY"
 ];
 
 #[derive(Debug, Clone)]
@@ -283,7 +297,7 @@ pub struct BuiltinFunction {
 }
 
 pub const BUILTIN_FUNCTIONS: &[BuiltinFunction] = &[
-    BuiltinFunction { name: "LENGTH", description: "Returns the length of a string", params: &["string"] },
+    BuiltinFunction { name: "LENGTH", description: "Returns the length of a string, or the number of elements in a set", params: &["string"] },
     BuiltinFunction { name: "UCASE", description: "Converts a string to uppercase", params: &["string"] },
     BuiltinFunction { name: "LCASE", description: "Converts a string to lowercase", params: &["string"] },
     BuiltinFunction { name: "SUBSTRING", description: "Extracts a substring from a string", params: &["string", "start", "length"] },
@@ -293,7 +307,16 @@ pub const BUILTIN_FUNCTIONS: &[BuiltinFunction] = &[
     BuiltinFunction { name: "RANDOM", description: "Returns a random number between 0 and 1", params: &[] },
     BuiltinFunction { name: "RAND", description: "Returns a random real number in the range 0 to x (not inclusive of x)", params: &["x"] },
     BuiltinFunction { name: "EOF", description: "Checks if end of file has been reached", params: &["file"] },
-    BuiltinFunction { name: "MOD", description: "Returns the remainder of division", params: &["dividend", "divisor"] },
+    BuiltinFunction { name: "MOD", description: "Returns the remainder of integer division; also written dividend MOD divisor", params: &["dividend", "divisor"] },
+    BuiltinFunction { name: "DIV", description: "Returns the quotient of integer division, rounded towards zero; also written dividend DIV divisor", params: &["dividend", "divisor"] },
+    BuiltinFunction { name: "DAY", description: "Returns the day number from a date", params: &["date"] },
+    BuiltinFunction { name: "MONTH", description: "Returns the month number from a date", params: &["date"] },
+    BuiltinFunction { name: "YEAR", description: "Returns the year from a date", params: &["date"] },
+    BuiltinFunction { name: "DAYINDEX", description: "Returns the day of the week of a date, where Sunday is 1 and Saturday is 7", params: &["date"] },
+    BuiltinFunction { name: "SETDATE", description: "Returns the date with the given day, month and year", params: &["day", "month", "year"] },
+    BuiltinFunction { name: "NOW", description: "Returns the current date", params: &[] },
+    BuiltinFunction { name: "SUCC", description: "Returns the enum value declared after the given one", params: &["value"] },
+    BuiltinFunction { name: "PRED", description: "Returns the enum value declared before the given one", params: &["value"] },
 ];
 
 pub struct CompletionProvider;
@@ -372,11 +395,15 @@ impl CompletionProvider {
         // Always include constants (without scope filtering for now)
         for constant in &symbols.constants {
             if matches_prefix(&constant.name) {
+                let (detail, documentation) = match &constant.value {
+                    Some(value) => (format!("Constant = {}", value), format!("Constant: {} = {}", constant.name, value)),
+                    None => ("Constant".to_string(), format!("Constant: {}", constant.name)),
+                };
                 suggestions.push(CompletionItem {
                     label: constant.name.clone(),
                     kind: CompletionItemKind::Constant,
-                    detail: Some("Constant".to_string()),
-                    documentation: Some(format!("Constant: {}", constant.name)),
+                    detail: Some(detail),
+                    documentation: Some(documentation),
                     insert_text: constant.name.clone(),
                 });
             }
@@ -509,20 +536,32 @@ impl CompletionProvider {
             "OUTPUT" => "Outputs a value".to_string(),
             "INPUT" => "Reads input from user".to_string(),
             "ARRAY" => "Array type declaration".to_string(),
+            "DATE" => "Date type; date literals are written dd/mm/yyyy".to_string(),
+            "IN" => "<value> IN <set> - Whether the set contains the value. Sets also support + (union), * (intersection) and - (difference)".to_string(),
+            "NULL" => "Pointer that points to nothing; NIL means the same".to_string(),
+            "NEW" => "NEW(<type>) - Allocates a value of the type and returns a pointer to it".to_string(),
             "CASE" => "CASE OF <identifier> - Switch statement".to_string(),
+            "BYREF" => "Passes parameters by reference: the parameter is bound to the caller's variable, element or field, so changes to it happen there at once".to_string(),
+            "BYVAL" => "Passes parameters by value (the default)".to_string(),
             _ => format!("Keyword: {}", keyword),
         }
     }
 
+    fn format_param(param: &ParamInfo) -> String {
+        let mode = match param.mode {
+            PassingMode::BYREF => "BYREF ",
+            PassingMode::BYVAL => "",
+        };
+        if let Some(ref t) = param.type_name {
+            format!("{}{}: {:?}", mode, param.name, t)
+        } else {
+            format!("{}{}", mode, param.name)
+        }
+    }
+
     fn format_function_documentation(func: &FunctionSymbol) -> String {
         let params: String = func.params.iter()
-            .map(|p| {
-                if let Some(ref t) = p.type_name {
-                    format!("{}: {:?}", p.name, t)
-                } else {
-                    p.name.clone()
-                }
-            })
+            .map(Self::format_param)
             .collect::<Vec<_>>()
             .join(", ");
         
@@ -537,13 +576,7 @@ impl CompletionProvider {
 
     fn format_procedure_documentation(proc: &ProcedureSymbol) -> String {
         let params: String = proc.params.iter()
-            .map(|p| {
-                if let Some(ref t) = p.type_name {
-                    format!("{}: {:?}", p.name, t)
-                } else {
-                    p.name.clone()
-                }
-            })
+            .map(Self::format_param)
             .collect::<Vec<_>>()
             .join(", ");
         
@@ -568,11 +601,14 @@ impl HoverProvider {
             return None;
         };
         
-        let before_cursor = if column > 0 && column <= current_line.len() + 1 {
-            &current_line[..(column - 1).min(current_line.len())]
-        } else {
-            current_line
-        };
+        let (before_cursor, after_cursor) = split_at_column(current_line, column);
+
+        // `&` is not part of a word, so it is looked for under the cursor itself
+        let at_cursor = after_cursor.chars().next();
+        let ends_word = before_cursor.chars().next_back().is_some_and(|c| c.is_alphanumeric() || c == '_');
+        if at_cursor == Some('&') && !ends_word {
+            return Some("**&**\n\n<string> & <string> - Joins two strings. A CHAR on either side joins as a one-character string".to_string());
+        }
 
         let word = ContextAnalyzer::extract_prefix(before_cursor);
         if word.is_empty() {
@@ -592,6 +628,21 @@ impl HoverProvider {
             return Some(format!("**{}({})**\n\n{}", func.name, params, func.description));
         }
 
+        // Before variables, as `CONSTANT Name` locks a variable declared under the same name
+        if let Some(constant) = symbols.constants.iter().find(|c| c.name == word) {
+            return Some(match &constant.value {
+                Some(value) => format!("**Constant:** `{} = {}`", constant.name, value),
+                None => {
+                    let type_info = symbols.variables.iter()
+                        .find(|v| v.name == word)
+                        .and_then(|v| v.type_name.as_ref())
+                        .map(|type_name| format!(": {:?}", type_name))
+                        .unwrap_or_default();
+                    format!("**Constant:** `{}{}`", constant.name, type_info)
+                }
+            });
+        }
+
         if let Some(variable) = symbols.variables.iter().find(|v| v.name == word) {
             let type_info = if let Some(ref type_name) = variable.type_name {
                 format!(": {:?}", type_name)
@@ -601,10 +652,6 @@ impl HoverProvider {
             return Some(format!("**Variable:** `{}{}`", variable.name, type_info));
         }
 
-        if let Some(constant) = symbols.constants.iter().find(|c| c.name == word) {
-            return Some(format!("**Constant:** `{}`", constant.name));
-        }
-
         if let Some(func) = symbols.functions.iter().find(|f| f.name == word) {
             return Some(format!("**Function:** {}", CompletionProvider::format_function_documentation(func)));
         }
@@ -621,3 +668,137 @@ impl HoverProvider {
     }
 }
 
+
+/// Where a name appears in the source, counting lines and columns from 1
+#[derive(Debug, Clone, PartialEq)]
+pub struct Occurrence {
+    pub line: usize,
+    pub column: usize,
+    /// Column just past the name
+    pub end_column: usize,
+    /// Whether the name is declared here rather than used
+    pub is_declaration: bool,
+}
+
+/// A name in the source, and the procedure or function it is local to
+struct NameUse {
+    name: String,
+    /// Index of the token naming the routine, `None` for globals
+    scope: Option<usize>,
+    occurrence: Occurrence,
+}
+
+/// Finds every use of a variable, constant, procedure, function or type, for
+/// go-to-definition, find-references and rename.
+///
+/// Inside a procedure or function, its parameters and the names it declares
+/// refer to its own variables; elsewhere a name refers to the global one.
+/// Record fields, reached through `.`, are not tracked.
+pub struct ReferenceFinder;
+
+impl ReferenceFinder {
+    /// The name at `line` and `column`, and everywhere that refers to the same thing
+    pub fn find(code: &str, line: usize, column: usize, statements: &[Stmt]) -> Option<(String, Vec<Occurrence>)> {
+        let names = Self::names(code, statements);
+        let target = names.iter().find(|name| {
+            name.occurrence.line == line && name.occurrence.column <= column && column <= name.occurrence.end_column
+        })?;
+        let occurrences = names.iter()
+            .filter(|name| name.name == target.name && name.scope == target.scope)
+            .map(|name| name.occurrence.clone())
+            .collect();
+        Some((target.name.clone(), occurrences))
+    }
+
+    /// Where the name at `line` and `column` is declared
+    pub fn definition(code: &str, line: usize, column: usize, statements: &[Stmt]) -> Option<Occurrence> {
+        let (_, occurrences) = Self::find(code, line, column, statements)?;
+        occurrences.into_iter().find(|occurrence| occurrence.is_declaration)
+    }
+
+    fn names(code: &str, statements: &[Stmt]) -> Vec<NameUse> {
+        let (tokens, _) = Lexer::new(code).tokenize_with_pos();
+        let locals = Self::routine_locals(statements);
+        let keyword = |index: Option<usize>, words: &[&str]| {
+            matches!(index.map(|i| &tokens[i].token), Some(Token::Keyword(k)) if words.contains(&k.as_str()))
+        };
+
+        let mut names = Vec::new();
+        // The routine being read, by the index of its name, and its local names
+        let mut routine: Option<(usize, &[String])> = None;
+        // On the first line of a routine or type, where parameters and enum values are declared
+        let mut header = false;
+        let mut in_record = false;
+        for (i, token) in tokens.iter().enumerate() {
+            let previous = i.checked_sub(1);
+            let name = match &token.token {
+                Token::Identifier(name) => name,
+                Token::Newline => {
+                    header = false;
+                    continue;
+                }
+                Token::Keyword(k) if k == "ENDPROCEDURE" || k == "ENDFUNCTION" => {
+                    routine = None;
+                    continue;
+                }
+                Token::Keyword(k) if k == "ENDTYPE" => {
+                    in_record = false;
+                    continue;
+                }
+                _ => continue,
+            };
+            let previous_token = previous.map(|p| &tokens[p].token);
+            if matches!(previous_token, Some(Token::Dot)) || (in_record && keyword(previous, &["DECLARE"])) {
+                continue;
+            }
+
+            let mut scope = None;
+            if keyword(previous, &["PROCEDURE", "FUNCTION"]) {
+                let own = locals.get(name).map_or(&[][..], Vec::as_slice);
+                routine = Some((i, own));
+                header = true;
+            } else if keyword(previous, &["TYPE"]) {
+                header = true;
+                in_record = matches!(tokens.get(i + 1).map(|t| &t.token), Some(Token::Newline));
+            } else if let Some((index, own)) = routine {
+                if own.contains(name) {
+                    scope = Some(index);
+                }
+            }
+
+            let is_declaration = keyword(previous, &["DECLARE", "CONSTANT", "TYPE", "PROCEDURE", "FUNCTION", "BYREF", "BYVAL"])
+                || (header && matches!(previous_token, Some(Token::LeftParen | Token::Comma)));
+            names.push(NameUse {
+                name: name.clone(),
+                scope,
+                occurrence: Occurrence {
+                    line: token.line,
+                    column: token.column,
+                    end_column: token.end_column,
+                    is_declaration,
+                },
+            });
+        }
+        names
+    }
+
+    /// Parameters and declared names of each procedure and function
+    fn routine_locals(statements: &[Stmt]) -> HashMap<String, Vec<String>> {
+        let mut locals = HashMap::new();
+        for stmt in statements {
+            let (name, params, body) = match stmt {
+                Stmt::FunctionDeclaration { function, .. } => (&function.name, &function.params, &function.body),
+                Stmt::ProcedureDeclaration { procedure, .. } => (&procedure.name, &procedure.params, &procedure.body),
+                _ => continue,
+            };
+            let mut names: Vec<String> = params.iter().map(|param| param.name.clone()).collect();
+            for stmt in body {
+                if let Stmt::Declare { name, .. } | Stmt::Constant { name, .. } = stmt {
+                    names.push(name.clone());
+                }
+            }
+            locals.insert(name.clone(), names);
+        }
+        locals
+    }
+}
@@ -1,8 +1,13 @@
-use log::{debug, trace, error};
+use log::{debug, trace};
+
+use crate::diagnostic::{codes, Diagnostic, SourceSpan};
 
 #[derive(Debug, Clone, PartialEq)]  // Add Debug if not already there
+#[allow(clippy::upper_case_acronyms)]
 pub enum Token {
     Number(String),
+    /// dd/mm/yyyy, checked to be a real date by the parser
+    Date(String),
     Identifier(String),
     String(String),
     Keyword(String),
@@ -13,6 +18,8 @@ pub enum Token {
     Multiply,
     Divide,
     _Modulus,
+    /// `&`, joining strings
+    Ampersand,
     Equals,
     NotEquals,
     LessThan,
@@ -58,6 +65,9 @@ pub struct TokenWithPos {
     pub token: Token,
     pub line: usize,
     pub column: usize,
+    /// Position just past the token's last character
+    pub end_line: usize,
+    pub end_column: usize,
 }
 
 impl Lexer {
@@ -70,34 +80,54 @@ impl Lexer {
         }
     }
 
-    pub fn _tokenize(&mut self) -> Vec<Token> {
+    pub fn _tokenize(&mut self) -> Result<Vec<Token>, Diagnostic> {
         let mut tokens = Vec::new();
         loop {
-            let token = self.next_token();
+            let token = self.next_token()?;
             tokens.push(token.clone());  // Need Clone on Token enum
             
             if token == Token::EOF {
                 break;
             }
         }
-        tokens
+        Ok(tokens)
     }
     
-    pub fn tokenize_with_pos(&mut self) -> Vec<TokenWithPos> {
+    /// Tokenize the whole input. Characters that do not start any token are
+    /// reported and skipped, so one stray character does not end lexing.
+    pub fn tokenize_with_pos(&mut self) -> (Vec<TokenWithPos>, Vec<Diagnostic>) {
         debug!("Starting tokenization");
         let mut tokens = Vec::new();
+        let mut errors = Vec::new();
         loop {
-            // Capture position BEFORE calling next_token (which may advance line/column)
+            // Capture position after leading whitespace, but BEFORE calling next_token
+            // (which may advance line/column)
+            self.skip_whitespace();
             let line = self.line;
             let column = self.column;
-            let token = self.next_token();
+            let token = match self.next_token() {
+                Ok(token) => token,
+                Err(error) => {
+                    errors.push(error);
+                    self.advance();
+                    continue;
+                }
+            };
             
             trace!("Tokenized: {:?} at {}:{}", token, line, column);
             
+            // A newline token ends on the line it terminates
+            let (end_line, end_column) = if token == Token::Newline {
+                (line, column + 1)
+            } else {
+                (self.line, self.column)
+            };
             tokens.push(TokenWithPos {
                 token: token.clone(),
                 line,
                 column,
+                end_line,
+                end_column,
             });
             
             if token == Token::EOF {
@@ -105,7 +135,7 @@ impl Lexer {
                 break;
             }
         }
-        tokens
+        (tokens, errors)
     }
 
     fn skip_whitespace(&mut self) {
@@ -184,6 +214,32 @@ impl Lexer {
         Token::Number(number)
     }
 
+    /// Length of the date literal starting here, if there is one: 1 or 2
+    /// digits, a slash, 1 or 2 digits, a slash and 4 digits
+    fn date_length(&self) -> Option<usize> {
+        let rest = &self.input[self.pos..];
+        let digits_at = |start: usize| rest[start.min(rest.len())..].iter()
+            .take_while(|ch| ch.is_ascii_digit())
+            .count();
+
+        let day = digits_at(0);
+        if !(1..=2).contains(&day) || rest.get(day) != Some(&'/') {
+            return None;
+        }
+        let month = digits_at(day + 1);
+        let month_end = day + 1 + month;
+        if !(1..=2).contains(&month) || rest.get(month_end) != Some(&'/') {
+            return None;
+        }
+        let year = digits_at(month_end + 1);
+        (year == 4).then_some(month_end + 1 + year)
+    }
+
+    fn read_date(&mut self, length: usize) -> Token {
+        let date: String = (0..length).filter_map(|_| self.advance()).collect();
+        Token::Date(date)
+    }
+
     fn read_id_or_kwd(&mut self) -> Token {
         let mut id = String::new();
         while let Some(ch) = self.peek() {
@@ -205,7 +261,7 @@ impl Lexer {
             | "READFILE" | "MOD" | "LENGTH" | "SET" | "OF" | "TO" | "STEP" | "UNTIL" | "ROUND" | "RAND"
             | "STRING" | "INTEGER" | "REAL" | "CHAR" | "BOOLEAN" | "DATE" | "ARRAY" | "ENDCASE" | "CONSTANT"
             | "UCASE" | "LCASE" | "READ" | "WRITE" | "RANDOM" | "CASE" | "OTHERWISE" | "DIV" | "INT"
-            | "SUBSTRING" | "MID" | "RIGHT" | "EOF" => Token::Keyword(id),
+            | "SUBSTRING" | "MID" | "RIGHT" | "EOF" | "BYREF" | "BYVAL" | "NULL" | "NIL" | "NEW" | "IN" => Token::Keyword(id),
             _ => Token::Identifier(id),
         }
     }
@@ -250,72 +306,75 @@ impl Lexer {
         Token::Char(char)
     }
 
-    pub fn next_token(&mut self) -> Token {
+    pub fn next_token(&mut self) -> Result<Token, Diagnostic> {
         self.skip_whitespace();
         
         if self.pos >= self.input.len() {
-            return Token::EOF;
+            return Ok(Token::EOF);
         }
 
         let ch = self.peek().unwrap();
 
         if ch == '\n' {
             self.advance();
-            return Token::Newline;
+            return Ok(Token::Newline);
         }
 
         if ch == '\r' {
             self.advance();
-            return Token::Newline;
+            return Ok(Token::Newline);
         }
 
         if ch == '"' {
-            return self.read_string();
+            return Ok(self.read_string());
         }
 
         if ch == '\'' {
-            return self.read_char();
+            return Ok(self.read_char());
         }
 
         if ch.is_ascii_digit() {
-            return self.read_number();
+            if let Some(length) = self.date_length() {
+                return Ok(self.read_date(length));
+            }
+            return Ok(self.read_number());
         }
         
         if ch.is_ascii_alphabetic() {
-            return self.read_id_or_kwd();
+            return Ok(self.read_id_or_kwd());
         }
 
         if ch == '-' && self.peek_next() == Some('>') {    
             self.advance();
             self.advance();
-            return Token::RightArrow;
+            return Ok(Token::RightArrow);
         }
 
         if ch == '<' && self.peek_next() == Some('-') {
             self.advance();
             self.advance();
-            return Token::LeftArrow;
+            return Ok(Token::LeftArrow);
         }
         
         if ch == '<' && self.peek_next() == Some('>') {
             self.advance();
             self.advance();
-            return Token::NotEquals;
+            return Ok(Token::NotEquals);
         }
 
         if ch == '<' && self.peek_next() == Some('=') {
             self.advance();
             self.advance();
-            return Token::LessThanOrEqual;
+            return Ok(Token::LessThanOrEqual);
         }
 
         if ch == '>' && self.peek_next() == Some('=') {
             self.advance();
             self.advance();
-            return Token::GreaterThanOrEqual;
+            return Ok(Token::GreaterThanOrEqual);
         }
 
-        match ch {
+        let token = match ch {
             '+' => { self.advance(); Token::Plus }
             '-' => { self.advance(); Token::Minus }
             '*' => { self.advance(); Token::Multiply }
@@ -331,12 +390,13 @@ impl Lexer {
             ':' => { self.advance(); Token::Colon }
             '^' => { self.advance(); Token::Caret }
             '.' => { self.advance(); Token::Dot }
+            '&' => { self.advance(); Token::Ampersand }
             _ => {
-                let ch = self.advance().unwrap();
-                let msg = format!("Unexpected character: '{}' at line {}:{}", ch, self.line, self.column);
-                error!("{}", msg);
-                panic!("{}", msg);
+                let span = SourceSpan::point(self.line, self.column);
+                return Err(Diagnostic::error(codes::UNEXPECTED_CHARACTER, format!("Unexpected character: '{}'", ch))
+                    .with_span(span));
             }
-        }
+        };
+        Ok(token)
     }
 }
\ No newline at end of file
@@ -1,13 +1,41 @@
-mod lexer;
-mod parser;
-mod ast;
-mod wasm_interpreter;
-mod language_service;
+// `Diagnostic` is the error type of every phase; boxing it everywhere buys nothing
+#![allow(clippy::result_large_err)]
+
+// Shared with the `pseudocode` CLI binary
+pub mod lexer;
+pub mod parser;
+pub mod checker;
+pub mod ast;
+pub mod log;
+pub mod diagnostic;
+pub mod value;
+pub mod heap;
+pub mod date;
+pub mod builtins;
+pub mod host;
+pub mod files;
+pub mod record;
+pub mod interpreter;
+pub mod bytecode;
+pub mod compiler;
+pub mod vm;
+pub mod debugger;
+pub mod limits;
+mod environment;
+pub mod language_service;
 
 use wasm_bindgen::prelude::*;
 use serde::{Serialize, Deserialize};
-use crate::wasm_interpreter::WasmInterpreter;
+use crate::checker::TypeChecker;
+use crate::date::Date;
+use crate::diagnostic::{Diagnostic, SourceSpan};
+use crate::files::VirtualFileSystem;
+use crate::host::{BufferConsole, FixedClock, QueueInput, ThreadRandom, VirtualHost};
+use crate::bytecode::Program;
+use crate::compiler::Compiler;
+use crate::limits::Limits;
 use crate::parser::Parser;
+use crate::vm::{Suspend, Vm};
 use crate::language_service::{CompletionProvider, HoverProvider, CompletionItemKind};
 
 // Initialize panic hook for better error messages in the browser
@@ -19,6 +47,7 @@ pub fn init() {
 #[derive(Serialize, Deserialize)]
 pub struct ExecutionResult {
     pub output: String,
+    /// Any error, and the warnings raised while running
     pub errors: Vec<ErrorInfo>,
 }
 
@@ -27,10 +56,33 @@ pub struct ErrorInfo {
     pub message: String,
     pub line: usize,
     pub column: usize,
+    pub end_line: usize,
+    pub end_column: usize,
+    pub severity: String, // "error", "warning", "note"
+    pub code: String,
+    pub notes: Vec<String>,
+}
+
+impl From<Diagnostic> for ErrorInfo {
+    fn from(diagnostic: Diagnostic) -> Self {
+        // Errors without a known position are reported at the start of the program
+        let span = diagnostic.span.unwrap_or(SourceSpan::point(1, 1));
+        ErrorInfo {
+            message: diagnostic.message,
+            line: span.start_line,
+            column: span.start_column,
+            end_line: span.end_line,
+            end_column: span.end_column,
+            severity: diagnostic.severity.as_str().to_string(),
+            code: diagnostic.code,
+            notes: diagnostic.notes,
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct SyntaxCheckResult {
+    /// Whether none of `errors` is more than a warning
     pub valid: bool,
     pub errors: Vec<ErrorInfo>,
 }
@@ -54,20 +106,37 @@ pub struct HoverInfo {
     pub contents: String,
 }
 
+/// What `resume` got to
+#[derive(Serialize, Deserialize)]
+pub struct RunResult {
+    /// Output since the last call
+    pub output: String,
+    /// Any error, and the warnings raised since the last call
+    pub errors: Vec<ErrorInfo>,
+    /// "finished", "input" or "error"
+    pub status: String,
+    /// The INPUT waiting for `provide_input`, when the status is "input"
+    pub input: Option<InputRequest>,
+}
+
 #[derive(Serialize, Deserialize)]
-pub struct StatementInfo {
-    pub is_input: bool,
-    pub input_var_name: Option<String>,
+pub struct InputRequest {
+    pub name: String,
+    pub type_name: String,
     pub line: usize,
 }
 
+type WasmVm = Vm<BufferConsole, QueueInput, VirtualFileSystem, ThreadRandom, FixedClock>;
+
 #[wasm_bindgen]
 pub struct PseudocodeEngine {
-    interpreter: WasmInterpreter,
-    #[wasm_bindgen(skip)]
-    parsed_statements: Vec<crate::ast::Stmt>,
-    #[wasm_bindgen(skip)]
-    current_statement_index: usize,
+    vm: WasmVm,
+}
+
+impl Default for PseudocodeEngine {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 #[wasm_bindgen]
@@ -75,171 +144,74 @@ impl PseudocodeEngine {
     #[wasm_bindgen(constructor)]
     pub fn new() -> PseudocodeEngine {
         PseudocodeEngine {
-            interpreter: WasmInterpreter::new(),
-            parsed_statements: Vec::new(),
-            current_statement_index: 0,
+            vm: Vm::new(Program::default(), VirtualHost::virtual_host()),
         }
     }
 
-    /// Parse code and prepare for step-by-step execution
+    /// Parse and compile code, ready for `resume` to run it
     #[wasm_bindgen]
     pub fn parse_for_execution(&mut self, code: &str) -> JsValue {
-        // Reset interpreter state (clear variables, functions, etc.)
-        self.interpreter.reset();
-        // Clear previous state
-        self.parsed_statements.clear();
-        self.current_statement_index = 0;
-        
-        // Parse the code
-        let mut parser = Parser::new(code);
-        match parser.parse_program() {
-            Ok(stmts) => {
-                self.parsed_statements = stmts;
-                serde_wasm_bindgen::to_value(&SyntaxCheckResult {
-                    valid: true,
-                    errors: Vec::new(),
-                }).unwrap()
+        self.reset();
+        let (valid, errors) = match compile(code) {
+            Ok((program, warnings)) => {
+                self.vm.load(program);
+                (true, warnings)
             }
-            Err(e) => {
-                let error_info = ErrorInfo {
-                    message: e,
-                    line: 1,
-                    column: 1,
-                };
-                serde_wasm_bindgen::to_value(&SyntaxCheckResult {
-                    valid: false,
-                    errors: vec![error_info],
-                }).unwrap()
-            }
-        }
-    }
-
-    /// Get information about the next statement to execute
-    #[wasm_bindgen]
-    pub fn get_next_statement_info(&self) -> JsValue {
-        if self.current_statement_index >= self.parsed_statements.len() {
-            return serde_wasm_bindgen::to_value(&StatementInfo {
-                is_input: false,
-                input_var_name: None,
-                line: 0,
-            }).unwrap();
-        }
-        
-        let stmt = &self.parsed_statements[self.current_statement_index];
-        let (is_input, input_var_name) = match stmt {
-            crate::ast::Stmt::Input { name, .. } => (true, Some(name.clone())),
-            _ => (false, None),
+            Err(errors) => (false, errors),
         };
-        
-        let line = get_stmt_span(stmt).map(|s| s.line).unwrap_or(0);
-        
-        serde_wasm_bindgen::to_value(&StatementInfo {
-            is_input,
-            input_var_name,
-            line,
+        serde_wasm_bindgen::to_value(&SyntaxCheckResult {
+            valid,
+            errors: errors.into_iter().map(ErrorInfo::from).collect(),
         }).unwrap()
     }
-    
-    /// Validate if a variable can be used for INPUT (before prompting)
-    /// Returns empty string if valid, or error message if invalid
-    #[wasm_bindgen]
-    pub fn validate_input_variable(&self, var_name: &str) -> String {
-        self.interpreter.validate_input_variable(var_name)
-            .unwrap_or_else(|| String::new())
-    }
 
-    /// Execute the next statement and return output since last call
+    /// Run the program from `parse_for_execution` until it finishes, fails or
+    /// reaches an INPUT with no input given. INPUT inside loops, procedures and
+    /// functions pauses the same way; `provide_input` then lets it carry on.
     #[wasm_bindgen]
-    pub fn execute_next_statement(&mut self) -> JsValue {
-        if self.current_statement_index >= self.parsed_statements.len() {
-            return serde_wasm_bindgen::to_value(&ExecutionResult {
-                output: String::new(),
-                errors: Vec::new(),
-            }).unwrap();
-        }
-        
-        // Get output before execution
-        let output_before = self.interpreter.get_output().to_string();
-        
-        // Execute the statement
-        let stmt = &self.parsed_statements[self.current_statement_index];
-        let mut errors = Vec::new();
-        let output_after = if let Err(e) = self.interpreter.evaluate_stmt(stmt) {
-            let line = get_stmt_span(stmt).map(|s| s.line).unwrap_or(1);
-            errors.push(ErrorInfo {
-                message: e,
-                line,
-                column: 1,
-            });
-            self.interpreter.get_output().to_string()
-        } else {
-            self.interpreter.get_output().to_string()
+    pub fn resume(&mut self) -> JsValue {
+        let (status, error, input) = match self.vm.resume() {
+            Ok(Suspend::Finished) => ("finished", None, None),
+            Ok(Suspend::Input { name, type_name, line }) => ("input", None, Some(InputRequest { name, type_name, line })),
+            Err(e) => ("error", Some(e), None),
         };
-        
-        // Calculate new output (difference)
-        let new_output = if output_after.len() > output_before.len() {
-            output_after[output_before.len()..].to_string()
-        } else {
-            String::new()
-        };
-        
-        self.current_statement_index += 1;
-        
-        serde_wasm_bindgen::to_value(&ExecutionResult {
-            output: new_output,
+        let errors = self.vm.take_warnings().into_iter().chain(error).map(ErrorInfo::from).collect();
+        let console = &mut self.vm.host_mut().console;
+        let output = console.output().to_string();
+        console.clear();
+        serde_wasm_bindgen::to_value(&RunResult {
+            output,
             errors,
+            status: status.to_string(),
+            input,
         }).unwrap()
     }
 
-    /// Check if there are more statements to execute
+    /// Give the INPUT `resume` stopped at its line
     #[wasm_bindgen]
-    pub fn has_more_statements(&self) -> bool {
-        self.current_statement_index < self.parsed_statements.len()
+    pub fn provide_input(&mut self, input: String) {
+        self.vm.host_mut().input.push(input);
     }
 
-    /// Execute pseudocode and return results
+    /// Execute pseudocode and return results. INPUT reads from `add_input`.
     #[wasm_bindgen]
     pub fn execute(&mut self, code: &str) -> JsValue {
-        // Reset interpreter state (clear variables, functions, etc.)
-        self.interpreter.reset();
-        
-        // Parse the code
-        let mut parser = Parser::new(code);
-        let statements = match parser.parse_program() {
-            Ok(stmts) => stmts,
-            Err(e) => {
-                // Extract line number from error if possible
-                let error_info = ErrorInfo {
-                    message: e.clone(),
-                    line: 1,
-                    column: 1,
-                };
+        self.reset();
+        let program = match compile(code) {
+            Ok((program, _)) => program,
+            Err(errors) => {
                 return serde_wasm_bindgen::to_value(&ExecutionResult {
                     output: String::new(),
-                    errors: vec![error_info],
+                    errors: errors.into_iter().map(ErrorInfo::from).collect(),
                 }).unwrap();
             }
         };
 
-        // Execute statements
-        let mut errors = Vec::new();
-        for stmt in &statements {
-            if let Err(e) = self.interpreter.evaluate_stmt(stmt) {
-                // Try to extract line number from error message
-                let line = if let Some(span) = get_stmt_span(stmt) {
-                    span.line
-                } else {
-                    1
-                };
-                errors.push(ErrorInfo {
-                    message: e,
-                    line,
-                    column: 1,
-                });
-            }
-        }
+        self.vm.load(program);
+        let error = self.vm.run().err();
+        let errors = self.vm.take_warnings().into_iter().chain(error).map(ErrorInfo::from).collect();
 
-        let output = self.interpreter.get_output().to_string();
+        let output = self.output().to_string();
         
         serde_wasm_bindgen::to_value(&ExecutionResult {
             output,
@@ -251,49 +223,69 @@ impl PseudocodeEngine {
     #[wasm_bindgen]
     pub fn check_syntax(&self, code: &str) -> JsValue {
         let mut parser = Parser::new(code);
-        match parser.parse_program() {
-            Ok(_) => {
-                serde_wasm_bindgen::to_value(&SyntaxCheckResult {
-                    valid: true,
-                    errors: Vec::new(),
-                }).unwrap()
-            }
-            Err(e) => {
-                let error_info = ErrorInfo {
-                    message: e,
-                    line: 1,
-                    column: 1,
-                };
-                serde_wasm_bindgen::to_value(&SyntaxCheckResult {
-                    valid: false,
-                    errors: vec![error_info],
-                }).unwrap()
-            }
+        let (statements, mut errors) = parser.parse_program_with_errors();
+        // Type errors are only meaningful once the program parses
+        if errors.is_empty() {
+            errors = TypeChecker::new().check(&statements);
         }
+        serde_wasm_bindgen::to_value(&SyntaxCheckResult {
+            valid: !errors.iter().any(Diagnostic::is_error),
+            errors: errors.into_iter().map(ErrorInfo::from).collect(),
+        }).unwrap()
+    }
+
+    /// Limit what programs run from now on may use, e.g. `{ max_steps: 1000000 }`.
+    /// Steps are VM instructions. Limits left out are unlimited, except the
+    /// call depth, which defaults to `DEFAULT_MAX_CALL_DEPTH`.
+    #[wasm_bindgen]
+    pub fn set_limits(&mut self, limits: JsValue) -> Result<(), JsValue> {
+        let limits: Limits = serde_wasm_bindgen::from_value(limits)?;
+        self.vm.set_limits(limits);
+        Ok(())
+    }
+
+    /// Set the date NOW returns. The engine has no clock of its own in the browser.
+    #[wasm_bindgen]
+    pub fn set_today(&mut self, day: i32, month: i32, year: i32) -> Result<(), JsValue> {
+        let today = Date::new(day.into(), month.into(), year.into()).map_err(|msg| JsValue::from_str(&msg))?;
+        self.vm.host_mut().clock.set(today);
+        Ok(())
     }
 
     /// Set a virtual file in the file system
     #[wasm_bindgen]
     pub fn set_virtual_file(&mut self, filename: String, content: String) {
-        self.interpreter.set_virtual_file(filename, content);
+        self.vm.host_mut().files.set_file(filename, content);
     }
 
     /// Get a virtual file from the file system
     #[wasm_bindgen]
     pub fn get_virtual_file(&self, filename: &str) -> Option<String> {
-        self.interpreter.get_virtual_file(filename).cloned()
+        self.vm.host().files.get_file(filename)
+    }
+
+    /// Set a virtual file with binary content, e.g. records written by PUTRECORD
+    #[wasm_bindgen]
+    pub fn set_virtual_file_bytes(&mut self, filename: String, content: Vec<u8>) {
+        self.vm.host_mut().files.set_file_bytes(filename, content);
+    }
+
+    /// Get a virtual file's raw bytes
+    #[wasm_bindgen]
+    pub fn get_virtual_file_bytes(&self, filename: &str) -> Option<Vec<u8>> {
+        self.vm.host().files.get_file_bytes(filename)
     }
 
     /// Add input to the input queue
     #[wasm_bindgen]
     pub fn add_input(&mut self, input: String) {
-        self.interpreter.add_input(input);
+        self.vm.host_mut().input.push(input);
     }
 
     /// Clear the input queue
     #[wasm_bindgen]
     pub fn clear_inputs(&mut self) {
-        self.interpreter.clear_inputs();
+        self.vm.host_mut().input.clear();
     }
 
     /// Get all INPUT statements from code (variable names in order)
@@ -317,16 +309,9 @@ impl PseudocodeEngine {
     /// Get autocomplete suggestions at a given position
     #[wasm_bindgen]
     pub fn get_completions(&self, code: &str, line: usize, column: usize) -> JsValue {
-        // Try to parse the code (best effort - collect symbols even if parse fails)
+        // Best effort - symbols from the statements that did parse are still offered
         let mut parser = Parser::new(code);
-        let statements = match parser.parse_program() {
-            Ok(stmts) => stmts,
-            Err(_) => {
-                // Even if parsing fails, we can still provide keywords and built-in functions
-                // Use empty statements vector - CompletionProvider will still return keywords/built-ins
-                Vec::new()
-            }
-        };
+        let (statements, _) = parser.parse_program_with_errors();
 
         let items = CompletionProvider::get_completions(code, line, column, &statements);
         
@@ -355,17 +340,9 @@ impl PseudocodeEngine {
     /// Get hover information at a given position
     #[wasm_bindgen]
     pub fn get_hover(&self, code: &str, line: usize, column: usize) -> JsValue {
-        // Try to parse the code (best effort)
+        // Best effort - a broken statement elsewhere does not stop hover working
         let mut parser = Parser::new(code);
-        let statements = match parser.parse_program() {
-            Ok(stmts) => stmts,
-            Err(_) => {
-                // Return empty hover if parse fails
-                return serde_wasm_bindgen::to_value(&HoverInfo {
-                    contents: String::new(),
-                }).unwrap();
-            }
-        };
+        let (statements, _) = parser.parse_program_with_errors();
 
         if let Some(contents) = HoverProvider::get_hover_info(code, line, column, &statements) {
             serde_wasm_bindgen::to_value(&HoverInfo {
@@ -379,28 +356,38 @@ impl PseudocodeEngine {
     }
 }
 
-// Helper function to extract span from statement
-fn get_stmt_span(stmt: &crate::ast::Stmt) -> Option<crate::ast::Span> {
-    match stmt {
-        crate::ast::Stmt::Declare { span, .. } => Some(span.clone()),
-        crate::ast::Stmt::Assign { span, .. } => Some(span.clone()),
-        crate::ast::Stmt::Output { span, .. } => Some(span.clone()),
-        crate::ast::Stmt::Input { span, .. } => Some(span.clone()),
-        crate::ast::Stmt::If { span, .. } => Some(span.clone()),
-        crate::ast::Stmt::While { span, .. } => Some(span.clone()),
-        crate::ast::Stmt::For { span, .. } => Some(span.clone()),
-        crate::ast::Stmt::FunctionDeclaration { span, .. } => Some(span.clone()),
-        crate::ast::Stmt::ProcedureDeclaration { span, .. } => Some(span.clone()),
-        _ => None,
+impl PseudocodeEngine {
+    /// Clear output and queued input. Virtual files persist across runs.
+    fn reset(&mut self) {
+        let host = self.vm.host_mut();
+        host.console.clear();
+        host.input.clear();
+    }
+
+    fn output(&self) -> &str {
+        self.vm.host().console.output()
+    }
+}
+
+/// Parse, type check and compile a program about to run, returning it with the
+/// type checker's warnings, or the first syntax error or every type error
+fn compile(code: &str) -> Result<(Program, Vec<Diagnostic>), Vec<Diagnostic>> {
+    let statements = Parser::new(code).parse_program().map_err(|e| vec![e])?;
+    let (errors, warnings): (Vec<_>, Vec<_>) = TypeChecker::new().check(&statements)
+        .into_iter()
+        .partition(Diagnostic::is_error);
+    if !errors.is_empty() {
+        return Err(errors);
     }
+    let program = Compiler::new().compile(&statements).map_err(|e| vec![e])?;
+    Ok((program, warnings))
 }
 
-// Helper function to extract all INPUT statements from AST
 fn extract_input_statements(statements: &[crate::ast::Stmt], input_vars: &mut Vec<String>) {
     for stmt in statements {
         match stmt {
-            crate::ast::Stmt::Input { name, .. } => {
-                input_vars.push(name.clone());
+            crate::ast::Stmt::Input { target, .. } => {
+                input_vars.push(target.to_string());
             }
             crate::ast::Stmt::If { then_stmt, else_stmt, .. } => {
                 extract_input_statements(then_stmt, input_vars);
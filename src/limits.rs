@@ -0,0 +1,168 @@
+use std::fmt;
+
+use serde::{Serialize, Deserialize};
+
+use crate::diagnostic::{codes, Diagnostic};
+
+/// Calls nested at once unless a limit is given, deep enough for any sensible
+/// recursion but caught long before the interpreter runs out of native stack.
+/// The command line accepts no deeper limit.
+pub const DEFAULT_MAX_CALL_DEPTH: u64 = 10_000;
+
+/// Upper bounds on the resources a program may use, for running untrusted
+/// code. `None` means unlimited, which is the default for everything but
+/// the call depth.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Limits {
+    /// Statements run by the interpreter (`eval`, `debug` and `dap`), or
+    /// instructions run by the VM (`run` and the browser), so the same
+    /// program takes more steps on the VM. Every loop iteration counts as a
+    /// step even when its body is empty.
+    pub max_steps: Option<u64>,
+    /// Procedure and function calls active at once
+    pub max_call_depth: Option<u64>,
+    /// Bytes written by OUTPUT
+    pub max_output_bytes: Option<u64>,
+    /// Array elements and NEW cells allocated over the whole run
+    pub max_elements: Option<u64>,
+    /// Files open at once
+    pub max_open_files: Option<u64>,
+    /// Wall-clock time in milliseconds. Only enforced natively: there is no
+    /// clock to read in the browser.
+    pub max_time_ms: Option<u64>,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_steps: None,
+            max_call_depth: Some(DEFAULT_MAX_CALL_DEPTH),
+            max_output_bytes: None,
+            max_elements: None,
+            max_open_files: None,
+            max_time_ms: None,
+        }
+    }
+}
+
+/// The resource a `LimitExceeded` error is about
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Limit {
+    Steps,
+    CallDepth,
+    OutputBytes,
+    Elements,
+    OpenFiles,
+    Time,
+}
+
+/// A program went over one of its `Limits`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LimitExceeded {
+    pub limit: Limit,
+    pub max: u64,
+}
+
+impl fmt::Display for LimitExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let max = self.max;
+        match self.limit {
+            Limit::Steps => write!(f, "Limit exceeded: ran more than {} steps", max),
+            Limit::CallDepth => write!(f, "Limit exceeded: calls nested more than {} deep", max),
+            Limit::OutputBytes => write!(f, "Limit exceeded: output more than {} bytes", max),
+            Limit::Elements => write!(f, "Limit exceeded: allocated more than {} array elements", max),
+            Limit::OpenFiles => write!(f, "Limit exceeded: more than {} files open at once", max),
+            Limit::Time => write!(f, "Limit exceeded: ran for more than {} ms", max),
+        }
+    }
+}
+
+impl From<LimitExceeded> for Diagnostic {
+    fn from(error: LimitExceeded) -> Self {
+        Diagnostic::error(codes::LIMIT_EXCEEDED, error.to_string())
+    }
+}
+
+/// What a running program has used so far, checked against its `Limits`
+#[derive(Debug, Default)]
+pub struct Budget {
+    limits: Limits,
+    steps: u64,
+    call_depth: u64,
+    output_bytes: u64,
+    elements: u64,
+    open_files: u64,
+    // Started on the first step, so time spent before the program runs is free
+    #[cfg(not(target_arch = "wasm32"))]
+    started: Option<std::time::Instant>,
+}
+
+fn check(limit: Limit, used: u64, max: Option<u64>) -> Result<(), LimitExceeded> {
+    match max {
+        Some(max) if used > max => Err(LimitExceeded { limit, max }),
+        _ => Ok(()),
+    }
+}
+
+impl Budget {
+    pub fn new(limits: Limits) -> Self {
+        Self { limits, ..Self::default() }
+    }
+
+    /// Forget everything used so far, keeping the limits
+    pub fn reset(&mut self) {
+        *self = Self::new(std::mem::take(&mut self.limits));
+    }
+
+    /// Count one statement, instruction or loop iteration, and check the clock
+    pub fn step(&mut self) -> Result<(), LimitExceeded> {
+        self.steps += 1;
+        check(Limit::Steps, self.steps, self.limits.max_steps)?;
+
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(max) = self.limits.max_time_ms {
+            let started = *self.started.get_or_insert_with(std::time::Instant::now);
+            check(Limit::Time, started.elapsed().as_millis() as u64, Some(max))?;
+        }
+        Ok(())
+    }
+
+    pub fn enter_call(&mut self) -> Result<(), LimitExceeded> {
+        self.call_depth += 1;
+        let result = check(Limit::CallDepth, self.call_depth, self.limits.max_call_depth);
+        if result.is_err() {
+            self.call_depth -= 1;
+        }
+        result
+    }
+
+    pub fn leave_call(&mut self) {
+        self.call_depth = self.call_depth.saturating_sub(1);
+    }
+
+    pub fn output(&mut self, bytes: usize) -> Result<(), LimitExceeded> {
+        self.output_bytes += bytes as u64;
+        check(Limit::OutputBytes, self.output_bytes, self.limits.max_output_bytes)
+    }
+
+    /// Count the elements of an array, or a NEW cell, about to be allocated
+    pub fn allocate(&mut self, elements: usize) -> Result<(), LimitExceeded> {
+        self.elements = self.elements.saturating_add(elements as u64);
+        check(Limit::Elements, self.elements, self.limits.max_elements)
+    }
+
+    /// Count a file about to be opened. Call `close_file` if opening it fails.
+    pub fn open_file(&mut self) -> Result<(), LimitExceeded> {
+        self.open_files += 1;
+        let result = check(Limit::OpenFiles, self.open_files, self.limits.max_open_files);
+        if result.is_err() {
+            self.open_files -= 1;
+        }
+        result
+    }
+
+    pub fn close_file(&mut self) {
+        self.open_files = self.open_files.saturating_sub(1);
+    }
+}
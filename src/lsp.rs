@@ -0,0 +1,315 @@
+use std::collections::HashMap;
+
+use serde_json::{json, Value as Json};
+
+use crate::ast::{Span, Stmt};
+use crate::checker::TypeChecker;
+use crate::diagnostic::{Diagnostic, Severity, SourceSpan};
+use crate::framing::{next_message, write_message};
+use crate::language_service::{CompletionItemKind, CompletionProvider, HoverProvider, Occurrence, ReferenceFinder, SymbolExtractor};
+use crate::lexer::{Lexer, Token};
+use crate::parser::Parser;
+
+/// JSON-RPC error codes
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const REQUEST_FAILED: i64 = -32803;
+
+type Reply = Result<Json, (i64, String)>;
+
+/// Serve the Language Server Protocol on stdin and stdout until the editor
+/// sends `exit`, answering from the same language service as the web editor.
+///
+/// The protocol counts lines and columns from 0, the language service from 1.
+/// Columns count characters, which matches the protocol's UTF-16 units for all
+/// but the rarest characters.
+pub fn serve() {
+    let mut server = Server::default();
+    while let Some(message) = next_message() {
+        // Only requests and notifications have a method; this server sends no
+        // requests, so there are no responses to read
+        let Some(method) = message["method"].as_str() else {
+            continue;
+        };
+        if method == "exit" {
+            std::process::exit(if server.shut_down { 0 } else { 1 });
+        }
+        let params = &message["params"];
+        match message.get("id") {
+            Some(id) => {
+                let reply = match server.request(method, params) {
+                    Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+                    Err((code, message)) => json!({ "jsonrpc": "2.0", "id": id, "error": { "code": code, "message": message } }),
+                };
+                write_message(&reply);
+            }
+            None => server.notify(method, params),
+        }
+    }
+}
+
+/// The open documents, by URI
+#[derive(Default)]
+struct Server {
+    documents: HashMap<String, String>,
+    shut_down: bool,
+}
+
+impl Server {
+    fn request(&mut self, method: &str, params: &Json) -> Reply {
+        match method {
+            "initialize" => Ok(json!({
+                "capabilities": {
+                    "textDocumentSync": 1,
+                    "completionProvider": {},
+                    "hoverProvider": true,
+                    "documentSymbolProvider": true,
+                    "definitionProvider": true,
+                    "referencesProvider": true,
+                    "renameProvider": true,
+                },
+                "serverInfo": { "name": "pseudocode", "version": env!("CARGO_PKG_VERSION") },
+            })),
+            "shutdown" => {
+                self.shut_down = true;
+                Ok(Json::Null)
+            }
+            "textDocument/completion" => {
+                let (_, code) = self.document(params)?;
+                let (line, column) = position(params)?;
+                let statements = parse(code);
+                let items: Vec<Json> = CompletionProvider::get_completions(code, line, column, &statements)
+                    .into_iter()
+                    .map(|item| json!({
+                        "label": item.label,
+                        "kind": completion_kind(item.kind),
+                        "detail": item.detail,
+                        "documentation": item.documentation,
+                        "insertText": item.insert_text,
+                    }))
+                    .collect();
+                Ok(json!(items))
+            }
+            "textDocument/hover" => {
+                let (_, code) = self.document(params)?;
+                let (line, column) = position(params)?;
+                // The hover provider reads the word before the cursor, so point it past the end of the word
+                let column = word_end(code, line, column);
+                let statements = parse(code);
+                Ok(match HoverProvider::get_hover_info(code, line, column, &statements) {
+                    Some(contents) => json!({ "contents": { "kind": "markdown", "value": contents } }),
+                    None => Json::Null,
+                })
+            }
+            "textDocument/documentSymbol" => {
+                let (_, code) = self.document(params)?;
+                Ok(document_symbols(code, &parse(code)))
+            }
+            "textDocument/definition" => {
+                let (uri, code) = self.document(params)?;
+                let (line, column) = position(params)?;
+                Ok(match ReferenceFinder::definition(code, line, column, &parse(code)) {
+                    Some(occurrence) => location(uri, &occurrence),
+                    None => Json::Null,
+                })
+            }
+            "textDocument/references" => {
+                let (uri, code) = self.document(params)?;
+                let (line, column) = position(params)?;
+                let include_declaration = params["context"]["includeDeclaration"].as_bool().unwrap_or(true);
+                let locations: Vec<Json> = ReferenceFinder::find(code, line, column, &parse(code))
+                    .map(|(_, occurrences)| occurrences)
+                    .unwrap_or_default()
+                    .iter()
+                    .filter(|occurrence| include_declaration || !occurrence.is_declaration)
+                    .map(|occurrence| location(uri, occurrence))
+                    .collect();
+                Ok(json!(locations))
+            }
+            "textDocument/rename" => {
+                let (uri, code) = self.document(params)?;
+                let (line, column) = position(params)?;
+                let new_name = params["newName"].as_str().unwrap_or_default();
+                if !is_identifier(new_name) {
+                    return Err((REQUEST_FAILED, format!("'{}' is not a valid name: it must be a letter followed by letters, digits and underscores, and not a keyword", new_name)));
+                }
+                let Some((_, occurrences)) = ReferenceFinder::find(code, line, column, &parse(code)) else {
+                    return Err((REQUEST_FAILED, "There is no variable, constant, procedure, function or type here to rename".to_string()));
+                };
+                let edits: Vec<Json> = occurrences.iter()
+                    .map(|occurrence| json!({ "range": range(occurrence), "newText": new_name }))
+                    .collect();
+                Ok(json!({ "changes": { uri: edits } }))
+            }
+            _ => Err((METHOD_NOT_FOUND, format!("Unsupported request '{}'", method))),
+        }
+    }
+
+    fn notify(&mut self, method: &str, params: &Json) {
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or_default().to_string();
+        match method {
+            "textDocument/didOpen" => {
+                let text = params["textDocument"]["text"].as_str().unwrap_or_default();
+                self.documents.insert(uri.clone(), text.to_string());
+                publish_diagnostics(&uri, text);
+            }
+            "textDocument/didChange" => {
+                // Documents are synced whole, so the last change holds all the text
+                let changes = params["contentChanges"].as_array();
+                if let Some(text) = changes.and_then(|changes| changes.last()).and_then(|change| change["text"].as_str()) {
+                    self.documents.insert(uri.clone(), text.to_string());
+                    publish_diagnostics(&uri, text);
+                }
+            }
+            "textDocument/didClose" => {
+                self.documents.remove(&uri);
+                write_message(&json!({
+                    "jsonrpc": "2.0",
+                    "method": "textDocument/publishDiagnostics",
+                    "params": { "uri": uri, "diagnostics": [] },
+                }));
+            }
+            _ => {}
+        }
+    }
+
+    /// The URI and text of the document a request is about
+    fn document<'a>(&'a self, params: &'a Json) -> Result<(&'a str, &'a str), (i64, String)> {
+        let uri = params["textDocument"]["uri"].as_str()
+            .ok_or((INVALID_PARAMS, "The request does not name a document".to_string()))?;
+        let code = self.documents.get(uri)
+            .ok_or_else(|| (REQUEST_FAILED, format!("Document '{}' is not open", uri)))?;
+        Ok((uri, code))
+    }
+}
+
+/// The position a request is about, as the language service's 1-based line and column
+fn position(params: &Json) -> Result<(usize, usize), (i64, String)> {
+    let line = params["position"]["line"].as_u64();
+    let character = params["position"]["character"].as_u64();
+    match (line, character) {
+        (Some(line), Some(character)) => Ok((line as usize + 1, character as usize + 1)),
+        _ => Err((INVALID_PARAMS, "The request does not give a position".to_string())),
+    }
+}
+
+/// Best effort, as in the web editor: a broken statement does not hide the symbols of the rest
+fn parse(code: &str) -> Vec<Stmt> {
+    Parser::new(code).parse_program_with_errors().0
+}
+
+/// The column just past the end of the word at `column`, or `column` if there is none
+fn word_end(code: &str, line: usize, column: usize) -> usize {
+    let Some(text) = code.split('\n').nth(line - 1) else {
+        return column;
+    };
+    let rest = text.chars().skip(column - 1);
+    column + rest.take_while(|c| c.is_alphanumeric() || *c == '_').count()
+}
+
+/// Whether `name` would be read back as a single identifier
+fn is_identifier(name: &str) -> bool {
+    let (tokens, errors) = Lexer::new(name).tokenize_with_pos();
+    errors.is_empty() && matches!(tokens.as_slice(), [first, last] if matches!(first.token, Token::Identifier(_)) && last.token == Token::EOF)
+}
+
+fn completion_kind(kind: CompletionItemKind) -> u8 {
+    match kind {
+        CompletionItemKind::Keyword => 14,
+        CompletionItemKind::Function => 3,
+        CompletionItemKind::Variable => 6,
+        CompletionItemKind::Constant => 21,
+        CompletionItemKind::Type => 22,
+    }
+}
+
+fn range(occurrence: &Occurrence) -> Json {
+    json!({
+        "start": { "line": occurrence.line - 1, "character": occurrence.column - 1 },
+        "end": { "line": occurrence.line - 1, "character": occurrence.end_column - 1 },
+    })
+}
+
+fn location(uri: &str, occurrence: &Occurrence) -> Json {
+    json!({ "uri": uri, "range": range(occurrence) })
+}
+
+fn span_range(span: &SourceSpan) -> Json {
+    json!({
+        "start": { "line": span.start_line.saturating_sub(1), "character": span.start_column.saturating_sub(1) },
+        "end": { "line": span.end_line.saturating_sub(1), "character": span.end_column.saturating_sub(1) },
+    })
+}
+
+/// Check the document as `pseudocode check` does and send the editor what was found.
+/// Type errors are only looked for once the document parses.
+fn publish_diagnostics(uri: &str, code: &str) {
+    let (statements, mut errors) = Parser::new(code).parse_program_with_errors();
+    if errors.is_empty() {
+        errors = TypeChecker::new().check(&statements);
+    }
+    let diagnostics: Vec<Json> = errors.iter().map(|e| diagnostic(uri, e)).collect();
+    write_message(&json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/publishDiagnostics",
+        "params": { "uri": uri, "diagnostics": diagnostics },
+    }));
+}
+
+fn diagnostic(uri: &str, diagnostic: &Diagnostic) -> Json {
+    let span = diagnostic.span.unwrap_or(SourceSpan::point(1, 1));
+    let severity = match diagnostic.severity {
+        Severity::Error => 1,
+        Severity::Warning => 2,
+        Severity::Note => 3,
+    };
+    let mut message = diagnostic.message.clone();
+    for note in &diagnostic.notes {
+        message.push_str(&format!("\nnote: {}", note));
+    }
+    let related: Vec<Json> = diagnostic.labels.iter()
+        .map(|label| json!({ "location": { "uri": uri, "range": span_range(&label.span) }, "message": label.message }))
+        .collect();
+    json!({
+        "range": span_range(&span),
+        "severity": severity,
+        "code": diagnostic.code,
+        "source": "pseudocode",
+        "message": message,
+        "relatedInformation": related,
+    })
+}
+
+/// The declarations in the document in source order, each covering its whole statement
+fn document_symbols(code: &str, statements: &[Stmt]) -> Json {
+    let lines: Vec<&str> = code.split('\n').collect();
+    let symbol = |name: &str, kind: u8, detail: &str, span: &Span| {
+        let text = lines.get(span.line.wrapping_sub(1)).copied().unwrap_or_default();
+        let start = span.column.saturating_sub(1);
+        // Point at the name itself where it can be found after the keyword
+        let after: String = text.chars().skip(start).collect();
+        let name_start = after.find(name).map_or(start, |offset| start + after[..offset].chars().count());
+        let line = span.line.saturating_sub(1);
+        let end = json!({ "line": span.end_line.saturating_sub(1), "character": span.end_column.saturating_sub(1) });
+        json!({
+            "name": name,
+            "detail": detail,
+            "kind": kind,
+            "range": { "start": { "line": line, "character": start }, "end": end },
+            "selectionRange": {
+                "start": { "line": line, "character": name_start },
+                "end": { "line": line, "character": name_start + name.chars().count() },
+            },
+        })
+    };
+
+    let table = SymbolExtractor::extract_symbols(statements);
+    let mut symbols = Vec::new();
+    symbols.extend(table.types.iter().map(|t| symbol(&t.name, 23, "TYPE", &t.span)));
+    symbols.extend(table.constants.iter().map(|c| symbol(&c.name, 14, "CONSTANT", &c.span)));
+    symbols.extend(table.variables.iter().map(|v| symbol(&v.name, 13, "DECLARE", &v.span)));
+    symbols.extend(table.procedures.iter().map(|p| symbol(&p.name, 12, "PROCEDURE", &p.span)));
+    symbols.extend(table.functions.iter().map(|f| symbol(&f.name, 12, "FUNCTION", &f.span)));
+    symbols.sort_by_key(|symbol| symbol["range"]["start"]["line"].as_u64());
+    Json::Array(symbols)
+}
@@ -1,14 +1,30 @@
-mod lexer;
-mod parser;
-mod ast;
-mod log;
-mod interpreter;
+// `Diagnostic` is the error type of every phase; boxing it everywhere buys nothing
+#![allow(clippy::result_large_err)]
+
+use pseudocode_wasm::{lexer, parser, checker, ast, log, diagnostic, value, host, files, interpreter, bytecode, compiler, vm, debugger, limits, language_service, log_error};
+
 mod cli;
+mod debug;
+mod dap;
+mod framing;
+mod lsp;
+mod test_runner;
+
+/// The interpreter recurses natively for each call the program makes, so it
+/// runs on a thread with room for `limits::DEFAULT_MAX_CALL_DEPTH` calls, even
+/// in a debug build. Only the pages actually used are committed.
+const STACK_SIZE: usize = 1 << 30;
 
 fn main() {
     // Initialize logger
     log::init();
     
     // Run CLI
-    cli::run();
+    let cli = std::thread::Builder::new()
+        .stack_size(STACK_SIZE)
+        .spawn(cli::run)
+        .expect("failed to start the main thread");
+    if cli.join().is_err() {
+        std::process::exit(101);
+    }
 }
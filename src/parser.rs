@@ -1,33 +1,55 @@
-use crate::ast::{Expr, BinaryOp, UnaryOp, Stmt, Type, FileMode, CaseBranch, TypeDeclarationVariant, TypeField, Function, Param, Procedure, Span};
+use crate::ast::{Expr, BinaryOp, UnaryOp, Stmt, Type, FileMode, CaseBranch, CaseLabel, TypeDeclarationVariant, TypeField, Function, Param, PassingMode, Procedure, Span, LValue};
+use crate::date::Date;
+use crate::diagnostic::{codes, Diagnostic, SourceSpan};
 use crate::lexer::{Token, Lexer, TokenWithPos};
-use log::{debug, trace, error};
+use crate::value;
+use log::{debug, trace};
 
 pub struct Parser {
     tokens: Vec<Token>,
     token_positions: Vec<(usize, usize)>, // (line, column) for each token
+    token_ends: Vec<(usize, usize)>, // (line, column) just past each token
     pos: usize,
+    // Every lexer and syntax error found so far; parsing carries on after each one
+    errors: Vec<Diagnostic>,
 }
 
+/// Keywords that end a block, or one section of it
+const BLOCK_END_KEYWORDS: &[&str] = &[
+    "ELSE", "ENDIF", "ENDWHILE", "NEXT", "UNTIL", "OTHERWISE", "ENDCASE",
+    "ENDPROCEDURE", "ENDFUNCTION", "ENDTYPE",
+];
+
+/// Keywords that start a statement. FOR is left out as it also appears inside OPENFILE.
+const STATEMENT_KEYWORDS: &[&str] = &[
+    "DECLARE", "DEFINE", "TYPE", "IF", "WHILE", "REPEAT", "CASE", "FUNCTION", "PROCEDURE",
+    "CALL", "INPUT", "OUTPUT", "OPENFILE", "CLOSEFILE", "READFILE", "WRITEFILE", "SEEK",
+    "GETRECORD", "PUTRECORD", "RETURN", "CONSTANT",
+];
+
 impl Parser {
     pub fn new(input: &str) -> Self {
         debug!("Initializing parser");
         let mut lexer = Lexer::new(input);
-        let tokens_with_pos = lexer.tokenize_with_pos();
+        let (tokens_with_pos, errors) = lexer.tokenize_with_pos();
         
         let mut tokens = Vec::new();
         let mut positions = Vec::new();
+        let mut ends = Vec::new();
         
-        for TokenWithPos { token, line, column } in tokens_with_pos {
+        for TokenWithPos { token, line, column, end_line, end_column } in tokens_with_pos {
             positions.push((line, column));
+            ends.push((end_line, end_column));
             tokens.push(token);
         }
-        
         debug!("Parser initialized with {} tokens", tokens.len());
         
         Parser { 
             tokens, 
             token_positions: positions,
+            token_ends: ends,
             pos: 0,
+            errors,
         }
     }
     
@@ -41,16 +63,39 @@ impl Parser {
         }
     }
     
+    /// Span of the current token
     fn get_span(&self) -> Span {
+        let index = self.pos.min(self.tokens.len().saturating_sub(1));
         let (line, column) = self.get_position();
-        Span { line, column }
+        let (end_line, end_column) = self.token_ends.get(index).copied().unwrap_or((line, column + 1));
+        Span { line, column, end_line, end_column }
+    }
+
+    /// `start` stretched to the end of the last token parsed, leaving out line
+    /// breaks after it
+    fn span_since(&self, start: &Span) -> Span {
+        let end = self.tokens[..self.pos.min(self.tokens.len())].iter()
+            .rposition(|token| *token != Token::Newline)
+            .map(|last| self.token_ends[last])
+            .filter(|end| *end > (start.end_line, start.end_column));
+        match end {
+            Some((end_line, end_column)) => Span { end_line, end_column, ..start.clone() },
+            None => start.clone(),
+        }
     }
     
-    fn error_with_pos(&self, msg: &str) -> String {
-        let (line, column) = self.get_position();
-        let error_msg = format!("{} at line {}:{}", msg, line, column);
-        error!("Parse error: {}", error_msg);
-        error_msg
+    /// Source range of the current token
+    fn token_span(&self) -> SourceSpan {
+        let index = self.pos.min(self.tokens.len() - 1);
+        let (line, column) = self.token_positions[index];
+        let (end_line, end_column) = self.token_ends[index];
+        SourceSpan::new(line, column, end_line, end_column)
+    }
+    
+    fn error_with_pos(&self, msg: &str) -> Diagnostic {
+        let error = Diagnostic::error(codes::SYNTAX, msg).with_span(self.token_span());
+        debug!("Parse error: {}", error);
+        error
     }
 
     fn _next_token(&mut self) -> &Token {
@@ -70,9 +115,13 @@ impl Parser {
         self.pos += 1;
     }
     
-    fn parse_number(&mut self) -> Result<Expr, String> {
+    fn parse_number(&mut self) -> Result<Expr, Diagnostic> {
         if let Token::Number(n) = self.current_token() {
             let number = n.clone();
+            // INTEGER literals must fit in 64 bits
+            if !number.contains('.') {
+                value::parse_integer(&number).map_err(|msg| self.error_with_pos(&msg))?;
+            }
             let span = self.get_span();
             self.advance();
             Ok(Expr::Number(number, span))
@@ -81,7 +130,19 @@ impl Parser {
         }
     }
 
-    fn parse_string(&mut self) -> Result<Expr, String> {
+    fn parse_date(&mut self) -> Result<Expr, Diagnostic> {
+        match self.current_token() {
+            Token::Date(text) => {
+                let date = Date::parse(text).map_err(|msg| self.error_with_pos(&msg))?;
+                let span = self.get_span();
+                self.advance();
+                Ok(Expr::Date(date, span))
+            }
+            _ => Err(self.error_with_pos("Expected date")),
+        }
+    }
+
+    fn parse_string(&mut self) -> Result<Expr, Diagnostic> {
         match self.current_token() {
             Token::String(s) => {
                 let string = s.clone();
@@ -93,7 +154,7 @@ impl Parser {
         }
     }
 
-    fn _parse_variable(&mut self) -> Result<Expr, String> {
+    fn _parse_variable(&mut self) -> Result<Expr, Diagnostic> {
         match self.current_token() {
             Token::Identifier(v) => {
                 let variable = v.clone();
@@ -105,7 +166,7 @@ impl Parser {
         }
     }
 
-    fn parse_char(&mut self) -> Result<Expr, String> {
+    fn parse_char(&mut self) -> Result<Expr, Diagnostic> {
         match self.current_token() {
             Token::Char(c) => {
                 let char = c.clone();
@@ -117,19 +178,19 @@ impl Parser {
         }
     }
 
-    fn parse_unary(&mut self) -> Result<Expr, String> {
+    fn parse_unary(&mut self) -> Result<Expr, Diagnostic> {
         match self.current_token() {
             Token::Not => {
                 let span = self.get_span();
                 self.advance();
                 let expr = self.parse_unary()?;
-                Ok(Expr::UnaryOp(UnaryOp::Not, Box::new(expr), span))
+                Ok(Expr::UnaryOp(UnaryOp::Not, Box::new(expr), self.span_since(&span)))
             }
             Token::Minus => {
                 let span = self.get_span();
                 self.advance();
                 let expr = self.parse_unary()?;
-                Ok(Expr::UnaryOp(UnaryOp::Negate, Box::new(expr), span))
+                Ok(Expr::UnaryOp(UnaryOp::Negate, Box::new(expr), self.span_since(&span)))
             }
             _ => self.parse_primary(),
         }
@@ -142,6 +203,10 @@ impl Parser {
             Token::Multiply => Some(BinaryOp::Multiply),
             Token::Divide => Some(BinaryOp::Divide),
             Token::_Modulus => Some(BinaryOp::Modulus),
+            Token::Ampersand => Some(BinaryOp::Concat),
+            // MOD(a, b) and DIV(a, b) are built-ins; between two operands they are operators
+            Token::Keyword(kw) if kw == "MOD" => Some(BinaryOp::Modulus),
+            Token::Keyword(kw) if kw == "DIV" => Some(BinaryOp::Div),
             Token::Equals => Some(BinaryOp::Equals),
             Token::NotEquals => Some(BinaryOp::NotEquals),
             Token::LessThan => Some(BinaryOp::LessThan),
@@ -150,15 +215,25 @@ impl Parser {
             Token::GreaterThanOrEqual => Some(BinaryOp::GreaterThanOrEqual),
             Token::And => Some(BinaryOp::And),
             Token::Or => Some(BinaryOp::Or),
+            Token::Keyword(kw) if kw == "IN" => Some(BinaryOp::In),
             _ => None,
         }
     }
 
-    pub fn parse_statement(&mut self) -> Result<Stmt, String> {
+    pub fn parse_statement(&mut self) -> Result<Stmt, Diagnostic> {
         trace!("Parsing statement, current token: {:?}", self.current_token());
+        let mut stmt = self.parse_statement_kind()?;
+        // A statement spans all of its tokens, including any block it holds
+        let span = self.span_since(stmt.span());
+        *stmt.span_mut() = span;
+        Ok(stmt)
+    }
+
+    fn parse_statement_kind(&mut self) -> Result<Stmt, Diagnostic> {
         match self.current_token() {
             Token::Keyword(kw) => match kw.as_str() {
                 "DECLARE" => self.parse_declare(),
+                "CONSTANT" => self.parse_constant(),
                 "DEFINE" => self.parse_define(),
                 "TYPE" => self.parse_type_declaration(),
                 "IF" => self.parse_if(),
@@ -189,9 +264,54 @@ impl Parser {
         }
     }
 
-    fn parse_procedure_declaration(&mut self) -> Result<Stmt, String> {
+    /// Parameter list of a procedure or function, up to the closing parenthesis.
+    /// BYREF or BYVAL applies to the parameter it precedes and every later one
+    /// until the other keyword appears; parameters are BYVAL by default.
+    fn parse_params(&mut self) -> Result<Vec<Param>, Diagnostic> {
+        let mut params = Vec::new();
+        if matches!(self.current_token(), Token::RightParen) {
+            return Ok(params);
+        }
+
+        let mut mode = PassingMode::BYVAL;
+        loop {
+            if let Token::Keyword(kw) = self.current_token() {
+                match kw.as_str() {
+                    "BYREF" => mode = PassingMode::BYREF,
+                    "BYVAL" => mode = PassingMode::BYVAL,
+                    _ => return Err(self.error_with_pos("Expected parameter name")),
+                }
+                self.advance();
+            }
+
+            let span = self.get_span();
+            let name = match self.current_token() {
+                Token::Identifier(n) => {
+                    let name = n.clone();
+                    self.advance();
+                    name
+                }
+                _ => return Err(self.error_with_pos("Expected parameter name")),
+            };
+
+            self.expect(Token::Colon)?;
+            let type_name = self.parse_type()?;
+
+            params.push(Param { name, type_name, mode, span });
+
+            match self.current_token() {
+                Token::Comma => {
+                    self.advance();
+                }
+                Token::RightParen => return Ok(params),
+                _ => return Err(self.error_with_pos("Expected comma or closing parenthesis")),
+            }
+        }
+    }
+
+    fn parse_procedure_declaration(&mut self) -> Result<Stmt, Diagnostic> {
         trace!("Parsing PROCEDURE declaration");
-        let span_start = self.get_position();
+        let span = self.get_span();
         self.expect(Token::Keyword("PROCEDURE".to_string()))?;
         
         // Parse procedure name
@@ -206,78 +326,18 @@ impl Parser {
         
         // Expect opening parenthesis
         self.expect(Token::LeftParen)?;
-        
-        // Parse parameters (can be empty)
-        let mut params = Vec::new();
-        
-        // Check if there are parameters (not immediately closing paren)
-        if !matches!(self.current_token(), Token::RightParen) {
-            loop {
-                // Parse parameter name
-                let param_span = self.get_position();
-                let param_name = match self.current_token() {
-                    Token::Identifier(n) => {
-                        let name = n.clone();
-                        self.advance();
-                        name
-                    }
-                    _ => return Err(self.error_with_pos("Expected parameter name")),
-                };
-                
-                // Expect colon
-                self.expect(Token::Colon)?;
-                
-                // Parse parameter type
-                let param_type = self.parse_type()?;
-                
-                params.push(Param {
-                    name: param_name,
-                    type_name: param_type,
-                    span: Span { line: param_span.0, column: param_span.1 },
-                });
-                
-                // Check for more parameters or closing paren
-                match self.current_token() {
-                    Token::Comma => {
-                        self.advance();
-                        continue;
-                    }
-                    Token::RightParen => {
-                        break;
-                    }
-                    _ => return Err(self.error_with_pos("Expected comma or closing parenthesis")),
-                }
-            }
-        }
+        let params = self.parse_params()?;
         
         // Expect closing parenthesis
         self.expect(Token::RightParen)?;
         
         // Parse procedure body until ENDPROCEDURE
-        let mut body = Vec::new();
-        while !matches!(self.current_token(), Token::Keyword(kw) if kw == "ENDPROCEDURE") {
-            // Skip leading newlines (whitespace)
-            while matches!(self.current_token(), Token::Newline) {
-                self.advance();
-            }
-            
-            // Check if we hit the end keyword
-            if matches!(self.current_token(), Token::Keyword(kw) if kw == "ENDPROCEDURE") {
-                break;
-            }
-            
-            body.push(self.parse_statement()?);
-            
-            // Consume trailing newline (statement terminator)
-            if matches!(self.current_token(), Token::Newline) {
-                self.advance();
-            }
-        }
+        let body = self.parse_block();
         
         // Expect ENDPROCEDURE
         self.expect(Token::Keyword("ENDPROCEDURE".to_string()))?;
         
-        let span = Span { line: span_start.0, column: span_start.1 };
+        let span = self.span_since(&span);
         Ok(Stmt::ProcedureDeclaration {
             procedure: Procedure {
                 name,
@@ -289,7 +349,8 @@ impl Parser {
         })
     }
     
-    fn parse_define(&mut self) -> Result<Stmt, String> {
+    fn parse_define(&mut self) -> Result<Stmt, Diagnostic> {
+        let span = self.get_span();
         self.expect(Token::Keyword("DEFINE".to_string()))?;
         
         let name = match self.current_token() {
@@ -366,11 +427,12 @@ impl Parser {
             name,
             values,
             type_name,
-            span: self.get_span(),
+            span,
         })
     }
 
-    fn parse_call(&mut self) -> Result<Stmt, String> {
+    fn parse_call(&mut self) -> Result<Stmt, Diagnostic> {
+        let span = self.get_span();
         self.expect(Token::Keyword("CALL".to_string()))?;
         
         // Parse procedure name
@@ -421,11 +483,12 @@ impl Parser {
         Ok(Stmt::Call {
             name,
             args,
-            span: self.get_span(),
+            span,
         })
     }
     
-    fn parse_return(&mut self) -> Result<Stmt, String> {
+    fn parse_return(&mut self) -> Result<Stmt, Diagnostic> {
+        let span = self.get_span();
         self.expect(Token::Keyword("RETURN".to_string()))?;
         
         // Check if there's a return value (expression)
@@ -442,12 +505,12 @@ impl Parser {
             Some(Box::new(self.parse_expression()?))
         };
         
-        Ok(Stmt::Return { value, span: self.get_span() })
+        Ok(Stmt::Return { value, span })
     }
 
-    fn parse_function_declaration(&mut self) -> Result<Stmt, String> {
+    fn parse_function_declaration(&mut self) -> Result<Stmt, Diagnostic> {
         trace!("Parsing FUNCTION declaration");
-        let span_start = self.get_position();
+        let span = self.get_span();
         self.expect(Token::Keyword("FUNCTION".to_string()))?;
 
         let name = match self.current_token() {
@@ -460,71 +523,18 @@ impl Parser {
         };
         
         self.expect(Token::LeftParen)?;
-
-        let mut params = Vec::new();
-
-        if !matches!(self.current_token(), Token::RightParen) {
-            loop {
-                let param = match self.current_token() {
-                    Token::Identifier(n) => {
-                        let name = n.clone();
-                        self.advance();
-                        name
-                    }
-                    _ => return Err(self.error_with_pos("Expected parameter name")),
-                };
-                
-                self.expect(Token::Colon)?;
-
-                let param_type = self.parse_type()?;
-
-                params.push(Param {
-                    name: param,
-                    type_name: param_type,
-                    span: self.get_span(),
-                });
-
-                match self.current_token() {
-                    Token::Comma => {
-                        self.advance();
-                        continue;
-                    }
-                    Token::RightParen => {
-                        break;
-                    }
-                    _ => return Err(self.error_with_pos("Expected comma or closing parenthesis")),
-                }
-            }
-        }
+        let params = self.parse_params()?;
 
         self.expect(Token::RightParen)?;
         self.expect(Token::Keyword("RETURNS".to_string()))?;
 
         let return_type = self.parse_type()?;
 
-        let mut body = Vec::new();
-        while !matches!(self.current_token(), Token::Keyword(kw) if kw == "ENDFUNCTION") {
-            // Skip leading newlines (whitespace)
-            while matches!(self.current_token(), Token::Newline) {
-                self.advance();
-            }
-            
-            // Check if we hit the end keyword
-            if matches!(self.current_token(), Token::Keyword(kw) if kw == "ENDFUNCTION") {
-                break;
-            }
-            
-            body.push(self.parse_statement()?);
-            
-            // Consume trailing newline (statement terminator)
-            if matches!(self.current_token(), Token::Newline) {
-                self.advance();
-            }
-        }
+        let body = self.parse_block();
 
         self.expect(Token::Keyword("ENDFUNCTION".to_string()))?;
 
-        let span = Span { line: span_start.0, column: span_start.1 };
+        let span = self.span_since(&span);
         Ok(Stmt::FunctionDeclaration {
             function: Function {
                 name,
@@ -537,8 +547,9 @@ impl Parser {
         })
     }
 
-    fn parse_type_declaration(&mut self) -> Result<Stmt, String> {
+    fn parse_type_declaration(&mut self) -> Result<Stmt, Diagnostic> {
         trace!("Parsing TYPE declaration");
+        let span = self.get_span();
         self.expect(Token::Keyword("TYPE".to_string()))?;
         
         let name = match self.current_token() {
@@ -568,7 +579,7 @@ impl Parser {
                             variant: TypeDeclarationVariant::Pointer {
                                 points_to: Box::new(points_to),
                             },
-                            span: self.get_span(),
+                            span,
                         })
                     }
                     
@@ -602,7 +613,7 @@ impl Parser {
                         Ok(Stmt::TypeDeclaration {
                             name,
                             variant: TypeDeclarationVariant::Enum { values },
-                            span: self.get_span(),
+                            span,
                         })
                     }
                     
@@ -617,11 +628,11 @@ impl Parser {
                             variant: TypeDeclarationVariant::Set {
                                 element_type: Box::new(element_type),
                             },
-                            span: self.get_span(),
+                            span,
                         })
                     }
                     
-                    _ => return Err(self.error_with_pos("Expected ^, (, or SET after = in TYPE declaration")),
+                    _ => Err(self.error_with_pos("Expected ^, (, or SET after = in TYPE declaration")),
                 }
             }
             
@@ -635,7 +646,7 @@ impl Parser {
                     variant: TypeDeclarationVariant::Pointer {
                         points_to: Box::new(points_to),
                     },
-                    span: self.get_span(),
+                    span,
                 })
             }
             
@@ -650,7 +661,7 @@ impl Parser {
                     variant: TypeDeclarationVariant::Set {
                         element_type: Box::new(element_type),
                     },
-                    span: self.get_span(),
+                    span,
                 })
             }
             
@@ -670,29 +681,13 @@ impl Parser {
                     }
                     
                     if matches!(self.current_token(), Token::Keyword(kw) if kw == "DECLARE") {
-                        self.advance();
-                        
-                        let field_name = match self.current_token() {
-                            Token::Identifier(n) => {
-                                let name = n.clone();
-                                self.advance();
-                                name
+                        match self.parse_type_field() {
+                            Ok(field) => fields.push(field),
+                            // A broken field does not stop the rest of the record being checked
+                            Err(error) => {
+                                self.errors.push(error);
+                                self.synchronize();
                             }
-                            _ => return Err(self.error_with_pos("Expected field name")),
-                        };
-                        
-                        self.expect(Token::Colon)?;
-                        let field_type = self.parse_type()?;
-                        
-                        fields.push(TypeField {
-                            name: field_name,
-                            type_name: field_type,
-                            span: self.get_span(),
-                        });
-                        
-                        // Consume trailing newline after DECLARE statement
-                        if matches!(self.current_token(), Token::Newline) {
-                            self.advance();
                         }
                     } else {
                         return Err(self.error_with_pos("Expected DECLARE or ENDTYPE"));
@@ -704,39 +699,21 @@ impl Parser {
                 Ok(Stmt::TypeDeclaration {
                     name,
                     variant: TypeDeclarationVariant::Record { fields },
-                    span: self.get_span(),
+                    span,
                 })
             }
         }
     }
 
-    fn parse_if(&mut self) -> Result<Stmt, String> {
+    fn parse_if(&mut self) -> Result<Stmt, Diagnostic> {
+        let span = self.get_span();
         self.expect(Token::Keyword("IF".to_string()))?;
 
         let condition = self.parse_expression()?;
 
         self.expect(Token::Keyword("THEN".to_string()))?;
 
-        let mut then_stmt = Vec::new();
-
-        while !matches!(self.current_token(), Token::Keyword(kw) if kw == "ENDIF" || kw == "ELSE") {
-            // Skip leading newlines (whitespace)
-            while matches!(self.current_token(), Token::Newline) {
-                self.advance();
-            }
-            
-            // Check if we hit the end keyword
-            if matches!(self.current_token(), Token::Keyword(kw) if kw == "ENDIF" || kw == "ELSE") {
-                break;
-            }
-            
-            then_stmt.push(self.parse_statement()?);
-            
-            // Consume trailing newline (statement terminator)
-            if matches!(self.current_token(), Token::Newline) {
-                self.advance();
-            }
-        }
+        let then_stmt = self.parse_block();
 
         let else_stmt = if matches!(self.current_token(), Token::Keyword(kw) if kw == "ELSE") {
             self.advance();
@@ -745,25 +722,7 @@ impl Parser {
                 let nested_if = self.parse_if()?;
                 Some(vec![nested_if])
             } else {
-                let mut else_body = Vec::new();
-                while !matches!(self.current_token(), Token::Keyword(kw) if kw == "ENDIF") {
-                    // Skip leading newlines (whitespace)
-                    while matches!(self.current_token(), Token::Newline) {
-                        self.advance();
-                    }
-                    
-                    // Check if we hit the end keyword
-                    if matches!(self.current_token(), Token::Keyword(kw) if kw == "ENDIF") {
-                        break;
-                    }
-                    
-                    else_body.push(self.parse_statement()?);
-                    
-                    // Consume trailing newline (statement terminator)
-                    if matches!(self.current_token(), Token::Newline) {
-                        self.advance();
-                    }
-                }
+                let else_body = self.parse_block();
                 Some(else_body)
             }
         } else {
@@ -782,11 +741,12 @@ impl Parser {
             condition: Box::new(condition),
             then_stmt,
             else_stmt,
-            span: self.get_span(),
+            span,
         })
     }
 
-    fn parse_while(&mut self) -> Result<Stmt, String> {
+    fn parse_while(&mut self) -> Result<Stmt, Diagnostic> {
+        let span = self.get_span();
         self.expect(Token::Keyword("WHILE".to_string()))?;
     
         let condition = self.parse_expression()?;
@@ -800,36 +760,19 @@ impl Parser {
             }
         }
     
-        let mut body = Vec::new();
-        while !matches!(self.current_token(), Token::Keyword(kw) if kw == "ENDWHILE") {
-            // Skip leading newlines (whitespace)
-            while matches!(self.current_token(), Token::Newline) {
-                self.advance();
-            }
-            
-            // Check if we hit the end keyword
-            if matches!(self.current_token(), Token::Keyword(kw) if kw == "ENDWHILE") {
-                break;
-            }
-            
-            body.push(self.parse_statement()?);
-            
-            // Consume trailing newline (statement terminator)
-            if matches!(self.current_token(), Token::Newline) {
-                self.advance();
-            }
-        }
+        let body = self.parse_block();
     
         self.expect(Token::Keyword("ENDWHILE".to_string()))?;
     
         Ok(Stmt::While {
             condition: Box::new(condition),
             body,
-            span: self.get_span(),
+            span,
         })
     }
 
-    fn parse_for(&mut self) -> Result<Stmt, String> {
+    fn parse_for(&mut self) -> Result<Stmt, Diagnostic> {
+        let span = self.get_span();
         self.expect(Token::Keyword("FOR".to_string()))?;
 
         // Parse counter variable name
@@ -857,25 +800,7 @@ impl Parser {
             None
         };
 
-        let mut body = Vec::new();
-        while !matches!(self.current_token(), Token::Keyword(kw) if kw == "NEXT") {
-            // Skip leading newlines (whitespace)
-            while matches!(self.current_token(), Token::Newline) {
-                self.advance();
-            }
-            
-            // Check if we hit the end keyword
-            if matches!(self.current_token(), Token::Keyword(kw) if kw == "NEXT") {
-                break;
-            }
-            
-            body.push(self.parse_statement()?);
-            
-            // Consume trailing newline (statement terminator)
-            if matches!(self.current_token(), Token::Newline) {
-                self.advance();
-            }
-        }
+        let body = self.parse_block();
         
         self.expect(Token::Keyword("NEXT".to_string()))?;
         
@@ -898,32 +823,15 @@ impl Parser {
             end: Box::new(end),
             step,
             body,
-            span: self.get_span(),
+            span,
         })
     }
 
-    fn parse_repeat_until(&mut self) -> Result<Stmt, String> {
+    fn parse_repeat_until(&mut self) -> Result<Stmt, Diagnostic> {
+        let span = self.get_span();
         self.expect(Token::Keyword("REPEAT".to_string()))?;
 
-        let mut body = Vec::new();
-        while !matches!(self.current_token(), Token::Keyword(kw) if kw == "UNTIL") {
-            // Skip leading newlines (whitespace)
-            while matches!(self.current_token(), Token::Newline) {
-                self.advance();
-            }
-            
-            // Check if we hit the end keyword
-            if matches!(self.current_token(), Token::Keyword(kw) if kw == "UNTIL") {
-                break;
-            }
-            
-            body.push(self.parse_statement()?);
-            
-            // Consume trailing newline (statement terminator)
-            if matches!(self.current_token(), Token::Newline) {
-                self.advance();
-            }
-        }
+        let body = self.parse_block();
 
         self.expect(Token::Keyword("UNTIL".to_string()))?;
 
@@ -932,12 +840,13 @@ impl Parser {
         Ok(Stmt::RepeatUntil {
                 body,
                 condition: Box::new(condition),
-                span: self.get_span(),
+                span,
             }
         )
     }
 
-    fn parse_case(&mut self) -> Result<Stmt, String> {
+    fn parse_case(&mut self) -> Result<Stmt, Diagnostic> {
+        let span = self.get_span();
         self.expect(Token::Keyword("CASE".to_string()))?;
         self.expect(Token::Keyword("OF".to_string()))?;
         
@@ -947,62 +856,53 @@ impl Parser {
         let mut otherwise = None;
         
         while !matches!(self.current_token(), Token::Keyword(kw) if kw == "ENDCASE") {
+            while matches!(self.current_token(), Token::Newline) {
+                self.advance();
+            }
+            
+            // ENDCASE, or the end of some other block when ENDCASE is missing
+            if self.at_block_end() && !matches!(self.current_token(), Token::Keyword(kw) if kw == "OTHERWISE") {
+                break;
+            }
+            
             if matches!(self.current_token(), Token::Keyword(kw) if kw == "OTHERWISE") {
                 self.advance();
                 self.expect(Token::Colon)?;
                 
-                let mut otherwise_body = Vec::new();
-                while !matches!(self.current_token(), Token::Keyword(kw) if kw == "ENDCASE") {
-                    // Skip leading newlines (whitespace)
-                    while matches!(self.current_token(), Token::Newline) {
-                        self.advance();
-                    }
-                    
-                    // Check if we hit the end keyword
-                    if matches!(self.current_token(), Token::Keyword(kw) if kw == "ENDCASE") {
-                        break;
-                    }
-                    
-                    otherwise_body.push(self.parse_statement()?);
-                    
-                    // Consume trailing newline (statement terminator)
-                    if matches!(self.current_token(), Token::Newline) {
-                        self.advance();
-                    }
-                }
+                let otherwise_body = self.parse_block();
                 otherwise = Some(otherwise_body);
                 break;
             }
             
-            let value = self.parse_expression()?;
-            
-            self.expect(Token::Colon)?;
+            let branch_span = self.get_span();
+            let labels = match self.parse_case_labels().and_then(|labels| self.expect(Token::Colon).map(|_| labels)) {
+                Ok(labels) => Some(labels),
+                Err(error) => {
+                    // Drop the branch but still check its body
+                    self.errors.push(error);
+                    self.synchronize();
+                    None
+                }
+            };
             
             let mut body = Vec::new();
             
-            while !matches!(self.current_token(), Token::Keyword(kw) if kw == "ENDCASE" || kw == "OTHERWISE") {
+            loop {
                 // Skip leading newlines (whitespace)
                 while matches!(self.current_token(), Token::Newline) {
                     self.advance();
                 }
                 
                 // Check if we hit the end keyword
-                if matches!(self.current_token(), Token::Keyword(kw) if kw == "ENDCASE" || kw == "OTHERWISE") {
+                if self.at_block_end() {
                     break;
                 }
                 
-                let is_case_value = matches!(
-                    self.current_token(),
-                    Token::Identifier(_) | Token::Number(_) | Token::String(_) | Token::Char(_)
-                );
-                
-                if is_case_value && self.pos + 1 < self.tokens.len() {
-                    if matches!(self.tokens[self.pos + 1], Token::Colon) {
-                        break;
-                    }
+                if self.at_case_labels() {
+                    break;
                 }
                 
-                body.push(self.parse_statement()?);
+                body.push(self.parse_statement_or_recover());
                 
                 // Consume trailing newline (statement terminator)
                 if matches!(self.current_token(), Token::Newline) {
@@ -1010,11 +910,13 @@ impl Parser {
                 }
             }
             
-            cases.push(CaseBranch {
-                value: Box::new(value),
-                body,
-                span: self.get_span(),
-            });
+            if let Some(labels) = labels {
+                cases.push(CaseBranch {
+                    labels,
+                    body,
+                    span: self.span_since(&branch_span),
+                });
+            }
         }
         
         self.expect(Token::Keyword("ENDCASE".to_string()))?;
@@ -1023,104 +925,103 @@ impl Parser {
             expression: Box::new(expression),
             cases,
             otherwise,
-            span: self.get_span(),
+            span,
         })
     }
 
-    fn parse_assignment(&mut self) -> Result<Stmt, String> {
-        // Parse the left-hand side (lvalue) - can be variable, array access, field access, or pointer dereference
-        let name = match self.current_token() {
-            Token::Identifier(n) => {
-                let var_name = n.clone();
-                self.advance();
-                var_name
-            },
-            _ => return Err(self.error_with_pos("Expected identifier")),
-        };
-
-        // Check for array access, field access, or pointer dereference
-        let mut indices = None;
-        
-        // Handle array access: arr[i] or arr[i, j]
-        if matches!(self.current_token(), Token::LeftBracket) {
-            self.advance();
-            let mut idxs = Vec::new();
-            
-            // Parse first index
-            idxs.push(self.parse_expression()?);
-            
-            // Parse additional comma-separated indices
-            while matches!(self.current_token(), Token::Comma) {
+    /// `value` or `low TO high`, separated by commas
+    fn parse_case_labels(&mut self) -> Result<Vec<CaseLabel>, Diagnostic> {
+        let mut labels = Vec::new();
+        loop {
+            let low = self.parse_expression()?;
+            if matches!(self.current_token(), Token::Keyword(kw) if kw == "TO") {
                 self.advance();
-                idxs.push(self.parse_expression()?);
+                let high = self.parse_expression()?;
+                labels.push(CaseLabel::Range(low, high));
+            } else {
+                labels.push(CaseLabel::Value(low));
             }
-            
-            self.expect(Token::RightBracket)?;
-            indices = Some(idxs);
-        }
-        
-        // Handle field access: obj.field
-        // Note: Field access assignments like Student1.LastName <- "Smith" need special handling
-        // We'll check if there's a dot after the identifier (or after array access)
-        let field_name = if matches!(self.current_token(), Token::Dot) {
-            self.advance();
-            match self.current_token() {
-                Token::Identifier(f) => {
-                    let f = f.clone();
-                    self.advance();
-                    Some(f)
-                }
-                _ => return Err(self.error_with_pos("Expected field name after dot")),
+            if !matches!(self.current_token(), Token::Comma) {
+                return Ok(labels);
             }
-        } else {
-            None
-        };
-        
-        // Handle pointer dereference: ptr^
-        let is_pointer_deref = if matches!(self.current_token(), Token::Caret) {
             self.advance();
-            true
-        } else {
-            false
-        };
+        }
+    }
 
-        self.expect(Token::LeftArrow)?;
+    /// Whether the line ahead starts a new CASE branch rather than being another
+    /// statement of the current one: it has a colon outside any brackets, before
+    /// any `<-`, and does not start with a statement keyword
+    fn at_case_labels(&self) -> bool {
+        if matches!(self.current_token(), Token::Keyword(kw) if kw != "TRUE" && kw != "FALSE") {
+            return false;
+        }
+        let mut depth = 0;
+        for token in &self.tokens[self.pos..] {
+            match token {
+                Token::LeftParen | Token::LeftBracket => depth += 1,
+                Token::RightParen | Token::RightBracket => depth -= 1,
+                Token::Colon if depth == 0 => return true,
+                Token::LeftArrow | Token::Newline | Token::EOF => return false,
+                _ => {}
+            }
+        }
+        false
+    }
 
+    fn parse_assignment(&mut self) -> Result<Stmt, Diagnostic> {
+        let span = self.get_span();
+        let target = self.parse_lvalue()?;
+        self.expect(Token::LeftArrow)?;
         let value = self.parse_expression()?;
 
-        // For now, we'll store field access and pointer dereference in the name field
-        // This is a simplification - in a full implementation, you might want separate AST nodes
-        let final_name = if let Some(field) = field_name {
-            format!("{}.{}", name, field)
-        } else if is_pointer_deref {
-            format!("{}^", name)
-        } else {
-            name
-        };
-
         Ok(Stmt::Assign {
-            name: final_name,
-            indices,
+            target,
             expression: Box::new(value),
-            span: self.get_span(),
+            span,
         })
     }
-    
-    fn parse_input(&mut self) -> Result<Stmt, String> {
-        self.expect(Token::Keyword("INPUT".to_string()))?;
 
-         match self.current_token() {
+    /// A place to store into: a variable followed by any chain of `[indices]`,
+    /// `.field` and `^`, as in `Students[i].Marks[j]` or `Node^.Next^.Value`
+    fn parse_lvalue(&mut self) -> Result<LValue, Diagnostic> {
+        let span = self.get_span();
+        let mut target = match self.current_token() {
             Token::Identifier(name) => {
                 let name = name.clone();
-                let span = self.get_span();
                 self.advance();
-                Ok(Stmt::Input { name, span })
+                LValue::Variable(name, span.clone())
+            }
+            _ => return Err(self.error_with_pos("Expected identifier")),
+        };
+        loop {
+            match self.current_token() {
+                Token::LeftBracket => {
+                    let indices = self.parse_indices()?;
+                    target = LValue::Index { target: Box::new(target), indices, span: self.span_since(&span) };
+                }
+                Token::Dot => {
+                    self.advance();
+                    let field = self.parse_field_name()?;
+                    target = LValue::Field { target: Box::new(target), field, span: self.span_since(&span) };
+                }
+                Token::Caret => {
+                    self.advance();
+                    target = LValue::Deref { target: Box::new(target), span: self.span_since(&span) };
+                }
+                _ => return Ok(target),
             }
-            _ => Err(self.error_with_pos("Expected identifier")),
         }
     }
+    
+    fn parse_input(&mut self) -> Result<Stmt, Diagnostic> {
+        self.expect(Token::Keyword("INPUT".to_string()))?;
+        let span = self.get_span();
+        let target = self.parse_lvalue()?;
+        Ok(Stmt::Input { target, span })
+    }
         
-    fn parse_output(&mut self) -> Result<Stmt, String> {
+    fn parse_output(&mut self) -> Result<Stmt, Diagnostic> {
+        let span = self.get_span();
         self.expect(Token::Keyword("OUTPUT".to_string()))?;
         
         let mut exprs = Vec::new();
@@ -1132,10 +1033,38 @@ impl Parser {
             exprs.push(self.parse_expression()?);
         }
 
-        Ok(Stmt::Output { exprs, span: self.get_span() })  
+        Ok(Stmt::Output { exprs, span })  
+    }
+
+    fn parse_type_field(&mut self) -> Result<TypeField, Diagnostic> {
+        let span = self.get_span();
+        self.expect(Token::Keyword("DECLARE".to_string()))?;
+        
+        let field_name = match self.current_token() {
+            Token::Identifier(n) => {
+                let name = n.clone();
+                self.advance();
+                name
+            }
+            _ => return Err(self.error_with_pos("Expected field name")),
+        };
+        
+        self.expect(Token::Colon)?;
+        let field_type = self.parse_type()?;
+        
+        // Consume trailing newline after DECLARE statement
+        if matches!(self.current_token(), Token::Newline) {
+            self.advance();
+        }
+        
+        Ok(TypeField {
+            name: field_name,
+            type_name: field_type,
+            span,
+        })
     }
 
-    fn parse_declare(&mut self) -> Result<Stmt, String> {
+    fn parse_declare(&mut self) -> Result<Stmt, Diagnostic> {
         self.expect(Token::Keyword("DECLARE".to_string()))?;
 
         let mut declarations = vec![self.parse_one_declare()?];
@@ -1148,7 +1077,8 @@ impl Parser {
         Ok(declarations.into_iter().next().unwrap())
     }
 
-    fn parse_one_declare(&mut self) -> Result<Stmt, String> {
+    fn parse_one_declare(&mut self) -> Result<Stmt, Diagnostic> {
+        let span = self.get_span();
         let name = match self.current_token() {
             Token::Identifier(name) => {
                 let name = name.clone();
@@ -1173,11 +1103,46 @@ impl Parser {
             name, 
             type_name,
             initial_value,
-            span: self.get_span(),
+            span,
         })
     }
 
-    fn parse_type(&mut self) -> Result<Type, String> {
+    /// `CONSTANT Name = literal` or `CONSTANT Name <- expr` declares a constant,
+    /// and a bare `CONSTANT Name` locks a variable that is already declared.
+    fn parse_constant(&mut self) -> Result<Stmt, Diagnostic> {
+        let span = self.get_span();
+        self.expect(Token::Keyword("CONSTANT".to_string()))?;
+
+        let name = match self.current_token() {
+            Token::Identifier(name) => {
+                let name = name.clone();
+                self.advance();
+                name
+            }
+            _ => return Err(self.error_with_pos("Expected identifier")),
+        };
+
+        let value = match self.current_token() {
+            Token::Equals => {
+                self.advance();
+                let value = self.parse_expression()?;
+                if !is_literal(&value) {
+                    let msg = format!("Constant '{}' must be given a literal value after '=', or use '<-' for an expression", name);
+                    return Err(Diagnostic::error(codes::SYNTAX, msg).with_span(value.span()));
+                }
+                Some(Box::new(value))
+            }
+            Token::LeftArrow => {
+                self.advance();
+                Some(Box::new(self.parse_expression()?))
+            }
+            _ => None,
+        };
+
+        Ok(Stmt::Constant { name, value, span })
+    }
+
+    fn parse_type(&mut self) -> Result<Type, Diagnostic> {
         if let Token::Keyword(kw) = self.current_token() {
             if kw == "ARRAY" {
                 self.advance();
@@ -1230,7 +1195,7 @@ impl Parser {
         self.parse_simple_types()
     }
     
-    fn parse_simple_types(&mut self) -> Result<Type, String> {
+    fn parse_simple_types(&mut self) -> Result<Type, Diagnostic> {
         let current_token = self.current_token();
         
         if let Token::Identifier(name) = current_token {
@@ -1256,7 +1221,8 @@ impl Parser {
         Err(self.error_with_pos("Expected type"))
     }
 
-    fn parse_openfile(&mut self) -> Result<Stmt, String> {
+    fn parse_openfile(&mut self) -> Result<Stmt, Diagnostic> {
+        let span = self.get_span();
         self.expect(Token::Keyword("OPENFILE".to_string()))?;
         
         let filename = self.parse_expression()?;
@@ -1280,44 +1246,43 @@ impl Parser {
         Ok(Stmt::OpenFile {
             filename: Box::new(filename),
             mode,
-            span: self.get_span(),
+            span,
         })
     }
 
-    fn parse_closefile(&mut self) -> Result<Stmt, String> {
+    fn parse_closefile(&mut self) -> Result<Stmt, Diagnostic> {
+        let span = self.get_span();
         self.expect(Token::Keyword("CLOSEFILE".to_string()))?;
         
         let filename = self.parse_expression()?;
         
         Ok(Stmt::CloseFile {
             filename: Box::new(filename),
-            span: self.get_span(),
+            span,
         })
     }
 
-    fn parse_readfile(&mut self) -> Result<Stmt, String> {
+    fn parse_readfile(&mut self) -> Result<Stmt, Diagnostic> {
+        let span = self.get_span();
         self.expect(Token::Keyword("READFILE".to_string()))?;
         
         let filename = self.parse_expression()?;
         self.expect(Token::Comma)?;
         
-        let variable = match self.current_token() {
-            Token::Identifier(n) => {
-                let name = n.clone();
-                self.advance();
-                name
-            }
-            _ => return Err(self.error_with_pos("Expected variable name after comma in READFILE")),
-        };
+        if !matches!(self.current_token(), Token::Identifier(_)) {
+            return Err(self.error_with_pos("Expected variable name after comma in READFILE"));
+        }
+        let target = self.parse_lvalue()?;
         
         Ok(Stmt::ReadFile {
             filename: Box::new(filename),
-            name: variable,
-            span: self.get_span(),
+            target,
+            span,
         })
     }
 
-    fn parse_writefile(&mut self) -> Result<Stmt, String> {
+    fn parse_writefile(&mut self) -> Result<Stmt, Diagnostic> {
+        let span = self.get_span();
         self.expect(Token::Keyword("WRITEFILE".to_string()))?;
         
         let filename = self.parse_expression()?;
@@ -1334,11 +1299,12 @@ impl Parser {
         Ok(Stmt::WriteFile {
             filename: Box::new(filename),
             exprs,
-            span: self.get_span(),
+            span,
         })
     }
 
-    fn parse_seek(&mut self) -> Result<Stmt, String> {
+    fn parse_seek(&mut self) -> Result<Stmt, Diagnostic> {
+        let span = self.get_span();
         self.expect(Token::Keyword("SEEK".to_string()))?;
         
         let filename = self.parse_expression()?;
@@ -1349,11 +1315,12 @@ impl Parser {
         Ok(Stmt::Seek {
             filename: Box::new(filename),
             address: Box::new(address),
-            span: self.get_span(),
+            span,
         })
     }
 
-    fn parse_getrecord(&mut self) -> Result<Stmt, String> {
+    fn parse_getrecord(&mut self) -> Result<Stmt, Diagnostic> {
+        let span = self.get_span();
         self.expect(Token::Keyword("GETRECORD".to_string()))?;
         
         let filename = self.parse_expression()?;
@@ -1371,11 +1338,12 @@ impl Parser {
         Ok(Stmt::GetRecord {
             filename: Box::new(filename),
             variable,
-            span: self.get_span(),
+            span,
         })
     }
 
-    fn parse_putrecord(&mut self) -> Result<Stmt, String> {
+    fn parse_putrecord(&mut self) -> Result<Stmt, Diagnostic> {
+        let span = self.get_span();
         self.expect(Token::Keyword("PUTRECORD".to_string()))?;
         
         let filename = self.parse_expression()?;
@@ -1393,15 +1361,21 @@ impl Parser {
         Ok(Stmt::PutRecord {
             filename: Box::new(filename),
             variable,
-            span: self.get_span(),
+            span,
         })
     }
 
-    fn parse_primary(&mut self) -> Result<Expr, String> {
+    fn parse_primary(&mut self) -> Result<Expr, Diagnostic> {
         match self.current_token() {
             Token::Number(_) => self.parse_number(),
+            Token::Date(_) => self.parse_date(),
             Token::String(_) => self.parse_string(),
             Token::Char(_) => self.parse_char(),
+            // Not a value: the expression is missing and the next statement has started
+            Token::Keyword(kw) if BLOCK_END_KEYWORDS.contains(&kw.as_str())
+                || STATEMENT_KEYWORDS.contains(&kw.as_str()) => {
+                Err(self.error_with_pos("Expected primary expression"))
+            }
             Token::Identifier(name) | Token::Keyword(name) => {
                 let span = self.get_span();
                 if name == "TRUE" {
@@ -1412,47 +1386,27 @@ impl Parser {
                     self.advance();
                     return Ok(Expr::Boolean(false, span));
                 }
-                let var_name = name.clone();
-                self.advance();
-                
-                // Check for field access (object.field)
-                if matches!(self.current_token(), Token::Dot) {
+                if name == "NULL" || name == "NIL" {
                     self.advance();
-                    let field = match self.current_token() {
-                        Token::Identifier(f) => {
-                            let f = f.clone();
-                            self.advance();
-                            f
-                        }
-                        _ => return Err(self.error_with_pos("Expected field name after dot")),
-                    };
-                    let field_span = self.get_span();
-                    return Ok(Expr::FieldAccess {
-                        object: Box::new(Expr::Variable(var_name, span)),
-                        field,
-                        span: field_span,
-                    });
+                    return Ok(Expr::Null(span));
                 }
-                
-                // Check for pointer dereference (var^)
-                if matches!(self.current_token(), Token::Caret) {
+                if name == "NEW" {
                     self.advance();
-                    let deref_span = self.get_span();
-                    return Ok(Expr::PointerDeref {
-                        pointer: Box::new(Expr::Variable(var_name, span)),
-                        span: deref_span,
-                    });
+                    self.expect(Token::LeftParen)?;
+                    let type_name = self.parse_type()?;
+                    self.expect(Token::RightParen)?;
+                    return Ok(Expr::New { type_name, span: self.span_since(&span) });
                 }
+                let var_name = name.clone();
+                self.advance();
                 
-                // Check for function call or array access
                 if let Token::LeftParen = self.current_token() {
-                    return self.parse_function_call(var_name);
-                }
-                if let Token::LeftBracket = self.current_token() {
-                    return self.parse_array_access(var_name);
+                    return self.parse_function_call(var_name, span);
                 }
                 
-                Ok(Expr::Variable(var_name, span))
+                // Array access (arr[i]), field access (object.field) and pointer
+                // dereference (var^), which may follow each other as in Node^.Next^
+                self.parse_postfix(Expr::Variable(var_name, span.clone()), span)
             },
             Token::LeftParen => {
                 self.advance();
@@ -1466,40 +1420,65 @@ impl Parser {
                 let target = self.parse_primary()?;
                 Ok(Expr::PointerRef {
                     target: Box::new(target),
-                    span,
+                    span: self.span_since(&span),
                 })
             }
             _ => Err(self.error_with_pos("Expected primary expression")),
         }
     }
 
-    fn parse_function_call(&mut self, name: String) -> Result<Expr, String> {
-        let span = self.get_span();
+    fn parse_postfix(&mut self, mut expr: Expr, span: Span) -> Result<Expr, Diagnostic> {
+        loop {
+            match self.current_token() {
+                Token::LeftBracket => {
+                    let indices = self.parse_indices()?;
+                    expr = Expr::ArrayAccess { array: Box::new(expr), indices, span: self.span_since(&span) };
+                }
+                Token::Dot => {
+                    self.advance();
+                    let field = self.parse_field_name()?;
+                    expr = Expr::FieldAccess { object: Box::new(expr), field, span: self.span_since(&span) };
+                }
+                Token::Caret => {
+                    self.advance();
+                    expr = Expr::PointerDeref { pointer: Box::new(expr), span: self.span_since(&span) };
+                }
+                _ => return Ok(expr),
+            }
+        }
+    }
+
+    fn parse_field_name(&mut self) -> Result<String, Diagnostic> {
+        match self.current_token() {
+            Token::Identifier(field) => {
+                let field = field.clone();
+                self.advance();
+                Ok(field)
+            }
+            _ => Err(self.error_with_pos("Expected field name after dot")),
+        }
+    }
+
+    fn parse_function_call(&mut self, name: String, span: Span) -> Result<Expr, Diagnostic> {
         self.expect(Token::LeftParen)?;
         let args = self.parse_function_call_args()?;
         self.expect(Token::RightParen)?;
-        Ok(Expr::FunctionCall { name, args, span })
+        Ok(Expr::FunctionCall { name, args, span: self.span_since(&span) })
     }
 
-    fn parse_array_access(&mut self, name: String) -> Result<Expr, String> {
-        let span = self.get_span();
+    /// `[i]` or `[i, j]`
+    fn parse_indices(&mut self) -> Result<Vec<Expr>, Diagnostic> {
         self.expect(Token::LeftBracket)?;
-        let mut indices = Vec::new();
-        
-        // Parse first index
-        indices.push(self.parse_expression()?);
-        
-        // Parse additional comma-separated indices
+        let mut indices = vec![self.parse_expression()?];
         while matches!(self.current_token(), Token::Comma) {
             self.advance();
             indices.push(self.parse_expression()?);
         }
-        
         self.expect(Token::RightBracket)?;
-        Ok(Expr::ArrayAccess { array: name, indices, span })
+        Ok(indices)
     }
 
-    fn parse_function_call_args(&mut self) -> Result<Vec<Expr>, String> {
+    fn parse_function_call_args(&mut self) -> Result<Vec<Expr>, Diagnostic> {
         let mut args = Vec::new();
         
         if let Token::RightParen = self.current_token() {
@@ -1516,7 +1495,7 @@ impl Parser {
         Ok(args)
     }
 
-    pub fn parse_expression(&mut self) -> Result<Expr, String> {
+    pub fn parse_expression(&mut self) -> Result<Expr, Diagnostic> {
         trace!("Parsing expression, current token: {:?}", self.current_token());
         // Skip leading newlines before parsing expression
         while matches!(self.current_token(), Token::Newline) {
@@ -1525,7 +1504,7 @@ impl Parser {
         self.parse_binary_expression(0) 
     }
 
-    fn parse_binary_expression(&mut self, min_prec: u8) -> Result<Expr, String> {
+    fn parse_binary_expression(&mut self, min_prec: u8) -> Result<Expr, Diagnostic> {
         let mut left = self.parse_unary()?;
     
         while let Some(op) = self.peek_binary_op() {
@@ -1541,29 +1520,194 @@ impl Parser {
         Ok(left)
     }
 
-    pub fn parse_program(&mut self) -> Result<Vec<Stmt>, String> {
+    /// Parse input that holds one expression and nothing else, such as a
+    /// debugger's `print` argument
+    pub fn parse_lone_expression(&mut self) -> Result<Expr, Diagnostic> {
+        if !self.errors.is_empty() {
+            return Err(self.errors.remove(0));
+        }
+        let expr = self.parse_expression()?;
+        while matches!(self.current_token(), Token::Newline) {
+            self.advance();
+        }
+        if !matches!(self.current_token(), Token::EOF) {
+            return Err(self.error_with_pos("Expected the end of the expression"));
+        }
+        Ok(expr)
+    }
+
+    /// Parse the whole program, failing with the first error
+    pub fn parse_program(&mut self) -> Result<Vec<Stmt>, Diagnostic> {
+        let (statements, mut errors) = self.parse_program_with_errors();
+        if errors.is_empty() {
+            Ok(statements)
+        } else {
+            Err(errors.remove(0))
+        }
+    }
+
+    /// Parse the whole program, carrying on past syntax errors. Returns every
+    /// statement that could be parsed, with `Stmt::Error` in place of broken
+    /// ones, and all errors in source order.
+    pub fn parse_program_with_errors(&mut self) -> (Vec<Stmt>, Vec<Diagnostic>) {
         let mut statements = Vec::new();
-        while !matches!(self.current_token(), Token::EOF) {
-            // Skip newlines between statements
-            if matches!(self.current_token(), Token::Newline) {
+        loop {
+            statements.extend(self.parse_block());
+            if matches!(self.current_token(), Token::EOF) {
+                break;
+            }
+            // An end keyword with no block to close
+            statements.push(self.parse_statement_or_recover());
+        }
+
+        let mut errors = std::mem::take(&mut self.errors);
+        errors.sort_by_key(|error| error.span.map(|span| (span.start_line, span.start_column)));
+        (statements, errors)
+    }
+
+    /// Parse statements up to the end of the enclosing block, leaving the end
+    /// keyword for the caller to expect. Stops at any block's end keyword, so a
+    /// missing ENDIF is reported where the outer block ends.
+    fn parse_block(&mut self) -> Vec<Stmt> {
+        let mut body = Vec::new();
+        loop {
+            // Skip leading newlines (whitespace)
+            while matches!(self.current_token(), Token::Newline) {
                 self.advance();
-                continue;
             }
+            
+            if self.at_block_end() {
+                break;
+            }
+            
             trace!("Parsing statement at position {}", self.pos);
-            statements.push(self.parse_statement()?);
+            body.push(self.parse_statement_or_recover());
+            
+            // Consume trailing newline (statement terminator)
+            if matches!(self.current_token(), Token::Newline) {
+                self.advance();
+            }
+        }
+        body
+    }
+
+    fn at_block_end(&self) -> bool {
+        match self.current_token() {
+            Token::EOF => true,
+            Token::Keyword(kw) => BLOCK_END_KEYWORDS.contains(&kw.as_str()),
+            _ => false,
+        }
+    }
+
+    /// Parse a statement. On a syntax error, record it, skip past the broken
+    /// statement and return an error node in its place.
+    fn parse_statement_or_recover(&mut self) -> Stmt {
+        let start = self.pos;
+        let span = self.get_span();
+        match self.parse_statement() {
+            Ok(stmt) => stmt,
+            Err(error) => {
+                self.errors.push(error);
+                // Expressions may run on past newlines, so ignore any the failing one skipped
+                let first_line_broken = !self.tokens[start..self.pos].iter().rev()
+                    .skip_while(|token| **token == Token::Newline)
+                    .any(|token| *token == Token::Newline);
+                // Always move on, or the caller would fail on the same token again
+                if self.pos == start {
+                    self.advance();
+                }
+                self.synchronize();
+                if first_line_broken {
+                    if let Token::Keyword(kw) = &self.tokens[start] {
+                        let opener = kw.clone();
+                        self.skip_block(&opener);
+                    }
+                }
+                Stmt::Error { span }
+            }
+        }
+    }
+
+    /// Skip the rest of a broken statement: up to the end of its line, or the
+    /// next keyword that starts or ends a statement
+    fn synchronize(&mut self) {
+        loop {
+            match self.current_token() {
+                Token::EOF => break,
+                Token::Newline => {
+                    self.advance();
+                    break;
+                }
+                Token::Keyword(kw) if BLOCK_END_KEYWORDS.contains(&kw.as_str())
+                    || STATEMENT_KEYWORDS.contains(&kw.as_str()) => break,
+                _ => self.advance(),
+            }
+        }
+    }
+
+    /// When the first line of a block fails to parse, its body and end keyword are
+    /// still in the way. Parse the body for its own errors, drop it, and consume the
+    /// end keyword so it is not reported as having no block to close.
+    fn skip_block(&mut self, opener: &str) {
+        let end = match opener {
+            "IF" => "ENDIF",
+            "WHILE" => "ENDWHILE",
+            "FOR" => "NEXT",
+            "REPEAT" => "UNTIL",
+            "CASE" => "ENDCASE",
+            "PROCEDURE" => "ENDPROCEDURE",
+            "FUNCTION" => "ENDFUNCTION",
+            _ => return,
+        };
+
+        if opener == "CASE" {
+            // Branch labels are not statements, so skip the branches unparsed
+            let mut depth = 0;
+            loop {
+                match self.current_token() {
+                    Token::EOF => return,
+                    Token::Keyword(kw) if kw == "CASE" => depth += 1,
+                    Token::Keyword(kw) if kw == "ENDCASE" => {
+                        if depth == 0 {
+                            break;
+                        }
+                        depth -= 1;
+                    }
+                    _ => {}
+                }
+                self.advance();
+            }
+        } else {
+            self.parse_block();
+            while opener == "IF" && matches!(self.current_token(), Token::Keyword(kw) if kw == "ELSE") {
+                self.advance();
+                self.parse_block();
+            }
+        }
+
+        if matches!(self.current_token(), Token::Keyword(kw) if kw == end) {
+            self.advance();
+            // NEXT <counter> and UNTIL <condition> run on to the end of the line
+            self.synchronize();
         }
-        Ok(statements)
     }
 
-    fn expect(&mut self, expected: Token) -> Result<(), String> {
+    fn expect(&mut self, expected: Token) -> Result<(), Diagnostic> {
         if self.current_token() == &expected {
             trace!("Matched expected token: {:?}", expected);
             self.advance();
             Ok(())
         } else {
-            let (line, column) = self.get_position();
-            error!("Expected {:?}, found {:?} at {}:{}", expected, self.current_token(), line, column);
             Err(self.error_with_pos(&format!("Expected {:?}, found {:?}", expected, self.current_token())))
         }
     }
-}
\ No newline at end of file
+}
+
+/// Whether `expr` is a literal value, optionally negated, as `CONSTANT Name = ...` requires
+fn is_literal(expr: &Expr) -> bool {
+    match expr {
+        Expr::Number(..) | Expr::Date(..) | Expr::String(..) | Expr::Char(..) | Expr::Boolean(..) => true,
+        Expr::UnaryOp(UnaryOp::Negate, operand, _) => matches!(operand.as_ref(), Expr::Number(..)),
+        _ => false,
+    }
+}
@@ -0,0 +1,304 @@
+use std::collections::HashMap;
+
+use crate::ast::Type;
+use crate::date::Date;
+use crate::value::Value;
+
+/// Bytes a STRING takes in a record: a 2-byte length, then the text padded with zeros
+pub const STRING_SIZE: usize = 256;
+
+// Fixed-width encodings, all little-endian:
+//   INTEGER  8 bytes, two's complement
+//   REAL     8 bytes, IEEE 754 double
+//   CHAR     4 bytes, the Unicode scalar value
+//   BOOLEAN  1 byte, 0 or 1
+//   DATE     4 bytes, days since 01/01/1970
+//   STRING   STRING_SIZE bytes
+//   enum     4 bytes, the index of the value in its declaration
+// Records store their fields in declaration order and arrays their elements in
+// row-major order, with nothing in between, so every value of a type encodes to
+// the same number of bytes and SEEK can find a record from its number.
+
+fn resolve<'t>(ty: &'t Type, type_definitions: &'t HashMap<String, Type>) -> Result<&'t Type, String> {
+    match ty {
+        Type::Custom(name) => {
+            let resolved_type = type_definitions.get(name)
+                .ok_or_else(|| format!("Type {} not found", name))?;
+            resolve(resolved_type, type_definitions)
+        }
+        _ => Ok(ty),
+    }
+}
+
+fn unsupported(ty: &Type) -> String {
+    format!("Cannot store {:?} in a record file", ty)
+}
+
+fn field<'v>(fields: &'v HashMap<String, Value>, name: &str) -> Result<&'v Value, String> {
+    fields.get(name).ok_or_else(|| format!("Record has no field '{}'", name))
+}
+
+/// Bytes one record of type `ty` takes in a file. `value` is a variable of that
+/// type: arrays inside it fix their bounds when they are declared, not in the type.
+pub fn record_size(ty: &Type, value: &Value, type_definitions: &HashMap<String, Type>) -> Result<usize, String> {
+    match (resolve(ty, type_definitions)?, value) {
+        (Type::CHAR | Type::DATE | Type::Enum { .. }, _) => Ok(4),
+        (Type::INTEGER | Type::REAL, _) => Ok(8),
+        (Type::BOOLEAN, _) => Ok(1),
+        (Type::STRING, _) => Ok(STRING_SIZE),
+        (Type::Record { fields, .. }, Value::Record { fields: values, .. }) => fields.iter()
+            .map(|f| record_size(&f.type_name, field(values, &f.name)?, type_definitions))
+            .sum(),
+        (Type::ARRAY { element_type, .. }, Value::Array { data, .. }) => match data.first() {
+            Some(element) => Ok(data.len() * record_size(element_type, element, type_definitions)?),
+            None => Ok(0),
+        },
+        (other, _) => Err(unsupported(other)),
+    }
+}
+
+/// Encode `value`, a variable of type `ty`, as one record
+pub fn encode_record(value: &Value, ty: &Type, type_definitions: &HashMap<String, Type>) -> Result<Vec<u8>, String> {
+    let mut bytes = Vec::new();
+    encode(value, ty, type_definitions, &mut bytes)?;
+    Ok(bytes)
+}
+
+fn encode(value: &Value, ty: &Type, type_definitions: &HashMap<String, Type>, bytes: &mut Vec<u8>) -> Result<(), String> {
+    match (resolve(ty, type_definitions)?, value) {
+        (Type::INTEGER, Value::Integer(i)) => bytes.extend_from_slice(&i.to_le_bytes()),
+        (Type::REAL, Value::Real(r)) => bytes.extend_from_slice(&r.to_le_bytes()),
+        (Type::REAL, Value::Integer(i)) => bytes.extend_from_slice(&(*i as f64).to_le_bytes()),
+        (Type::CHAR, Value::Char(c)) => bytes.extend_from_slice(&(*c as u32).to_le_bytes()),
+        (Type::BOOLEAN, Value::Boolean(b)) => bytes.push(*b as u8),
+        (Type::DATE, Value::Date(date)) => bytes.extend_from_slice(&(date.day_number() as i32).to_le_bytes()),
+        (Type::STRING, Value::String(s)) => {
+            let text = s.as_bytes();
+            if text.len() > STRING_SIZE - 2 {
+                return Err(format!("String is too long for a record: {} bytes, at most {}", text.len(), STRING_SIZE - 2));
+            }
+            bytes.extend_from_slice(&(text.len() as u16).to_le_bytes());
+            bytes.extend_from_slice(text);
+            bytes.resize(bytes.len() + STRING_SIZE - 2 - text.len(), 0);
+        }
+        (Type::Enum { .. }, Value::Enum { index, .. }) => bytes.extend_from_slice(&(*index as u32).to_le_bytes()),
+        (Type::Record { fields, .. }, Value::Record { fields: values, .. }) => {
+            for f in fields {
+                encode(field(values, &f.name)?, &f.type_name, type_definitions, bytes)?;
+            }
+        }
+        (Type::ARRAY { element_type, .. }, Value::Array { data, .. }) => {
+            for element in data {
+                encode(element, element_type, type_definitions, bytes)?;
+            }
+        }
+        (Type::Pointer { .. } | Type::Set { .. }, _) => return Err(unsupported(ty)),
+        (expected, value) => return Err(format!("Type mismatch in record: expected {:?}, got {:?}", expected, value)),
+    }
+    Ok(())
+}
+
+/// Decode one record into a value of type `ty`. `template` is the variable
+/// being read into, which gives arrays their bounds.
+pub fn decode_record(bytes: &[u8], ty: &Type, template: &Value, type_definitions: &HashMap<String, Type>) -> Result<Value, String> {
+    let mut rest = bytes;
+    decode(&mut rest, ty, template, type_definitions)
+}
+
+/// The next `N` bytes of a record
+fn take<const N: usize>(bytes: &mut &[u8]) -> Result<[u8; N], String> {
+    if bytes.len() < N {
+        return Err("Record is shorter than its type".to_string());
+    }
+    let (head, tail) = bytes.split_at(N);
+    *bytes = tail;
+    Ok(head.try_into().expect("split at N"))
+}
+
+fn decode(bytes: &mut &[u8], ty: &Type, template: &Value, type_definitions: &HashMap<String, Type>) -> Result<Value, String> {
+    Ok(match (resolve(ty, type_definitions)?, template) {
+        (Type::INTEGER, _) => Value::Integer(i64::from_le_bytes(take(bytes)?)),
+        (Type::REAL, _) => Value::Real(f64::from_le_bytes(take(bytes)?)),
+        (Type::CHAR, _) => {
+            let code = u32::from_le_bytes(take(bytes)?);
+            Value::Char(char::from_u32(code)
+                .ok_or_else(|| format!("Corrupt record: {} is not a character", code))?)
+        }
+        (Type::BOOLEAN, _) => Value::Boolean(take::<1>(bytes)?[0] != 0),
+        (Type::DATE, _) => {
+            let days = i32::from_le_bytes(take(bytes)?);
+            Value::Date(Date::from_day_number(days as i64)
+                .map_err(|msg| format!("Corrupt record: {}", msg))?)
+        }
+        (Type::STRING, _) => {
+            let text: [u8; STRING_SIZE] = take(bytes)?;
+            let len = u16::from_le_bytes([text[0], text[1]]) as usize;
+            if len > STRING_SIZE - 2 {
+                return Err(format!("Corrupt record: string length {} is more than {}", len, STRING_SIZE - 2));
+            }
+            Value::String(String::from_utf8_lossy(&text[2..2 + len]).to_string())
+        }
+        (Type::Enum { name, values }, _) => {
+            let index = u32::from_le_bytes(take(bytes)?) as usize;
+            let value = values.get(index)
+                .ok_or_else(|| format!("Corrupt record: enum {} has no value number {}", name, index))?;
+            Value::Enum { type_name: name.clone(), value: value.clone(), index }
+        }
+        (Type::Record { name, fields }, Value::Record { fields: templates, .. }) => {
+            let mut values = HashMap::new();
+            for f in fields {
+                let value = decode(bytes, &f.type_name, field(templates, &f.name)?, type_definitions)?;
+                values.insert(f.name.clone(), value);
+            }
+            Value::Record { type_name: name.clone(), fields: values }
+        }
+        (Type::ARRAY { .. }, Value::Array { element_type, dimensions, start_indices, data }) => {
+            let data = data.iter()
+                .map(|element| decode(bytes, element_type, element, type_definitions))
+                .collect::<Result<Vec<Value>, String>>()?;
+            Value::Array {
+                element_type: element_type.clone(),
+                dimensions: dimensions.clone(),
+                start_indices: start_indices.clone(),
+                data,
+            }
+        }
+        (other, _) => return Err(unsupported(other)),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::{Span, TypeField};
+    use crate::value::default_value;
+
+    fn season() -> Type {
+        Type::Enum { name: "Season".to_string(), values: vec!["Spring".to_string(), "Summer".to_string()] }
+    }
+
+    fn definitions() -> HashMap<String, Type> {
+        HashMap::from([("Season".to_string(), season())])
+    }
+
+    fn field(name: &str, type_name: Type) -> TypeField {
+        TypeField { name: name.to_string(), type_name, span: Span::default() }
+    }
+
+    /// A record with a field of every type a record file can hold
+    fn student() -> Type {
+        Type::Record {
+            name: "Student".to_string(),
+            fields: vec![
+                field("Id", Type::INTEGER),
+                field("Average", Type::REAL),
+                field("Initial", Type::CHAR),
+                field("Enrolled", Type::BOOLEAN),
+                field("Born", Type::DATE),
+                field("Name", Type::STRING),
+                field("Term", Type::Custom("Season".to_string())),
+            ],
+        }
+    }
+
+    fn student_value() -> Value {
+        let fields = HashMap::from([
+            ("Id".to_string(), Value::Integer(-42)),
+            ("Average".to_string(), Value::Real(71.25)),
+            ("Initial".to_string(), Value::Char('é')),
+            ("Enrolled".to_string(), Value::Boolean(true)),
+            ("Born".to_string(), Value::Date(Date::new(29, 2, 2008).unwrap())),
+            ("Name".to_string(), Value::String("Zoë Smith".to_string())),
+            ("Term".to_string(), Value::Enum { type_name: "Season".to_string(), value: "Summer".to_string(), index: 1 }),
+        ]);
+        Value::Record { type_name: "Student".to_string(), fields }
+    }
+
+    fn marks(data: Vec<Value>) -> Value {
+        Value::Array { element_type: Box::new(Type::INTEGER), dimensions: vec![2, 2], start_indices: vec![1, 1], data }
+    }
+
+    fn marks_type() -> Type {
+        Type::ARRAY { dimensions: Vec::new(), element_type: Box::new(Type::INTEGER) }
+    }
+
+    #[test]
+    fn scalar_sizes() {
+        let types = definitions();
+        for (ty, size) in [(Type::INTEGER, 8), (Type::REAL, 8), (Type::CHAR, 4), (Type::BOOLEAN, 1),
+                           (Type::DATE, 4), (Type::STRING, STRING_SIZE), (season(), 4)] {
+            let value = default_value(&ty, &types).unwrap();
+            assert_eq!(record_size(&ty, &value, &types), Ok(size), "{:?}", ty);
+            assert_eq!(encode_record(&value, &ty, &types).unwrap().len(), size, "{:?}", ty);
+        }
+    }
+
+    #[test]
+    fn records_round_trip() {
+        let types = definitions();
+        let value = student_value();
+        let template = default_value(&student(), &types).unwrap();
+        let bytes = encode_record(&value, &student(), &types).unwrap();
+        assert_eq!(record_size(&student(), &template, &types), Ok(bytes.len()));
+        assert_eq!(bytes.len(), 8 + 8 + 4 + 1 + 4 + STRING_SIZE + 4);
+        assert_eq!(decode_record(&bytes, &student(), &template, &types), Ok(value));
+    }
+
+    #[test]
+    fn arrays_round_trip_with_the_template_bounds() {
+        let types = definitions();
+        let value = marks((1..=4).map(Value::Integer).collect());
+        let template = marks(vec![Value::Integer(0); 4]);
+        let bytes = encode_record(&value, &marks_type(), &types).unwrap();
+        assert_eq!(record_size(&marks_type(), &template, &types), Ok(4 * 8));
+        assert_eq!(decode_record(&bytes, &marks_type(), &template, &types), Ok(value));
+    }
+
+    #[test]
+    fn integers_store_into_real_fields() {
+        let types = definitions();
+        let bytes = encode_record(&Value::Integer(3), &Type::REAL, &types).unwrap();
+        assert_eq!(decode_record(&bytes, &Type::REAL, &Value::Real(0.0), &types), Ok(Value::Real(3.0)));
+    }
+
+    #[test]
+    fn strings_must_fit() {
+        let types = definitions();
+        let longest = Value::String("x".repeat(STRING_SIZE - 2));
+        let bytes = encode_record(&longest, &Type::STRING, &types).unwrap();
+        assert_eq!(decode_record(&bytes, &Type::STRING, &Value::String(String::new()), &types), Ok(longest));
+        let too_long = Value::String("x".repeat(STRING_SIZE - 1));
+        assert!(encode_record(&too_long, &Type::STRING, &types).unwrap_err().contains("too long"));
+    }
+
+    #[test]
+    fn short_and_corrupt_records_are_errors() {
+        let types = definitions();
+        let template = default_value(&student(), &types).unwrap();
+        let bytes = encode_record(&student_value(), &student(), &types).unwrap();
+        assert!(decode_record(&bytes[..bytes.len() - 1], &student(), &template, &types)
+            .unwrap_err().contains("shorter than its type"));
+
+        let char_template = Value::Char('\0');
+        assert!(decode_record(&0xD800u32.to_le_bytes(), &Type::CHAR, &char_template, &types)
+            .unwrap_err().contains("not a character"));
+        assert!(decode_record(&7u32.to_le_bytes(), &season(), &default_value(&season(), &types).unwrap(), &types)
+            .unwrap_err().contains("no value number 7"));
+        let mut string = vec![0xFF, 0xFF];
+        string.resize(STRING_SIZE, 0);
+        assert!(decode_record(&string, &Type::STRING, &Value::String(String::new()), &types)
+            .unwrap_err().contains("string length"));
+    }
+
+    #[test]
+    fn pointers_and_sets_cannot_be_stored() {
+        let types = definitions();
+        let pointer = Type::Pointer { points_to: Box::new(Type::INTEGER) };
+        assert!(record_size(&pointer, &Value::Pointer(None), &types).is_err());
+        assert!(encode_record(&Value::Pointer(None), &pointer, &types).is_err());
+        let set = Type::Set { element_type: Box::new(Type::INTEGER) };
+        let empty = default_value(&set, &types).unwrap();
+        assert!(record_size(&set, &empty, &types).is_err());
+        assert!(encode_record(&empty, &set, &types).is_err());
+    }
+}
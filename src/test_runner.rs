@@ -0,0 +1,317 @@
+use std::fs;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use colored::Colorize;
+
+use crate::limits::Limits;
+
+/// How long each program may run, when no `--timeout-ms` is given
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How a test runs its program
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Engine {
+    /// `pseudocode eval`, the tree-walking interpreter
+    Interpreter,
+    /// `pseudocode compile` and then `pseudocode run`, the bytecode VM
+    Vm,
+}
+
+impl Engine {
+    /// The engines `--engine` names: `interpreter`, `vm` or `both`
+    pub fn parse(name: &str) -> Option<&'static [Engine]> {
+        match name {
+            "interpreter" => Some(&[Engine::Interpreter]),
+            "vm" => Some(&[Engine::Vm]),
+            "both" => Some(&[Engine::Interpreter, Engine::Vm]),
+            _ => None,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Engine::Interpreter => "interpreter",
+            Engine::Vm => "vm",
+        }
+    }
+}
+
+/// Run every `.pseu` program under `dir` on each of `engines` and compare
+/// what it prints with the files beside it: `name.in` is fed to INPUT,
+/// `name.out` holds the expected output and `name.err` the expected errors,
+/// if it should fail. With `bless` the expectations are rewritten from what
+/// the programs do on the first engine instead, and the others are compared
+/// with that.
+///
+/// Each program runs in a scratch copy of its directory, so errors name the
+/// file the same way wherever the tests are run from and the files it writes
+/// are thrown away, and is killed if it runs longer than `timeout`. Returns
+/// whether every program matched.
+pub fn run_tests(dir: &str, bless: bool, engines: &[Engine], timeout: Duration, limits: &Limits) -> bool {
+    let mut programs = Vec::new();
+    if let Err(e) = find_programs(Path::new(dir), &mut programs) {
+        eprintln!("Error: Failed to read directory '{}': {}", dir, e);
+        return false;
+    }
+    programs.sort();
+    if programs.is_empty() {
+        eprintln!("Error: No .pseu programs found in '{}'", dir);
+        return false;
+    }
+
+    let mut failed = Vec::new();
+    for program in &programs {
+        for (i, engine) in engines.iter().enumerate() {
+            let test = format!("{} ({})", program.display(), engine.name());
+            print!("test {} ... ", test);
+            let _ = std::io::stdout().flush();
+            match run_test(program, *engine, bless && i == 0, timeout, limits) {
+                Ok(Outcome::Passed) => println!("{}", "ok".green()),
+                Ok(Outcome::Blessed) => println!("{}", "blessed".cyan()),
+                Ok(Outcome::Failed(report)) => {
+                    println!("{}", "FAILED".red());
+                    print!("{}", report);
+                    failed.push(test);
+                }
+                Err(e) => {
+                    println!("{}", "FAILED".red());
+                    println!("  {}", e);
+                    failed.push(test);
+                }
+            }
+        }
+    }
+
+    let total = programs.len() * engines.len();
+    println!();
+    if failed.is_empty() {
+        println!("{}: {} passed", "test result".bold(), total);
+    } else {
+        println!("failures:");
+        for test in &failed {
+            println!("    {}", test);
+        }
+        println!();
+        println!("{}: {} passed, {} failed", "test result".bold(), total - failed.len(), failed.len());
+    }
+    failed.is_empty()
+}
+
+enum Outcome {
+    Passed,
+    Blessed,
+    /// What differed, ready to print
+    Failed(String),
+}
+
+fn find_programs(dir: &Path, programs: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            find_programs(&path, programs)?;
+        } else if path.extension().is_some_and(|ext| ext == "pseu") {
+            programs.push(path);
+        }
+    }
+    Ok(())
+}
+
+fn run_test(program: &Path, engine: Engine, bless: bool, timeout: Duration, limits: &Limits) -> Result<Outcome, String> {
+    let input = fs::read(program.with_extension("in")).unwrap_or_default();
+    let (stdout, stderr) = run_program(program, engine, &input, timeout, limits)?;
+    let out_file = program.with_extension("out");
+    let err_file = program.with_extension("err");
+
+    if bless {
+        fs::write(&out_file, &stdout).map_err(|e| format!("Failed to write '{}': {}", out_file.display(), e))?;
+        if stderr.is_empty() {
+            let _ = fs::remove_file(&err_file);
+        } else {
+            fs::write(&err_file, &stderr).map_err(|e| format!("Failed to write '{}': {}", err_file.display(), e))?;
+        }
+        return Ok(Outcome::Blessed);
+    }
+
+    let expected_out = fs::read_to_string(&out_file)
+        .map_err(|_| format!("No expected output: '{}' is missing; run with --bless to create it", out_file.display()))?;
+    // No .err file means the program should not fail
+    let expected_err = fs::read_to_string(&err_file).unwrap_or_default();
+
+    let mut report = String::new();
+    if stdout != expected_out {
+        report.push_str(&format!("  output differs from {}:\n", out_file.display()));
+        report.push_str(&diff(&expected_out, &stdout));
+    }
+    if stderr != expected_err {
+        report.push_str(&format!("  errors differ from {}:\n", err_file.display()));
+        report.push_str(&diff(&expected_err, &stderr));
+    }
+    Ok(if report.is_empty() { Outcome::Passed } else { Outcome::Failed(report) })
+}
+
+/// Run `program` on `engine` with `input` as stdin, returning its stdout and
+/// stderr. Under the VM, stderr starts with what compiling it reported, as
+/// `eval` reports the same warnings and errors before running.
+fn run_program(program: &Path, engine: Engine, input: &[u8], timeout: Duration, limits: &Limits) -> Result<(String, String), String> {
+    let dir = scratch_copy(program)?;
+    let result = run_in(&dir, program, engine, input, timeout, limits);
+    let _ = fs::remove_dir_all(&dir);
+    result
+}
+
+/// A fresh directory holding copies of the files beside `program`, for it to
+/// run in, so what it writes stays out of the tree and every run starts from
+/// the same files
+fn scratch_copy(program: &Path) -> Result<PathBuf, String> {
+    let source = program.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    let scratch = std::env::temp_dir().join(format!("pseudocode-test-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&scratch);
+    let copy = || -> std::io::Result<()> {
+        fs::create_dir_all(&scratch)?;
+        for entry in fs::read_dir(source)? {
+            let path = entry?.path();
+            if path.is_file() {
+                fs::copy(&path, scratch.join(path.file_name().unwrap_or_default()))?;
+            }
+        }
+        Ok(())
+    };
+    copy().map_err(|e| format!("Failed to copy '{}' to run '{}' in: {}", source.display(), program.display(), e))?;
+    Ok(scratch)
+}
+
+/// Run the copy of `program` in `dir`
+fn run_in(dir: &Path, program: &Path, engine: Engine, input: &[u8], timeout: Duration, limits: &Limits) -> Result<(String, String), String> {
+    let exe = std::env::current_exe().map_err(|e| format!("Failed to find the pseudocode executable: {}", e))?;
+    let name = program.file_name().unwrap_or_default();
+    let command = |args: &[&std::ffi::OsStr]| {
+        let mut command = Command::new(&exe);
+        command.args(args).current_dir(dir);
+        command
+    };
+
+    // Compiling and running share the time a program has
+    let deadline = Instant::now() + timeout;
+    let timed_out = || format!("'{}' timed out after {} ms", program.display(), timeout.as_millis());
+    match engine {
+        Engine::Interpreter => {
+            let mut eval = command(&["eval".as_ref(), name]);
+            eval.args(limit_args(limits));
+            run_command(eval, program, input, deadline)?.ok_or_else(timed_out)
+        }
+        Engine::Vm => {
+            let (_, compile_errors) = run_command(command(&["compile".as_ref(), name]), program, &[], deadline)?
+                .ok_or_else(timed_out)?;
+            let bytecode = Path::new(name).with_extension("pseuc");
+            if !dir.join(&bytecode).exists() {
+                return Ok((String::new(), compile_errors));
+            }
+            let mut run = command(&["run".as_ref(), bytecode.as_os_str()]);
+            run.args(limit_args(limits));
+            let (stdout, stderr) = run_command(run, program, input, deadline)?.ok_or_else(timed_out)?;
+            Ok((stdout, compile_errors + &stderr))
+        }
+    }
+}
+
+/// Run `command` with `input` as stdin and return its stdout and stderr, or
+/// `None` if it was still running at `deadline` and had to be killed
+fn run_command(mut command: Command, program: &Path, input: &[u8], deadline: Instant) -> Result<Option<(String, String)>, String> {
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("Failed to run '{}': {}", program.display(), e))?;
+
+    // Fed and drained on their own threads, so a program that stops reading
+    // or fills a pipe cannot keep the timeout from being noticed
+    let mut stdin = child.stdin.take();
+    let input = input.to_vec();
+    thread::spawn(move || {
+        if let Some(stdin) = stdin.as_mut() {
+            // A program that stops reading early closes the pipe; that is not an error
+            let _ = stdin.write_all(&input);
+        }
+    });
+    let drain = |pipe: Option<Box<dyn Read + Send>>| thread::spawn(move || {
+        let mut bytes = Vec::new();
+        if let Some(mut pipe) = pipe {
+            let _ = pipe.read_to_end(&mut bytes);
+        }
+        String::from_utf8_lossy(&bytes).into_owned()
+    });
+    let stdout = drain(child.stdout.take().map(|pipe| Box::new(pipe) as Box<dyn Read + Send>));
+    let stderr = drain(child.stderr.take().map(|pipe| Box::new(pipe) as Box<dyn Read + Send>));
+
+    loop {
+        match child.try_wait() {
+            Ok(Some(_)) => break,
+            Ok(None) if Instant::now() >= deadline => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Ok(None);
+            }
+            Ok(None) => thread::sleep(Duration::from_millis(10)),
+            Err(e) => return Err(format!("Failed to run '{}': {}", program.display(), e)),
+        }
+    }
+    let output = |reader: thread::JoinHandle<String>| reader.join().unwrap_or_default();
+    Ok(Some((output(stdout), output(stderr))))
+}
+
+/// The command-line flags setting `limits`, passed on to each program
+fn limit_args(limits: &Limits) -> Vec<String> {
+    let flags = [
+        ("--max-steps", limits.max_steps),
+        ("--max-call-depth", limits.max_call_depth),
+        ("--max-output-bytes", limits.max_output_bytes),
+        ("--max-elements", limits.max_elements),
+        ("--max-open-files", limits.max_open_files),
+        ("--max-time-ms", limits.max_time_ms),
+    ];
+    flags.iter()
+        .filter_map(|(flag, limit)| limit.map(|limit| [flag.to_string(), limit.to_string()]))
+        .flatten()
+        .collect()
+}
+
+/// Line-by-line difference, expected lines marked `-` in red and actual
+/// lines `+` in green, with the lines they share left unmarked
+fn diff(expected: &str, actual: &str) -> String {
+    let expected: Vec<&str> = expected.lines().collect();
+    let actual: Vec<&str> = actual.lines().collect();
+
+    // Longest common subsequence, built from the ends so it can be walked forwards
+    let mut common = vec![vec![0usize; actual.len() + 1]; expected.len() + 1];
+    for i in (0..expected.len()).rev() {
+        for j in (0..actual.len()).rev() {
+            common[i][j] = if expected[i] == actual[j] {
+                common[i + 1][j + 1] + 1
+            } else {
+                common[i + 1][j].max(common[i][j + 1])
+            };
+        }
+    }
+
+    let mut out = String::new();
+    let (mut i, mut j) = (0, 0);
+    while i < expected.len() || j < actual.len() {
+        if i < expected.len() && j < actual.len() && expected[i] == actual[j] {
+            out.push_str(&format!("     {}\n", expected[i]));
+            i += 1;
+            j += 1;
+        } else if i < expected.len() && (j == actual.len() || common[i + 1][j] >= common[i][j + 1]) {
+            out.push_str(&format!("{}\n", format!("   - {}", expected[i]).red()));
+            i += 1;
+        } else {
+            out.push_str(&format!("{}\n", format!("   + {}", actual[j]).green()));
+            j += 1;
+        }
+    }
+    out
+}
@@ -0,0 +1,826 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::num::IntErrorKind;
+use serde::{Serialize, Deserialize};
+
+use crate::ast::{BinaryOp, UnaryOp, Type};
+use crate::date::Date;
+
+/// Where a pointer points
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Address {
+    /// A variable: `frame` identifies the call it belongs to, 0 for globals,
+    /// and `slot` is its place in that call's variables
+    Variable { frame: u64, slot: usize },
+    /// A cell allocated with NEW
+    Heap(usize),
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Address::Variable { frame, slot } => write!(f, "^var[{}:{}]", frame, slot),
+            Address::Heap(cell) => write!(f, "^heap[{}]", cell),
+        }
+    }
+}
+
+/// Runtime value shared by the tree-walking interpreter and the bytecode VM
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Value {
+    Integer(i64),
+    Real(f64),
+    String(String),
+    Char(char),
+    Boolean(bool),
+    Date(Date),
+    Record {
+        type_name: String,
+        fields: HashMap<String, Value>,
+    },
+    Enum {
+        type_name: String,
+        value: String,
+        /// Position of the value in its declaration, which orders enum values
+        index: usize,
+    },
+    /// `None` is NULL
+    Pointer(Option<Address>),
+    Set {
+        element_type: Box<Type>,
+        elements: Vec<Value>,
+    },
+    Array {
+        element_type: Box<Type>,
+        dimensions: Vec<usize>,
+        start_indices: Vec<i64>,
+        data: Vec<Value>,
+    },
+}
+
+impl Value {
+    /// Interpret the value as a condition (IF, WHILE, REPEAT...UNTIL)
+    pub fn as_condition(&self) -> Result<bool, String> {
+        match self {
+            Value::Boolean(b) => Ok(*b),
+            _ => Err(format!("Condition must be BOOLEAN, got {:?}", self)),
+        }
+    }
+}
+
+fn format_array_with_dimensions(data: &[Value], dimensions: &[usize], dim_index: usize) -> String {
+    if dimensions.is_empty() || data.is_empty() {
+        return "[]".to_string();
+    }
+
+    let current_dim = dimensions[dim_index];
+    let remaining_dims = &dimensions[dim_index + 1..];
+
+    let elements_per_sub = if remaining_dims.is_empty() {
+        1
+    } else {
+        remaining_dims.iter().product::<usize>()
+    };
+
+    let mut result = String::new();
+    result.push('[');
+
+    for i in 0..current_dim {
+        let start_idx = i * elements_per_sub;
+        let end_idx = (i + 1) * elements_per_sub;
+
+        if start_idx >= data.len() {
+            break;
+        }
+
+        let slice = &data[start_idx..end_idx.min(data.len())];
+
+        if remaining_dims.is_empty() {
+            if !slice.is_empty() {
+                result.push_str(&value_to_string(&slice[0]));
+                for val in slice.iter().skip(1) {
+                    result.push_str(", ");
+                    result.push_str(&value_to_string(val));
+                }
+            }
+        } else {
+            result.push_str(&format_array_with_dimensions(slice, dimensions, dim_index + 1));
+        }
+
+        if i < current_dim - 1 {
+            result.push_str(", ");
+        }
+    }
+
+    result.push(']');
+    result
+}
+
+/// Format a value the way OUTPUT and WRITEFILE print it
+pub fn value_to_string(value: &Value) -> String {
+    match value {
+        Value::Integer(i) => i.to_string(),
+        Value::Real(r) => r.to_string(),
+        Value::String(s) => s.clone(),
+        Value::Char(c) => c.to_string(),
+        Value::Boolean(b) => b.to_string(),
+        Value::Date(d) => d.to_string(),
+        Value::Record { .. } => format!("{:?}", value), // For now, use debug format for complex types
+        Value::Enum { value, .. } => value.clone(),
+        Value::Pointer(Some(address)) => address.to_string(),
+        Value::Pointer(None) => "NULL".to_string(),
+        Value::Set { elements, .. } => {
+            let elements: Vec<String> = elements.iter().map(value_to_string).collect();
+            format!("{{{}}}", elements.join(", "))
+        }
+        Value::Array { dimensions, data, .. } => {
+            format_array_with_dimensions(data, dimensions, 0)
+        },
+    }
+}
+
+/// `value` as it would be written in source, with strings and characters quoted
+pub fn value_to_literal(value: &Value) -> String {
+    match value {
+        Value::String(s) => format!("\"{}\"", s),
+        Value::Char(c) => format!("'{}'", c),
+        _ => value_to_string(value),
+    }
+}
+
+/// Build the default (uninitialised) value for a declared type
+pub fn default_value(type_name: &Type, type_definitions: &HashMap<String, Type>) -> Result<Value, String> {
+    match type_name {
+        Type::INTEGER => Ok(Value::Integer(0)),
+        Type::REAL => Ok(Value::Real(0.0)),
+        Type::BOOLEAN => Ok(Value::Boolean(false)),
+        Type::CHAR => Ok(Value::Char('\0')),
+        Type::STRING => Ok(Value::String("".to_string())),
+        Type::DATE => Ok(Value::Date(Date::default())),
+
+        Type::Custom(name) => {
+            let resolved_type = type_definitions.get(name)
+                .ok_or_else(|| format!("Type {} not found", name))?;
+            default_value(resolved_type, type_definitions)
+        }
+
+        Type::Record { name, fields } => {
+            let mut field_values = HashMap::new();
+            for field in fields {
+                field_values.insert(field.name.clone(), default_value(&field.type_name, type_definitions)?);
+            }
+            Ok(Value::Record {
+                type_name: name.clone(),
+                fields: field_values,
+            })
+        }
+
+        Type::Enum { name, values } => {
+            if values.is_empty() {
+                return Err(format!("Enum type {} has no values", name));
+            }
+            Ok(Value::Enum {
+                type_name: name.clone(),
+                value: values[0].clone(),
+                index: 0,
+            })
+        }
+
+        Type::Pointer { .. } => Ok(Value::Pointer(None)),
+
+        Type::Set { element_type } => {
+            Ok(Value::Set {
+                element_type: element_type.clone(),
+                elements: Vec::new(),
+            })
+        }
+
+        _ => Err(format!("Unsupported type: {:?}", type_name)),
+    }
+}
+
+/// Elements in an array whose dimensions have `sizes`
+pub fn array_size(sizes: &[usize]) -> Result<usize, String> {
+    sizes.iter()
+        .try_fold(1usize, |total, size| total.checked_mul(*size))
+        .ok_or_else(|| format!("Array too large to allocate: more than {} elements", usize::MAX))
+}
+
+/// The elements of a new array: `size` copies of `default`, if there is room for them
+pub fn array_data(size: usize, default: Value) -> Result<Vec<Value>, String> {
+    let mut data = Vec::new();
+    data.try_reserve_exact(size)
+        .map_err(|_| format!("Array too large to allocate: {} elements", size))?;
+    data.resize(size, default);
+    Ok(data)
+}
+
+/// The value at `index` in the declaration of enum `type_name`
+pub fn enum_value(type_name: &str, index: i64, type_definitions: &HashMap<String, Type>) -> Result<Value, String> {
+    let Some(Type::Enum { values, .. }) = type_definitions.get(type_name) else {
+        return Err(format!("Enum type {} not found", type_name));
+    };
+    let value = usize::try_from(index).ok()
+        .and_then(|index| values.get(index))
+        .ok_or_else(|| format!("Enum {} has no value number {}", type_name, index))?;
+    Ok(Value::Enum { type_name: type_name.to_string(), value: value.clone(), index: index as usize })
+}
+
+/// The enum value spelt `name`, from whichever TYPE declares it
+pub fn enum_literal(name: &str, type_definitions: &HashMap<String, Type>) -> Option<Value> {
+    type_definitions.values().find_map(|ty| match ty {
+        Type::Enum { name: type_name, values } => values.iter().position(|v| v == name)
+            .map(|index| Value::Enum { type_name: type_name.clone(), value: name.to_string(), index }),
+        _ => None,
+    })
+}
+
+/// Check that `value` is of the declared type, widening INTEGER to REAL where a REAL is expected
+pub fn conform_to_type(value: Value, type_name: &Type, type_definitions: &HashMap<String, Type>) -> Result<Value, String> {
+    let matches = match (type_name, &value) {
+        (Type::Custom(name), _) => {
+            let resolved_type = type_definitions.get(name)
+                .ok_or_else(|| format!("Type {} not found", name))?;
+            return conform_to_type(value, resolved_type, type_definitions);
+        }
+        (Type::REAL, Value::Integer(i)) => return Ok(Value::Real(*i as f64)),
+        (Type::INTEGER, Value::Integer(_))
+        | (Type::REAL, Value::Real(_))
+        | (Type::STRING, Value::String(_))
+        | (Type::CHAR, Value::Char(_))
+        | (Type::BOOLEAN, Value::Boolean(_))
+        | (Type::DATE, Value::Date(_))
+        | (Type::ARRAY { .. }, Value::Array { .. })
+        | (Type::Pointer { .. }, Value::Pointer(_)) => true,
+        (Type::Set { element_type: expected }, Value::Set { element_type, .. }) => expected == element_type,
+        (Type::Record { name, .. }, Value::Record { type_name, .. })
+        | (Type::Enum { name, .. }, Value::Enum { type_name, .. }) => name == type_name,
+        _ => false,
+    };
+    if matches {
+        Ok(value)
+    } else {
+        Err(format!("Expected a value of type {:?}, got {:?}", type_name, value))
+    }
+}
+
+/// `value` made to fit where `current` is stored, when no declared type is at
+/// hand, as for the target of a pointer: it must be the same kind of value,
+/// though an INTEGER is widened for a REAL
+pub fn conform_to_value(value: Value, current: &Value) -> Result<Value, String> {
+    let matches = match (current, &value) {
+        (Value::Real(_), Value::Integer(i)) => return Ok(Value::Real(*i as f64)),
+        (Value::Record { type_name: expected, .. }, Value::Record { type_name, .. })
+        | (Value::Enum { type_name: expected, .. }, Value::Enum { type_name, .. }) => expected == type_name,
+        (Value::Set { element_type: expected, .. }, Value::Set { element_type, .. }) => expected == element_type,
+        _ => std::mem::discriminant(current) == std::mem::discriminant(&value),
+    };
+    if matches {
+        Ok(value)
+    } else {
+        Err(format!("Expected a value like {:?}, got {:?}", current, value))
+    }
+}
+
+/// Parse INTEGER text, telling numbers too large to hold apart from text that is not a number
+pub fn parse_integer(text: &str) -> Result<i64, String> {
+    text.parse::<i64>().map_err(|error| match error.kind() {
+        IntErrorKind::PosOverflow | IntErrorKind::NegOverflow => {
+            format!("Integer out of range: {} does not fit in an INTEGER ({} to {})", text, i64::MIN, i64::MAX)
+        }
+        _ => format!("Invalid integer: '{}'", text),
+    })
+}
+
+/// Parse a DEFINE value list entry into a value of the set's element type
+pub fn parse_value_string(val_str: &str, element_type: &Type) -> Result<Value, String> {
+    match element_type {
+        Type::INTEGER => {
+            parse_integer(val_str).map(Value::Integer)
+        }
+        Type::REAL => {
+            val_str.parse::<f64>()
+                .map(Value::Real)
+                .map_err(|_| format!("Invalid real: {}", val_str))
+        }
+        Type::STRING => {
+            Ok(Value::String(val_str.to_string()))
+        }
+        Type::CHAR => {
+            let ch = val_str.trim_matches('\'').chars().next()
+                .ok_or_else(|| format!("Invalid char: {}", val_str))?;
+            Ok(Value::Char(ch))
+        }
+        Type::BOOLEAN => {
+            match val_str.to_uppercase().as_str() {
+                "TRUE" => Ok(Value::Boolean(true)),
+                "FALSE" => Ok(Value::Boolean(false)),
+                _ => Err(format!("Invalid boolean: {}", val_str))
+            }
+        }
+        _ => {
+            Err(format!("Unsupported element type for set: {:?}", element_type))
+        }
+    }
+}
+
+/// Check that INPUT can read a value of the given type
+pub fn validate_input_type(var_type: &Type) -> Result<(), String> {
+    match var_type {
+        Type::INTEGER | Type::REAL | Type::STRING | Type::CHAR | Type::BOOLEAN | Type::DATE => Ok(()),
+        _ => Err(format!("Input not supported for type: {:?}", var_type)),
+    }
+}
+
+/// Convert a line of user input into a value of the variable's type
+pub fn parse_input(input: &str, var_type: &Type) -> Result<Value, String> {
+    let input = input.trim();
+    match var_type {
+        Type::INTEGER => {
+            Ok(Value::Integer(parse_integer(input)?))
+        }
+        Type::REAL => {
+            Ok(Value::Real(input.parse().map_err(|_| format!("Invalid real number: '{}'", input))?))
+        }
+        Type::STRING => {
+            Ok(Value::String(input.to_string()))
+        }
+        Type::CHAR => {
+            if input.len() == 1 {
+                Ok(Value::Char(input.chars().next().unwrap()))
+            } else {
+                Err(format!("Invalid char: expected single character, got '{}'", input))
+            }
+        }
+        Type::BOOLEAN => {
+            match input.to_lowercase().as_str() {
+                "true" | "1" | "yes" => Ok(Value::Boolean(true)),
+                "false" | "0" | "no" => Ok(Value::Boolean(false)),
+                _ => Err(format!("Invalid boolean: '{}' (expected true/false)", input)),
+            }
+        }
+        Type::DATE => Date::parse(input).map(Value::Date),
+        _ => Err(format!("Input not supported for type: {:?}", var_type)),
+    }
+}
+
+/// Convert user-facing indices into a flat offset into an array's data
+pub fn array_offset(index_values: &[Value], dimensions: &[usize], start_indices: &[i64]) -> Result<usize, String> {
+    if index_values.len() != start_indices.len() {
+        return Err(format!("Index dimension mismatch: expected {} dimensions, got {}", start_indices.len(), index_values.len()));
+    }
+
+    let mut index_positions = Vec::new();
+    for (idx_val, start_idx) in index_values.iter().zip(start_indices.iter()) {
+        match idx_val {
+            Value::Integer(i) => {
+                if *i < *start_idx {
+                    return Err(format!("Index must be >= {}, got {}", start_idx, i));
+                }
+                // Convert user index to 0-based internal index
+                index_positions.push(i.abs_diff(*start_idx) as usize);
+            }
+            _ => return Err(format!("Index must be integer, got {:?}", idx_val)),
+        }
+    }
+
+    calculate_array_index(&index_positions, dimensions)
+}
+
+pub fn calculate_array_index(indices: &[usize], dimensions: &[usize]) -> Result<usize, String> {
+    if indices.len() != dimensions.len() {
+        return Err(format!(
+            "Index dimension mismatch: expected {} dimensions, got {}",
+            dimensions.len(),
+            indices.len()
+        ));
+    }
+
+    for (i, (idx, dim_size)) in indices.iter().zip(dimensions.iter()).enumerate() {
+        if *idx >= *dim_size {
+            return Err(format!(
+                "Index {} out of bounds: {} >= {}",
+                i, idx, dim_size
+            ));
+        }
+    }
+
+    let mut flat_index = 0;
+    let mut stride = 1;
+
+    for (idx, dim_size) in indices.iter().zip(dimensions.iter()).rev() {
+        flat_index += idx * stride;
+        stride *= dim_size;
+    }
+
+    Ok(flat_index)
+}
+
+/// Read an element of an array (or a 1-based element of a set)
+pub fn index_value<'a>(container: &'a Value, index_values: &[Value], name: &str) -> Result<&'a Value, String> {
+    match container {
+        Value::Array { dimensions, start_indices, data, .. } => {
+            let flat_index = array_offset(index_values, dimensions, start_indices)?;
+            data.get(flat_index)
+                .ok_or_else(|| format!("Array index out of bounds: {}", flat_index))
+        }
+        Value::Set { elements, .. } => {
+            // Sets use 1-based indexing (no start index stored)
+            if index_values.len() != 1 {
+                return Err(format!("Set access requires exactly 1 index, got {}", index_values.len()));
+            }
+            let index = match &index_values[0] {
+                Value::Integer(i) => {
+                    if *i < 1 {
+                        return Err(format!("Set index must be >= 1, got {}", i));
+                    }
+                    (i - 1) as usize  // Convert 1-based to 0-based
+                }
+                other => return Err(format!("Set index must be integer, got {:?}", other)),
+            };
+            elements.get(index)
+                .ok_or_else(|| format!("Set index out of bounds: {}", index))
+        }
+        // Enums don't support indexed access - they're single values
+        Value::Enum { .. } => Err(format!("Cannot use indexed access on enum value: {}", name)),
+        _ => Err(format!("Indexed access on unsupported type: {}", name)),
+    }
+}
+
+/// Get a mutable reference to an array element for assignment
+pub fn index_value_mut<'a>(container: &'a mut Value, index_values: &[Value], name: &str) -> Result<&'a mut Value, String> {
+    match container {
+        Value::Array { dimensions, start_indices, data, .. } => {
+            let flat_index = array_offset(index_values, dimensions, start_indices)?;
+            data.get_mut(flat_index)
+                .ok_or_else(|| format!("Index out of bounds: {} for array {}", flat_index, name))
+        }
+        Value::Set { .. } => Err(format!("Cannot assign to set '{}' - sets are immutable", name)),
+        _ => Err(format!("Variable '{}' is not an array", name)),
+    }
+}
+
+/// One step from a value to a part of it: an element or a record field
+#[derive(Debug, Clone, PartialEq)]
+pub enum Step {
+    Index(Vec<Value>),
+    Field(String),
+}
+
+/// The storage a BYREF parameter stands for: a variable or heap cell, and the
+/// element or field `steps` lead to inside it. Indices are evaluated once, on call.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Reference {
+    pub address: Address,
+    pub steps: Vec<Step>,
+}
+
+/// The part of `value` that `steps` lead to, such as an element's field
+pub fn part<'a>(value: &'a Value, steps: &[Step], name: &str) -> Result<&'a Value, String> {
+    steps.iter().try_fold(value, |value, step| match step {
+        Step::Index(indices) => index_value(value, indices, name),
+        Step::Field(field) => match value {
+            Value::Record { type_name, fields } => fields.get(field)
+                .ok_or_else(|| format!("Field '{}' not found in record of type '{}'", field, type_name)),
+            _ => Err(format!("Field access on non-record variable: {}", name)),
+        },
+    })
+}
+
+/// Like `part`, for assigning to it. Set elements cannot be assigned.
+pub fn part_mut<'a>(value: &'a mut Value, steps: &[Step], name: &str) -> Result<&'a mut Value, String> {
+    steps.iter().try_fold(value, |value, step| match step {
+        Step::Index(indices) => index_value_mut(value, indices, name),
+        Step::Field(field) => match value {
+            Value::Record { type_name, fields } => fields.get_mut(field)
+                .ok_or_else(|| format!("Field '{}' not found in record of type '{}'", field, type_name)),
+            _ => Err(format!("Field access on non-record variable: {}", name)),
+        },
+    })
+}
+
+/// Declared type of the part of `value` that `steps` lead to, for reading
+/// input into it. With no steps only the built-in types can be told apart.
+pub fn part_type(value: &Value, steps: &[Step], type_definitions: &HashMap<String, Type>, name: &str) -> Result<Type, String> {
+    let Some((last, steps)) = steps.split_last() else {
+        return match value {
+            Value::Integer(_) => Ok(Type::INTEGER),
+            Value::Real(_) => Ok(Type::REAL),
+            Value::String(_) => Ok(Type::STRING),
+            Value::Char(_) => Ok(Type::CHAR),
+            Value::Boolean(_) => Ok(Type::BOOLEAN),
+            Value::Date(_) => Ok(Type::DATE),
+            other => Err(format!("Input not supported for value: {:?}", other)),
+        };
+    };
+    match (part(value, steps, name)?, last) {
+        (Value::Array { element_type, .. }, Step::Index(_)) => Ok((**element_type).clone()),
+        (Value::Set { .. }, Step::Index(_)) => Err(format!("Cannot assign to set '{}' - sets are immutable", name)),
+        (_, Step::Index(_)) => Err(format!("Indexed access on unsupported type: {}", name)),
+        (Value::Record { type_name, .. }, Step::Field(field)) => match type_definitions.get(type_name) {
+            Some(Type::Record { fields, .. }) => fields.iter()
+                .find(|f| &f.name == field)
+                .map(|f| f.type_name.clone())
+                .ok_or_else(|| format!("Field '{}' not found in record of type '{}'", field, type_name)),
+            _ => Err(format!("Type '{}' is not defined", type_name)),
+        },
+        (_, Step::Field(_)) => Err(format!("Field access on non-record variable: {}", name)),
+    }
+}
+
+pub fn unary_op(op: &UnaryOp, val: Value) -> Result<Value, String> {
+    match op {
+        UnaryOp::Negate => {
+            match val {
+                Value::Integer(l) => l.checked_neg()
+                    .map(Value::Integer)
+                    .ok_or_else(|| format!("Integer overflow: -({}) is outside the INTEGER range", l)),
+                Value::Real(l) => Ok(Value::Real(-l)),
+                _ => Err(format!("Unsupported negation operation: {:?}", op)),
+            }
+        }
+        UnaryOp::Not => {
+            match val {
+                Value::Boolean(l) => Ok(Value::Boolean(!l)),
+                _ => Err(format!("Unsupported NOT operation: {:?}", op)),
+            }
+        }
+    }
+}
+
+/// A set of `element_type` holding each of `elements` once, in the order they first appear
+pub fn make_set(element_type: &Type, elements: impl IntoIterator<Item = Value>) -> Value {
+    let mut unique: Vec<Value> = Vec::new();
+    for element in elements {
+        if !unique.contains(&element) {
+            unique.push(element);
+        }
+    }
+    Value::Set { element_type: Box::new(element_type.clone()), elements: unique }
+}
+
+/// Whether `value` lies in the CASE range `low TO high`, both ends included.
+/// Ranges are over INTEGER, CHAR or the values of one enum.
+pub fn in_range(value: &Value, low: &Value, high: &Value) -> Result<bool, String> {
+    match (value, low, high) {
+        (Value::Integer(v), Value::Integer(l), Value::Integer(h)) => Ok(l <= v && v <= h),
+        (Value::Char(v), Value::Char(l), Value::Char(h)) => Ok(l <= v && v <= h),
+        (Value::Enum { type_name, index: v, .. }, Value::Enum { type_name: l_type, index: l, .. }, Value::Enum { type_name: h_type, index: h, .. })
+            if type_name == l_type && type_name == h_type => Ok(l <= v && v <= h),
+        _ => Err(format!("Unsupported CASE range: {:?} TO {:?} with {:?}", low, high, value)),
+    }
+}
+
+fn is_subset(left: &[Value], right: &[Value]) -> bool {
+    left.iter().all(|element| right.contains(element))
+}
+
+/// IN, and the operators between two sets: union (+), intersection (*),
+/// difference (-), equality (= and <>) and subset (<= and >=).
+/// `None` when neither applies.
+fn set_op(op: &BinaryOp, left: &Value, right: &Value) -> Option<Result<Value, String>> {
+    use BinaryOp::*;
+    if *op == In {
+        let Value::Set { element_type, elements } = right else {
+            return Some(Err(format!("IN needs a set on the right, got {:?}", right)));
+        };
+        // Custom element types cannot be resolved here, so only built-in ones are converted
+        let element = match element_type.as_ref() {
+            Type::Custom(_) => Ok(left.clone()),
+            _ => conform_to_type(left.clone(), element_type, &HashMap::new()),
+        };
+        return Some(match element {
+            Ok(element) => Ok(Value::Boolean(elements.contains(&element))),
+            Err(_) => Err(format!("IN needs a {:?} to look for in this set, got {:?}", element_type, left)),
+        });
+    }
+
+    let (Value::Set { element_type, elements: l }, Value::Set { element_type: right_type, elements: r }) = (left, right) else {
+        return None;
+    };
+    if element_type != right_type {
+        return Some(Err(format!("Cannot combine SET OF {:?} with SET OF {:?}", element_type, right_type)));
+    }
+    let result = match op {
+        Add => make_set(element_type, l.iter().chain(r).cloned()),
+        Multiply => make_set(element_type, l.iter().filter(|e| r.contains(e)).cloned()),
+        Subtract => make_set(element_type, l.iter().filter(|e| !r.contains(e)).cloned()),
+        Equals => Value::Boolean(is_subset(l, r) && is_subset(r, l)),
+        NotEquals => Value::Boolean(!(is_subset(l, r) && is_subset(r, l))),
+        LessThanOrEqual => Value::Boolean(is_subset(l, r)),
+        GreaterThanOrEqual => Value::Boolean(is_subset(r, l)),
+        _ => return Some(Err(format!("Unsupported set operation: {:?}", op))),
+    };
+    Some(Ok(result))
+}
+
+/// The result of checked INTEGER arithmetic, or an overflow error naming the operation
+fn checked(result: Option<i64>, left: i64, symbol: &str, right: i64) -> Result<Value, String> {
+    result.map(Value::Integer)
+        .ok_or_else(|| format!("Integer overflow: {} {} {} is outside the INTEGER range", left, symbol, right))
+}
+
+pub fn binary_op(op: &BinaryOp, left: &Value, right: &Value) -> Result<Value, String> {
+    use BinaryOp::*;
+    if let Some(result) = set_op(op, left, right) {
+        return result;
+    }
+    match op {
+        Add => {
+            match (left, right) {
+                (Value::Integer(l), Value::Integer(r)) => checked(l.checked_add(*r), *l, "+", *r),
+                (Value::Real(l), Value::Real(r)) => Ok(Value::Real(l + r)),
+                (Value::String(l), Value::String(r)) => Ok(Value::String(format!("{}{}", l, r))),
+                (Value::String(l), Value::Integer(r)) => Ok(Value::String(format!("{}{}", l, r))),
+                (Value::Integer(l), Value::String(r)) => Ok(Value::String(format!("{}{}", l, r))),
+                (Value::String(l), Value::Real(r)) => Ok(Value::String(format!("{}{}", l, r))),
+                (Value::Real(l), Value::String(r)) => Ok(Value::String(format!("{}{}", l, r))),
+                (Value::Char(l), Value::Char(r)) => Ok(Value::String(format!("{}{}", l, r))),
+                (Value::Real(l), Value::Integer(r)) => Ok(Value::Real(l + *r as f64)),
+                (Value::Integer(l), Value::Real(r)) => Ok(Value::Real(*l as f64 + r)),
+                (Value::Date(d), Value::Integer(days)) | (Value::Integer(days), Value::Date(d)) => {
+                    Ok(Value::Date(d.add_days(*days)?))
+                }
+                _ => Err(format!("Unsupported addition operation: {:?} with {:?} and {:?}", op, left, right)),
+            }
+        }
+        Subtract => {
+            match (left, right) {
+                (Value::Integer(l), Value::Integer(r)) => checked(l.checked_sub(*r), *l, "-", *r),
+                (Value::Real(l), Value::Real(r)) => Ok(Value::Real(l - r)),
+                (Value::Real(l), Value::Integer(r)) => Ok(Value::Real(l - *r as f64)),
+                (Value::Integer(l), Value::Real(r)) => Ok(Value::Real(*l as f64 - r)),
+                (Value::Date(l), Value::Date(r)) => Ok(Value::Integer(l.days_since(r))),
+                (Value::Date(d), Value::Integer(days)) => Ok(Value::Date(d.add_days(days.saturating_neg())?)),
+                _ => Err(format!("Unsupported subtraction operation: {:?} with {:?} and {:?}", op, left, right)),
+            }
+        }
+        Multiply => {
+            match (left, right) {
+                (Value::Integer(l), Value::Integer(r)) => checked(l.checked_mul(*r), *l, "*", *r),
+                (Value::Real(l), Value::Real(r)) => Ok(Value::Real(l * r)),
+                (Value::Real(l), Value::Integer(r)) => Ok(Value::Real(l * *r as f64)),
+                (Value::Integer(l), Value::Real(r)) => Ok(Value::Real(*l as f64 * r)),
+                _ => Err(format!("Unsupported multiplication operation: {:?} with {:?} and {:?}", op, left, right)),
+            }
+        }
+        Divide => {
+            match (left, right) {
+                (Value::Integer(a), Value::Integer(b)) => {
+                    if *b == 0 {
+                        return Err("Division by zero".to_string());
+                    }
+                    Ok(Value::Real(*a as f64 / *b as f64))
+                }
+                (Value::Real(a), Value::Real(b)) => {
+                    if *b == 0.0 {
+                        return Err("Division by zero".to_string());
+                    }
+                    Ok(Value::Real(a / b))
+                }
+                (Value::Integer(a), Value::Real(b)) => {
+                    if *b == 0.0 {
+                        return Err("Division by zero".to_string());
+                    }
+                    Ok(Value::Real(*a as f64 / b))
+                }
+                (Value::Real(a), Value::Integer(b)) => {
+                    if *b == 0 {
+                        return Err("Division by zero".to_string());
+                    }
+                    Ok(Value::Real(a / *b as f64))
+                }
+                _ => Err("Invalid operands for division".to_string()),
+            }
+        }
+        Div => {
+            match (left, right) {
+                (Value::Integer(a), Value::Integer(b)) => {
+                    if *b == 0 {
+                        return Err("Division by zero in DIV".to_string());
+                    }
+                    checked(a.checked_div(*b), *a, "DIV", *b)
+                }
+                _ => Err("DIV requires integer operands".to_string()),
+            }
+        }
+        Modulus => {
+            match (left, right) {
+                (Value::Integer(a), Value::Integer(b)) => {
+                    if *b == 0 {
+                        return Err("Modulo by zero".to_string());
+                    }
+                    checked(a.checked_rem(*b), *a, "MOD", *b)
+                }
+                _ => Err("Modulus requires integer operands".to_string()),
+            }
+        }
+        // A CHAR joins like a one-character STRING
+        Concat => {
+            match (left, right) {
+                (Value::String(_) | Value::Char(_), Value::String(_) | Value::Char(_)) => {
+                    Ok(Value::String(format!("{}{}", value_to_string(left), value_to_string(right))))
+                }
+                _ => Err(format!("Operator & joins STRING and CHAR values, got {:?} and {:?}", left, right)),
+            }
+        }
+
+        Equals => {
+            match (left, right) {
+                (Value::Integer(l), Value::Integer(r)) => Ok(Value::Boolean(l == r)),
+                (Value::Real(l), Value::Real(r)) => Ok(Value::Boolean(l == r)),
+                (Value::String(l), Value::String(r)) => Ok(Value::Boolean(l == r)),
+                (Value::Char(l), Value::Char(r)) => Ok(Value::Boolean(l == r)),
+                (Value::Real(l), Value::Integer(r)) => Ok(Value::Boolean(*l == (*r as f64))),
+                (Value::Integer(l), Value::Real(r)) => Ok(Value::Boolean((*l as f64) == *r)),
+                (Value::Date(l), Value::Date(r)) => Ok(Value::Boolean(l == r)),
+                (Value::Pointer(l), Value::Pointer(r)) => Ok(Value::Boolean(l == r)),
+                (Value::Enum { type_name: l_type, index: l, .. }, Value::Enum { type_name: r_type, index: r, .. }) if l_type == r_type => {
+                    Ok(Value::Boolean(l == r))
+                }
+                _ => Err(format!("Unsupported equality operation: {:?} with {:?} and {:?}", op, left, right)),
+            }
+        }
+        NotEquals => {
+            match (left, right) {
+                (Value::Integer(l), Value::Integer(r)) => Ok(Value::Boolean(l != r)),
+                (Value::Real(l), Value::Real(r)) => Ok(Value::Boolean(l != r)),
+                (Value::String(l), Value::String(r)) => Ok(Value::Boolean(l != r)),
+                (Value::Char(l), Value::Char(r)) => Ok(Value::Boolean(l != r)),
+                (Value::Real(l), Value::Integer(r)) => Ok(Value::Boolean(*l != (*r as f64))),
+                (Value::Integer(l), Value::Real(r)) => Ok(Value::Boolean((*l as f64) != *r)),
+                (Value::Date(l), Value::Date(r)) => Ok(Value::Boolean(l != r)),
+                (Value::Pointer(l), Value::Pointer(r)) => Ok(Value::Boolean(l != r)),
+                (Value::Enum { type_name: l_type, index: l, .. }, Value::Enum { type_name: r_type, index: r, .. }) if l_type == r_type => {
+                    Ok(Value::Boolean(l != r))
+                }
+                _ => Err(format!("Unsupported not equals operation: {:?} with {:?} and {:?}", op, left, right)),
+            }
+        }
+        LessThan => {
+            match (left, right) {
+                (Value::Integer(l), Value::Integer(r)) => Ok(Value::Boolean(l < r)),
+                (Value::Real(l), Value::Real(r)) => Ok(Value::Boolean(l < r)),
+                (Value::Real(l), Value::Integer(r)) => Ok(Value::Boolean(*l < (*r as f64))),
+                (Value::Integer(l), Value::Real(r)) => Ok(Value::Boolean((*l as f64) < *r)),
+                (Value::Date(l), Value::Date(r)) => Ok(Value::Boolean(l < r)),
+                (Value::Enum { type_name: l_type, index: l, .. }, Value::Enum { type_name: r_type, index: r, .. }) if l_type == r_type => {
+                    Ok(Value::Boolean(l < r))
+                }
+                _ => Err(format!("Unsupported less than operation: {:?} with {:?} and {:?}", op, left, right)),
+            }
+        }
+        GreaterThan => {
+            match (left, right) {
+                (Value::Integer(l), Value::Integer(r)) => Ok(Value::Boolean(l > r)),
+                (Value::Real(l), Value::Real(r)) => Ok(Value::Boolean(l > r)),
+                (Value::Real(l), Value::Integer(r)) => Ok(Value::Boolean(*l > (*r as f64))),
+                (Value::Integer(l), Value::Real(r)) => Ok(Value::Boolean((*l as f64) > *r)),
+                (Value::Date(l), Value::Date(r)) => Ok(Value::Boolean(l > r)),
+                (Value::Enum { type_name: l_type, index: l, .. }, Value::Enum { type_name: r_type, index: r, .. }) if l_type == r_type => {
+                    Ok(Value::Boolean(l > r))
+                }
+                _ => Err(format!("Unsupported greater than operation: {:?} with {:?} and {:?}", op, left, right)),
+            }
+        }
+        LessThanOrEqual => {
+            match (left, right) {
+                (Value::Integer(l), Value::Integer(r)) => Ok(Value::Boolean(l <= r)),
+                (Value::Real(l), Value::Real(r)) => Ok(Value::Boolean(l <= r)),
+                (Value::Real(l), Value::Integer(r)) => Ok(Value::Boolean(*l <= (*r as f64))),
+                (Value::Integer(l), Value::Real(r)) => Ok(Value::Boolean((*l as f64) <= *r)),
+                (Value::Date(l), Value::Date(r)) => Ok(Value::Boolean(l <= r)),
+                (Value::Enum { type_name: l_type, index: l, .. }, Value::Enum { type_name: r_type, index: r, .. }) if l_type == r_type => {
+                    Ok(Value::Boolean(l <= r))
+                }
+                _ => Err(format!("Unsupported less than or equal operation: {:?} with {:?} and {:?}", op, left, right)),
+            }
+        }
+        GreaterThanOrEqual => {
+            match (left, right) {
+                (Value::Integer(l), Value::Integer(r)) => Ok(Value::Boolean(l >= r)),
+                (Value::Real(l), Value::Real(r)) => Ok(Value::Boolean(l >= r)),
+                (Value::Real(l), Value::Integer(r)) => Ok(Value::Boolean(*l >= (*r as f64))),
+                (Value::Integer(l), Value::Real(r)) => Ok(Value::Boolean((*l as f64) >= *r)),
+                (Value::Date(l), Value::Date(r)) => Ok(Value::Boolean(l >= r)),
+                (Value::Enum { type_name: l_type, index: l, .. }, Value::Enum { type_name: r_type, index: r, .. }) if l_type == r_type => {
+                    Ok(Value::Boolean(l >= r))
+                }
+                _ => Err(format!("Unsupported greater than or equal operation: {:?} with {:?} and {:?}", op, left, right)),
+            }
+        }
+        And => {
+            match (left, right) {
+                (Value::Boolean(l), Value::Boolean(r)) => Ok(Value::Boolean(*l && *r)),
+                _ => Err(format!("Unsupported AND operation: {:?} with {:?} and {:?}", op, left, right)),
+            }
+        }
+        Or => {
+            match (left, right) {
+                (Value::Boolean(l), Value::Boolean(r)) => Ok(Value::Boolean(*l || *r)),
+                _ => Err(format!("Unsupported OR operation: {:?} with {:?} and {:?}", op, left, right)),
+            }
+        }
+        In => unreachable!("IN is handled by set_op"),
+    }
+}
@@ -0,0 +1,1099 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::ast::{PassingMode, Type};
+use crate::builtins::{call_builtin, enum_neighbour};
+use crate::bytecode::{path_indices, Instr, PathStep, Program, VarRef};
+use crate::host::{Clock, Console, FileSystem, Host, InputSource, RandomSource};
+use crate::diagnostic::{codes, Diagnostic};
+use crate::heap::Heap;
+use crate::limits::{Budget, Limits};
+use crate::record;
+use crate::value::{self, Address, Reference, Step, Value, default_value, parse_value_string, value_to_string};
+
+/// Storage for one variable. `ty` is set once the variable has been declared.
+/// A BYREF parameter has no value of its own, only the `reference` it stands for.
+#[derive(Debug, Clone, Default)]
+struct Slot {
+    value: Option<Value>,
+    ty: Option<Type>,
+    constant: bool,
+    reference: Option<Reference>,
+}
+
+fn dangling() -> String {
+    "Dangling pointer: the variable it points to no longer exists".to_string()
+}
+
+#[derive(Debug)]
+struct Frame {
+    /// Unique to this call, so pointers to its locals can tell once it has
+    /// returned. 0 stands for the globals.
+    id: u64,
+    routine: usize,
+    return_pc: usize,
+    base: usize,
+}
+
+/// Storage a store writes to: a variable, or whatever a pointer points to,
+/// and then the elements and fields `steps` lead to inside it
+struct Place {
+    root: PlaceRoot,
+    steps: Vec<Step>,
+}
+
+enum PlaceRoot {
+    Variable(VarRef),
+    Pointer(Option<Address>),
+}
+
+/// Why `resume` returned
+#[derive(Debug, Clone, PartialEq)]
+pub enum Suspend {
+    Finished,
+    /// INPUT is waiting for a line for the variable `name`, of type `type_name`.
+    /// The program carries on from the INPUT once a line has been supplied.
+    Input { name: String, type_name: String, line: usize },
+}
+
+/// Stack machine executing a compiled `Program`
+pub struct Vm<C, I, F, R, K> {
+    program: Rc<Program>,
+    pc: usize,
+    stack: Vec<Value>,
+    globals: Vec<Slot>,
+    locals: Vec<Slot>,
+    frames: Vec<Frame>,
+    /// References made by `ByRef` for the next call's BYREF arguments
+    references: Vec<Reference>,
+    calls: u64,
+    heap: Heap,
+    type_definitions: HashMap<String, Type>,
+    host: Host<C, I, F, R, K>,
+    budget: Budget,
+    /// Warnings raised while running, until `take_warnings`
+    warnings: Vec<Diagnostic>,
+}
+
+impl<C: Console, I: InputSource, F: FileSystem, R: RandomSource, K: Clock> Vm<C, I, F, R, K> {
+    /// Create a VM for `program` doing its I/O through `host`
+    pub fn new(program: Program, host: Host<C, I, F, R, K>) -> Self {
+        let mut vm = Self {
+            program: Rc::default(),
+            pc: 0,
+            stack: Vec::new(),
+            globals: Vec::new(),
+            locals: Vec::new(),
+            frames: Vec::new(),
+            references: Vec::new(),
+            calls: 0,
+            heap: Heap::default(),
+            type_definitions: HashMap::new(),
+            host,
+            budget: Budget::default(),
+            warnings: Vec::new(),
+        };
+        vm.load(program);
+        vm
+    }
+
+    /// Forget the current program and close open files, ready to run `program`
+    /// from the start. The host's console and input are left to the caller.
+    pub fn load(&mut self, program: Program) {
+        self.pc = 0;
+        self.stack.clear();
+        self.globals = vec![Slot::default(); program.globals.len()];
+        self.locals.clear();
+        self.frames.clear();
+        self.references.clear();
+        self.calls = 0;
+        self.heap.clear();
+        self.type_definitions.clear();
+        self.host.files.close_all();
+        self.budget.reset();
+        self.warnings.clear();
+        self.program = Rc::new(program);
+    }
+
+    pub fn host(&self) -> &Host<C, I, F, R, K> {
+        &self.host
+    }
+
+    pub fn host_mut(&mut self) -> &mut Host<C, I, F, R, K> {
+        &mut self.host
+    }
+
+    /// The warnings raised since the last call, each instruction's at most once
+    pub fn take_warnings(&mut self) -> Vec<Diagnostic> {
+        std::mem::take(&mut self.warnings)
+    }
+
+    /// Stop the program with an error once it goes over `limits`
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.budget = Budget::new(limits);
+    }
+
+    /// Run to the end, reading INPUT from the host whether or not it has a line ready
+    pub fn run(&mut self) -> Result<(), Diagnostic> {
+        self.execute(false).map(|_| ())
+    }
+
+    /// Run until the program ends or an INPUT finds the host's input empty.
+    /// Calling again after input has been added carries on from the INPUT.
+    pub fn resume(&mut self) -> Result<Suspend, Diagnostic> {
+        self.execute(true)
+    }
+
+    fn execute(&mut self, suspend_for_input: bool) -> Result<Suspend, Diagnostic> {
+        loop {
+            let pc = self.pc;
+            if suspend_for_input {
+                if let Some(suspend) = self.waiting_for_input(pc).map_err(|e| self.locate(e, pc))? {
+                    return Ok(suspend);
+                }
+            }
+            self.pc += 1;
+            match self.budget.step().map_err(Diagnostic::from).and_then(|_| self.step(pc)) {
+                Ok(true) => {}
+                Ok(false) => return Ok(Suspend::Finished),
+                Err(error) => return Err(self.locate(error, pc)),
+            }
+        }
+    }
+
+    /// Whether the instruction at `pc` is an INPUT with no line to read yet
+    fn waiting_for_input(&mut self, pc: usize) -> Result<Option<Suspend>, Diagnostic> {
+        let program = Rc::clone(&self.program);
+        let Some(Instr::Input { var, path }) = program.code.get(pc) else {
+            return Ok(None);
+        };
+        if self.host.input.has_line() {
+            return Ok(None);
+        }
+        // The indices stay on the stack for the INPUT itself
+        let path = path.map_or(&[][..], |path| &program.paths[path as usize]);
+        let count = path_indices(path);
+        let indices = self.stack[self.stack.len() - count..].to_vec();
+        let name = self.place_name(*var, path, &indices);
+        let place = self.place(*var, path, indices)?;
+        let var_type = self.place_type(&place, self.var_name(*var).to_string())?;
+        // Only prompt for input that could be accepted
+        value::validate_input_type(&var_type)?;
+        Ok(Some(Suspend::Input {
+            name,
+            // INPUT only reads the built-in types, whose names are their variants'
+            type_name: format!("{:?}", var_type),
+            line: self.program.spans.get(pc).map_or(0, |span| span.line),
+        }))
+    }
+
+    /// Point `error` at the instruction at `pc` and the calls in progress
+    fn locate(&self, mut error: Diagnostic, pc: usize) -> Diagnostic {
+        if let Some(span) = self.program.spans.get(pc) {
+            error = error.or_span(span);
+        }
+        self.with_call_stack(error)
+    }
+
+    fn with_call_stack(&self, error: Diagnostic) -> Diagnostic {
+        if self.frames.is_empty() {
+            return error;
+        }
+        let calls: Vec<&str> = self.frames.iter()
+            .map(|frame| self.program.routines[frame.routine].name.as_str())
+            .collect();
+        error.with_call_stack(&calls)
+    }
+
+    fn pop(&mut self) -> Value {
+        self.stack.pop().expect("VM stack underflow")
+    }
+
+    fn pop_n(&mut self, n: u32) -> Vec<Value> {
+        let at = self.stack.len() - n as usize;
+        self.stack.split_off(at)
+    }
+
+    fn pop_filename(&mut self, statement: &str) -> Result<String, String> {
+        match self.pop() {
+            Value::String(s) => Ok(s),
+            other if statement == "OPENFILE" => Err(format!("Filename must be a string, got {:?}", other)),
+            other => Err(format!("{} expects STRING filename, got {:?}", statement, other)),
+        }
+    }
+
+    fn var_name(&self, var: VarRef) -> &str {
+        match var {
+            VarRef::Global(i) => &self.program.globals[i as usize],
+            VarRef::Local(i) => {
+                let frame = self.frames.last().expect("local slot outside of a call frame");
+                &self.program.routines[frame.routine].locals[i as usize]
+            }
+        }
+    }
+
+    fn slot(&self, var: VarRef) -> &Slot {
+        match var {
+            VarRef::Global(i) => &self.globals[i as usize],
+            VarRef::Local(i) => {
+                let base = self.frames.last().expect("local slot outside of a call frame").base;
+                &self.locals[base + i as usize]
+            }
+        }
+    }
+
+    fn slot_mut(&mut self, var: VarRef) -> &mut Slot {
+        match var {
+            VarRef::Global(i) => &mut self.globals[i as usize],
+            VarRef::Local(i) => {
+                let base = self.frames.last().expect("local slot outside of a call frame").base;
+                &mut self.locals[base + i as usize]
+            }
+        }
+    }
+
+    fn value(&self, var: VarRef) -> Result<&Value, String> {
+        let slot = self.slot(var);
+        match &slot.reference {
+            Some(reference) => self.resolve(reference),
+            None => slot.value.as_ref().ok_or_else(|| format!("Variable '{}' not found", self.var_name(var))),
+        }
+    }
+
+    fn value_mut(&mut self, var: VarRef) -> Result<&mut Value, String> {
+        if let Some(reference) = self.slot(var).reference.clone() {
+            return self.resolve_mut(&reference);
+        }
+        if self.slot(var).value.is_none() {
+            return Err(format!("Variable '{}' not found", self.var_name(var)));
+        }
+        Ok(self.slot_mut(var).value.as_mut().expect("checked above"))
+    }
+
+    /// Assign to a variable, or for a BYREF parameter to the storage it refers to
+    fn set_value(&mut self, var: VarRef, value: Value) -> Result<(), String> {
+        match self.slot(var).reference.clone() {
+            Some(reference) => *self.resolve_mut(&reference)? = value,
+            None => self.slot_mut(var).value = Some(value),
+        }
+        Ok(())
+    }
+
+    /// The slot a variable address refers to, unless its call has returned.
+    /// It may belong to any active call, not just the innermost one.
+    fn slot_at(&self, frame: u64, slot: usize) -> Result<&Slot, String> {
+        let slot = match frame {
+            0 => self.globals.get(slot),
+            _ => self.frames.iter().rev()
+                .find(|f| f.id == frame)
+                .and_then(|f| self.locals.get(f.base + slot)),
+        };
+        slot.filter(|slot| slot.value.is_some() || slot.reference.is_some())
+            .ok_or_else(dangling)
+    }
+
+    fn slot_at_mut(&mut self, frame: u64, slot: usize) -> Result<&mut Slot, String> {
+        let index = match frame {
+            0 => Some(slot),
+            _ => self.frames.iter().rev()
+                .find(|f| f.id == frame)
+                .map(|f| f.base + slot),
+        };
+        let slots = if frame == 0 { &mut self.globals } else { &mut self.locals };
+        index.and_then(|i| slots.get_mut(i))
+            .filter(|slot| slot.value.is_some() || slot.reference.is_some())
+            .ok_or_else(dangling)
+    }
+
+    /// Value of the variable or heap cell at `address`
+    fn load_at(&self, address: &Address) -> Result<&Value, String> {
+        match *address {
+            Address::Variable { frame, slot } => {
+                let slot = self.slot_at(frame, slot)?;
+                match &slot.reference {
+                    Some(reference) => self.resolve(reference),
+                    None => slot.value.as_ref().ok_or_else(dangling),
+                }
+            }
+            Address::Heap(cell) => self.heap.get(cell),
+        }
+    }
+
+    fn load_at_mut(&mut self, address: &Address) -> Result<&mut Value, String> {
+        match *address {
+            Address::Variable { frame, slot } => {
+                if let Some(reference) = self.slot_at(frame, slot)?.reference.clone() {
+                    return self.resolve_mut(&reference);
+                }
+                self.slot_at_mut(frame, slot)?.value.as_mut().ok_or_else(dangling)
+            }
+            Address::Heap(cell) => self.heap.get_mut(cell),
+        }
+    }
+
+    /// The value `reference` refers to
+    fn resolve(&self, reference: &Reference) -> Result<&Value, String> {
+        value::part(self.load_at(&reference.address)?, &reference.steps, "BYREF argument")
+    }
+
+    fn resolve_mut(&mut self, reference: &Reference) -> Result<&mut Value, String> {
+        value::part_mut(self.load_at_mut(&reference.address)?, &reference.steps, "BYREF argument")
+    }
+
+    /// The value a pointer points to
+    fn deref(&self, address: Option<Address>) -> Result<&Value, String> {
+        match address {
+            Some(address) => self.load_at(&address),
+            None => Err("Cannot dereference a NULL pointer".to_string()),
+        }
+    }
+
+    /// Assign `value` to whatever a pointer points to
+    fn store_through(&mut self, address: Option<Address>, value: Value) -> Result<(), String> {
+        let Some(address) = address else {
+            return Err("Cannot assign through a NULL pointer".to_string());
+        };
+        if let Address::Variable { frame, slot } = address {
+            if self.slot_at(frame, slot)?.constant {
+                return Err("Cannot assign through a pointer to a constant - constants are locked".to_string());
+            }
+        }
+        *self.load_at_mut(&address)? = value;
+        Ok(())
+    }
+
+    /// Address of a variable of the innermost call or the globals, for ^var
+    fn address_of(&self, var: VarRef) -> Address {
+        match var {
+            VarRef::Global(i) => Address::Variable { frame: 0, slot: i as usize },
+            VarRef::Local(i) => {
+                let frame = self.frames.last().expect("local slot outside of a call frame");
+                Address::Variable { frame: frame.id, slot: i as usize }
+            }
+        }
+    }
+
+    /// Follow `path` from `var`, taking the indices it needs from `indices` in order
+    fn place(&mut self, var: VarRef, path: &[PathStep], indices: Vec<Value>) -> Result<Place, String> {
+        let mut indices = indices.into_iter();
+        let mut place = Place { root: PlaceRoot::Variable(var), steps: Vec::new() };
+        for step in path {
+            match step {
+                PathStep::Index(n) => place.steps.push(Step::Index(indices.by_ref().take(*n as usize).collect())),
+                PathStep::Field(field) => place.steps.push(Step::Field(self.program.names[*field as usize].clone())),
+                PathStep::Deref => {
+                    let name = self.var_name(var).to_string();
+                    match self.load_place(&place, &name)? {
+                        Value::Pointer(address) => place = Place { root: PlaceRoot::Pointer(address), steps: Vec::new() },
+                        _ => return Err(format!("Pointer dereference assignment on non-pointer variable: {}", name)),
+                    }
+                }
+            }
+        }
+        Ok(place)
+    }
+
+    /// How `path` from `var` is written in the source, with the indices filled in
+    fn place_name(&self, var: VarRef, path: &[PathStep], indices: &[Value]) -> String {
+        let mut name = self.var_name(var).to_string();
+        let mut indices = indices.iter();
+        for step in path {
+            match step {
+                PathStep::Index(n) => {
+                    let index: Vec<String> = indices.by_ref().take(*n as usize).map(value_to_string).collect();
+                    name.push_str(&format!("[{}]", index.join(", ")));
+                }
+                PathStep::Field(field) => name.push_str(&format!(".{}", self.program.names[*field as usize])),
+                PathStep::Deref => name.push('^'),
+            }
+        }
+        name
+    }
+
+    fn load_place(&mut self, place: &Place, name: &str) -> Result<Value, String> {
+        let whole = match place.root {
+            PlaceRoot::Variable(var) => self.value(var)?,
+            PlaceRoot::Pointer(address) => self.deref(address)?,
+        };
+        value::part(whole, &place.steps, name).cloned()
+    }
+
+    /// Type of the value INPUT or READFILE should read into `place`
+    fn place_type(&mut self, place: &Place, name: String) -> Result<Type, String> {
+        let whole = match place.root {
+            PlaceRoot::Variable(var) if place.steps.is_empty() => {
+                return self.slot(var).ty.clone().ok_or_else(|| format!("Variable {} not found", name));
+            }
+            PlaceRoot::Variable(var) => self.value(var)?.clone(),
+            PlaceRoot::Pointer(address) => self.deref(address)?.clone(),
+        };
+        value::part_type(&whole, &place.steps, &self.type_definitions, &name)
+    }
+
+    /// `value` made to fit `place`: its declared type or, through a pointer,
+    /// the kind of value it holds now
+    fn conform_to_place(&mut self, place: &Place, value: Value, name: &str) -> Result<Value, String> {
+        let ty = match place.root {
+            // An undeclared variable is left for `store_place` to report
+            PlaceRoot::Variable(var) if place.steps.is_empty() => match &self.slot(var).ty {
+                Some(ty) => ty.clone(),
+                None => return Ok(value),
+            },
+            PlaceRoot::Pointer(address) if place.steps.is_empty() => return value::conform_to_value(value, self.deref(address)?),
+            _ => self.place_type(place, name.to_string())?,
+        };
+        value::conform_to_type(value, &ty, &self.type_definitions)
+    }
+
+    /// Assign to `place`, converting `value` to its type. Constant variables are checked
+    /// by the caller, which knows the statement; constants reached through a pointer are checked here.
+    fn store_place(&mut self, place: Place, value: Value, name: &str) -> Result<(), String> {
+        let value = self.conform_to_place(&place, value, name)?;
+        match place.root {
+            PlaceRoot::Variable(var) if place.steps.is_empty() => {
+                if self.slot(var).ty.is_none() {
+                    return Err(format!("Variable '{}' must be declared before assignment", name));
+                }
+                self.set_value(var, value)
+            }
+            PlaceRoot::Variable(var) => {
+                *value::part_mut(self.value_mut(var)?, &place.steps, name)? = value;
+                Ok(())
+            }
+            PlaceRoot::Pointer(address) if place.steps.is_empty() => self.store_through(address, value),
+            PlaceRoot::Pointer(address) => {
+                let mut whole = self.deref(address)?.clone();
+                *value::part_mut(&mut whole, &place.steps, name)? = value;
+                self.store_through(address, whole)
+            }
+        }
+    }
+
+    /// Pop the indices `path` needs and follow it from `var`
+    fn pop_place(&mut self, var: VarRef, path: Option<u32>) -> Result<Place, String> {
+        let program = Rc::clone(&self.program);
+        let path = path.map_or(&[][..], |path| &program.paths[path as usize]);
+        let indices = self.pop_n(path_indices(path) as u32);
+        self.place(var, path, indices)
+    }
+
+    /// Declared type and current value of a GETRECORD or PUTRECORD variable
+    fn record_variable(&self, var: VarRef) -> Result<(Type, Value), String> {
+        match (&self.slot(var).ty, self.value(var)) {
+            (Some(ty), Ok(value)) => Ok((ty.clone(), value.clone())),
+            _ => Err(format!("Variable '{}' must be declared before it is used with a record file", self.var_name(var))),
+        }
+    }
+
+    fn check_not_constant(&self, var: VarRef) -> Result<(), String> {
+        if self.slot(var).constant {
+            return Err(format!("Cannot assign to constant '{}' - constants are locked", self.var_name(var)));
+        }
+        Ok(())
+    }
+
+    /// INPUT, READFILE and GETRECORD cannot read into a constant
+    fn check_not_constant_target(&self, var: VarRef, statement: &str) -> Result<(), String> {
+        if self.slot(var).constant {
+            return Err(format!("{} cannot read into constant '{}' - constants are locked", statement, self.var_name(var)));
+        }
+        Ok(())
+    }
+
+    /// FOR loop state, which ForInit sets. Only bytecode the compiler did not
+    /// write can test or step a loop it never entered.
+    fn hidden_int(&self, var: VarRef, offset: u32) -> Result<i64, String> {
+        let var = match var {
+            VarRef::Global(i) => VarRef::Global(i + offset),
+            VarRef::Local(i) => VarRef::Local(i + offset),
+        };
+        match self.slot(var).value {
+            Some(Value::Integer(i)) => Ok(i),
+            _ => Err("FOR loop state used before the loop started".to_string()),
+        }
+    }
+
+    fn set_hidden(&mut self, var: VarRef, offset: u32, value: Value) {
+        let var = match var {
+            VarRef::Global(i) => VarRef::Global(i + offset),
+            VarRef::Local(i) => VarRef::Local(i + offset),
+        };
+        self.slot_mut(var).value = Some(value);
+    }
+
+    /// Value of a FOR counter of type `ty` at position `current`
+    fn counter_value(&self, ty: &Type, current: i64) -> Result<Value, String> {
+        match ty {
+            Type::Custom(type_name) => value::enum_value(type_name, current, &self.type_definitions),
+            _ => Ok(Value::Integer(current)),
+        }
+    }
+
+    fn default_value(&self, type_name: &Type) -> Result<Value, String> {
+        default_value(type_name, &self.type_definitions)
+    }
+
+    fn declare(&mut self, var: VarRef, type_name: &Type, init: Option<Value>) -> Result<(), String> {
+        let resolved = match type_name {
+            Type::Custom(custom_name) => self.type_definitions.get(custom_name)
+                .ok_or_else(|| format!("Type {} not found", custom_name))?
+                .clone(),
+            Type::ARRAY { .. } => return Err(format!("Unsupported type: {:?}", type_name)),
+            _ => type_name.clone(),
+        };
+        let value = match init {
+            Some(value) => value,
+            None => self.default_value(&resolved)?,
+        };
+        *self.slot_mut(var) = Slot { value: Some(value), ty: Some(resolved), ..Slot::default() };
+        Ok(())
+    }
+
+    fn declare_array(&mut self, var: VarRef, type_name: &Type, dims: u32) -> Result<(), Diagnostic> {
+        let element_type = match type_name {
+            Type::ARRAY { element_type, .. } => element_type.clone(),
+            _ => unreachable!("DeclareArray always carries an array type"),
+        };
+        let bounds = self.pop_n(dims * 2);
+
+        let mut dimensions = Vec::new();
+        let mut start_indices = Vec::new();
+        for pair in bounds.chunks(2) {
+            let start = match pair[0] {
+                Value::Integer(i) => i,
+                ref other => return Err(format!("Invalid start index type: {:?}", other).into()),
+            };
+            let end = match pair[1] {
+                Value::Integer(i) => i,
+                ref other => return Err(format!("Invalid end index type: {:?}", other).into()),
+            };
+            if start < 0 || end < start {
+                return Err("Invalid array dimensions: start index must be >= 0 and end index must be >= start index".into());
+            }
+            let size = (end - start) as usize + 1;
+            dimensions.push(size);
+            start_indices.push(start);
+        }
+
+        let total_size = value::array_size(&dimensions)?;
+        self.budget.allocate(total_size)?;
+        let data = value::array_data(total_size, self.default_value(&element_type)?)?;
+        *self.slot_mut(var) = Slot {
+            value: Some(Value::Array {
+                element_type,
+                dimensions,
+                start_indices,
+                data,
+            }),
+            ty: Some(type_name.clone()),
+            ..Slot::default()
+        };
+        Ok(())
+    }
+
+    fn call(&mut self, routine: usize, argc: u32) -> Result<(), String> {
+        let program = Rc::clone(&self.program);
+        let info = &program.routines[routine];
+        let byref = info.modes.iter().filter(|mode| **mode == PassingMode::BYREF).count();
+        let mut values = self.pop_n(argc - byref as u32).into_iter();
+        let at = self.references.len() - byref;
+        let mut references = self.references.split_off(at).into_iter();
+
+        let base = self.locals.len();
+        self.locals.resize(base + info.locals.len(), Slot::default());
+        for (i, (mode, param_type)) in info.modes.iter().zip(&info.params).enumerate() {
+            let slot = &mut self.locals[base + i];
+            match mode {
+                PassingMode::BYVAL => slot.value = values.next(),
+                PassingMode::BYREF => slot.reference = references.next(),
+            }
+            slot.ty = Some(param_type.clone());
+        }
+
+        self.calls += 1;
+        self.frames.push(Frame {
+            id: self.calls,
+            routine,
+            return_pc: self.pc,
+            base,
+        });
+        self.pc = info.entry as usize;
+        Ok(())
+    }
+
+    fn return_from(&mut self, value: Option<Value>) -> Result<(), Diagnostic> {
+        let frame = self.frames.pop().expect("RETURN outside of a call frame");
+        self.locals.truncate(frame.base);
+        self.budget.leave_call();
+        self.pc = frame.return_pc;
+
+        // The call is over, so a missing or wrong value is reported at the
+        // call that wanted it
+        let program = Rc::clone(&self.program);
+        let info = &program.routines[frame.routine];
+        let at_call = |message: String| Diagnostic::from(message).or_span(&program.spans[frame.return_pc - 1]);
+        match (&info.return_type, value) {
+            (Some(return_type), Some(value)) => {
+                let value = value::conform_to_type(value, return_type, &self.type_definitions)
+                    .map_err(|msg| at_call(format!("Function '{}' returned the wrong type: {}", info.name, msg)))?;
+                self.stack.push(value);
+            }
+            (Some(_), None) => return Err(at_call(format!("Function '{}' ended without returning a value", info.name))),
+            (None, _) => {}
+        }
+        Ok(())
+    }
+
+    /// Execute the instruction at `pc`. Returns `Ok(false)` when the program halts.
+    fn step(&mut self, pc: usize) -> Result<bool, Diagnostic> {
+        let program = Rc::clone(&self.program);
+        match &program.code[pc] {
+            Instr::Const(i) => {
+                self.stack.push(program.constants[*i as usize].clone());
+            }
+            Instr::Load(var) => {
+                let value = self.value(*var)?.clone();
+                self.stack.push(value);
+            }
+            Instr::Store(var) => {
+                let value = self.pop();
+                self.check_not_constant(*var)?;
+                let name = self.var_name(*var).to_string();
+                self.store_place(Place { root: PlaceRoot::Variable(*var), steps: Vec::new() }, value, &name)?;
+            }
+            Instr::StorePath { var, path } => {
+                let place = self.pop_place(*var, Some(*path))?;
+                let value = self.pop();
+                if let PlaceRoot::Variable(var) = place.root {
+                    self.check_not_constant(var)?;
+                }
+                let name = self.var_name(*var).to_string();
+                self.store_place(place, value, &name)?;
+            }
+            Instr::Index { name, count } => {
+                let indices = self.pop_n(*count);
+                let container = self.pop();
+                let element = value::index_value(&container, &indices, &program.names[*name as usize])?.clone();
+                self.stack.push(element);
+            }
+            Instr::LoadIndex(var, n) => {
+                let indices = self.pop_n(*n);
+                let name = self.var_name(*var);
+                let element = value::index_value(self.value(*var)?, &indices, name)?.clone();
+                self.stack.push(element);
+            }
+            Instr::Field(field) => {
+                let field = &program.names[*field as usize];
+                match self.pop() {
+                    Value::Record { type_name, mut fields } => {
+                        let value = fields.remove(field)
+                            .ok_or_else(|| format!("Field '{}' not found in record of type '{}'", field, type_name))?;
+                        self.stack.push(value);
+                    }
+                    other => return Err(format!("Field access on non-record value: {:?}", other).into()),
+                }
+            }
+            Instr::Deref => {
+                match self.pop() {
+                    Value::Pointer(address) => {
+                        let value = self.deref(address)?.clone();
+                        self.stack.push(value);
+                    }
+                    other => return Err(format!("Pointer dereference (^) can only be applied to pointer values, got {:?}", other).into()),
+                }
+            }
+            Instr::Ref(var) => {
+                if self.value(*var).is_err() {
+                    return Err(format!("Variable '{}' not found for pointer reference", self.var_name(*var)).into());
+                }
+                self.stack.push(Value::Pointer(Some(self.address_of(*var))));
+            }
+            Instr::New(ty) => {
+                let value = self.default_value(&program.types[*ty as usize])?;
+                self.budget.allocate(1)?;
+                let address = self.heap.allocate(value);
+                self.stack.push(Value::Pointer(Some(address)));
+            }
+            Instr::Binary(op) => {
+                let right = self.pop();
+                let left = self.pop();
+                self.stack.push(value::binary_op(op, &left, &right)?);
+            }
+            Instr::Unary(op) => {
+                let operand = self.pop();
+                self.stack.push(value::unary_op(op, operand)?);
+            }
+            Instr::Jump(to) => {
+                self.pc = *to as usize;
+            }
+            Instr::JumpIfFalse(to) => {
+                if !self.pop().as_condition()? {
+                    self.pc = *to as usize;
+                }
+            }
+            Instr::SetTemp(var) => {
+                let value = self.pop();
+                self.slot_mut(*var).value = Some(value);
+            }
+            Instr::CaseEq => {
+                let label = self.pop();
+                let subject = self.pop();
+                self.stack.push(Value::Boolean(subject == label));
+            }
+            Instr::CaseIn => {
+                let high = self.pop();
+                let low = self.pop();
+                let subject = self.pop();
+                self.stack.push(Value::Boolean(value::in_range(&subject, &low, &high)?));
+            }
+            Instr::CaseUnmatched(subject) => {
+                let subject = value::value_to_literal(self.value(*subject)?);
+                let mut warning = Diagnostic::warning(codes::UNMATCHED_CASE, format!("No CASE label matches {}", subject))
+                    .with_note("add an OTHERWISE branch to handle every other value");
+                if let Some(span) = self.program.spans.get(pc) {
+                    warning = warning.with_span(span);
+                }
+                if !self.warnings.iter().any(|w| w.span == warning.span) {
+                    self.warnings.push(warning);
+                }
+            }
+
+            Instr::Declare { var, ty, init } => {
+                let init = if *init { Some(self.pop()) } else { None };
+                self.declare(*var, &program.types[*ty as usize], init)?;
+            }
+            Instr::DeclareArray { var, ty, dims } => {
+                self.declare_array(*var, &program.types[*ty as usize], *dims)?;
+            }
+            Instr::Define { var, type_name, count } => {
+                let values = self.pop_n(*count);
+                let type_name = &program.names[*type_name as usize];
+                let type_def = self.type_definitions.get(type_name)
+                    .ok_or_else(|| format!("Type {} not found", type_name))?
+                    .clone();
+                let element_type = match &type_def {
+                    Type::Set { element_type } => element_type.clone(),
+                    _ => return Err(format!("Define statement for type {} is not supported", type_name).into()),
+                };
+                let mut elements = Vec::new();
+                for value in values {
+                    if let Value::String(val_str) = value {
+                        elements.push(parse_value_string(&val_str, &element_type)?);
+                    }
+                }
+                *self.slot_mut(*var) = Slot {
+                    value: Some(value::make_set(&element_type, elements)),
+                    ty: Some(type_def),
+                    ..Slot::default()
+                };
+            }
+            Instr::Constant { var, init } => {
+                let value = if *init {
+                    self.pop()
+                } else {
+                    self.value(*var).cloned().map_err(|_| {
+                        format!("Constant '{}' cannot be locked: variable does not exist", self.var_name(*var))
+                    })?
+                };
+                if self.slot(*var).ty.is_none() {
+                    let inferred_type = match &value {
+                        Value::Integer(_) => Type::INTEGER,
+                        Value::Real(_) => Type::REAL,
+                        Value::Boolean(_) => Type::BOOLEAN,
+                        Value::Char(_) => Type::CHAR,
+                        Value::String(_) => Type::STRING,
+                        Value::Date(_) => Type::DATE,
+                        Value::Array { element_type, .. } => Type::ARRAY {
+                            dimensions: vec![],
+                            element_type: element_type.clone(),
+                        },
+                        _ => return Err(format!("Cannot infer type for constant '{}'", self.var_name(*var)).into()),
+                    };
+                    self.slot_mut(*var).ty = Some(inferred_type);
+                }
+                self.set_value(*var, value)?;
+                self.slot_mut(*var).constant = true;
+            }
+            Instr::DefineType { name, ty } => {
+                self.type_definitions.insert(program.names[*name as usize].clone(), program.types[*ty as usize].clone());
+            }
+
+            Instr::ForInit { var, state } => {
+                let step = self.pop();
+                let end = self.pop();
+                let start = self.pop();
+                // Enum values are counted by their position, and the counter
+                // takes the enum as its type so ForStep can name each value
+                let (start, end, step, ty) = match (start, end, step) {
+                    (Value::Integer(s), Value::Integer(e), Value::Integer(st)) => (s, e, st, Type::INTEGER),
+                    (Value::Enum { type_name, index: s, .. }, Value::Enum { type_name: end_type, index: e, .. }, Value::Integer(st))
+                        if type_name == end_type => (s as i64, e as i64, st, Type::Custom(type_name)),
+                    _ => return Err("FOR loop requires integer values for start, end, and step".into()),
+                };
+                if step == 0 {
+                    return Err("FOR loop step cannot be zero".into());
+                }
+                // Save the original counter so it can be restored after the loop.
+                // A BYREF parameter keeps counting in the storage it refers to.
+                let mut saved = self.slot(*var).clone();
+                if saved.reference.is_some() {
+                    saved.value = self.value(*var).ok().cloned();
+                }
+                *self.slot_mut(*state) = saved.clone();
+                self.set_hidden(*state, 1, Value::Integer(end));
+                self.set_hidden(*state, 2, Value::Integer(step));
+                self.set_hidden(*state, 3, Value::Integer(start));
+                let value = self.counter_value(&ty, start)?;
+                match saved.reference {
+                    Some(_) => self.set_value(*var, value)?,
+                    None => *self.slot_mut(*var) = Slot {
+                        value: Some(value),
+                        ty: Some(ty),
+                        constant: saved.constant,
+                        reference: None,
+                    },
+                }
+            }
+            Instr::ForTest { state, exit } => {
+                let end = self.hidden_int(*state, 1)?;
+                let step = self.hidden_int(*state, 2)?;
+                let current = self.hidden_int(*state, 3)?;
+                let should_continue = match step {
+                    0 => false,
+                    step if step > 0 => current <= end,
+                    _ => current >= end,
+                };
+                if !should_continue {
+                    self.pc = *exit as usize;
+                }
+            }
+            Instr::ForStep { var, state } => {
+                let Some(current) = self.hidden_int(*state, 3)?.checked_add(self.hidden_int(*state, 2)?) else {
+                    // Stepping past the INTEGER range ends the loop. ForInit never
+                    // accepts a step of zero, so ForTest takes it to mean this.
+                    self.set_hidden(*state, 2, Value::Integer(0));
+                    return Ok(true);
+                };
+                self.set_hidden(*state, 3, Value::Integer(current));
+                let ty = self.slot(*var).ty.clone().unwrap_or(Type::INTEGER);
+                // Stepping past the last enum value only happens as the loop ends,
+                // and ForEnd restores the counter then
+                if let Ok(value) = self.counter_value(&ty, current) {
+                    self.set_value(*var, value)?;
+                }
+            }
+            Instr::ForEnd { var, state } => {
+                let mut saved = std::mem::take(self.slot_mut(*state));
+                match saved.reference {
+                    Some(_) => {
+                        let value = saved.value.take();
+                        *self.slot_mut(*var) = saved;
+                        if let Some(value) = value {
+                            self.set_value(*var, value)?;
+                        }
+                    }
+                    None => *self.slot_mut(*var) = saved,
+                }
+            }
+
+            Instr::ByRef { var, path, routine, param } => {
+                let param = &program.routines[*routine as usize].locals[*param as usize];
+                let constant = |name: &str| format!("Cannot pass constant '{}' to BYREF parameter '{}'", name, param);
+                let steps = path.map_or(&[][..], |path| &program.paths[path as usize]);
+                let indices = &self.stack[self.stack.len() - path_indices(steps)..];
+                let argument = self.place_name(*var, steps, indices);
+                let place = self.pop_place(*var, *path)?;
+                let reference = match place.root {
+                    PlaceRoot::Variable(var) if self.slot(var).constant => return Err(constant(self.var_name(var)).into()),
+                    PlaceRoot::Variable(var) => match self.slot(var).reference.clone() {
+                        Some(mut reference) => {
+                            reference.steps.extend(place.steps);
+                            reference
+                        }
+                        None => {
+                            self.value(var)?;
+                            Reference { address: self.address_of(var), steps: place.steps }
+                        }
+                    },
+                    PlaceRoot::Pointer(Some(address)) => Reference { address, steps: place.steps },
+                    PlaceRoot::Pointer(None) => return Err("Cannot dereference a NULL pointer".into()),
+                };
+                if let Address::Variable { frame, slot } = reference.address {
+                    if self.slot_at(frame, slot)?.constant {
+                        return Err(constant(&argument).into());
+                    }
+                }
+                // Out of range indices are reported on call, not when the parameter is first used
+                self.resolve(&reference)?;
+                self.references.push(reference);
+            }
+            Instr::Call { routine, argc } => {
+                self.budget.enter_call()?;
+                self.call(*routine as usize, *argc)?;
+            }
+            Instr::CallBuiltin { name, argc } => {
+                let name = &program.names[*name as usize];
+                let args = self.pop_n(*argc);
+                let result = if name == "EOF" {
+                    match args.as_slice() {
+                        [Value::String(filename)] => Value::Boolean(self.host.files.eof(filename)?),
+                        [other] => return Err(format!("EOF expects STRING argument (filename), got {:?}", other).into()),
+                        _ => return Err(format!("EOF expects 1 argument (filename), got {}", args.len()).into()),
+                    }
+                } else if name == "NOW" {
+                    match args.as_slice() {
+                        [] => Value::Date(self.host.clock.today()?),
+                        _ => return Err(format!("NOW expects 0 arguments, got {}", args.len()).into()),
+                    }
+                } else if name == "SUCC" || name == "PRED" {
+                    enum_neighbour(name, &args, &self.type_definitions)?
+                } else {
+                    call_builtin(name, &args, &mut self.host.random)
+                        .unwrap_or_else(|| Err(format!("Function '{}' not found", name)))?
+                };
+                self.stack.push(result);
+            }
+            Instr::Return => {
+                let value = self.pop();
+                self.return_from(Some(value))?;
+            }
+            Instr::Leave => {
+                self.return_from(None)?;
+            }
+
+            Instr::Output(n) => {
+                let values = self.pop_n(*n);
+                let mut line: String = values.iter().map(value_to_string).collect();
+                line.push('\n');
+                self.budget.output(line.len())?;
+                self.host.console.write(&line);
+            }
+            Instr::Input { var, path } => {
+                let name = self.var_name(*var).to_string();
+                let place = self.pop_place(*var, *path)?;
+                let var_type = self.place_type(&place, name.clone())?;
+                value::validate_input_type(&var_type)?;
+                if let PlaceRoot::Variable(var) = place.root {
+                    self.check_not_constant_target(var, "INPUT")?;
+                }
+
+                let input = self.host.input.read_line()?;
+                let value = value::parse_input(&input, &var_type)?;
+                self.store_place(place, value, &name)?;
+            }
+            Instr::OpenFile(mode) => {
+                let filename = self.pop_filename("OPENFILE")?;
+                self.budget.open_file()?;
+                if let Err(msg) = self.host.files.open(&filename, mode) {
+                    self.budget.close_file();
+                    return Err(msg.into());
+                }
+            }
+            Instr::CloseFile => {
+                let filename = self.pop_filename("CLOSEFILE")?;
+                self.host.files.close(&filename)?;
+                self.budget.close_file();
+            }
+            Instr::ReadFile { var, path } => {
+                let name = self.var_name(*var).to_string();
+                let place = self.pop_place(*var, *path)?;
+                let filename = self.pop_filename("READFILE")?;
+                if let PlaceRoot::Variable(var) = place.root {
+                    self.check_not_constant_target(var, "READFILE")?;
+                }
+                let line = self.host.files.read_line(&filename)?;
+                let var_type = self.place_type(&place, name.clone())?;
+                let value = match &var_type {
+                    // Lines are kept as they are for STRING, and read like INPUT otherwise
+                    Type::STRING => Value::String(line),
+                    ty if value::validate_input_type(ty).is_ok() => value::parse_input(&line, ty)?,
+                    ty => return Err(format!("READFILE cannot read into '{}', which is {:?}", name, ty).into()),
+                };
+                self.store_place(place, value, &name)?;
+            }
+            Instr::WriteFile(n) => {
+                let values = self.pop_n(*n);
+                let filename = self.pop_filename("WRITEFILE")?;
+                let output: String = values.iter().map(value_to_string).collect();
+                self.host.files.write(&filename, &output)?;
+            }
+            Instr::Seek => {
+                let address = self.pop();
+                let filename = self.pop_filename("SEEK")?;
+                let address = match address {
+                    Value::Integer(i) => i,
+                    other => return Err(format!("SEEK expects INTEGER address, got {:?}", other).into()),
+                };
+                self.host.files.seek(&filename, address)?;
+            }
+            Instr::GetRecord(var) => {
+                let filename = self.pop_filename("GETRECORD")?;
+                self.check_not_constant_target(*var, "GETRECORD")?;
+                let (ty, template) = self.record_variable(*var)?;
+                let size = record::record_size(&ty, &template, &self.type_definitions)?;
+                let record = self.host.files.get_record(&filename, size)?;
+                let value = record::decode_record(&record, &ty, &template, &self.type_definitions)?;
+                self.set_value(*var, value)?;
+            }
+            Instr::PutRecord(var) => {
+                let filename = self.pop_filename("PUTRECORD")?;
+                let (ty, value) = self.record_variable(*var)?;
+                let record_data = record::encode_record(&value, &ty, &self.type_definitions)?;
+                self.host.files.put_record(&filename, &record_data)?;
+            }
+
+            Instr::Halt => return Ok(false),
+        }
+        Ok(true)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::Compiler;
+    use crate::files::VirtualFileSystem;
+    use crate::host::{BufferConsole, FixedClock, QueueInput, ThreadRandom, VirtualHost};
+    use crate::parser::Parser;
+
+    fn vm(source: &str) -> Vm<BufferConsole, QueueInput, VirtualFileSystem, ThreadRandom, FixedClock> {
+        let statements = Parser::new(source).parse_program().expect("parses");
+        let program = Compiler::new().compile(&statements).expect("compiles");
+        Vm::new(program, VirtualHost::virtual_host())
+    }
+
+    fn input(name: &str, type_name: &str, line: usize) -> Suspend {
+        Suspend::Input { name: name.to_string(), type_name: type_name.to_string(), line }
+    }
+
+    #[test]
+    fn resume_stops_at_input_inside_a_function_called_from_a_loop() {
+        let mut vm = vm("\
+DECLARE Names : ARRAY[1:2] OF STRING
+DECLARE Total : INTEGER
+DECLARE Round : INTEGER
+
+FUNCTION Ask() RETURNS INTEGER
+    DECLARE Answer : INTEGER
+    INPUT Answer
+    RETURN Answer
+ENDFUNCTION
+
+Total <- 0
+FOR Round <- 1 TO 2
+    INPUT Names[Round]
+    Total <- Total + Ask()
+NEXT Round
+OUTPUT Names[1], Names[2], Total
+");
+        for (round, (name, score)) in [("Ada", "3"), ("Alan", "4")].into_iter().enumerate() {
+            assert_eq!(vm.resume(), Ok(input(&format!("Names[{}]", round + 1), "STRING", 13)));
+            vm.host_mut().input.push(name.to_string());
+            assert_eq!(vm.resume(), Ok(input("Answer", "INTEGER", 7)));
+            vm.host_mut().input.push(score.to_string());
+        }
+        assert_eq!(vm.resume(), Ok(Suspend::Finished));
+        assert_eq!(vm.host().console.output(), "AdaAlan7\n");
+    }
+}
@@ -0,0 +1,88 @@
+//! A client for `pseudocode dap` and `pseudocode lsp`, which both exchange
+//! JSON messages framed with a `Content-Length` header over stdin and stdout
+
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use std::time::Duration;
+
+use serde_json::Value as Json;
+
+/// How long to wait for a message before deciding the server is stuck
+const PATIENCE: Duration = Duration::from_secs(10);
+
+pub struct Server {
+    child: Child,
+    stdin: Option<ChildStdin>,
+    messages: Receiver<Json>,
+}
+
+impl Server {
+    /// Start `pseudocode <command>`
+    pub fn start(command: &str) -> Server {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_pseudocode"))
+            .arg(command)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .expect("failed to start pseudocode");
+        let stdin = child.stdin.take();
+        let mut stdout = BufReader::new(child.stdout.take().unwrap());
+        let (sender, messages) = mpsc::channel();
+        // Read on a thread, so a server that stops answering fails the test
+        // instead of hanging it
+        thread::spawn(move || {
+            while let Some(message) = read_message(&mut stdout) {
+                if sender.send(message).is_err() {
+                    break;
+                }
+            }
+        });
+        Server { child, stdin, messages }
+    }
+
+    pub fn send(&mut self, message: &Json) {
+        let body = message.to_string();
+        let stdin = self.stdin.as_mut().expect("stdin is open");
+        write!(stdin, "Content-Length: {}\r\n\r\n{}", body.len(), body).unwrap();
+        stdin.flush().unwrap();
+    }
+
+    /// The next message from the server
+    pub fn receive(&self) -> Json {
+        self.messages.recv_timeout(PATIENCE).expect("the server stopped sending messages")
+    }
+
+    /// Close the server's input and wait for it to exit, returning its exit code
+    pub fn finish(mut self) -> Option<i32> {
+        self.stdin.take();
+        self.child.wait().unwrap().code()
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+fn read_message(input: &mut impl BufRead) -> Option<Json> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header).ok()? == 0 {
+            return None;
+        }
+        match header.trim_end().split_once(": ") {
+            Some(("Content-Length", value)) => length = value.parse::<usize>().ok(),
+            _ if header.trim_end().is_empty() && length.is_some() => break,
+            _ => {}
+        }
+    }
+    let mut body = vec![0; length?];
+    input.read_exact(&mut body).ok()?;
+    serde_json::from_slice(&body).ok()
+}
@@ -0,0 +1,286 @@
+//! Scripted sessions with `pseudocode dap`, playing the editor's part
+
+mod common;
+
+use std::fs;
+use std::path::PathBuf;
+
+use serde_json::{json, Value as Json};
+
+use common::Server;
+
+const PROGRAM: &str = "\
+DECLARE Total : INTEGER
+DECLARE Scores : ARRAY[1:3] OF INTEGER
+
+FUNCTION Double(N : INTEGER) RETURNS INTEGER
+    DECLARE Result : INTEGER
+    Result <- N * 2
+    RETURN Result
+ENDFUNCTION
+
+Scores[1] <- 4
+Total <- Double(Scores[1])
+OUTPUT Total
+INPUT Total
+OUTPUT Total
+";
+
+/// Write `source` to a scratch file named `name`, returning its path
+fn program(name: &str, source: &str) -> PathBuf {
+    let dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("dap");
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join(name);
+    fs::write(&path, source).unwrap();
+    path
+}
+
+/// The editor's side of a debugging session
+struct Editor {
+    server: Server,
+    seq: u64,
+    /// Events that arrived while waiting for a response
+    events: Vec<Json>,
+}
+
+impl Editor {
+    fn start() -> Editor {
+        Editor { server: Server::start("dap"), seq: 0, events: Vec::new() }
+    }
+
+    /// Send a request and return its response
+    fn request(&mut self, command: &str, arguments: Json) -> Json {
+        self.seq += 1;
+        self.server.send(&json!({ "seq": self.seq, "type": "request", "command": command, "arguments": arguments }));
+        loop {
+            let message = self.server.receive();
+            if message["type"] == "response" && message["request_seq"] == self.seq {
+                assert_eq!(message["command"], command);
+                return message;
+            }
+            self.events.push(message);
+        }
+    }
+
+    /// Send a request that should succeed and return the response's body
+    fn body(&mut self, command: &str, arguments: Json) -> Json {
+        let response = self.request(command, arguments);
+        assert_eq!(response["success"], true, "{} failed: {}", command, response);
+        response["body"].clone()
+    }
+
+    /// Wait for the event `name`, returning its body
+    fn event(&mut self, name: &str) -> Json {
+        if let Some(at) = self.events.iter().position(|event| event["event"] == name) {
+            return self.events.remove(at)["body"].clone();
+        }
+        loop {
+            let message = self.server.receive();
+            if message["type"] == "event" && message["event"] == name {
+                return message["body"].clone();
+            }
+            self.events.push(message);
+        }
+    }
+
+    /// Wait for the program to pause, returning why
+    fn stopped(&mut self) -> String {
+        self.event("stopped")["reason"].as_str().unwrap().to_string()
+    }
+
+    /// Everything the program printed to `category` until it ended, and its exit code
+    fn run_to_end(&mut self, category: &str) -> (String, i64) {
+        let mut printed = String::new();
+        loop {
+            let message = match self.events.is_empty() {
+                true => self.server.receive(),
+                false => self.events.remove(0),
+            };
+            match message["event"].as_str() {
+                Some("output") if message["body"]["category"] == category => {
+                    printed.push_str(message["body"]["output"].as_str().unwrap());
+                }
+                Some("exited") => return (printed, message["body"]["exitCode"].as_i64().unwrap()),
+                _ => {}
+            }
+        }
+    }
+
+    /// The `(line, name)` of each frame, innermost first
+    fn frames(&mut self) -> Vec<(u64, String)> {
+        self.body("stackTrace", json!({ "threadId": 1 }))["stackFrames"].as_array().unwrap().iter()
+            .map(|frame| (frame["line"].as_u64().unwrap(), frame["name"].as_str().unwrap().to_string()))
+            .collect()
+    }
+
+    /// The `(name, value)` of each variable under `reference`
+    fn variables(&mut self, reference: &Json) -> Vec<(String, String)> {
+        self.body("variables", json!({ "variablesReference": reference }))["variables"].as_array().unwrap().iter()
+            .map(|variable| (variable["name"].as_str().unwrap().to_string(), variable["value"].as_str().unwrap().to_string()))
+            .collect()
+    }
+
+    /// Initialize and launch `path`, setting breakpoints on `lines` before configuration is done
+    fn launch(&mut self, path: &str, extra: Json, lines: &[u64]) {
+        let capabilities = self.body("initialize", json!({ "adapterID": "pseudocode" }));
+        assert_eq!(capabilities["supportsConfigurationDoneRequest"], true);
+        let mut arguments = json!({ "program": path, "input": ["9"] });
+        arguments.as_object_mut().unwrap().extend(extra.as_object().unwrap().clone());
+        self.body("launch", arguments);
+        self.event("initialized");
+        let breakpoints: Vec<Json> = lines.iter().map(|line| json!({ "line": line })).collect();
+        let set = self.body("setBreakpoints", json!({ "source": { "path": path }, "breakpoints": breakpoints }));
+        assert_eq!(set["breakpoints"].as_array().unwrap().len(), lines.len());
+        self.body("configurationDone", json!({}));
+    }
+}
+
+#[test]
+fn breakpoints_stack_variables_and_evaluate() {
+    let path = program("breakpoints.pseu", PROGRAM);
+    let mut editor = Editor::start();
+    editor.launch(path.to_str().unwrap(), json!({}), &[6]);
+
+    assert_eq!(editor.stopped(), "breakpoint");
+    assert_eq!(editor.frames(), [(6, "Double".to_string()), (11, "main program".to_string())]);
+
+    let scopes = editor.body("scopes", json!({ "frameId": 2 }))["scopes"].clone();
+    assert_eq!(scopes[0]["name"], "Locals");
+    assert_eq!(scopes[1]["name"], "Globals");
+    let mut locals = editor.variables(&scopes[0]["variablesReference"]);
+    locals.sort();
+    assert_eq!(locals, [("N".to_string(), "4".to_string()), ("Result".to_string(), "0".to_string())]);
+
+    // Arrays are shown by their bounds and expand into their elements
+    let globals = editor.body("variables", json!({ "variablesReference": scopes[1]["variablesReference"] }))["variables"].clone();
+    let scores = globals.as_array().unwrap().iter().find(|variable| variable["name"] == "Scores").unwrap().clone();
+    assert_eq!(scores["value"], "ARRAY[1:3]");
+    let elements = editor.variables(&scores["variablesReference"]);
+    assert_eq!(elements[0], ("[1]".to_string(), "4".to_string()));
+
+    let result = editor.body("evaluate", json!({ "expression": "N * 10", "frameId": 2 }));
+    assert_eq!(result["result"], "40");
+    let outer = editor.request("evaluate", json!({ "expression": "N", "frameId": 1 }));
+    assert_eq!(outer["success"], false);
+    let broken = editor.request("evaluate", json!({ "expression": "N +", "frameId": 2 }));
+    assert_eq!(broken["success"], false);
+
+    editor.body("continue", json!({ "threadId": 1 }));
+    assert_eq!(editor.run_to_end("stdout"), ("8\n9\n".to_string(), 0));
+    editor.event("terminated");
+    editor.body("disconnect", json!({}));
+}
+
+#[test]
+fn stepping_in_over_and_out() {
+    let path = program("stepping.pseu", PROGRAM);
+    let mut editor = Editor::start();
+    editor.launch(path.to_str().unwrap(), json!({ "stopOnEntry": true }), &[]);
+
+    assert_eq!(editor.stopped(), "entry");
+    assert_eq!(editor.frames()[0].0, 1);
+    // Stepping over passes the routine's definition by
+    for line in [2, 10, 11] {
+        editor.body("next", json!({ "threadId": 1 }));
+        assert_eq!(editor.stopped(), "step");
+        assert_eq!(editor.frames()[0].0, line);
+    }
+
+    editor.body("stepIn", json!({ "threadId": 1 }));
+    assert_eq!(editor.stopped(), "step");
+    assert_eq!(editor.frames(), [(5, "Double".to_string()), (11, "main program".to_string())]);
+    editor.body("next", json!({ "threadId": 1 }));
+    assert_eq!(editor.stopped(), "step");
+    assert_eq!(editor.frames()[0].0, 6);
+
+    editor.body("stepOut", json!({ "threadId": 1 }));
+    assert_eq!(editor.stopped(), "step");
+    assert_eq!(editor.frames(), [(12, "main program".to_string())]);
+
+    editor.body("continue", json!({ "threadId": 1 }));
+    assert_eq!(editor.run_to_end("stdout"), ("8\n9\n".to_string(), 0));
+}
+
+#[test]
+fn function_breakpoints() {
+    let path = program("function_breakpoints.pseu", PROGRAM);
+    let mut editor = Editor::start();
+    editor.body("initialize", json!({ "adapterID": "pseudocode" }));
+    editor.body("launch", json!({ "program": path, "input": ["9"] }));
+    let set = editor.body("setFunctionBreakpoints", json!({ "breakpoints": [{ "name": "Double" }] }));
+    assert_eq!(set["breakpoints"][0]["verified"], true);
+    editor.body("configurationDone", json!({}));
+
+    assert_eq!(editor.stopped(), "function breakpoint");
+    assert_eq!(editor.frames()[0], (5, "Double".to_string()));
+    editor.body("continue", json!({ "threadId": 1 }));
+    assert_eq!(editor.run_to_end("stdout").1, 0);
+}
+
+#[test]
+fn breakpoints_are_checked_against_the_program() {
+    let path = program("checked_breakpoints.pseu", PROGRAM);
+    let mut editor = Editor::start();
+    editor.body("initialize", json!({ "adapterID": "pseudocode" }));
+    editor.body("launch", json!({ "program": path, "input": ["9"] }));
+    editor.event("initialized");
+
+    // Line 3 is blank and line 4 the function's header, so that breakpoint
+    // moves to the first statement in the function
+    let breakpoints = json!([{ "line": 3 }, { "line": 12 }, { "line": 99 }]);
+    let set = editor.body("setBreakpoints", json!({ "source": { "path": path }, "breakpoints": breakpoints }));
+    let set: Vec<(bool, u64)> = set["breakpoints"].as_array().unwrap().iter()
+        .map(|breakpoint| (breakpoint["verified"].as_bool().unwrap(), breakpoint["line"].as_u64().unwrap()))
+        .collect();
+    assert_eq!(set, [(true, 5), (true, 12), (false, 99)]);
+
+    let set = editor.body("setFunctionBreakpoints", json!({ "breakpoints": [{ "name": "Double" }, { "name": "Triple" }] }));
+    assert_eq!(set["breakpoints"][0]["verified"], true);
+    assert_eq!(set["breakpoints"][1]["verified"], false);
+    assert!(set["breakpoints"][1]["message"].as_str().unwrap().contains("Triple"), "{}", set);
+    editor.body("configurationDone", json!({}));
+
+    assert_eq!(editor.stopped(), "function breakpoint");
+    assert_eq!(editor.frames()[0], (5, "Double".to_string()));
+    editor.body("continue", json!({ "threadId": 1 }));
+    assert_eq!(editor.stopped(), "breakpoint");
+    assert_eq!(editor.frames()[0].0, 12);
+    editor.body("continue", json!({ "threadId": 1 }));
+    assert_eq!(editor.run_to_end("stdout"), ("8\n9\n".to_string(), 0));
+}
+
+#[test]
+fn runtime_errors_end_the_program() {
+    let path = program("runtime_error.pseu", "DECLARE X : INTEGER\nX <- 0\nOUTPUT 10 DIV X\n");
+    let mut editor = Editor::start();
+    editor.launch(path.to_str().unwrap(), json!({}), &[]);
+    let (errors, exit_code) = editor.run_to_end("stderr");
+    assert!(errors.contains("Division by zero"), "{}", errors);
+    assert_eq!(exit_code, 1);
+}
+
+#[test]
+fn launch_reports_programs_that_do_not_check() {
+    let path = program("type_error.pseu", "DECLARE X : INTEGER\nX <- \"text\"\n");
+    let mut editor = Editor::start();
+    editor.body("initialize", json!({ "adapterID": "pseudocode" }));
+    let response = editor.request("launch", json!({ "program": path }));
+    assert_eq!(response["success"], false);
+    assert!(response["message"].as_str().unwrap().contains("Cannot assign STRING"), "{}", response);
+
+    let response = editor.request("launch", json!({ "program": "missing.pseu" }));
+    assert_eq!(response["success"], false);
+
+    let response = editor.request("stackTrace", json!({ "threadId": 1 }));
+    assert_eq!(response["message"], "The program is not paused");
+}
+
+#[test]
+fn disconnect_while_paused_ends_the_session() {
+    let path = program("disconnect.pseu", PROGRAM);
+    let mut editor = Editor::start();
+    editor.launch(path.to_str().unwrap(), json!({}), &[12]);
+    assert_eq!(editor.stopped(), "breakpoint");
+    editor.body("disconnect", json!({}));
+    assert_eq!(editor.server.finish(), Some(0));
+}
@@ -0,0 +1,126 @@
+//! Scripted sessions with `pseudocode debug`, typing commands on its stdin
+
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+const PROGRAM: &str = "\
+DECLARE Total : INTEGER
+DECLARE Count : INTEGER
+
+PROCEDURE Add(N : INTEGER)
+    Total <- Total + N
+    Count <- Count + 1
+ENDPROCEDURE
+
+Total <- 0
+Count <- 0
+CALL Add(4)
+CALL Add(5)
+OUTPUT Total
+";
+
+/// Debug `PROGRAM`, saved as `name`, typing `commands` one per line. Returns
+/// the exit code and what was printed after each prompt: first the pause
+/// before the program starts, then the answer to each command.
+fn debug(name: &str, commands: &[&str]) -> (Option<i32>, Vec<String>) {
+    let dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("debug");
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join(name), PROGRAM).unwrap();
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_pseudocode"))
+        .args(["debug", name])
+        .current_dir(&dir)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .expect("failed to start pseudocode");
+    let mut stdin = child.stdin.take().unwrap();
+    for command in commands {
+        writeln!(stdin, "{}", command).unwrap();
+    }
+    drop(stdin);
+    let output = child.wait_with_output().unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    (output.status.code(), stdout.split("(debug) ").map(str::to_string).collect())
+}
+
+/// The line each `Stopped at line ...` in `replies` names
+fn stops(replies: &[String]) -> Vec<usize> {
+    replies.iter()
+        .filter_map(|reply| reply.strip_prefix("Stopped at line "))
+        .map(|rest| rest.split(|c: char| !c.is_ascii_digit()).next().unwrap().parse().unwrap())
+        .collect()
+}
+
+#[test]
+fn stepping_in_over_and_out() {
+    let (code, replies) = debug("stepping.pseu", &["next", "next", "next", "step", "step", "finish", "next", "continue"]);
+    assert_eq!(code, Some(0));
+    assert!(replies[0].starts_with("Stopped at line 1, before the program starts\n>    1 | DECLARE Total"), "{}", replies[0]);
+    // Stepping over passes the procedure's definition by, stepping in enters
+    // it, and finishing returns to the line after the call
+    assert_eq!(stops(&replies), [1, 2, 9, 10, 11, 5, 12, 13]);
+    assert_eq!(replies.last().unwrap(), "9\nProgram finished.\n");
+}
+
+#[test]
+fn breakpoints_on_lines_and_routines() {
+    let (code, replies) = debug("breakpoints.pseu", &["break 12", "b Add", "info", "c", "bt", "c", "delete Add", "c", "c"]);
+    assert_eq!(code, Some(0));
+    assert_eq!(replies[1], "Breakpoint on line 12\n");
+    assert_eq!(replies[2], "Breakpoint on entry to Add\n");
+    assert_eq!(replies[3], "Breakpoints on lines: 12\nBreakpoints on entry to: Add\n");
+    assert!(replies[4].starts_with("Stopped at line 5 (entering Add)"), "{}", replies[4]);
+    assert_eq!(replies[5], "#0 Add at line 5\n#1 main program at line 11\n");
+    assert!(replies[6].starts_with("Stopped at line 12 (breakpoint)"), "{}", replies[6]);
+    assert_eq!(replies[7], "Removed the breakpoint on Add\n");
+    assert_eq!(replies[8], "9\nProgram finished.\n");
+}
+
+#[test]
+fn breakpoints_that_match_nothing_are_reported() {
+    let (_, replies) = debug("missing_breakpoints.pseu", &["break 3", "break 99", "break Subtract", "info"]);
+    // Line 3 is blank and line 4 the procedure's header
+    assert_eq!(replies[1], "No statement starts on line 3\nBreakpoint on line 5\n");
+    assert_eq!(replies[2], "No statement on or after line 99, so no breakpoint was set\n");
+    assert_eq!(replies[3], "No procedure or function named Subtract, so no breakpoint was set\n");
+    assert_eq!(replies[4], "Breakpoints on lines: 5\n");
+}
+
+#[test]
+fn watches_are_shown_at_every_pause() {
+    let (_, replies) = debug("watches.pseu", &["watch Total * 10", "w Count", "b 6", "c", "unwatch 1", "c", "info"]);
+    assert_eq!(replies[1], "Watch 1: Total * 10\n");
+    assert_eq!(replies[2], "Watch 2: Count\n");
+    assert!(replies[4].ends_with("  watch 1: Total * 10 = 40\n  watch 2: Count = 0\n"), "{}", replies[4]);
+    assert_eq!(replies[5], "Removed watch 1: Total * 10\n");
+    assert!(replies[6].ends_with("  watch 1: Count = 1\n"), "{}", replies[6]);
+    assert_eq!(replies[7], "Breakpoints on lines: 6\nWatch 1: Count\n");
+}
+
+#[test]
+fn commands_are_parsed_forgivingly() {
+    let commands = ["n", "", "  p   Count + 1  ", "p Count +", "unwatch 7", "break", "delete 4", "frobnicate", "quit"];
+    let (code, replies) = debug("commands.pseu", &commands);
+    assert_eq!(code, Some(0));
+    // An empty line repeats the last command
+    assert_eq!(stops(&replies[1..3]), [2, 9]);
+    assert_eq!(replies[3], "1\n");
+    assert!(!replies[4].is_empty() && !replies[4].starts_with('<'), "{}", replies[4]);
+    assert_eq!(replies[5], "No watch expression 7\n");
+    assert_eq!(replies[6], "Give a line number or a procedure or function name\n");
+    assert_eq!(replies[7], "No breakpoint on 4\n");
+    assert_eq!(replies[8], "Unknown command 'frobnicate'. Type 'help' for the commands.\n");
+    assert_eq!(replies[9], "Stopped.\n");
+}
+
+#[test]
+fn end_of_input_stops_the_program() {
+    let (code, replies) = debug("end_of_input.pseu", &["next"]);
+    assert_eq!(code, Some(0));
+    assert_eq!(replies.len(), 3);
+    assert_eq!(replies[2], "Stopped.\n");
+}
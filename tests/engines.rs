@@ -0,0 +1,100 @@
+//! The interpreter (`pseudocode eval`) and the bytecode VM (`pseudocode
+//! compile` then `run`) must print the same output and the same errors for
+//! every program, down to the spans and notes.
+
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+const EXE: &str = env!("CARGO_BIN_EXE_pseudocode");
+
+/// Run `pseudocode` with `args` in `dir`, feeding it `input`, and return its stdout and stderr
+fn pseudocode(dir: &Path, args: &[&str], input: &[u8]) -> (String, String) {
+    let mut child = Command::new(EXE)
+        .args(args)
+        .current_dir(dir)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("failed to run pseudocode");
+    if let Some(mut stdin) = child.stdin.take() {
+        let _ = stdin.write_all(input);
+    }
+    let output = child.wait_with_output().expect("failed to run pseudocode");
+    (
+        String::from_utf8_lossy(&output.stdout).into_owned(),
+        String::from_utf8_lossy(&output.stderr).into_owned(),
+    )
+}
+
+/// Copy the files in `dir` to a fresh directory for `engine`, so each engine
+/// starts from the same files and what they write stays out of the tree
+fn scratch_copy(dir: &str, engine: &str) -> PathBuf {
+    let scratch = Path::new(env!("CARGO_TARGET_TMPDIR")).join("engines").join(engine).join(dir);
+    let _ = fs::remove_dir_all(&scratch);
+    fs::create_dir_all(&scratch).unwrap();
+    let source = Path::new(env!("CARGO_MANIFEST_DIR")).join(dir);
+    for entry in fs::read_dir(source).unwrap() {
+        let path = entry.unwrap().path();
+        if path.is_file() {
+            fs::copy(&path, scratch.join(path.file_name().unwrap())).unwrap();
+        }
+    }
+    scratch
+}
+
+/// Every program in `dir` behaves the same on both engines
+fn assert_engines_agree(dir: &str) {
+    let interpreter = scratch_copy(dir, "interpreter");
+    let vm = scratch_copy(dir, "vm");
+    let mut programs: Vec<String> = fs::read_dir(&interpreter).unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "pseu"))
+        .map(|path| path.file_name().unwrap().to_str().unwrap().to_string())
+        .collect();
+    programs.sort();
+    assert!(!programs.is_empty(), "no programs in {}", dir);
+
+    let mut disagreements = Vec::new();
+    for name in &programs {
+        let input = fs::read(interpreter.join(name).with_extension("in")).unwrap_or_default();
+
+        let interpreted = pseudocode(&interpreter, &["eval", name], &input);
+
+        // Compiling reports the checker's warnings and errors, as eval does before running
+        let (_, compile_errors) = pseudocode(&vm, &["compile", name], &[]);
+        let bytecode = Path::new(name).with_extension("pseuc");
+        let compiled = if vm.join(&bytecode).exists() {
+            let bytecode = bytecode.to_str().unwrap();
+            let (stdout, stderr) = pseudocode(&vm, &["run", bytecode], &input);
+            (stdout, compile_errors + &stderr)
+        } else {
+            (String::new(), compile_errors)
+        };
+
+        if interpreted != compiled {
+            disagreements.push(format!(
+                "{}/{}:\n--- eval stdout\n{}--- run stdout\n{}--- eval stderr\n{}--- run stderr\n{}",
+                dir, name, interpreted.0, compiled.0, interpreted.1, compiled.1
+            ));
+        }
+    }
+    assert!(disagreements.is_empty(), "the engines disagree on:\n{}", disagreements.join("\n"));
+}
+
+#[test]
+fn unit_tests_agree() {
+    assert_engines_agree("unit_tests");
+}
+
+#[test]
+fn full_programs_agree() {
+    assert_engines_agree("unit_tests/full_program");
+}
+
+#[test]
+fn examples_agree() {
+    assert_engines_agree("code");
+}
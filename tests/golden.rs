@@ -0,0 +1,17 @@
+//! The programs under `unit_tests/` still print what their `.out` and `.err`
+//! files say, on both engines
+
+use std::process::{Command, Stdio};
+
+#[test]
+fn unit_tests_match_their_expected_output() {
+    let output = Command::new(env!("CARGO_BIN_EXE_pseudocode"))
+        .args(["test", "unit_tests"])
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .stdin(Stdio::null())
+        .output()
+        .expect("failed to run pseudocode");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(output.status.code(), Some(0), "{}{}", stdout, String::from_utf8_lossy(&output.stderr));
+    assert!(stdout.contains("test result: "), "{}", stdout);
+}
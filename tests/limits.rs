@@ -0,0 +1,152 @@
+//! Every execution limit stops a program that goes over it with a
+//! `LimitExceeded` error, the same on the interpreter (`pseudocode eval`) and
+//! the VM (`pseudocode compile` then `run`).
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+const EXE: &str = env!("CARGO_BIN_EXE_pseudocode");
+
+/// Run `pseudocode` with `args` in `dir`, returning its exit code, stdout and stderr
+fn pseudocode(dir: &Path, args: &[&str]) -> (Option<i32>, String, String) {
+    let output = Command::new(EXE)
+        .args(args)
+        .current_dir(dir)
+        .stdin(Stdio::null())
+        .output()
+        .expect("failed to run pseudocode");
+    (
+        output.status.code(),
+        String::from_utf8_lossy(&output.stdout).into_owned(),
+        String::from_utf8_lossy(&output.stderr).into_owned(),
+    )
+}
+
+/// Write `source` to `<name>.pseu` in a fresh directory of its own, so the
+/// files it opens stay out of the way of other tests
+fn program(name: &str, source: &str) -> PathBuf {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("limits").join(name);
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join(format!("{}.pseu", name)), source).unwrap();
+    dir
+}
+
+/// Run `source` on both engines under `limit` set to `max`, and check that
+/// each stops with the same `LimitExceeded` error saying `message`, returning
+/// what the program printed first.
+///
+/// Steps count statements on one engine and instructions on the other, and
+/// time depends on the machine, so those two stop the engines at different
+/// places; every other limit stops both at the same statement.
+fn assert_exceeds(name: &str, source: &str, limit: &str, max: &str, message: &str) -> String {
+    let dir = program(name, source);
+    let source_file = format!("{}.pseu", name);
+    let bytecode = format!("{}.pseuc", name);
+
+    let (code, interpreted, interpreter_errors) = pseudocode(&dir, &["eval", &source_file, limit, max]);
+    assert_eq!(code, Some(1), "eval exit code, stderr:\n{}", interpreter_errors);
+    let expected = format!("error[E0500]: Limit exceeded: {}", message);
+    assert!(interpreter_errors.starts_with(&expected), "eval:\n{}", interpreter_errors);
+
+    let (code, _, compile_errors) = pseudocode(&dir, &["compile", &source_file]);
+    assert_eq!(code, Some(0), "compile:\n{}", compile_errors);
+    let (code, ran, vm_errors) = pseudocode(&dir, &["run", &bytecode, limit, max]);
+    assert_eq!(code, Some(1), "run exit code, stderr:\n{}", vm_errors);
+    assert!(vm_errors.starts_with(&expected), "run:\n{}", vm_errors);
+    if !matches!(limit, "--max-steps" | "--max-time-ms") {
+        assert_eq!((&ran, &vm_errors), (&interpreted, &interpreter_errors), "the engines disagree");
+    }
+    interpreted
+}
+
+#[test]
+fn steps() {
+    let source = "\
+DECLARE Count : INTEGER
+Count <- 0
+WHILE TRUE DO
+    Count <- Count + 1
+ENDWHILE
+";
+    assert_exceeds("steps", source, "--max-steps", "1000", "ran more than 1000 steps");
+}
+
+#[test]
+fn time() {
+    let source = "\
+WHILE TRUE DO
+ENDWHILE
+";
+    assert_exceeds("time", source, "--max-time-ms", "50", "ran for more than 50 ms");
+}
+
+#[test]
+fn call_depth() {
+    let source = "\
+PROCEDURE Down(N : INTEGER)
+    CALL Down(N + 1)
+ENDPROCEDURE
+
+CALL Down(1)
+";
+    assert_exceeds("call_depth", source, "--max-call-depth", "50", "calls nested more than 50 deep");
+}
+
+#[test]
+fn call_depth_without_a_limit_given() {
+    let source = "\
+FUNCTION Down(N : INTEGER) RETURNS INTEGER
+    RETURN Down(N + 1)
+ENDFUNCTION
+
+OUTPUT Down(1)
+";
+    let dir = program("default_call_depth", source);
+    let (code, _, errors) = pseudocode(&dir, &["eval", "default_call_depth.pseu"]);
+    assert_eq!(code, Some(1));
+    assert!(errors.starts_with("error[E0500]: Limit exceeded: calls nested more than 10000 deep"), "{}", errors);
+}
+
+#[test]
+fn call_depth_beyond_the_interpreter_stack_is_refused() {
+    let dir = program("deep_call_depth", "OUTPUT 1\n");
+    let (code, stdout, errors) = pseudocode(&dir, &["eval", "deep_call_depth.pseu", "--max-call-depth", "50000"]);
+    assert_eq!(code, Some(1));
+    assert_eq!(stdout, "");
+    assert!(errors.contains("'--max-call-depth' can be at most 10000"), "{}", errors);
+}
+
+#[test]
+fn output_bytes() {
+    let source = "\
+DECLARE Line : INTEGER
+FOR Line <- 1 TO 100
+    OUTPUT \"line \", Line
+NEXT Line
+";
+    let printed = assert_exceeds("output_bytes", source, "--max-output-bytes", "20", "output more than 20 bytes");
+    // Only the lines that fit are printed
+    assert_eq!(printed, "line 1\nline 2\n");
+}
+
+#[test]
+fn elements() {
+    let source = "\
+DECLARE Small : ARRAY[1:10] OF INTEGER
+DECLARE Grid : ARRAY[1:10, 1:10] OF INTEGER
+OUTPUT \"not reached\"
+";
+    assert_exceeds("elements", source, "--max-elements", "100", "allocated more than 100 array elements");
+}
+
+#[test]
+fn open_files() {
+    let source = "\
+OPENFILE \"first.txt\" FOR WRITE
+OPENFILE \"second.txt\" FOR WRITE
+OUTPUT \"not reached\"
+";
+    assert_exceeds("open_files", source, "--max-open-files", "1", "more than 1 files open at once");
+}
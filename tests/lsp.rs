@@ -0,0 +1,234 @@
+//! Scripted sessions with `pseudocode lsp`, playing the editor's part
+
+mod common;
+
+use serde_json::{json, Value as Json};
+
+use common::Server;
+
+const URI: &str = "file:///scores.pseu";
+
+const DOCUMENT: &str = "\
+DECLARE Total : INTEGER
+
+FUNCTION Double(N : INTEGER) RETURNS INTEGER
+    RETURN N * 2
+ENDFUNCTION
+
+Total <- Double(4)
+OUTPUT Total
+";
+
+/// The editor's side of a session
+struct Editor {
+    server: Server,
+    id: u64,
+}
+
+impl Editor {
+    /// Start a server, initialize it and open `DOCUMENT`
+    fn start() -> Editor {
+        let mut editor = Editor { server: Server::start("lsp"), id: 0 };
+        let capabilities = editor.result("initialize", json!({ "capabilities": {} }))["capabilities"].clone();
+        for provider in ["hoverProvider", "definitionProvider", "referencesProvider", "renameProvider"] {
+            assert_eq!(capabilities[provider], true, "{} is not advertised", provider);
+        }
+        editor.notify("initialized", json!({}));
+        editor.open(DOCUMENT);
+        editor
+    }
+
+    fn notify(&mut self, method: &str, params: Json) {
+        self.server.send(&json!({ "jsonrpc": "2.0", "method": method, "params": params }));
+    }
+
+    /// Send a request and return its response
+    fn request(&mut self, method: &str, params: Json) -> Json {
+        self.id += 1;
+        self.server.send(&json!({ "jsonrpc": "2.0", "id": self.id, "method": method, "params": params }));
+        let response = self.server.receive();
+        assert_eq!(response["id"], self.id, "expected the response to {}, got {}", method, response);
+        response
+    }
+
+    /// Send a request that should succeed and return its result
+    fn result(&mut self, method: &str, params: Json) -> Json {
+        let response = self.request(method, params);
+        assert!(response.get("error").is_none(), "{} failed: {}", method, response);
+        response["result"].clone()
+    }
+
+    /// A request about the 0-based `line` and `character` of the document
+    fn at(&mut self, method: &str, line: u64, character: u64, extra: Json) -> Json {
+        let mut params = json!({ "textDocument": { "uri": URI }, "position": { "line": line, "character": character } });
+        params.as_object_mut().unwrap().extend(extra.as_object().unwrap().clone());
+        self.request(method, params)
+    }
+
+    /// Open the document with `text`, returning the diagnostics published for it
+    fn open(&mut self, text: &str) -> Vec<Json> {
+        self.notify("textDocument/didOpen", json!({
+            "textDocument": { "uri": URI, "languageId": "pseudocode", "version": 1, "text": text },
+        }));
+        self.diagnostics()
+    }
+
+    fn diagnostics(&mut self) -> Vec<Json> {
+        let notification = self.server.receive();
+        assert_eq!(notification["method"], "textDocument/publishDiagnostics");
+        assert_eq!(notification["params"]["uri"], URI);
+        notification["params"]["diagnostics"].as_array().unwrap().clone()
+    }
+}
+
+/// The `(line, start character, end character)` of each location or edit's range
+fn ranges(items: &Json) -> Vec<(u64, u64, u64)> {
+    let mut ranges: Vec<_> = items.as_array().unwrap().iter()
+        .map(|item| {
+            if let Some(uri) = item.get("uri") {
+                assert_eq!(uri, URI);
+            }
+            let range = &item["range"];
+            let line = range["start"]["line"].as_u64().unwrap();
+            assert_eq!(range["end"]["line"], line);
+            (line, range["start"]["character"].as_u64().unwrap(), range["end"]["character"].as_u64().unwrap())
+        })
+        .collect();
+    ranges.sort();
+    ranges
+}
+
+#[test]
+fn diagnostics_follow_the_document() {
+    let mut editor = Editor::start();
+    // The document opened by `start` has no problems
+    editor.notify("textDocument/didChange", json!({
+        "textDocument": { "uri": URI, "version": 2 },
+        "contentChanges": [{ "text": "DECLARE Total : INTEGER\nTotal <- \"many\"\n" }],
+    }));
+    let diagnostics = editor.diagnostics();
+    assert_eq!(diagnostics.len(), 1, "{:?}", diagnostics);
+    assert_eq!(diagnostics[0]["severity"], 1);
+    assert_eq!(diagnostics[0]["range"]["start"]["line"], 1);
+    assert!(diagnostics[0]["message"].as_str().unwrap().contains("Cannot assign STRING"), "{}", diagnostics[0]);
+
+    editor.notify("textDocument/didChange", json!({
+        "textDocument": { "uri": URI, "version": 3 },
+        "contentChanges": [{ "text": "DECLARE Total INTEGER\n" }],
+    }));
+    assert_eq!(editor.diagnostics().len(), 1);
+
+    editor.notify("textDocument/didClose", json!({ "textDocument": { "uri": URI } }));
+    assert!(editor.diagnostics().is_empty());
+    let response = editor.at("textDocument/hover", 0, 8, json!({}));
+    assert_eq!(response["error"]["code"], -32803);
+}
+
+#[test]
+fn completion_offers_what_matches_the_word_being_typed() {
+    let mut editor = Editor::start();
+    let labels = |items: Json| -> Vec<String> {
+        items.as_array().unwrap().iter().map(|item| item["label"].as_str().unwrap().to_string()).collect()
+    };
+    // After "OUTPUT To", in any case
+    let items = editor.at("textDocument/completion", 7, 9, json!({}))["result"].clone();
+    let offered = labels(items);
+    assert!(offered.contains(&"Total".to_string()), "{:?}", offered);
+    assert!(offered.iter().all(|label| label.to_lowercase().starts_with("to")), "{:?}", offered);
+    // After "Total <- Do"
+    let items = editor.at("textDocument/completion", 6, 11, json!({}))["result"].clone();
+    let offered = labels(items.clone());
+    assert!(offered.contains(&"Double".to_string()), "{:?}", offered);
+    let double = items.as_array().unwrap().iter().find(|item| item["label"] == "Double").unwrap();
+    assert_eq!(double["kind"], 3);
+}
+
+#[test]
+fn hover_describes_the_word_under_the_cursor() {
+    let mut editor = Editor::start();
+    let hover = editor.at("textDocument/hover", 6, 11, json!({}))["result"].clone();
+    assert_eq!(hover["contents"]["kind"], "markdown");
+    let contents = hover["contents"]["value"].as_str().unwrap();
+    assert!(contents.contains("Double") && contents.contains("INTEGER"), "{}", contents);
+
+    assert_eq!(editor.at("textDocument/hover", 1, 0, json!({}))["result"], Json::Null);
+}
+
+#[test]
+fn columns_count_characters_not_bytes() {
+    let mut editor = Editor::start();
+    editor.notify("textDocument/didChange", json!({
+        "textDocument": { "uri": URI, "version": 2 },
+        "contentChanges": [{ "text": "DECLARE Name : STRING\nName <- \"ééé\" & Name\n" }],
+    }));
+    assert!(editor.diagnostics().is_empty());
+
+    // Inside the string, and on the name after it, where the byte and character columns differ
+    assert_eq!(editor.at("textDocument/hover", 1, 10, json!({}))["result"], Json::Null);
+    let hover = editor.at("textDocument/hover", 1, 16, json!({}))["result"].clone();
+    assert!(hover["contents"]["value"].as_str().unwrap().contains("Name"), "{}", hover);
+    let items = editor.at("textDocument/completion", 1, 18, json!({}))["result"].clone();
+    assert!(items.as_array().unwrap().iter().any(|item| item["label"] == "Name"), "{}", items);
+
+    let references = editor.at("textDocument/references", 1, 16, json!({ "context": { "includeDeclaration": true } }));
+    assert_eq!(ranges(&references["result"]), [(0, 8, 12), (1, 0, 4), (1, 16, 20)]);
+}
+
+#[test]
+fn definition_finds_the_declaration() {
+    let mut editor = Editor::start();
+    let location = editor.at("textDocument/definition", 7, 9, json!({}))["result"].clone();
+    assert_eq!(location["uri"], URI);
+    assert_eq!(location["range"]["start"]["line"], 0);
+
+    let location = editor.at("textDocument/definition", 6, 10, json!({}))["result"].clone();
+    assert_eq!(location["range"]["start"]["line"], 2);
+}
+
+#[test]
+fn references_with_and_without_the_declaration() {
+    let mut editor = Editor::start();
+    let all = editor.at("textDocument/references", 7, 9, json!({ "context": { "includeDeclaration": true } }));
+    assert_eq!(ranges(&all["result"]), [(0, 8, 13), (6, 0, 5), (7, 7, 12)]);
+
+    let uses = editor.at("textDocument/references", 7, 9, json!({ "context": { "includeDeclaration": false } }));
+    assert_eq!(ranges(&uses["result"]), [(6, 0, 5), (7, 7, 12)]);
+
+    // Parameters are found only within their routine
+    let parameter = editor.at("textDocument/references", 3, 11, json!({ "context": { "includeDeclaration": true } }));
+    assert_eq!(ranges(&parameter["result"]), [(2, 16, 17), (3, 11, 12)]);
+}
+
+#[test]
+fn rename_edits_every_occurrence() {
+    let mut editor = Editor::start();
+    let rename = editor.at("textDocument/rename", 6, 10, json!({ "newName": "Twice" }))["result"].clone();
+    let edits = &rename["changes"][URI];
+    assert_eq!(ranges(edits), [(2, 9, 15), (6, 9, 15)]);
+    assert!(edits.as_array().unwrap().iter().all(|edit| edit["newText"] == "Twice"));
+
+    for name in ["2nd", "OUTPUT", ""] {
+        let response = editor.at("textDocument/rename", 6, 10, json!({ "newName": name }));
+        assert_eq!(response["error"]["code"], -32803, "renaming to '{}': {}", name, response);
+    }
+    let response = editor.at("textDocument/rename", 1, 0, json!({ "newName": "Twice" }));
+    assert_eq!(response["error"]["code"], -32803);
+}
+
+#[test]
+fn unknown_requests_and_shutdown() {
+    let mut editor = Editor::start();
+    let response = editor.request("workspace/symbol", json!({ "query": "" }));
+    assert_eq!(response["error"]["code"], -32601);
+
+    assert_eq!(editor.result("shutdown", Json::Null), Json::Null);
+    editor.notify("exit", Json::Null);
+    assert_eq!(editor.server.finish(), Some(0));
+}
+
+#[test]
+fn exit_without_shutdown_fails() {
+    let mut editor = Editor::start();
+    editor.notify("exit", Json::Null);
+    assert_eq!(editor.server.finish(), Some(1));
+}
@@ -69,6 +69,13 @@ function termWrite(text, color = null) {
     }
 }
 
+// Print the warnings among diagnostics, which do not stop a program running
+function writeWarnings(diagnostics) {
+    diagnostics
+        .filter(diagnostic => diagnostic.severity === 'warning')
+        .forEach(warning => termWrite(`Line ${warning.line}: warning: ${warning.message}`, '33'));
+}
+
 // Helper to get FitAddon class
 function getFitAddonClass() {
     if (typeof window.FitAddon === 'undefined') return null;
@@ -796,17 +803,19 @@ function initMonaco() {
                 'DECLARE', 'CONSTANT', 'FUNCTION', 'PROCEDURE', 'ENDFUNCTION', 'ENDPROCEDURE',
                 'IF', 'THEN', 'ELSE', 'ENDIF', 'WHILE', 'DO', 'ENDWHILE',
                 'FOR', 'TO', 'NEXT', 'REPEAT', 'UNTIL',
-                'RETURN', 'CALL', 'INPUT', 'OUTPUT',
+                'RETURN', 'CALL', 'INPUT', 'OUTPUT', 'BYREF', 'BYVAL',
                 'OPENFILE', 'CLOSEFILE', 'READFILE', 'WRITEFILE', 'SEEK',
                 'GETRECORD', 'PUTRECORD',
-                'INTEGER', 'REAL', 'STRING', 'CHAR', 'BOOLEAN', 'ARRAY', 'OF',
-                'AND', 'OR', 'NOT', 'TRUE', 'FALSE',
-                'TYPE', 'ENDTYPE', 'CASE', 'ENDCASE', 'OTHERWISE'
+                'INTEGER', 'REAL', 'STRING', 'CHAR', 'BOOLEAN', 'DATE', 'ARRAY', 'OF',
+                'AND', 'OR', 'NOT', 'TRUE', 'FALSE', 'NULL', 'NIL', 'NEW', 'IN',
+                'TYPE', 'ENDTYPE', 'CASE', 'ENDCASE', 'OTHERWISE', 'MOD', 'DIV'
             ],
-            operators: ['<-', '=', '<>', '<', '>', '<=', '>=', '+', '-', '*', '/', 'MOD'],
+            operators: ['<-', '=', '<>', '<', '>', '<=', '>=', '+', '-', '*', '/', '&', 'MOD', 'DIV'],
             builtinFunctions: [
                 'LENGTH', 'UCASE', 'LCASE', 'SUBSTRING', 'RIGHT', 'MID',
                 'ROUND', 'RANDOM', 'RAND', 'EOF',
+                'DAY', 'MONTH', 'YEAR', 'DAYINDEX', 'SETDATE', 'NOW',
+                'SUCC', 'PRED',
             ],
             tokenizer: {
                 root: [
@@ -826,11 +835,12 @@ function initMonaco() {
                     }],
                     [/"([^"\\]|\\.)*"/, 'string'],
                     [/'([^'\\]|\\.)*'/, 'string'],
+                    [/\d{1,2}\/\d{1,2}\/\d{4}/, 'number'],
                     [/\d+\.\d+/, 'number.float'],
                     [/\d+/, 'number'],
                     [/\/\/.*$/, 'comment'],
                     [/<-/, 'operator'],
-                    [/[=<>+\-*/]/, 'operator'],
+                    [/[=<>+\-*/&]/, 'operator'],
                     [/[(),:;\[\]]/, 'delimiter']
                 ]
             }
@@ -1037,6 +1047,8 @@ async function initWasm() {
     try {
         await init();
         engine = new PseudocodeEngine();
+        // Stop runaway loops and recursion instead of freezing the tab
+        engine.set_limits({ max_steps: 10000000, max_call_depth: 1000 });
         languageService = new PseudocodeLanguageService(engine);
         console.log('WASM initialized successfully');
     } catch (error) {
@@ -1062,15 +1074,24 @@ function highlightErrors(errors) {
     
     clearErrorDecorations();
     
-    const decorations = errors.map(error => ({
-        range: new monaco.Range(error.line, 1, error.line, 1),
-        options: {
-            isWholeLine: true,
-            className: 'error-line',
-            glyphMarginClassName: 'error-glyph',
-            hoverMessage: { value: error.message }
+    const decorations = errors.flatMap(error => [
+        {
+            range: new monaco.Range(error.line, 1, error.line, 1),
+            options: {
+                isWholeLine: true,
+                className: 'error-line',
+                glyphMarginClassName: 'error-glyph',
+                hoverMessage: { value: error.message }
+            }
+        },
+        {
+            // Underline the exact span the error points at
+            range: new monaco.Range(error.line, error.column, error.end_line, error.end_column),
+            options: {
+                inlineClassName: 'error-range'
+            }
         }
-    }));
+    ]);
     
     errorDecorations = editor.deltaDecorations([], decorations);
 }
@@ -1166,7 +1187,11 @@ async function runCode() {
     }
     
     try {
-        // Parse the code for step-by-step execution
+        // NOW returns the visitor's local date
+        const today = new Date();
+        engine.set_today(today.getDate(), today.getMonth() + 1, today.getFullYear());
+
+        // Parse and compile the code, ready to run
         const parseResult = engine.parse_for_execution(code);
         if (!parseResult || !parseResult.valid) {
             const errors = parseResult?.errors || [];
@@ -1179,19 +1204,12 @@ async function runCode() {
             highlightErrors(errors);
             return;
         }
+        writeWarnings(parseResult.errors);
         
-        // Execute statements one by one
-        while (engine.has_more_statements()) {
-            // Check if next statement is INPUT
-            const stmtInfo = engine.get_next_statement_info();
-            if (stmtInfo.is_input && stmtInfo.input_var_name) {
-                const inputValue = await promptInput('');
-                engine.clear_inputs();
-                engine.add_input(inputValue);
-            }
-            
-            // Execute the next statement
-            const result = engine.execute_next_statement();
+        // Run until the program finishes, fails or waits for INPUT, which
+        // may be anywhere, including inside loops and procedures
+        while (true) {
+            const result = engine.resume();
             
             // Display any output immediately
             if (result.output) {
@@ -1204,14 +1222,22 @@ async function runCode() {
             }
             
             // Check for errors
-            if (result.errors && result.errors.length > 0) {
+            const diagnostics = result.errors || [];
+            writeWarnings(diagnostics);
+            const errors = diagnostics.filter(error => error.severity !== 'warning');
+            if (errors.length > 0) {
                 termWrite('\r\n--- Errors ---', '31');
-                result.errors.forEach(error => {
+                errors.forEach(error => {
                     termWrite(`Line ${error.line}: ${error.message}`, '31');
                 });
-                highlightErrors(result.errors);
+                highlightErrors(errors);
+                break;
+            }
+            
+            if (result.status !== 'input') {
                 break;
             }
+            engine.provide_input(await promptInput(''));
         }
         
         termWrite('\r\nProgram execution complete.\r\n', '32');
@@ -1243,6 +1269,7 @@ async function checkSyntax() {
         const result = engine.check_syntax(code);
         if (result.valid) {
             termWrite('Syntax check passed!', '32');
+            writeWarnings(result.errors);
         } else {
             termWrite('Syntax errors found:', '31');
             result.errors.forEach(error => {
@@ -1462,6 +1489,9 @@ style.textContent = `
         background-color: #f48771;
         width: 4px !important;
     }
+    .error-range {
+        text-decoration: underline wavy #f48771;
+    }
 `;
 document.head.appendChild(style);
 